@@ -1,8 +1,10 @@
 use cubestore::config::Config;
+use cubestore::flight::FlightServer;
+use cubestore::http;
 use cubestore::mysql::MySqlServer;
 use cubestore::telemetry::{track_event, ReportingLogger};
-use log::debug;
 use log::Level;
+use log::{debug, error};
 use simple_logger::SimpleLogger;
 use std::collections::HashMap;
 use std::env;
@@ -33,7 +35,13 @@ fn main() {
         .build()
         .unwrap();
 
-    let config = Config::default();
+    let config = match Config::default() {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Invalid configuration: {}", e);
+            std::process::exit(1);
+        }
+    };
 
     config.configure_worker();
 
@@ -44,12 +52,38 @@ fn main() {
     });
 
     runtime.block_on(async move {
-        let services = config.configure().await;
+        let services = match config.configure().await {
+            Ok(services) => services,
+            Err(e) => {
+                error!("Can't start Cube Store: {}", e);
+                std::process::exit(1);
+            }
+        };
         services.start_processing_loops().await.unwrap();
 
         track_event("Cube Store Start".to_string(), HashMap::new()).await;
 
         if !services.cluster.is_select_worker() {
+            if let Some(http_address) = config.config_obj().http_bind_address() {
+                let http_address = http_address.to_string();
+                let sql_service = services.sql_service.clone();
+                let config_obj = config.config_obj();
+                tokio::spawn(async move {
+                    http::run_server(http_address, sql_service, config_obj)
+                        .await
+                        .unwrap();
+                });
+            }
+            if let Some(flight_address) = config.config_obj().flight_bind_address() {
+                let flight_address = flight_address.to_string();
+                let sql_service = services.sql_service.clone();
+                let config_obj = config.config_obj();
+                tokio::spawn(async move {
+                    FlightServer::listen(flight_address, sql_service, config_obj)
+                        .await
+                        .unwrap();
+                });
+            }
             MySqlServer::listen(
                 format!(
                     "{}:{}",
@@ -57,6 +91,7 @@ fn main() {
                     config.config_obj().bind_port()
                 ),
                 services.sql_service.clone(),
+                config.config_obj(),
             )
             .await
             .unwrap();
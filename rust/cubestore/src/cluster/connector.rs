@@ -0,0 +1,48 @@
+use crate::cluster::tls;
+use crate::config::TlsConfig;
+use crate::CubeError;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tokio_rustls::TlsConnector;
+
+pub trait NodeStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> NodeStream for S {}
+
+/// Opens connections to worker and metastore ports of other nodes, over TLS if it's configured.
+pub struct NodeConnector {
+    connect_timeout: Duration,
+    tls: Option<(TlsConnector, TlsConfig)>,
+}
+
+impl NodeConnector {
+    pub fn new(
+        connect_timeout: Duration,
+        tls_config: &Option<TlsConfig>,
+    ) -> Result<NodeConnector, CubeError> {
+        let tls = match tls_config {
+            Some(tls_config) => Some((tls::tls_connector(tls_config)?, tls_config.clone())),
+            None => None,
+        };
+        Ok(NodeConnector {
+            connect_timeout,
+            tls,
+        })
+    }
+
+    pub async fn connect(&self, address: &str) -> Result<Box<dyn NodeStream>, CubeError> {
+        let stream = timeout(self.connect_timeout, TcpStream::connect(address)).await??;
+        match &self.tls {
+            Some((connector, tls_config)) => {
+                let server_name = tls::server_name(tls_config, address);
+                let stream = connector
+                    .connect(tls::dns_name(&server_name)?, stream)
+                    .await?;
+                Ok(Box::new(stream))
+            }
+            None => Ok(Box::new(stream)),
+        }
+    }
+}
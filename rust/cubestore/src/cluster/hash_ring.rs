@@ -0,0 +1,114 @@
+use sha1::{Digest, Sha1};
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+
+/// Number of points each node takes on the ring. More points give a more even distribution.
+const VIRTUAL_NODES: u32 = 128;
+
+/// Consistent-hash ring which assigns partitions to nodes.
+/// Adding or removing a node moves only about 1/N of the partitions so files
+/// downloaded by the partition owner stay warm on its local disk.
+pub struct HashRing {
+    ring: BTreeMap<u64, String>,
+}
+
+impl HashRing {
+    pub fn new(nodes: &Vec<String>) -> HashRing {
+        let mut ring = BTreeMap::new();
+        for node in nodes.iter() {
+            for i in 0..VIRTUAL_NODES {
+                ring.insert(hash(format!("{}#{}", node, i).as_bytes()), node.to_string());
+            }
+        }
+        HashRing { ring }
+    }
+
+    pub fn node_for_partition(&self, partition_id: u64) -> Option<&String> {
+        let point = hash(&partition_id.to_be_bytes());
+        self.ring
+            .range(point..)
+            .next()
+            .or_else(|| self.ring.iter().next())
+            .map(|(_, node)| node)
+    }
+
+    /// Distinct nodes in the order they follow the point of `partition_id` on the ring, the
+    /// owner first.
+    pub fn nodes_for_partition(&self, partition_id: u64) -> Vec<&String> {
+        let point = hash(&partition_id.to_be_bytes());
+        let mut nodes = Vec::new();
+        for (_, node) in self.ring.range(point..).chain(self.ring.range(..point)) {
+            if !nodes.contains(&node) {
+                nodes.push(node);
+            }
+        }
+        nodes
+    }
+}
+
+/// Has to be stable across processes and builds as every router should pick the same owners.
+fn hash(bytes: &[u8]) -> u64 {
+    let digest = Sha1::digest(bytes);
+    u64::from_be_bytes(digest[0..8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nodes(count: usize) -> Vec<String> {
+        (0..count).map(|i| format!("worker-{}:9001", i)).collect()
+    }
+
+    #[test]
+    fn stable_owners() {
+        let ring = HashRing::new(&nodes(3));
+        for partition_id in 0..100 {
+            assert_eq!(
+                ring.node_for_partition(partition_id),
+                HashRing::new(&nodes(3)).node_for_partition(partition_id)
+            );
+        }
+        assert!(HashRing::new(&Vec::new()).node_for_partition(1).is_none());
+    }
+
+    #[test]
+    fn nodes_in_ring_order() {
+        let ring = HashRing::new(&nodes(3));
+        for partition_id in 0..100 {
+            let ordered = ring.nodes_for_partition(partition_id);
+            assert_eq!(ordered.len(), 3);
+            assert_eq!(Some(ordered[0]), ring.node_for_partition(partition_id));
+            assert!(nodes(3).iter().all(|n| ordered.contains(&n)));
+        }
+        assert!(HashRing::new(&Vec::new()).nodes_for_partition(1).is_empty());
+    }
+
+    #[test]
+    fn add_node_moves_part_of_partitions() {
+        let before = HashRing::new(&nodes(4));
+        let after = HashRing::new(&nodes(5));
+        let total = 10000;
+        let mut moved = 0;
+        for partition_id in 0..total {
+            let old_owner = before.node_for_partition(partition_id).unwrap();
+            let new_owner = after.node_for_partition(partition_id).unwrap();
+            if old_owner != new_owner {
+                assert_eq!(new_owner, "worker-4:9001");
+                moved += 1;
+            }
+        }
+        assert!(
+            moved > total / 10 && moved < total * 3 / 10,
+            "moved {}",
+            moved
+        );
+
+        for node in nodes(4).iter() {
+            let owned = (0..total)
+                .filter(|p| before.node_for_partition(*p).unwrap() == node)
+                .count();
+            assert!(owned > 1500 && owned < 3500, "{} owns {}", node, owned);
+        }
+    }
+}
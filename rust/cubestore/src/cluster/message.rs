@@ -1,18 +1,29 @@
+use crate::cluster::JobEvent;
+use crate::metastore::rpc::{MetaStoreRpcMethodCall, MetaStoreRpcMethodResult};
 use crate::queryplanner::query_executor::SerializedRecordBatchStream;
 use crate::queryplanner::serialized_plan::SerializedPlan;
 use crate::CubeError;
 use serde::{Deserialize, Serialize};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum NetworkMessage {
     Select(SerializedPlan),
-    SelectResult(Result<SerializedRecordBatchStream, CubeError>),
+    /// Outer error means the worker failed to run the select, the inner one is returned by it.
+    SelectResult(Result<Result<SerializedRecordBatchStream, CubeError>, CubeError>),
+    /// Sent by the router over the connection of a running `Select` to cancel it.
+    CancelSelect,
+    /// Wakes up job runners of the receiving node. No response is sent.
+    NotifyJobRunner,
+    /// Sent by workers to the router metastore port.
+    MetaStoreCall(MetaStoreRpcMethodCall),
+    MetaStoreResult(MetaStoreRpcMethodResult),
+    /// Result of a job run by a worker which is sent to the router. No response is sent.
+    JobEvent(JobEvent),
 }
 
 impl NetworkMessage {
-    pub async fn send(&self, socket: &mut TcpStream) -> Result<(), CubeError> {
+    pub async fn send<S: AsyncWrite + Unpin>(&self, socket: &mut S) -> Result<(), CubeError> {
         let mut ser = flexbuffers::FlexbufferSerializer::new();
         self.serialize(&mut ser).unwrap();
         let message_buffer = ser.take_buffer();
@@ -21,7 +32,7 @@ impl NetworkMessage {
         Ok(())
     }
 
-    pub async fn receive(socket: &mut TcpStream) -> Result<Self, CubeError> {
+    pub async fn receive<S: AsyncRead + Unpin>(socket: &mut S) -> Result<Self, CubeError> {
         let len = socket.read_u64().await?;
         let mut buffer = Vec::with_capacity(len as usize);
         socket.take(len).read_to_end(&mut buffer).await?;
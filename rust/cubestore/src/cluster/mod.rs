@@ -1,12 +1,19 @@
+pub mod connector;
+pub mod hash_ring;
 pub mod message;
+pub mod process_list;
+pub mod tls;
 pub mod worker_pool;
 
+use crate::cluster::connector::NodeConnector;
+use crate::cluster::hash_ring::HashRing;
 use crate::cluster::message::NetworkMessage;
+use crate::cluster::process_list::{cancelled, ProcessList};
 use crate::cluster::worker_pool::{MessageProcessor, WorkerPool};
 use crate::config::{Config, ConfigObj};
 use crate::import::ImportService;
 use crate::metastore::job::{Job, JobStatus, JobType};
-use crate::metastore::{IdRow, MetaStore, Partition, RowKey, TableId};
+use crate::metastore::{IdRow, MetaStore, RowKey, TableId};
 use crate::queryplanner::query_executor::{QueryExecutor, SerializedRecordBatchStream};
 use crate::queryplanner::serialized_plan::SerializedPlan;
 use crate::remotefs::RemoteFs;
@@ -15,36 +22,37 @@ use crate::store::ChunkDataStore;
 use crate::CubeError;
 use arrow::record_batch::RecordBatch;
 use async_trait::async_trait;
-use chrono::Utc;
+use chrono::{DateTime, NaiveDateTime, Utc};
 use core::mem;
 use futures::future::join_all;
 use futures::FutureExt;
 use itertools::Itertools;
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use mockall::automock;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::collections::hash_map::DefaultHasher;
 use std::collections::{HashMap, HashSet};
-use std::hash::{Hash, Hasher};
+use std::iter;
 use std::path::Path;
 use std::sync::Arc;
 use std::time::Duration;
 use std::time::SystemTime;
 use tokio::fs::File;
-use tokio::io::AsyncWriteExt;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::runtime::Handle;
 use tokio::sync::broadcast::{Receiver, Sender};
 use tokio::sync::{broadcast, oneshot, watch, Notify, RwLock};
 use tokio::time::timeout;
 use tokio::{fs, time};
+use tokio_rustls::TlsAcceptor;
 
 #[automock]
 #[async_trait]
 pub trait Cluster: Send + Sync {
     async fn notify_job_runner(&self, node_name: String) -> Result<(), CubeError>;
 
+    /// Plan is retried on other live nodes if `node_name` fails.
     async fn run_select(
         &self,
         node_name: String,
@@ -53,16 +61,21 @@ pub trait Cluster: Send + Sync {
 
     async fn available_nodes(&self) -> Result<Vec<String>, CubeError>;
 
+    /// Select workers which have ever sent a heart-beat, including dead ones.
+    async fn nodes(&self) -> Result<Vec<NodeState>, CubeError>;
+
     fn server_name(&self) -> &str;
 
     async fn download(&self, remote_path: &str) -> Result<String, CubeError>;
 
     fn job_result_listener(&self) -> JobResultListener;
+}
 
-    async fn node_name_by_partitions(
-        &self,
-        partitions: &Vec<IdRow<Partition>>,
-    ) -> Result<String, CubeError>;
+#[derive(Clone, Debug, PartialEq)]
+pub struct NodeState {
+    pub name: String,
+    pub last_heart_beat: DateTime<Utc>,
+    pub alive: bool,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, Hash, Eq, PartialEq)]
@@ -93,6 +106,11 @@ pub struct ClusterImpl {
     query_executor: Arc<dyn QueryExecutor>,
     close_worker_socket_tx: watch::Sender<bool>,
     close_worker_socket_rx: RwLock<watch::Receiver<bool>>,
+    tls_acceptor: Option<TlsAcceptor>,
+    connector: Arc<NodeConnector>,
+    process_list: Arc<ProcessList>,
+    nodes: RwLock<Vec<NodeState>>,
+    heart_beat_stop_rx: watch::Receiver<bool>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -131,8 +149,22 @@ pub struct JobRunner {
     notify: Arc<Notify>,
     event_sender: Sender<JobEvent>,
     jobs_enabled: Arc<RwLock<bool>>,
+    connector: Arc<NodeConnector>,
+    /// Metastore address of the router which events are sent to if jobs run on a worker.
+    router_address: Option<String>,
 }
 
+/// Ports accept different messages: selects and job notifications are sent to every node while
+/// metastore calls and job events are sent only to the router.
+#[derive(Clone, Copy, Debug)]
+enum Port {
+    Worker,
+    MetaStore,
+}
+
+/// Attempts to resend a job event to the router, about a minute in total.
+const JOB_EVENT_RETRIES: u32 = 5;
+
 lazy_static! {
     static ref HEART_BEAT_NODE_REGEX: Regex =
         Regex::new(r"^node-heart-beats/(?P<node>.*)$").unwrap();
@@ -145,7 +177,7 @@ impl Cluster for ClusterImpl {
             self.job_notify.notify();
             Ok(())
         } else {
-            unimplemented!()
+            self.send_job_notification(&node_name).await
         }
     }
 
@@ -154,24 +186,29 @@ impl Cluster for ClusterImpl {
         node_name: String,
         plan_node: SerializedPlan,
     ) -> Result<Vec<RecordBatch>, CubeError> {
-        if self.server_name == node_name {
-            // TODO timeout config
-            timeout(
-                Duration::from_secs(self.config_obj.query_timeout()),
-                self.run_local_select(plan_node),
-            )
-            .await?
+        self.run_select_with_retries(node_name, plan_node).await
+    }
+
+    async fn available_nodes(&self) -> Result<Vec<String>, CubeError> {
+        let live_nodes = self
+            .nodes
+            .read()
+            .await
+            .iter()
+            .filter(|n| n.alive)
+            .map(|n| n.name.to_string())
+            .collect::<Vec<_>>();
+        if !live_nodes.is_empty() {
+            Ok(live_nodes)
+        } else if !self.config_obj.select_workers().is_empty() {
+            Ok(self.config_obj.select_workers().clone())
         } else {
-            timeout(
-                Duration::from_secs(self.config_obj.query_timeout()),
-                self.send_select_to_worker(node_name, plan_node),
-            )
-            .await?
+            Ok(vec![self.server_name.to_string()])
         }
     }
 
-    async fn available_nodes(&self) -> Result<Vec<String>, CubeError> {
-        Ok(vec![self.server_name.to_string()])
+    async fn nodes(&self) -> Result<Vec<NodeState>, CubeError> {
+        Ok(self.nodes.read().await.clone())
     }
 
     fn server_name(&self) -> &str {
@@ -185,28 +222,14 @@ impl Cluster for ClusterImpl {
     fn job_result_listener(&self) -> JobResultListener {
         JobResultListener {
             receiver: self.event_sender.subscribe(),
+            wait_timeout: Duration::from_secs(self.config_obj.job_wait_timeout()),
         }
     }
-
-    async fn node_name_by_partitions(
-        &self,
-        partitions: &Vec<IdRow<Partition>>,
-    ) -> Result<String, CubeError> {
-        let workers = self.config_obj.select_workers();
-        if workers.is_empty() {
-            return Ok(self.server_name.to_string());
-        }
-
-        let mut hasher = DefaultHasher::new();
-        for p in partitions.iter() {
-            p.get_id().hash(&mut hasher);
-        }
-        Ok(workers[(hasher.finish() % workers.len() as u64) as usize].to_string())
-    }
 }
 
 pub struct JobResultListener {
     receiver: Receiver<JobEvent>,
+    wait_timeout: Duration,
 }
 
 impl JobResultListener {
@@ -223,16 +246,39 @@ impl JobResultListener {
             .unwrap())
     }
 
+    /// Events of jobs which are lost with their node are never received, so waiting is limited
+    /// by the job wait timeout.
     pub async fn wait_for_job_results(
         mut self,
         mut results: Vec<(RowKey, JobType)>,
     ) -> Result<Vec<JobEvent>, CubeError> {
         let mut res = Vec::new();
+        let waited = timeout(
+            self.wait_timeout,
+            Self::receive_job_results(&mut self.receiver, &mut results, &mut res),
+        )
+        .await;
+        match waited {
+            Ok(Ok(())) => Ok(res),
+            Ok(Err(e)) => Err(e),
+            Err(_) => Err(CubeError::user(format!(
+                "Jobs are still running after {} seconds: {:?}",
+                self.wait_timeout.as_secs(),
+                results
+            ))),
+        }
+    }
+
+    async fn receive_job_results(
+        receiver: &mut Receiver<JobEvent>,
+        results: &mut Vec<(RowKey, JobType)>,
+        res: &mut Vec<JobEvent>,
+    ) -> Result<(), CubeError> {
         loop {
             if results.len() == 0 {
-                return Ok(res);
+                return Ok(());
             }
-            let event = self.receiver.recv().await?;
+            let event = receiver.recv().await?;
             if let JobEvent::Success(k, t) | JobEvent::Error(k, t, _) = &event {
                 if let Some((index, _)) = results
                     .iter()
@@ -295,10 +341,11 @@ impl JobRunner {
             }
         });
         debug!("Running job: {:?}", job);
-        self.event_sender.send(JobEvent::Started(
+        self.send_event(JobEvent::Started(
             job.get_row().row_reference().clone(),
             job.get_row().job_type().clone(),
-        ))?;
+        ))
+        .await?;
         let res = timeout(Duration::from_secs(300), self.route_job(job.get_row())).await;
         mem::drop(rx);
         heart_beat_timer.await?;
@@ -311,11 +358,12 @@ impl JobRunner {
                 start.elapsed()?,
                 self.meta_store.get_job(job_id).await?
             );
-            self.event_sender.send(JobEvent::Error(
+            self.send_event(JobEvent::Error(
                 job.get_row().row_reference().clone(),
                 job.get_row().job_type().clone(),
                 timeout_err.to_string(),
-            ))?;
+            ))
+            .await?;
         } else if let Ok(Err(cube_err)) = res {
             self.meta_store
                 .update_status(job_id, JobStatus::Error(cube_err.to_string()))
@@ -325,11 +373,12 @@ impl JobRunner {
                 start.elapsed()?,
                 self.meta_store.get_job(job_id).await?
             );
-            self.event_sender.send(JobEvent::Error(
+            self.send_event(JobEvent::Error(
                 job.get_row().row_reference().clone(),
                 job.get_row().job_type().clone(),
                 cube_err.to_string(),
-            ))?;
+            ))
+            .await?;
         } else {
             let deleted_job = self.meta_store.delete_job(job_id).await?;
             debug!(
@@ -337,11 +386,50 @@ impl JobRunner {
                 start.elapsed()?,
                 deleted_job
             );
-            self.event_sender.send(JobEvent::Success(
+            self.send_event(JobEvent::Success(
                 job.get_row().row_reference().clone(),
                 job.get_row().job_type().clone(),
-            ))?;
+            ))
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Queries waiting for job results listen to events of the router. The job row is already
+    /// updated when the event is sent, so it's retried as waiting queries can't get it otherwise.
+    async fn send_event(&self, event: JobEvent) -> Result<(), CubeError> {
+        let address = match &self.router_address {
+            Some(address) => address,
+            None => {
+                self.event_sender.send(event)?;
+                return Ok(());
+            }
+        };
+        let mut attempt = 0;
+        loop {
+            match Self::send_event_to_router(&self.connector, address, event.clone()).await {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt < JOB_EVENT_RETRIES => {
+                    attempt += 1;
+                    warn!(
+                        "Error sending {:?} to router, retrying ({}): {}",
+                        event, attempt, e
+                    );
+                    time::delay_for(Duration::from_secs(1 << attempt)).await;
+                }
+                Err(e) => return Err(e),
+            }
         }
+    }
+
+    async fn send_event_to_router(
+        connector: &NodeConnector,
+        address: &str,
+        event: JobEvent,
+    ) -> Result<(), CubeError> {
+        let mut stream = connector.connect(address).await?;
+        NetworkMessage::JobEvent(event).send(&mut stream).await?;
+        stream.flush().await?;
         Ok(())
     }
 
@@ -407,10 +495,19 @@ impl ClusterImpl {
         import_service: Arc<dyn ImportService>,
         config_obj: Arc<dyn ConfigObj>,
         query_executor: Arc<dyn QueryExecutor>,
-    ) -> Arc<ClusterImpl> {
+        process_list: Arc<ProcessList>,
+    ) -> Result<Arc<ClusterImpl>, CubeError> {
         let (sender, receiver) = broadcast::channel(10000); // TODO config
         let (close_worker_socket_tx, close_worker_socket_rx) = watch::channel(false);
-        Arc::new(ClusterImpl {
+        let tls_acceptor = match config_obj.worker_tls() {
+            Some(tls_config) => Some(tls::tls_acceptor(tls_config)?),
+            None => None,
+        };
+        let connector = Arc::new(NodeConnector::new(
+            connect_timeout,
+            config_obj.worker_tls(),
+        )?);
+        Ok(Arc::new(ClusterImpl {
             server_name,
             server_addresses,
             remote_fs,
@@ -427,8 +524,13 @@ impl ClusterImpl {
             config_obj,
             query_executor,
             close_worker_socket_tx,
+            heart_beat_stop_rx: close_worker_socket_rx.clone(),
             close_worker_socket_rx: RwLock::new(close_worker_socket_rx),
-        })
+            tls_acceptor,
+            connector,
+            process_list,
+            nodes: RwLock::new(Vec::new()),
+        }))
     }
 
     pub fn is_select_worker(&self) -> bool {
@@ -452,7 +554,8 @@ impl ClusterImpl {
                 Duration::from_secs(self.config_obj.query_timeout()),
             )));
         }
-        if !self.is_select_worker() {
+        let router_address = self.config_obj.metastore_remote_address().clone();
+        if !self.is_select_worker() || router_address.is_some() {
             for _ in 0..4 {
                 // TODO number of job event loops
                 let job_runner = JobRunner {
@@ -464,6 +567,8 @@ impl ClusterImpl {
                     notify: self.job_notify.clone(),
                     event_sender: self.event_sender.clone(),
                     jobs_enabled: self.jobs_enabled.clone(),
+                    connector: self.connector.clone(),
+                    router_address: router_address.clone(),
                 };
                 tokio::spawn(async move {
                     job_runner.processing_loop().await;
@@ -486,67 +591,393 @@ impl ClusterImpl {
         Ok(())
     }
 
+    /// Select workers register themselves by heart-beats while routers track live workers.
+    pub async fn heart_beat_loop(cluster: Arc<ClusterImpl>) {
+        let interval = Duration::from_secs(cluster.config_obj.heart_beat_interval());
+        let stopped = cancelled(Some(cluster.heart_beat_stop_rx.clone()));
+        tokio::pin!(stopped);
+        loop {
+            let res = if cluster.is_select_worker() {
+                cluster.send_heart_beat().await
+            } else {
+                cluster.refresh_nodes().await
+            };
+            if let Err(e) = res {
+                error!("Error during heart beat: {}", e);
+            }
+            tokio::select! {
+                _ = &mut stopped => break,
+                _ = time::delay_for(interval) => {}
+            }
+        }
+        if cluster.is_select_worker() {
+            let heart_beat_path = format!("node-heart-beats/{}", cluster.server_name);
+            if let Err(e) = cluster.remote_fs.delete_file(&heart_beat_path).await {
+                error!("Error during heart beat removal: {}", e);
+            }
+        }
+    }
+
+    async fn send_heart_beat(&self) -> Result<(), CubeError> {
+        let heart_beats_dir =
+            Path::new(self.remote_fs.local_path().await.as_str()).join("node-heart-beats");
+
+        fs::create_dir_all(heart_beats_dir.clone()).await?;
+
+        let heart_beat_path = heart_beats_dir.join(&self.server_name);
+
+        {
+            let mut heart_beat_file = File::create(heart_beat_path.clone()).await?;
+
+            heart_beat_file
+                .write_u64(
+                    SystemTime::now()
+                        .duration_since(SystemTime::UNIX_EPOCH)?
+                        .as_secs(),
+                )
+                .await?;
+            heart_beat_file.flush().await?;
+        }
+
+        let to_upload = heart_beat_path
+            .to_str()
+            .unwrap()
+            .to_string()
+            .replace(&self.remote_fs.local_path().await, "")
+            .trim_start_matches("/")
+            .to_string();
+
+        self.remote_fs.upload_file(&to_upload).await
+    }
+
+    /// Liveness is decided by the time written by the worker rather than the file modification
+    /// time which is set by the remote storage.
+    async fn refresh_nodes(&self) -> Result<(), CubeError> {
+        let heart_beats = self
+            .remote_fs
+            .list_with_metadata("node-heart-beats/")
+            .await?;
+        let heart_beat_timeout =
+            chrono::Duration::seconds(self.config_obj.heart_beat_timeout() as i64);
+        let dead_node_timeout =
+            chrono::Duration::seconds(self.config_obj.dead_node_timeout() as i64);
+        let now = Utc::now();
+        let mut nodes = Vec::new();
+        for heart_beat in heart_beats.iter() {
+            let name = match HEART_BEAT_NODE_REGEX
+                .captures(heart_beat.remote_path())
+                .and_then(|v| v.name("node"))
+            {
+                Some(name) => name.as_str().to_string(),
+                None => continue,
+            };
+            let last_heart_beat = match self.read_heart_beat(heart_beat.remote_path()).await {
+                Ok(time) => time,
+                Err(e) => {
+                    warn!("Can't read heart beat of {}: {}", name, e);
+                    heart_beat.updated().clone()
+                }
+            };
+            if last_heart_beat + dead_node_timeout < now {
+                info!("Removing heart beat of dead node {}", name);
+                self.remote_fs.delete_file(heart_beat.remote_path()).await?;
+                continue;
+            }
+            nodes.push(NodeState {
+                name,
+                last_heart_beat,
+                alive: last_heart_beat + heart_beat_timeout >= now,
+            });
+        }
+        nodes.sort_by(|a, b| a.name.cmp(&b.name));
+        *self.nodes.write().await = nodes;
+        Ok(())
+    }
+
+    async fn read_heart_beat(&self, remote_path: &str) -> Result<DateTime<Utc>, CubeError> {
+        // Heart-beat files are overwritten so the previously downloaded copy is stale.
+        let local_path = self.remote_fs.local_file(remote_path).await?;
+        if Path::new(&local_path).exists() {
+            fs::remove_file(&local_path).await?;
+        }
+        let local_path = self.remote_fs.download_file(remote_path).await?;
+        let seconds = File::open(local_path).await?.read_u64().await?;
+        Ok(DateTime::from_utc(
+            NaiveDateTime::from_timestamp(seconds as i64, 0),
+            Utc,
+        ))
+    }
+
+    /// Each attempt is limited by the query timeout. Only attempts which failed to reach the node,
+    /// lost its worker process or timed out are retried as errors of the select itself would be
+    /// the same on any node.
+    async fn run_select_with_retries(
+        &self,
+        node_name: String,
+        plan_node: SerializedPlan,
+    ) -> Result<Vec<RecordBatch>, CubeError> {
+        let mut node_name = node_name;
+        let mut failed_nodes = Vec::new();
+        loop {
+            let res = timeout(
+                Duration::from_secs(self.config_obj.query_timeout()),
+                self.run_select_on_node(node_name.clone(), plan_node.clone()),
+            )
+            .await;
+            let error = match res {
+                Ok(Ok(res)) => return res,
+                Ok(Err(e)) => e,
+                Err(_) => CubeError::internal(format!(
+                    "Select on {} timed out after {} seconds",
+                    node_name,
+                    self.config_obj.query_timeout()
+                )),
+            };
+            if failed_nodes.len() >= self.config_obj.select_retries() {
+                return Err(error);
+            }
+            failed_nodes.push(node_name);
+            node_name = match self.retry_node(&plan_node, &failed_nodes).await? {
+                Some(node) => node,
+                None => return Err(error),
+            };
+            warn!(
+                "Retrying select on {} after failure on {}: {}",
+                node_name,
+                failed_nodes.last().unwrap(),
+                error
+            );
+            if let Some(id) = plan_node.query_id() {
+                self.process_list.add_retry(id);
+            }
+        }
+    }
+
+    /// Outer error means the node couldn't be reached or failed to run the select, the inner one
+    /// is returned by the select.
+    async fn run_select_on_node(
+        &self,
+        node_name: String,
+        plan_node: SerializedPlan,
+    ) -> Result<Result<Vec<RecordBatch>, CubeError>, CubeError> {
+        let cancel_rx = plan_node
+            .query_id()
+            .and_then(|id| self.process_list.add_node(id, &node_name));
+        if self.server_name == node_name {
+            tokio::select! {
+                res = self.run_local_select(plan_node) => res,
+                _ = cancelled(cancel_rx) => Ok(Err(CubeError::user("Query was cancelled".to_string()))),
+            }
+        } else {
+            self.send_select_to_worker(node_name, plan_node, cancel_rx)
+                .await
+        }
+    }
+
+    /// Any node can run a sub-plan as partition files are immutable. Nodes are tried in the ring
+    /// order of its first partition, so retries of a failed node are spread like its partitions.
+    /// Router itself is the last resort.
+    async fn retry_node(
+        &self,
+        plan_node: &SerializedPlan,
+        failed_nodes: &Vec<String>,
+    ) -> Result<Option<String>, CubeError> {
+        let partition_id = plan_node
+            .partition_ids_to_execute()
+            .into_iter()
+            .min()
+            .unwrap_or(0);
+        let nodes = self.available_nodes().await?;
+        Ok(HashRing::new(&nodes)
+            .nodes_for_partition(partition_id)
+            .into_iter()
+            .cloned()
+            .chain(iter::once(self.server_name.to_string()))
+            .find(|n| !failed_nodes.contains(n)))
+    }
+
     pub async fn send_select_to_worker(
         &self,
         worker_node: String,
         plan: SerializedPlan,
-    ) -> Result<Vec<RecordBatch>, CubeError> {
-        let mut stream = timeout(self.connect_timeout, TcpStream::connect(worker_node)).await??;
-        NetworkMessage::Select(plan).send(&mut stream).await?;
-        let response = NetworkMessage::receive(&mut stream).await?;
-        match response {
-            NetworkMessage::SelectResult(res) => res.and_then(|r| r.read()),
-            NetworkMessage::Select(_) => panic!("Router received Select as a response"),
+        cancel_rx: Option<watch::Receiver<bool>>,
+    ) -> Result<Result<Vec<RecordBatch>, CubeError>, CubeError> {
+        let mut stream = self.connector.connect(&worker_node).await?;
+        match Self::exchange_select(&mut stream, plan, cancel_rx).await? {
+            NetworkMessage::SelectResult(res) => Ok(res?.and_then(|r| r.read())),
+            response => Err(CubeError::internal(format!(
+                "Unexpected select response: {:?}",
+                response
+            ))),
+        }
+    }
+
+    async fn send_job_notification(&self, node_name: &str) -> Result<(), CubeError> {
+        let mut stream = self.connector.connect(node_name).await?;
+        NetworkMessage::NotifyJobRunner.send(&mut stream).await?;
+        stream.flush().await?;
+        Ok(())
+    }
+
+    async fn exchange_select<S: AsyncRead + AsyncWrite + Unpin>(
+        stream: &mut S,
+        plan: SerializedPlan,
+        cancel_rx: Option<watch::Receiver<bool>>,
+    ) -> Result<NetworkMessage, CubeError> {
+        NetworkMessage::Select(plan).send(stream).await?;
+        tokio::select! {
+            response = NetworkMessage::receive(stream) => response,
+            _ = cancelled(cancel_rx) => {
+                // Worker stops the select once the connection is closed if it can't be notified.
+                let _ = NetworkMessage::CancelSelect.send(stream).await;
+                Ok(NetworkMessage::SelectResult(Ok(Err(CubeError::user(
+                    "Query was cancelled".to_string(),
+                )))))
+            }
         }
     }
 
     pub async fn listen_on_worker_port(cluster: Arc<ClusterImpl>) -> Result<(), CubeError> {
-        if let Some(address) = cluster.config_obj.worker_bind_address() {
-            let mut listener = TcpListener::bind(address.clone()).await?;
+        if let Some(address) = cluster.config_obj.worker_bind_address().clone() {
+            Self::listen(cluster, address, Port::Worker).await?;
+        }
+        Ok(())
+    }
 
-            info!("Worker port open on {}", address);
+    /// Workers running jobs read and write metadata of the router through this port.
+    pub async fn listen_on_metastore_port(cluster: Arc<ClusterImpl>) -> Result<(), CubeError> {
+        if let Some(address) = cluster.config_obj.metastore_bind_address().clone() {
+            if cluster.is_select_worker() {
+                return Err(CubeError::internal(
+                    "Metastore port can be open only on the router".to_string(),
+                ));
+            }
+            Self::listen(cluster, address, Port::MetaStore).await?;
+        }
+        Ok(())
+    }
 
-            loop {
-                let mut stop_receiver = cluster.close_worker_socket_rx.write().await;
-                let (mut socket, _) = tokio::select! {
-                    Some(stopped) = stop_receiver.recv() => {
-                        if stopped {
-                            return Ok(());
-                        } else {
+    async fn listen(
+        cluster: Arc<ClusterImpl>,
+        address: String,
+        port: Port,
+    ) -> Result<(), CubeError> {
+        let mut listener = TcpListener::bind(address.clone()).await?;
+
+        info!("{:?} port open on {}", port, address);
+
+        let mut stop_receiver = cluster.close_worker_socket_rx.read().await.clone();
+        loop {
+            let (socket, _) = tokio::select! {
+                Some(stopped) = stop_receiver.recv() => {
+                    if stopped {
+                        return Ok(());
+                    } else {
+                        continue;
+                    }
+                }
+                accept_res = listener.accept() => {
+                    match accept_res {
+                        Ok(res) => res,
+                        Err(err) => {
+                            error!("Network error: {}", err);
                             continue;
                         }
                     }
-                    accept_res = listener.accept() => {
-                        match accept_res {
-                            Ok(res) => res,
-                            Err(err) => {
-                                error!("Network error: {}", err);
-                                continue;
-                            }
+                }
+            };
+            let cluster_to_move = cluster.clone();
+
+            tokio::spawn(async move {
+                match &cluster_to_move.tls_acceptor {
+                    Some(acceptor) => match acceptor.accept(socket).await {
+                        Ok(mut socket) => {
+                            Self::process_connection(&cluster_to_move, &mut socket, port).await
                         }
+                        Err(err) => error!("TLS handshake error: {}", err),
+                    },
+                    None => {
+                        let mut socket = socket;
+                        Self::process_connection(&cluster_to_move, &mut socket, port).await
                     }
-                };
-                let cluster_to_move = cluster.clone();
+                }
+            });
+        }
+    }
 
-                tokio::spawn(async move {
-                    let res = NetworkMessage::receive(&mut socket).await;
-                    match res {
-                        Ok(message) => match message {
-                            NetworkMessage::Select(plan) => {
-                                let res = cluster_to_move.run_local_select_serialized(plan).await;
-                                if let Err(err) =
-                                    NetworkMessage::SelectResult(res).send(&mut socket).await
-                                {
-                                    error!("Network error: {}", err);
-                                }
-                            }
-                            NetworkMessage::SelectResult(_) => {
-                                panic!("WorkerResult should not be sent to worker");
-                            }
-                        },
-                        Err(err) => error!("Network error: {}", err),
+    async fn process_connection<S: AsyncRead + AsyncWrite + Unpin>(
+        cluster: &Arc<ClusterImpl>,
+        socket: &mut S,
+        port: Port,
+    ) {
+        let res = match port {
+            Port::Worker => Self::process_worker_connection(cluster, socket).await,
+            Port::MetaStore => Self::process_metastore_connection(cluster, socket).await,
+        };
+        if let Err(err) = res {
+            error!("Network error on {:?} port: {}", port, err);
+        }
+    }
+
+    async fn process_worker_connection<S: AsyncRead + AsyncWrite + Unpin>(
+        cluster: &Arc<ClusterImpl>,
+        socket: &mut S,
+    ) -> Result<(), CubeError> {
+        match NetworkMessage::receive(socket).await? {
+            NetworkMessage::Select(plan) => {
+                let process = cluster.process_list.start(
+                    None,
+                    None,
+                    &match plan.query_id() {
+                        Some(id) => format!("Sub-plan of router query {}", id),
+                        None => "Sub-plan".to_string(),
+                    },
+                );
+                // Router sends CancelSelect or closes the connection to cancel the select.
+                // Dropping the select future also stops the worker pool process running it.
+                let res = tokio::select! {
+                    res = cluster.run_local_select_serialized(plan) => res,
+                    e = process.cancelled() => Ok(Err(e)),
+                    message = NetworkMessage::receive(socket) => {
+                        debug!("Select cancelled by router: {:?}", message);
+                        return Ok(());
                     }
-                });
+                };
+                NetworkMessage::SelectResult(res).send(socket).await?;
+            }
+            NetworkMessage::CancelSelect => {
+                debug!("CancelSelect received without running select");
+            }
+            NetworkMessage::NotifyJobRunner => {
+                cluster.job_notify.notify();
+            }
+            message => {
+                return Err(CubeError::internal(format!(
+                    "Unexpected message on worker port: {:?}",
+                    message
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    async fn process_metastore_connection<S: AsyncRead + AsyncWrite + Unpin>(
+        cluster: &Arc<ClusterImpl>,
+        socket: &mut S,
+    ) -> Result<(), CubeError> {
+        match NetworkMessage::receive(socket).await? {
+            NetworkMessage::MetaStoreCall(call) => {
+                let res = call.invoke(cluster.meta_store.as_ref()).await;
+                NetworkMessage::MetaStoreResult(res).send(socket).await?;
+            }
+            NetworkMessage::JobEvent(event) => {
+                cluster.event_sender.send(event)?;
+            }
+            message => {
+                return Err(CubeError::internal(format!(
+                    "Unexpected message on metastore port: {:?}",
+                    message
+                )));
             }
         }
         Ok(())
@@ -555,14 +986,19 @@ impl ClusterImpl {
     async fn run_local_select(
         &self,
         plan_node: SerializedPlan,
-    ) -> Result<Vec<RecordBatch>, CubeError> {
-        self.run_local_select_serialized(plan_node).await?.read()
+    ) -> Result<Result<Vec<RecordBatch>, CubeError>, CubeError> {
+        Ok(self
+            .run_local_select_serialized(plan_node)
+            .await?
+            .and_then(|r| r.read()))
     }
 
+    /// Outer error means files couldn't be downloaded or the worker process failed, the inner
+    /// one is returned by the select.
     async fn run_local_select_serialized(
         &self,
         plan_node: SerializedPlan,
-    ) -> Result<SerializedRecordBatchStream, CubeError> {
+    ) -> Result<Result<SerializedRecordBatchStream, CubeError>, CubeError> {
         let start = SystemTime::now();
         debug!("Running select: {:?}", plan_node);
         let to_download = plan_node.files_to_download();
@@ -588,17 +1024,16 @@ impl ClusterImpl {
                 serialized_plan_node,
                 remote_to_local_names,
             ))
-            .await
+            .await?
         } else {
             // TODO optimize for no double conversion
-            SerializedRecordBatchStream::write(
-                self.query_executor
-                    .execute_worker_plan(plan_node.clone(), remote_to_local_names)
-                    .await?,
-            )
+            self.query_executor
+                .execute_worker_plan(plan_node.clone(), remote_to_local_names)
+                .await
+                .and_then(SerializedRecordBatchStream::write)
         };
         info!("Running select completed ({:?})", start.elapsed()?);
-        res
+        Ok(res)
     }
 
     pub async fn try_to_connect(&mut self) -> Result<(), CubeError> {
@@ -614,33 +1049,7 @@ impl ClusterImpl {
     }
 
     pub async fn elect_leader(&self) -> Result<String, CubeError> {
-        let heart_beats_dir =
-            Path::new(self.remote_fs.local_path().await.as_str()).join("node-heart-beats");
-
-        fs::create_dir_all(heart_beats_dir.clone()).await?;
-
-        let heart_beat_path = heart_beats_dir.join(&self.server_name);
-
-        {
-            let mut heart_beat_file = File::create(heart_beat_path.clone()).await?;
-
-            heart_beat_file.write_u64(
-                SystemTime::now()
-                    .duration_since(SystemTime::UNIX_EPOCH)?
-                    .as_secs(),
-            );
-            heart_beat_file.flush().await?;
-        }
-
-        let to_upload = heart_beat_path
-            .to_str()
-            .unwrap()
-            .to_string()
-            .replace(&self.remote_fs.local_path().await, "")
-            .trim_start_matches("/")
-            .to_string();
-
-        self.remote_fs.upload_file(&to_upload).await?;
+        self.send_heart_beat().await?;
 
         let heart_beats = self
             .remote_fs
@@ -697,10 +1106,22 @@ mod tests {
             unimplemented!()
         }
 
+        async fn add_wal_batches(
+            &self,
+            _table: IdRow<Table>,
+            _batches: Vec<RecordBatch>,
+        ) -> Result<IdRow<WAL>, CubeError> {
+            unimplemented!()
+        }
+
         async fn get_wal(&self, _wal_id: u64) -> Result<DataFrame, CubeError> {
             unimplemented!()
         }
 
+        async fn get_wal_batches(&self, _wal_id: u64) -> Result<Vec<RecordBatch>, CubeError> {
+            unimplemented!()
+        }
+
         async fn delete_wal(&self, _wal_id: u64) -> Result<(), CubeError> {
             unimplemented!()
         }
@@ -708,6 +1129,10 @@ mod tests {
         fn get_wal_chunk_size(&self) -> usize {
             unimplemented!()
         }
+
+        async fn recover_wals(&self) -> Result<Vec<IdRow<WAL>>, CubeError> {
+            unimplemented!()
+        }
     }
 
     struct MockChunkStore;
@@ -779,7 +1204,9 @@ mod tests {
             Arc::new(MockImportService::new()),
             config.config_obj(),
             Arc::new(QueryExecutorImpl),
-        );
+            ProcessList::new(),
+        )
+        .unwrap();
 
         let bar = ClusterImpl::new(
             "bar".to_string(),
@@ -792,7 +1219,9 @@ mod tests {
             Arc::new(MockImportService::new()),
             config.config_obj(),
             Arc::new(QueryExecutorImpl),
-        );
+            ProcessList::new(),
+        )
+        .unwrap();
 
         remote_fs.drop_local_path().await.unwrap();
 
@@ -800,4 +1229,18 @@ mod tests {
         assert_eq!(foo.elect_leader().await.unwrap(), "foo");
         assert_eq!(foo.elect_leader().await.unwrap(), "foo");
     }
+
+    #[actix_rt::test]
+    async fn job_results_wait_timeout() {
+        let (sender, receiver) = broadcast::channel(10);
+        let listener = JobResultListener {
+            receiver,
+            wait_timeout: Duration::from_millis(100),
+        };
+        let res = listener
+            .wait_for_job_result(RowKey::Table(TableId::WALs, 1), JobType::WalPartitioning)
+            .await;
+        assert!(res.is_err());
+        mem::drop(sender);
+    }
 }
@@ -0,0 +1,191 @@
+use crate::CubeError;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+
+/// Selects running on this router, or sub-plans run by this worker, which can be listed by
+/// `SHOW PROCESSLIST` and cancelled by `KILL QUERY`.
+pub struct ProcessList {
+    next_id: AtomicU32,
+    processes: Mutex<HashMap<u32, Process>>,
+}
+
+struct Process {
+    info: ProcessInfo,
+    cancel_tx: watch::Sender<bool>,
+    cancel_rx: watch::Receiver<bool>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProcessInfo {
+    pub id: u32,
+    pub connection_id: Option<u32>,
+    pub user: Option<String>,
+    pub query: String,
+    pub start_time: DateTime<Utc>,
+    pub nodes: Vec<String>,
+    /// Sub-plans rerun on another node after a node failure.
+    pub retries: u32,
+}
+
+impl ProcessList {
+    pub fn new() -> Arc<ProcessList> {
+        Arc::new(ProcessList {
+            next_id: AtomicU32::new(1),
+            processes: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Process is removed from the list once the returned guard is dropped.
+    pub fn start(
+        self: &Arc<Self>,
+        connection_id: Option<u32>,
+        user: Option<String>,
+        query: &str,
+    ) -> ProcessGuard {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let (cancel_tx, cancel_rx) = watch::channel(false);
+        self.processes.lock().unwrap().insert(
+            id,
+            Process {
+                info: ProcessInfo {
+                    id,
+                    connection_id,
+                    user,
+                    query: query.to_string(),
+                    start_time: Utc::now(),
+                    nodes: Vec::new(),
+                    retries: 0,
+                },
+                cancel_tx,
+                cancel_rx: cancel_rx.clone(),
+            },
+        );
+        ProcessGuard {
+            id,
+            process_list: self.clone(),
+            cancel_rx,
+        }
+    }
+
+    pub fn processes(&self) -> Vec<ProcessInfo> {
+        let mut processes = self
+            .processes
+            .lock()
+            .unwrap()
+            .values()
+            .map(|p| p.info.clone())
+            .collect::<Vec<_>>();
+        processes.sort_by_key(|p| p.id);
+        processes
+    }
+
+    pub fn get(&self, id: u32) -> Option<ProcessInfo> {
+        self.processes
+            .lock()
+            .unwrap()
+            .get(&id)
+            .map(|p| p.info.clone())
+    }
+
+    /// Records `node` as involved in process `id` and returns its cancellation receiver.
+    pub fn add_node(&self, id: u32, node: &str) -> Option<watch::Receiver<bool>> {
+        let mut processes = self.processes.lock().unwrap();
+        let process = processes.get_mut(&id)?;
+        if !process.info.nodes.iter().any(|n| n == node) {
+            process.info.nodes.push(node.to_string());
+        }
+        Some(process.cancel_rx.clone())
+    }
+
+    pub fn add_retry(&self, id: u32) {
+        if let Some(process) = self.processes.lock().unwrap().get_mut(&id) {
+            process.info.retries += 1;
+        }
+    }
+
+    pub fn kill(&self, id: u32) -> Result<(), CubeError> {
+        let processes = self.processes.lock().unwrap();
+        let process = processes
+            .get(&id)
+            .ok_or_else(|| CubeError::user(format!("Unknown query id: {}", id)))?;
+        process.cancel_tx.broadcast(true)?;
+        Ok(())
+    }
+}
+
+pub struct ProcessGuard {
+    id: u32,
+    process_list: Arc<ProcessList>,
+    cancel_rx: watch::Receiver<bool>,
+}
+
+impl ProcessGuard {
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Resolves once the process is killed.
+    pub async fn cancelled(&self) -> CubeError {
+        cancelled(Some(self.cancel_rx.clone())).await;
+        CubeError::user(format!("Query {} was cancelled", self.id))
+    }
+}
+
+impl Drop for ProcessGuard {
+    fn drop(&mut self) {
+        self.process_list.processes.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// Resolves once `true` is sent to the cancellation channel, never if there's no channel.
+pub async fn cancelled(receiver: Option<watch::Receiver<bool>>) {
+    if let Some(mut receiver) = receiver {
+        while let Some(cancelled) = receiver.recv().await {
+            if cancelled {
+                return;
+            }
+        }
+    }
+    futures::future::pending::<()>().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::FutureExt;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn kill_process() {
+        let process_list = ProcessList::new();
+        let process = process_list.start(Some(3), Some("analyst".to_string()), "SELECT 1");
+        let receiver = process_list.add_node(process.id(), "worker-1:9001");
+        process_list.add_node(process.id(), "worker-1:9001");
+        process_list.add_retry(process.id());
+
+        let processes = process_list.processes();
+        assert_eq!(processes.len(), 1);
+        assert_eq!(processes[0].connection_id, Some(3));
+        assert_eq!(processes[0].query, "SELECT 1");
+        assert_eq!(processes[0].nodes, vec!["worker-1:9001".to_string()]);
+        assert_eq!(processes[0].retries, 1);
+        assert!(cancelled(receiver.clone()).now_or_never().is_none());
+
+        process_list.kill(process.id()).unwrap();
+        tokio::time::timeout(Duration::from_secs(1), cancelled(receiver))
+            .await
+            .unwrap();
+        let error = tokio::time::timeout(Duration::from_secs(1), process.cancelled())
+            .await
+            .unwrap();
+        assert_eq!(error.message, "Query 1 was cancelled");
+
+        let id = process.id();
+        drop(process);
+        assert!(process_list.processes().is_empty());
+        assert!(process_list.kill(id).is_err());
+    }
+}
@@ -0,0 +1,279 @@
+use crate::config::{MySqlTlsConfig, TlsConfig};
+use crate::CubeError;
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+use tokio_rustls::rustls::internal::pemfile::{certs, pkcs8_private_keys, rsa_private_keys};
+use tokio_rustls::rustls::{
+    AllowAnyAuthenticatedClient, Certificate, ClientConfig, NoClientAuth, PrivateKey,
+    RootCertStore, ServerConfig,
+};
+use tokio_rustls::webpki::DNSNameRef;
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+/// Accepts only clients which present a certificate signed by the cluster CA.
+pub fn tls_acceptor(config: &TlsConfig) -> Result<TlsAcceptor, CubeError> {
+    let mut server_config =
+        ServerConfig::new(AllowAnyAuthenticatedClient::new(load_ca(&config.ca_path)?));
+    server_config.set_single_cert(
+        load_certs(&config.cert_path)?,
+        load_private_key(&config.key_path)?,
+    )?;
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+pub fn mysql_tls_acceptor(config: &MySqlTlsConfig) -> Result<TlsAcceptor, CubeError> {
+    let mut server_config = ServerConfig::new(NoClientAuth::new());
+    server_config.set_single_cert(
+        load_certs(&config.cert_path)?,
+        load_private_key(&config.key_path)?,
+    )?;
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+pub fn tls_connector(config: &TlsConfig) -> Result<TlsConnector, CubeError> {
+    let mut client_config = ClientConfig::new();
+    client_config.root_store = load_ca(&config.ca_path)?;
+    client_config.set_single_client_cert(
+        load_certs(&config.cert_path)?,
+        load_private_key(&config.key_path)?,
+    )?;
+    Ok(TlsConnector::from(Arc::new(client_config)))
+}
+
+/// Name the certificate of the node at `address` is verified against.
+pub fn server_name(config: &TlsConfig, address: &str) -> String {
+    config.server_name.clone().unwrap_or_else(|| {
+        address
+            .rsplitn(2, ':')
+            .last()
+            .unwrap_or(address)
+            .to_string()
+    })
+}
+
+pub fn dns_name(name: &str) -> Result<DNSNameRef, CubeError> {
+    DNSNameRef::try_from_ascii_str(name)
+        .map_err(|_| CubeError::user(format!("Invalid TLS server name: {}", name)))
+}
+
+fn load_certs(path: &str) -> Result<Vec<Certificate>, CubeError> {
+    let certs = certs(&mut BufReader::new(open(path)?))
+        .map_err(|_| CubeError::user(format!("Can't read certificates from {}", path)))?;
+    if certs.is_empty() {
+        return Err(CubeError::user(format!(
+            "No certificates found in {}",
+            path
+        )));
+    }
+    Ok(certs)
+}
+
+fn load_private_key(path: &str) -> Result<PrivateKey, CubeError> {
+    let mut keys = pkcs8_private_keys(&mut BufReader::new(open(path)?))
+        .map_err(|_| CubeError::user(format!("Can't read private key from {}", path)))?;
+    if keys.is_empty() {
+        keys = rsa_private_keys(&mut BufReader::new(open(path)?))
+            .map_err(|_| CubeError::user(format!("Can't read private key from {}", path)))?;
+    }
+    keys.into_iter()
+        .next()
+        .ok_or_else(|| CubeError::user(format!("No private key found in {}", path)))
+}
+
+fn open(path: &str) -> Result<File, CubeError> {
+    File::open(path).map_err(|e| CubeError::user(format!("Can't open {}: {}", path, e)))
+}
+
+fn load_ca(path: &str) -> Result<RootCertStore, CubeError> {
+    let mut store = RootCertStore::empty();
+    for cert in load_certs(path)? {
+        store
+            .add(&cert)
+            .map_err(|e| CubeError::user(format!("Invalid CA certificate in {}: {:?}", path, e)))?;
+    }
+    Ok(store)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::cluster::message::NetworkMessage;
+    use openssl::asn1::Asn1Time;
+    use openssl::bn::BigNum;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::hash::MessageDigest;
+    use openssl::nid::Nid;
+    use openssl::pkey::{PKey, Private};
+    use openssl::x509::extension::{
+        BasicConstraints, ExtendedKeyUsage, KeyUsage, SubjectAlternativeName,
+    };
+    use openssl::x509::{X509Builder, X509Name, X509NameBuilder, X509};
+    use std::fs;
+    use std::path::PathBuf;
+    use tokio::net::{TcpListener, TcpStream};
+
+    /// Self-signed CA which issues certificates for `localhost` into a test directory.
+    pub struct TestCa {
+        dir: PathBuf,
+        cert: X509,
+        key: PKey<Private>,
+    }
+
+    impl TestCa {
+        pub fn new(name: &str) -> TestCa {
+            let dir = std::env::current_dir()
+                .unwrap()
+                .join(format!("{}-tls", name));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            let key = generate_key();
+            let mut builder = certificate_builder(name, &key);
+            builder.set_issuer_name(&x509_name(name)).unwrap();
+            builder
+                .append_extension(BasicConstraints::new().critical().ca().build().unwrap())
+                .unwrap();
+            builder
+                .append_extension(KeyUsage::new().critical().key_cert_sign().build().unwrap())
+                .unwrap();
+            builder.sign(&key, MessageDigest::sha256()).unwrap();
+            let ca = TestCa {
+                dir,
+                cert: builder.build(),
+                key,
+            };
+            fs::write(ca.ca_path(), ca.cert.to_pem().unwrap()).unwrap();
+            ca
+        }
+
+        pub fn ca_path(&self) -> String {
+            self.path("ca.pem")
+        }
+
+        /// Certificate and key of `node` valid for both server and client authentication.
+        pub fn config(&self, node: &str) -> TlsConfig {
+            let key = generate_key();
+            let mut builder = certificate_builder(node, &key);
+            builder.set_issuer_name(self.cert.subject_name()).unwrap();
+            let alt_name = SubjectAlternativeName::new()
+                .dns("localhost")
+                .build(&builder.x509v3_context(Some(&self.cert), None))
+                .unwrap();
+            builder.append_extension(alt_name).unwrap();
+            builder
+                .append_extension(
+                    ExtendedKeyUsage::new()
+                        .server_auth()
+                        .client_auth()
+                        .build()
+                        .unwrap(),
+                )
+                .unwrap();
+            builder.sign(&self.key, MessageDigest::sha256()).unwrap();
+            let config = TlsConfig {
+                cert_path: self.path(&format!("{}.pem", node)),
+                key_path: self.path(&format!("{}-key.pem", node)),
+                ca_path: self.ca_path(),
+                server_name: None,
+            };
+            fs::write(&config.cert_path, builder.build().to_pem().unwrap()).unwrap();
+            fs::write(&config.key_path, key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+            config
+        }
+
+        fn path(&self, file: &str) -> String {
+            self.dir.join(file).to_str().unwrap().to_string()
+        }
+    }
+
+    impl Drop for TestCa {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    fn generate_key() -> PKey<Private> {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap()
+    }
+
+    fn x509_name(common_name: &str) -> X509Name {
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_nid(Nid::COMMONNAME, common_name)
+            .unwrap();
+        name.build()
+    }
+
+    fn certificate_builder(common_name: &str, key: &PKey<Private>) -> X509Builder {
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        let serial_number = BigNum::from_u32(rand::random::<u32>())
+            .unwrap()
+            .to_asn1_integer()
+            .unwrap();
+        builder.set_serial_number(&serial_number).unwrap();
+        builder.set_subject_name(&x509_name(common_name)).unwrap();
+        builder.set_pubkey(key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        builder
+    }
+
+    async fn roundtrip(
+        server_config: TlsConfig,
+        client_config: TlsConfig,
+    ) -> Result<NetworkMessage, CubeError> {
+        let acceptor = tls_acceptor(&server_config)?;
+        let mut listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?.to_string();
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            if let Ok(mut socket) = acceptor.accept(socket).await {
+                let message = NetworkMessage::receive(&mut socket).await.unwrap();
+                message.send(&mut socket).await.unwrap();
+            }
+        });
+
+        let connector = tls_connector(&client_config)?;
+        let stream = TcpStream::connect(address).await?;
+        let mut stream = connector.connect(dns_name("localhost")?, stream).await?;
+        NetworkMessage::SelectResult(Ok(Err(CubeError::user("ping".to_string()))))
+            .send(&mut stream)
+            .await?;
+        NetworkMessage::receive(&mut stream).await
+    }
+
+    #[tokio::test]
+    async fn mutual_tls() {
+        let ca = TestCa::new("mutual_tls");
+        let node = ca.config("node");
+        match roundtrip(node.clone(), ca.config("other-node"))
+            .await
+            .unwrap()
+        {
+            NetworkMessage::SelectResult(Ok(Err(e))) => assert_eq!(e.message, "ping"),
+            x => panic!("Unexpected message: {:?}", x),
+        }
+
+        // Signed by another CA.
+        let other_ca = TestCa::new("mutual_tls_other");
+        let outsider = TlsConfig {
+            ca_path: ca.ca_path(),
+            ..other_ca.config("outsider")
+        };
+        assert!(roundtrip(node.clone(), outsider).await.is_err());
+
+        assert_eq!(server_name(&node, "worker-1:9001"), "worker-1");
+
+        let missing_key = TlsConfig {
+            key_path: ca.path("missing-key.pem"),
+            ..node
+        };
+        let error = tls_acceptor(&missing_key).err().unwrap();
+        assert!(error.message.contains("missing-key.pem"), "{}", error);
+    }
+}
@@ -31,7 +31,7 @@ pub struct Message<
     R: Serialize + DeserializeOwned + Sync + Send + 'static,
 > {
     message: T,
-    sender: Sender<Result<R, CubeError>>,
+    sender: Sender<Result<Result<R, CubeError>, CubeError>>,
 }
 
 pub trait MessageProcessor<
@@ -72,13 +72,15 @@ impl<
         }
     }
 
-    pub async fn process(&self, message: T) -> Result<R, CubeError> {
+    /// Outer error means the worker process failed or timed out, the inner one is returned by
+    /// the processor.
+    pub async fn process(&self, message: T) -> Result<Result<R, CubeError>, CubeError> {
         let (tx, rx) = oneshot::channel();
         self.queue.push(Message {
             message,
             sender: tx,
         });
-        Ok(rx.await??)
+        rx.await?
     }
 
     pub async fn stop_workers(&self) -> Result<(), CubeError> {
@@ -129,7 +131,10 @@ impl<
             match process {
                 Ok((mut args_tx, mut res_rx, mut handle)) => loop {
                     let mut stopped_rx = self.stopped_rx.write().await;
-                    let Message { message, sender } = tokio::select! {
+                    let Message {
+                        message,
+                        mut sender,
+                    } = tokio::select! {
                         stopped = stopped_rx.recv() => {
                             if let Some(x) = stopped {
                                 if x {
@@ -144,11 +149,17 @@ impl<
                             message
                         }
                     };
-                    let process_message_res_timeout = tokio::time::timeout(
-                        self.timeout,
-                        self.process_message(message, args_tx, res_rx),
-                    )
-                    .await;
+                    let process_message_res_timeout = tokio::select! {
+                        res = tokio::time::timeout(
+                            self.timeout,
+                            self.process_message(message, args_tx, res_rx),
+                        ) => res,
+                        _ = sender.closed() => {
+                            // Result isn't awaited anymore so the process is killed to cancel the message.
+                            <WorkerProcess<T, R, P>>::kill(&mut handle);
+                            break;
+                        }
+                    };
                     let process_message_res = match process_message_res_timeout {
                         Ok(r) => r,
                         Err(e) => Err(CubeError::internal(format!(
@@ -192,10 +203,17 @@ impl<
         message: T,
         args_tx: IpcSender<T>,
         res_rx: IpcReceiver<Result<R, CubeError>>,
-    ) -> Result<(R, IpcSender<T>, IpcReceiver<Result<R, CubeError>>), CubeError> {
+    ) -> Result<
+        (
+            Result<R, CubeError>,
+            IpcSender<T>,
+            IpcReceiver<Result<R, CubeError>>,
+        ),
+        CubeError,
+    > {
         args_tx.send(message)?;
         let (res, res_rx) = tokio::task::spawn_blocking(move || (res_rx.recv(), res_rx)).await?;
-        Ok((res??, args_tx, res_rx))
+        Ok((res?, args_tx, res_rx))
     }
 
     fn spawn_process(
@@ -259,6 +277,7 @@ mod tests {
     #[derive(Serialize, Deserialize, Eq, PartialEq, Debug)]
     pub enum Message {
         Delay(u64),
+        Error,
     }
 
     #[derive(Serialize, Deserialize, Eq, PartialEq, Debug)]
@@ -275,6 +294,7 @@ mod tests {
                     thread::sleep(Duration::from_millis(x));
                     Ok(Response::Foo(x))
                 }
+                Message::Error => Err(CubeError::user("error".to_string())),
             }
         }
     }
@@ -283,12 +303,24 @@ mod tests {
     async fn test_basic() {
         let pool = WorkerPool::<Message, Response, Processor>::new(4, Duration::from_millis(1000));
         assert_eq!(
-            pool.process(Message::Delay(100)).await.unwrap(),
+            pool.process(Message::Delay(100)).await.unwrap().unwrap(),
             Response::Foo(100)
         );
         pool.stop_workers().await.unwrap();
     }
 
+    #[tokio::test]
+    async fn test_processor_error() {
+        let pool = WorkerPool::<Message, Response, Processor>::new(1, Duration::from_millis(1000));
+        // Process which returned an error keeps processing messages.
+        assert!(pool.process(Message::Error).await.unwrap().is_err());
+        assert_eq!(
+            pool.process(Message::Delay(10)).await.unwrap().unwrap(),
+            Response::Foo(10)
+        );
+        pool.stop_workers().await.unwrap();
+    }
+
     #[tokio::test]
     async fn test_concurrent() {
         let pool = WorkerPool::<Message, Response, Processor>::new(4, Duration::from_millis(1000));
@@ -298,7 +330,7 @@ mod tests {
         }
         for (i, f) in futures {
             println!("Testing {} future", i);
-            assert_eq!(f.await.unwrap(), Response::Foo(i * 100));
+            assert_eq!(f.await.unwrap().unwrap(), Response::Foo(i * 100));
         }
         pool.stop_workers().await.unwrap();
     }
@@ -315,12 +347,35 @@ mod tests {
             if i > 1 {
                 assert_eq!(f.await.is_err(), true);
             } else {
-                assert_eq!(f.await.unwrap(), Response::Foo(i * 300));
+                assert_eq!(f.await.unwrap().unwrap(), Response::Foo(i * 300));
             }
         }
         pool.stop_workers().await.unwrap();
     }
 
+    #[tokio::test]
+    async fn test_cancel() {
+        let pool = WorkerPool::<Message, Response, Processor>::new(1, Duration::from_millis(5000));
+        assert!(tokio::time::timeout(
+            Duration::from_millis(200),
+            pool.process(Message::Delay(3000))
+        )
+        .await
+        .is_err());
+        assert_eq!(
+            tokio::time::timeout(
+                Duration::from_millis(2000),
+                pool.process(Message::Delay(100))
+            )
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap(),
+            Response::Foo(100)
+        );
+        pool.stop_workers().await.unwrap();
+    }
+
     #[tokio::test]
     async fn serialize_plan() -> Result<(), CubeError> {
         let schema = Schema::new(vec![
@@ -1,6 +1,9 @@
+use crate::cluster::connector::NodeConnector;
+use crate::cluster::process_list::ProcessList;
 use crate::cluster::ClusterImpl;
 use crate::import::ImportServiceImpl;
-use crate::metastore::RocksMetaStore;
+use crate::metastore::rpc::MetaStoreRpcClient;
+use crate::metastore::{MetaStore, RocksMetaStore};
 use crate::queryplanner::query_executor::{QueryExecutor, QueryExecutorImpl};
 use crate::queryplanner::QueryPlannerImpl;
 use crate::remotefs::s3::S3RemoteFs;
@@ -14,9 +17,12 @@ use crate::CubeError;
 use log::Level;
 use mockall::automock;
 use rocksdb::{Options, DB};
+use serde::Deserialize;
 use simple_logger::SimpleLogger;
+use std::fmt::Display;
 use std::future::Future;
 use std::path::PathBuf;
+use std::str::FromStr;
 use std::sync::Arc;
 use std::{env, fs};
 use tokio::sync::broadcast;
@@ -26,7 +32,9 @@ use tokio::time::Duration;
 pub struct CubeServices {
     pub sql_service: Arc<dyn SqlService>,
     pub scheduler: Arc<SchedulerImpl>,
-    pub meta_store: Arc<RocksMetaStore>,
+    pub meta_store: Arc<dyn MetaStore>,
+    /// Local metastore, `None` on workers which use the router metastore over RPC.
+    pub rocks_meta_store: Option<Arc<RocksMetaStore>>,
     pub cluster: Arc<ClusterImpl>,
     pub remote_fs: Arc<dyn RemoteFs>,
 }
@@ -39,11 +47,18 @@ pub struct WorkerServices {
 impl CubeServices {
     pub async fn start_processing_loops(&self) -> Result<(), CubeError> {
         self.cluster.start_processing_loops().await;
-        if !self.cluster.is_select_worker() {
-            let meta_store = self.meta_store.clone();
+        let cluster = self.cluster.clone();
+        tokio::spawn(async move { ClusterImpl::heart_beat_loop(cluster).await });
+        if let Some(meta_store) = self.rocks_meta_store.clone() {
             tokio::spawn(async move { meta_store.run_upload_loop().await });
+        }
+        if !self.cluster.is_select_worker() {
+            self.scheduler.drop_replacement_tables().await?;
+            self.scheduler.recover_pending_wals().await?;
             let scheduler = self.scheduler.clone();
             tokio::spawn(async move { scheduler.run_scheduler().await });
+            let cluster = self.cluster.clone();
+            tokio::spawn(async move { ClusterImpl::listen_on_metastore_port(cluster).await });
         } else {
             let cluster = self.cluster.clone();
             tokio::spawn(async move { ClusterImpl::listen_on_worker_port(cluster).await });
@@ -54,7 +69,9 @@ impl CubeServices {
 
     pub async fn stop_processing_loops(&self) -> Result<(), CubeError> {
         self.cluster.stop_processing_loops().await?;
-        self.meta_store.stop_processing_loops().await;
+        if let Some(meta_store) = &self.rocks_meta_store {
+            meta_store.stop_processing_loops().await;
+        }
         self.scheduler.stop_processing_loops()?;
         stop_track_event_loop().await;
         Ok(())
@@ -74,6 +91,50 @@ pub enum FileStoreProvider {
     },
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WalDurability {
+    /// WALs are kept only on the local disk of the node which accepted the insert.
+    Local,
+    /// WALs are uploaded to the remote storage in background.
+    Async,
+    /// `INSERT` returns only after its WALs are uploaded to the remote storage.
+    Sync,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TlsConfig {
+    pub cert_path: String,
+    pub key_path: String,
+    /// CA which signs certificates of all cluster members.
+    pub ca_path: String,
+    /// Name worker certificates are verified against, host of the worker address if `None`.
+    pub server_name: Option<String>,
+}
+
+/// Certificate of the MySQL endpoint, clients aren't asked for one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MySqlTlsConfig {
+    pub cert_path: String,
+    pub key_path: String,
+    /// Clients which don't ask for TLS are rejected.
+    pub required: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct UserConfig {
+    pub name: String,
+    #[serde(default)]
+    pub password: String,
+    /// Schemas the user can access, all schemas are accessible if `None`.
+    #[serde(rename = "schemas", default)]
+    pub allowed_schemas: Option<Vec<String>>,
+}
+
+/// Compares secrets in time which doesn't depend on the position of the first mismatch.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
 pub struct Config {
     config_obj: Arc<ConfigObjImpl>,
 }
@@ -96,9 +157,63 @@ pub trait ConfigObj: Send + Sync {
 
     fn not_used_timeout(&self) -> u64;
 
+    /// Seconds queries wait for results of jobs they scheduled.
+    fn job_wait_timeout(&self) -> u64;
+
+    /// Address this node is reachable by other cluster members, set by `CUBESTORE_SERVER_NAME`.
+    /// Defaults to `localhost:<CUBESTORE_WORKER_PORT>` for select workers.
+    fn server_name(&self) -> &str;
+
+    /// Seconds between heart-beats of select workers.
+    fn heart_beat_interval(&self) -> u64;
+
+    /// Select workers without a heart-beat for this many seconds aren't routed to.
+    fn heart_beat_timeout(&self) -> u64;
+
+    /// Heart-beats of select workers dead for this many seconds are removed.
+    fn dead_node_timeout(&self) -> u64;
+
+    /// Select workers listed by `CUBESTORE_WORKERS` which are used if none of the workers
+    /// sends heart-beats.
     fn select_workers(&self) -> &Vec<String>;
 
+    /// Number of times a failed select sub-plan is rerun on another node.
+    fn select_retries(&self) -> usize;
+
     fn worker_bind_address(&self) -> &Option<String>;
+
+    /// Metastore port of the router which workers running jobs connect to. Jobs run only on the
+    /// router if `None`.
+    fn metastore_bind_address(&self) -> &Option<String>;
+
+    /// Router metastore address set by `CUBESTORE_META_ADDR`. Workers run jobs if it's set.
+    fn metastore_remote_address(&self) -> &Option<String>;
+
+    /// HTTP query endpoint is disabled if `None`.
+    fn http_bind_address(&self) -> &Option<String>;
+
+    /// Arrow Flight endpoint is disabled if `None`.
+    fn flight_bind_address(&self) -> &Option<String>;
+
+    fn store_provider(&self) -> &FileStoreProvider;
+
+    fn import_batch_size(&self) -> usize;
+
+    /// Number of locations of a table which are imported at the same time.
+    fn import_concurrency(&self) -> usize;
+
+    fn wal_durability(&self) -> WalDurability;
+
+    /// MySQL protocol users, any credentials are accepted if empty. `CUBESTORE_USERS` is a JSON
+    /// list of `{"name": .., "password": .., "schemas": [..]}` objects, a single user can also be
+    /// set by `CUBESTORE_USER`, `CUBESTORE_PASSWORD` and `CUBESTORE_USER_SCHEMAS`.
+    fn users(&self) -> &Vec<UserConfig>;
+
+    /// Mutual TLS for connections between router and workers, plain TCP if `None`.
+    fn worker_tls(&self) -> &Option<TlsConfig>;
+
+    /// TLS offered to MySQL clients, which can still connect without it.
+    fn mysql_tls(&self) -> &Option<MySqlTlsConfig>;
 }
 
 #[derive(Debug, Clone)]
@@ -112,8 +227,22 @@ pub struct ConfigObjImpl {
     pub bind_port: u16,
     pub bind_address: String,
     pub query_timeout: u64,
+    pub job_wait_timeout: u64,
+    pub server_name: String,
+    pub heart_beat_interval: u64,
     pub select_workers: Vec<String>,
+    pub select_retries: usize,
     pub worker_bind_address: Option<String>,
+    pub metastore_bind_address: Option<String>,
+    pub metastore_remote_address: Option<String>,
+    pub http_bind_address: Option<String>,
+    pub flight_bind_address: Option<String>,
+    pub import_batch_size: usize,
+    pub import_concurrency: usize,
+    pub wal_durability: WalDurability,
+    pub users: Vec<UserConfig>,
+    pub worker_tls: Option<TlsConfig>,
+    pub mysql_tls: Option<MySqlTlsConfig>,
 }
 
 impl ConfigObj for ConfigObjImpl {
@@ -149,13 +278,81 @@ impl ConfigObj for ConfigObjImpl {
         self.query_timeout * 2
     }
 
+    fn job_wait_timeout(&self) -> u64 {
+        self.job_wait_timeout
+    }
+
+    fn server_name(&self) -> &str {
+        &self.server_name
+    }
+
+    fn heart_beat_interval(&self) -> u64 {
+        self.heart_beat_interval
+    }
+
+    fn heart_beat_timeout(&self) -> u64 {
+        self.heart_beat_interval * 4
+    }
+
+    fn dead_node_timeout(&self) -> u64 {
+        self.heart_beat_interval * 60
+    }
+
     fn select_workers(&self) -> &Vec<String> {
         &self.select_workers
     }
 
+    fn select_retries(&self) -> usize {
+        self.select_retries
+    }
+
     fn worker_bind_address(&self) -> &Option<String> {
         &self.worker_bind_address
     }
+
+    fn metastore_bind_address(&self) -> &Option<String> {
+        &self.metastore_bind_address
+    }
+
+    fn metastore_remote_address(&self) -> &Option<String> {
+        &self.metastore_remote_address
+    }
+
+    fn http_bind_address(&self) -> &Option<String> {
+        &self.http_bind_address
+    }
+
+    fn flight_bind_address(&self) -> &Option<String> {
+        &self.flight_bind_address
+    }
+
+    fn store_provider(&self) -> &FileStoreProvider {
+        &self.store_provider
+    }
+
+    fn import_batch_size(&self) -> usize {
+        self.import_batch_size
+    }
+
+    fn import_concurrency(&self) -> usize {
+        self.import_concurrency
+    }
+
+    fn wal_durability(&self) -> WalDurability {
+        self.wal_durability
+    }
+
+    fn users(&self) -> &Vec<UserConfig> {
+        &self.users
+    }
+
+    fn worker_tls(&self) -> &Option<TlsConfig> {
+        &self.worker_tls
+    }
+
+    fn mysql_tls(&self) -> &Option<MySqlTlsConfig> {
+        &self.mysql_tls
+    }
 }
 
 lazy_static! {
@@ -168,14 +365,33 @@ lazy_static! {
         tokio::sync::RwLock::new(false);
 }
 
+fn required_env_var(name: &str, required_by: &str) -> Result<String, CubeError> {
+    env::var(name)
+        .map_err(|_| CubeError::user(format!("{} is required when {} is set", name, required_by)))
+}
+
+fn parse_env_var<T: FromStr>(name: &str) -> Result<Option<T>, CubeError>
+where
+    T::Err: Display,
+{
+    env::var(name)
+        .ok()
+        .map(|v| {
+            v.parse::<T>()
+                .map_err(|e| CubeError::user(format!("Can't parse {}={}: {}", name, v, e)))
+        })
+        .transpose()
+}
+
 impl Config {
-    pub fn default() -> Config {
-        Config {
+    pub fn default() -> Result<Config, CubeError> {
+        let current_dir = env::current_dir()?;
+        Ok(Config {
             config_obj: Arc::new(ConfigObjImpl {
                 data_dir: env::var("CUBESTORE_DATA_DIR")
                     .ok()
                     .map(|v| PathBuf::from(v))
-                    .unwrap_or(env::current_dir().unwrap().join(".cubestore").join("data")),
+                    .unwrap_or(current_dir.join(".cubestore").join("data")),
                 partition_split_threshold: 1000000,
                 compaction_chunks_count_threshold: 4,
                 compaction_chunks_total_size_threshold: 500000,
@@ -183,7 +399,7 @@ impl Config {
                     if let Ok(bucket_name) = env::var("CUBESTORE_S3_BUCKET") {
                         FileStoreProvider::S3 {
                             bucket_name,
-                            region: env::var("CUBESTORE_S3_REGION").unwrap(),
+                            region: required_env_var("CUBESTORE_S3_REGION", "CUBESTORE_S3_BUCKET")?,
                             sub_path: env::var("CUBESTORE_S3_SUB_PATH").ok(),
                         }
                     } else if let Ok(remote_dir) = env::var("CUBESTORE_REMOTE_DIR") {
@@ -192,34 +408,117 @@ impl Config {
                         }
                     } else {
                         FileStoreProvider::Filesystem {
-                            remote_dir: env::current_dir().unwrap().join("upstream"),
+                            remote_dir: current_dir.join("upstream"),
                         }
                     }
                 },
-                select_worker_pool_size: env::var("CUBESTORE_SELECT_WORKERS")
-                    .ok()
-                    .map(|v| v.parse::<usize>().unwrap())
+                select_worker_pool_size: parse_env_var::<usize>("CUBESTORE_SELECT_WORKERS")?
                     .unwrap_or(4),
                 bind_address: env::var("CUBESTORE_BIND_ADDR")
                     .ok()
                     .unwrap_or("0.0.0.0".to_string()),
-                bind_port: env::var("CUBESTORE_PORT")
-                    .ok()
-                    .map(|v| v.parse::<u16>().unwrap())
-                    .unwrap_or(3306u16),
-                query_timeout: env::var("CUBESTORE_QUERY_TIMEOUT")
-                    .ok()
-                    .map(|v| v.parse::<u64>().unwrap())
-                    .unwrap_or(120),
+                bind_port: parse_env_var::<u16>("CUBESTORE_PORT")?.unwrap_or(3306u16),
+                query_timeout: parse_env_var::<u64>("CUBESTORE_QUERY_TIMEOUT")?.unwrap_or(120),
+                job_wait_timeout: parse_env_var::<u64>("CUBESTORE_JOB_WAIT_TIMEOUT")?
+                    .unwrap_or(600),
+                // Other nodes reach workers by their names.
+                server_name: match env::var("CUBESTORE_WORKER_PORT") {
+                    Ok(_) => required_env_var("CUBESTORE_SERVER_NAME", "CUBESTORE_WORKER_PORT")?,
+                    Err(_) => env::var("CUBESTORE_SERVER_NAME").unwrap_or("localhost".to_string()),
+                },
+                heart_beat_interval: parse_env_var::<u64>("CUBESTORE_HEART_BEAT_INTERVAL")?
+                    .unwrap_or(5),
                 select_workers: env::var("CUBESTORE_WORKERS")
                     .ok()
                     .map(|v| v.split(",").map(|s| s.to_string()).collect())
                     .unwrap_or(Vec::new()),
-                worker_bind_address: env::var("CUBESTORE_WORKER_PORT")
-                    .ok()
+                select_retries: parse_env_var::<usize>("CUBESTORE_SELECT_RETRIES")?.unwrap_or(2),
+                worker_bind_address: parse_env_var::<u16>("CUBESTORE_WORKER_PORT")?
                     .map(|v| format!("0.0.0.0:{}", v)),
+                metastore_bind_address: parse_env_var::<u16>("CUBESTORE_META_PORT")?
+                    .map(|v| format!("0.0.0.0:{}", v)),
+                metastore_remote_address: env::var("CUBESTORE_META_ADDR").ok(),
+                http_bind_address: parse_env_var::<u16>("CUBESTORE_HTTP_PORT")?.map(|v| {
+                    format!(
+                        "{}:{}",
+                        env::var("CUBESTORE_HTTP_BIND_ADDR").unwrap_or("0.0.0.0".to_string()),
+                        v
+                    )
+                }),
+                flight_bind_address: parse_env_var::<u16>("CUBESTORE_FLIGHT_PORT")?.map(|v| {
+                    format!(
+                        "{}:{}",
+                        env::var("CUBESTORE_FLIGHT_BIND_ADDR").unwrap_or("0.0.0.0".to_string()),
+                        v
+                    )
+                }),
+                import_batch_size: parse_env_var::<usize>("CUBESTORE_IMPORT_BATCH_SIZE")?
+                    .unwrap_or(100000),
+                import_concurrency: parse_env_var::<usize>("CUBESTORE_IMPORT_CONCURRENCY")?
+                    .unwrap_or(4),
+                wal_durability: env::var("CUBESTORE_WAL_DURABILITY")
+                    .ok()
+                    .map(|v| match v.to_lowercase().as_str() {
+                        "local" => Ok(WalDurability::Local),
+                        "async" => Ok(WalDurability::Async),
+                        "sync" => Ok(WalDurability::Sync),
+                        x => Err(CubeError::user(format!(
+                            "Unknown WAL durability mode: {}",
+                            x
+                        ))),
+                    })
+                    .transpose()?
+                    .unwrap_or(WalDurability::Sync),
+                users: env::var("CUBESTORE_USERS")
+                    .ok()
+                    .map(|v| {
+                        serde_json::from_str::<Vec<UserConfig>>(&v).map_err(|e| {
+                            CubeError::user(format!("Can't parse CUBESTORE_USERS: {}", e))
+                        })
+                    })
+                    .transpose()?
+                    .unwrap_or(Vec::new())
+                    .into_iter()
+                    .chain(env::var("CUBESTORE_USER").ok().map(|name| {
+                        UserConfig {
+                            name,
+                            password: env::var("CUBESTORE_PASSWORD").unwrap_or("".to_string()),
+                            allowed_schemas: env::var("CUBESTORE_USER_SCHEMAS")
+                                .ok()
+                                .map(|v| v.split(",").map(|s| s.trim().to_string()).collect()),
+                        }
+                    }))
+                    .collect(),
+                worker_tls: match env::var("CUBESTORE_WORKER_TLS_CERT") {
+                    Ok(cert_path) => Some(TlsConfig {
+                        cert_path,
+                        key_path: required_env_var(
+                            "CUBESTORE_WORKER_TLS_KEY",
+                            "CUBESTORE_WORKER_TLS_CERT",
+                        )?,
+                        ca_path: required_env_var(
+                            "CUBESTORE_WORKER_TLS_CA",
+                            "CUBESTORE_WORKER_TLS_CERT",
+                        )?,
+                        server_name: env::var("CUBESTORE_WORKER_TLS_SERVER_NAME").ok(),
+                    }),
+                    Err(_) => None,
+                },
+                mysql_tls: match env::var("CUBESTORE_MYSQL_TLS_CERT") {
+                    Ok(cert_path) => Some(MySqlTlsConfig {
+                        cert_path,
+                        key_path: required_env_var(
+                            "CUBESTORE_MYSQL_TLS_KEY",
+                            "CUBESTORE_MYSQL_TLS_CERT",
+                        )?,
+                        required: env::var("CUBESTORE_MYSQL_TLS_REQUIRED")
+                            .map(|v| v.eq_ignore_ascii_case("true") || v == "1")
+                            .unwrap_or(false),
+                    }),
+                    Err(_) => None,
+                },
             }),
-        }
+        })
     }
 
     pub fn test(name: &str) -> Config {
@@ -240,8 +539,22 @@ impl Config {
                 bind_port: 3306,
                 bind_address: "0.0.0.0".to_string(),
                 query_timeout: 15,
+                job_wait_timeout: 120,
+                server_name: "localhost".to_string(),
+                heart_beat_interval: 1,
                 select_workers: Vec::new(),
+                select_retries: 2,
                 worker_bind_address: None,
+                metastore_bind_address: None,
+                metastore_remote_address: None,
+                http_bind_address: None,
+                flight_bind_address: None,
+                import_batch_size: 100000,
+                import_concurrency: 4,
+                wal_durability: WalDurability::Sync,
+                users: Vec::new(),
+                worker_tls: None,
+                mysql_tls: None,
             }),
         }
     }
@@ -299,7 +612,7 @@ impl Config {
             let _ = fs::remove_dir_all(remote_store_path.clone());
         }
         {
-            let services = self.configure().await;
+            let services = self.configure().await.unwrap();
             services.start_processing_loops().await.unwrap();
 
             test_fn(services.clone()).await;
@@ -359,19 +672,40 @@ impl Config {
         })
     }
 
-    pub async fn configure(&self) -> CubeServices {
-        let remote_fs = self.remote_fs().unwrap();
+    pub async fn configure(&self) -> Result<CubeServices, CubeError> {
+        let remote_fs = self.remote_fs()?;
         let (event_sender, event_receiver) = broadcast::channel(10000); // TODO config
 
-        let meta_store = RocksMetaStore::load_from_remote(
-            self.meta_store_path().to_str().unwrap(),
+        // Only the router writes and uploads the metastore, workers running jobs call it over RPC
+        let (meta_store, rocks_meta_store): (Arc<dyn MetaStore>, _) =
+            match self.config_obj.metastore_remote_address() {
+                Some(address) => (
+                    MetaStoreRpcClient::new(
+                        address.to_string(),
+                        Arc::new(NodeConnector::new(
+                            Duration::from_secs(30),
+                            self.config_obj.worker_tls(),
+                        )?),
+                    ),
+                    None,
+                ),
+                None => {
+                    let rocks_meta_store = RocksMetaStore::load_from_remote(
+                        self.meta_store_path().to_str().unwrap(),
+                        remote_fs.clone(),
+                        self.config_obj.clone(),
+                    )
+                    .await?;
+                    rocks_meta_store.add_listener(event_sender).await;
+                    (rocks_meta_store.clone(), Some(rocks_meta_store))
+                }
+            };
+        let wal_store = WALStore::new(
+            meta_store.clone(),
             remote_fs.clone(),
+            500000,
             self.config_obj.clone(),
-        )
-        .await
-        .unwrap();
-        meta_store.add_listener(event_sender).await;
-        let wal_store = WALStore::new(meta_store.clone(), remote_fs.clone(), 500000);
+        );
         let chunk_store = ChunkStore::new(
             meta_store.clone(),
             remote_fs.clone(),
@@ -384,12 +718,18 @@ impl Config {
             remote_fs.clone(),
             self.config_obj.clone(),
         );
-        let import_service = ImportServiceImpl::new(meta_store.clone(), wal_store.clone());
+        let import_service = ImportServiceImpl::new(
+            meta_store.clone(),
+            wal_store.clone(),
+            remote_fs.clone(),
+            self.config_obj.clone(),
+        );
         let query_planner = QueryPlannerImpl::new(meta_store.clone());
         let query_executor = Arc::new(QueryExecutorImpl);
+        let process_list = ProcessList::new();
         let cluster = ClusterImpl::new(
-            "localhost".to_string(),
-            vec!["localhost".to_string()],
+            self.config_obj.server_name().to_string(),
+            vec![self.config_obj.server_name().to_string()],
             remote_fs.clone(),
             Duration::from_secs(30),
             chunk_store.clone(),
@@ -398,7 +738,8 @@ impl Config {
             import_service.clone(),
             self.config_obj.clone(),
             query_executor.clone(),
-        );
+            process_list.clone(),
+        )?;
 
         let sql_service = SqlServiceImpl::new(
             meta_store.clone(),
@@ -406,22 +747,26 @@ impl Config {
             query_planner.clone(),
             query_executor.clone(),
             cluster.clone(),
+            process_list,
+            self.config_obj.clone(),
         );
         let scheduler = SchedulerImpl::new(
             meta_store.clone(),
             cluster.clone(),
             remote_fs.clone(),
+            wal_store.clone(),
             event_receiver,
             self.config_obj.clone(),
         );
 
-        CubeServices {
+        Ok(CubeServices {
             sql_service,
             scheduler: Arc::new(scheduler),
             meta_store,
+            rocks_meta_store,
             cluster,
             remote_fs,
-        }
+        })
     }
 
     pub fn configure_worker(&self) {
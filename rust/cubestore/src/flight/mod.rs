@@ -0,0 +1,352 @@
+use std::convert::TryFrom;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use arrow::datatypes::Schema;
+use arrow::ipc::writer::IpcWriteOptions;
+use arrow_flight::flight_service_server::{FlightService, FlightServiceServer};
+use arrow_flight::utils::{
+    flight_data_from_arrow_batch, flight_data_from_arrow_schema, flight_data_to_arrow_batch,
+};
+use arrow_flight::{
+    Action, ActionType, Criteria, Empty, FlightData, FlightDescriptor, FlightInfo,
+    HandshakeRequest, HandshakeResponse, PutResult, SchemaResult, Ticket,
+};
+use futures::{stream, Stream, StreamExt};
+use log::{debug, info};
+use tokio::net::TcpListener;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status, Streaming};
+
+use crate::config::ConfigObj;
+use crate::http::authenticate;
+use crate::sql::{SqlQueryContext, SqlService};
+use crate::{CubeError, CubeErrorCauseType};
+
+type FlightStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send + Sync + 'static>>;
+
+pub struct FlightServer;
+
+impl FlightServer {
+    pub async fn listen(
+        address: String,
+        sql_service: Arc<dyn SqlService>,
+        config_obj: Arc<dyn ConfigObj>,
+    ) -> Result<(), CubeError> {
+        let socket_address = address.parse::<SocketAddr>().map_err(|e| {
+            CubeError::user(format!(
+                "Invalid Arrow Flight bind address {}: {}",
+                address, e
+            ))
+        })?;
+
+        let listener = TcpListener::bind(socket_address).await?;
+
+        info!("Arrow Flight port open on {}", address);
+
+        Self::serve(listener, sql_service, config_obj).await
+    }
+
+    pub async fn serve(
+        mut listener: TcpListener,
+        sql_service: Arc<dyn SqlService>,
+        config_obj: Arc<dyn ConfigObj>,
+    ) -> Result<(), CubeError> {
+        Server::builder()
+            .add_service(FlightServiceServer::new(CubeFlightService {
+                sql_service,
+                config_obj,
+            }))
+            .serve_with_incoming(listener.incoming())
+            .await
+            .map_err(|e| CubeError::internal(e.to_string()))
+    }
+}
+
+/// `DoGet` tickets are SQL queries and `DoPut` descriptors are `[schema, table]` paths.
+pub struct CubeFlightService {
+    sql_service: Arc<dyn SqlService>,
+    config_obj: Arc<dyn ConfigObj>,
+}
+
+impl CubeFlightService {
+    fn context<T>(&self, request: &Request<T>) -> Result<SqlQueryContext, Status> {
+        let authorization = request
+            .metadata()
+            .get("authorization")
+            .and_then(|v| v.to_str().ok());
+        authenticate(self.config_obj.as_ref(), authorization)
+            .map_err(|e| Status::unauthenticated(e.message))
+    }
+}
+
+fn status(e: CubeError) -> Status {
+    match e.cause {
+        CubeErrorCauseType::User => Status::invalid_argument(e.message),
+        CubeErrorCauseType::Internal => Status::internal(e.message),
+    }
+}
+
+fn table_path(descriptor: &FlightDescriptor) -> Result<(String, String), Status> {
+    let path = match descriptor.path.as_slice() {
+        [name] => name
+            .splitn(2, '.')
+            .map(|s| s.to_string())
+            .collect::<Vec<_>>(),
+        path => path.to_vec(),
+    };
+    match path.as_slice() {
+        [schema, table] => Ok((schema.to_string(), table.to_string())),
+        _ => Err(Status::invalid_argument(format!(
+            "Flight descriptor path should be [schema, table] but found: {:?}",
+            descriptor.path
+        ))),
+    }
+}
+
+#[tonic::async_trait]
+impl FlightService for CubeFlightService {
+    type HandshakeStream = FlightStream<HandshakeResponse>;
+    type ListFlightsStream = FlightStream<FlightInfo>;
+    type DoGetStream = FlightStream<FlightData>;
+    type DoPutStream = FlightStream<PutResult>;
+    type DoActionStream = FlightStream<arrow_flight::Result>;
+    type ListActionsStream = FlightStream<ActionType>;
+    type DoExchangeStream = FlightStream<FlightData>;
+
+    async fn handshake(
+        &self,
+        _request: Request<Streaming<HandshakeRequest>>,
+    ) -> Result<Response<Self::HandshakeStream>, Status> {
+        Err(Status::unimplemented(
+            "Handshake isn't required, pass basic authorization header with each request",
+        ))
+    }
+
+    async fn list_flights(
+        &self,
+        _request: Request<Criteria>,
+    ) -> Result<Response<Self::ListFlightsStream>, Status> {
+        Err(Status::unimplemented("ListFlights is not supported"))
+    }
+
+    async fn get_flight_info(
+        &self,
+        _request: Request<FlightDescriptor>,
+    ) -> Result<Response<FlightInfo>, Status> {
+        Err(Status::unimplemented("GetFlightInfo is not supported"))
+    }
+
+    async fn get_schema(
+        &self,
+        _request: Request<FlightDescriptor>,
+    ) -> Result<Response<SchemaResult>, Status> {
+        Err(Status::unimplemented("GetSchema is not supported"))
+    }
+
+    async fn do_get(
+        &self,
+        request: Request<Ticket>,
+    ) -> Result<Response<Self::DoGetStream>, Status> {
+        let context = self.context(&request)?;
+        let query = String::from_utf8(request.into_inner().ticket)
+            .map_err(|e| Status::invalid_argument(format!("Ticket is not a SQL query: {}", e)))?;
+        debug!("Flight query: {}", query);
+        let data_stream = self
+            .sql_service
+            .exec_query_stream(context, &query)
+            .await
+            .map_err(status)?;
+
+        let options = IpcWriteOptions::default();
+        let schema_data = flight_data_from_arrow_schema(data_stream.schema().as_ref(), &options);
+        let batches_data = data_stream.flat_map(move |batch| match batch {
+            Ok(batch) => {
+                let (dictionaries, data) = flight_data_from_arrow_batch(&batch, &options);
+                stream::iter(
+                    dictionaries
+                        .into_iter()
+                        .chain(std::iter::once(data))
+                        .map(Ok),
+                )
+                .left_stream()
+            }
+            Err(e) => stream::once(async move { Err(status(e)) }).right_stream(),
+        });
+        let output = stream::once(async move { Ok(schema_data) }).chain(batches_data);
+        Ok(Response::new(Box::pin(output) as Self::DoGetStream))
+    }
+
+    async fn do_put(
+        &self,
+        request: Request<Streaming<FlightData>>,
+    ) -> Result<Response<Self::DoPutStream>, Status> {
+        let context = self.context(&request)?;
+        let mut input = request.into_inner();
+        let first = input
+            .next()
+            .await
+            .ok_or_else(|| Status::invalid_argument("Flight data is empty"))??;
+        let (schema_name, table_name) = table_path(
+            first
+                .flight_descriptor
+                .as_ref()
+                .ok_or_else(|| Status::invalid_argument("Flight descriptor is missing"))?,
+        )?;
+        let schema = Arc::new(
+            Schema::try_from(&first)
+                .map_err(|e| Status::invalid_argument(format!("Invalid schema: {}", e)))?,
+        );
+
+        let batches = input.map(move |data| {
+            let data = data
+                .map_err(|e| CubeError::user(format!("Can't read Flight data: {}", e.message())))?;
+            flight_data_to_arrow_batch(&data, schema.clone(), &[])
+                .map_err(|e| CubeError::user(format!("Invalid batch: {}", e)))
+        });
+        let rows = self
+            .sql_service
+            .insert_batches(context, &schema_name, &table_name, Box::pin(batches))
+            .await
+            .map_err(status)?;
+
+        let output = stream::iter(vec![Ok(PutResult {
+            app_metadata: rows.to_string().into_bytes(),
+        })]);
+        Ok(Response::new(Box::pin(output) as Self::DoPutStream))
+    }
+
+    async fn do_action(
+        &self,
+        _request: Request<Action>,
+    ) -> Result<Response<Self::DoActionStream>, Status> {
+        Err(Status::unimplemented("DoAction is not supported"))
+    }
+
+    async fn list_actions(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<Self::ListActionsStream>, Status> {
+        Err(Status::unimplemented("ListActions is not supported"))
+    }
+
+    async fn do_exchange(
+        &self,
+        _request: Request<Streaming<FlightData>>,
+    ) -> Result<Response<Self::DoExchangeStream>, Status> {
+        Err(Status::unimplemented("DoExchange is not supported"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::queryplanner::query_executor::batch_to_dataframe;
+    use crate::table::{Row, TableValue};
+    use arrow::array::{Int32Array, Int64Array, StringArray};
+    use arrow::datatypes::{DataType, Field};
+    use arrow::record_batch::RecordBatch;
+    use arrow_flight::flight_descriptor::DescriptorType;
+    use arrow_flight::flight_service_client::FlightServiceClient;
+
+    #[tokio::test]
+    async fn flight_put_and_get() {
+        Config::run_test("flight_put_and_get", async move |services| {
+            let service = services.sql_service;
+            service.exec_query("CREATE SCHEMA foo").await.unwrap();
+            service
+                .exec_query("CREATE TABLE foo.orders (id int, city text, amount int)")
+                .await
+                .unwrap();
+
+            // Connections wait in the listen backlog until the server starts accepting them.
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = listener.local_addr().unwrap();
+            tokio::spawn(FlightServer::serve(
+                listener,
+                service.clone(),
+                Config::test("flight_put_and_get").config_obj(),
+            ));
+            let mut client = FlightServiceClient::connect(format!("http://{}", address))
+                .await
+                .unwrap();
+
+            // Amounts are sent as Int32 to check they are cast to the table type.
+            let schema = Schema::new(vec![
+                Field::new("id", DataType::Int64, false),
+                Field::new("amount", DataType::Int32, false),
+                Field::new("city", DataType::Utf8, true),
+            ]);
+            let batch = RecordBatch::try_new(
+                Arc::new(schema.clone()),
+                vec![
+                    Arc::new(Int64Array::from(vec![1, 2, 3])),
+                    Arc::new(Int32Array::from(vec![10, 5, 7])),
+                    Arc::new(StringArray::from(vec![
+                        Some("New York"),
+                        Some("Boston"),
+                        Some("New York"),
+                    ])),
+                ],
+            )
+            .unwrap();
+            let options = IpcWriteOptions::default();
+            let mut schema_data = flight_data_from_arrow_schema(&schema, &options);
+            schema_data.flight_descriptor = Some(FlightDescriptor {
+                r#type: DescriptorType::Path as i32,
+                cmd: Vec::new(),
+                path: vec!["foo".to_string(), "orders".to_string()],
+            });
+            let (_, batch_data) = flight_data_from_arrow_batch(&batch, &options);
+            let mut put_results = client
+                .do_put(stream::iter(vec![
+                    schema_data,
+                    batch_data.clone(),
+                    batch_data,
+                ]))
+                .await
+                .unwrap()
+                .into_inner();
+            let put_result = put_results.message().await.unwrap().unwrap();
+            assert_eq!(put_result.app_metadata, b"6".to_vec());
+
+            let ticket = Ticket {
+                ticket: b"SELECT city, sum(amount) FROM foo.orders GROUP BY 1 ORDER BY 1".to_vec(),
+            };
+            let data = client
+                .do_get(ticket)
+                .await
+                .unwrap()
+                .into_inner()
+                .map(|d| d.unwrap())
+                .collect::<Vec<_>>()
+                .await;
+            let schema = Arc::new(Schema::try_from(&data[0]).unwrap());
+            let batches = data[1..]
+                .iter()
+                .map(|d| flight_data_to_arrow_batch(d, schema.clone(), &[]).unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(
+                batch_to_dataframe(&batches).unwrap().get_rows(),
+                &vec![
+                    Row::new(vec![
+                        TableValue::String("Boston".to_string()),
+                        TableValue::Int(10)
+                    ]),
+                    Row::new(vec![
+                        TableValue::String("New York".to_string()),
+                        TableValue::Int(34)
+                    ]),
+                ]
+            );
+
+            let ticket = Ticket {
+                ticket: b"SELECT * FROM foo.missing".to_vec(),
+            };
+            assert!(client.do_get(ticket).await.is_err());
+        })
+        .await;
+    }
+}
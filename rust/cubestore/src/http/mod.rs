@@ -1,45 +1,434 @@
-use std::sync::Arc;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
 
-use log::debug;
+use arrow::ipc::writer::StreamWriter;
+use bytes::Bytes;
+use futures::StreamExt;
+use hex::ToHex;
+use log::{debug, error, info};
 use serde_derive::Deserialize;
+use serde_json::{json, Number, Value};
+use warp::http::header::{CONTENT_TYPE, WWW_AUTHENTICATE};
+use warp::http::{Response, StatusCode};
+use warp::hyper::body::Sender;
+use warp::hyper::Body;
 use warp::{Filter, Rejection};
 
-use crate::sql::SqlService;
-use crate::CubeError;
+use crate::config::{constant_time_eq, ConfigObj};
+use crate::queryplanner::query_executor::{arrow_to_column_type, batch_to_dataframe};
+use crate::sql::{SqlQueryContext, SqlService};
+use crate::store::DataStream;
+use crate::table::{Row, TableValue};
+use crate::{CubeError, CubeErrorCauseType};
 
 #[derive(Deserialize, Debug)]
 pub struct SqlQueryBody {
     query: String,
 }
 
-pub async fn run_server(sql_service: Arc<dyn SqlService>) -> Result<(), CubeError> {
-    let sql_service_filter = warp::any().map(move || sql_service.clone());
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResultFormat {
+    Json,
+    Csv,
+    ArrowIpc,
+}
 
-    let query_route = warp::path!("query")
-        .and(warp::body::json())
-        .and(sql_service_filter.clone())
-        .and_then(post_query);
+impl ResultFormat {
+    fn from_accept(accept: Option<&str>) -> ResultFormat {
+        match accept {
+            Some(a) if a.contains("application/vnd.apache.arrow.stream") => ResultFormat::ArrowIpc,
+            Some(a) if a.contains("text/csv") => ResultFormat::Csv,
+            _ => ResultFormat::Json,
+        }
+    }
 
-    // let import_route = warp::path!("insert" / String)
-    //     .and(warp::body::aggregate())
-    //     .and_then(post_insert);
+    fn content_type(&self) -> &'static str {
+        match self {
+            ResultFormat::Json => "application/json",
+            ResultFormat::Csv => "text/csv",
+            ResultFormat::ArrowIpc => "application/vnd.apache.arrow.stream",
+        }
+    }
+}
 
-    warp::serve(
-        query_route, // .or(import_route)
-    )
-    .run(([127, 0, 0, 1], 3030))
-    .await;
+pub async fn run_server(
+    address: String,
+    sql_service: Arc<dyn SqlService>,
+    config_obj: Arc<dyn ConfigObj>,
+) -> Result<(), CubeError> {
+    let address = address
+        .parse::<SocketAddr>()
+        .map_err(|e| CubeError::user(format!("Invalid HTTP bind address {}: {}", address, e)))?;
+    let (address, server) = warp::serve(routes(sql_service, config_obj))
+        .try_bind_ephemeral(address)
+        .map_err(|e| CubeError::internal(e.to_string()))?;
+    info!("HTTP port open on {}", address);
+    server.await;
 
     Ok(())
 }
 
-// curl -X POST  -d '{"query":"create schema boo"}' -H "Content-Type: application/json" http://127.0.0.1:3030/query
+pub fn routes(
+    sql_service: Arc<dyn SqlService>,
+    config_obj: Arc<dyn ConfigObj>,
+) -> impl Filter<Extract = (Response<Body>,), Error = Rejection> + Clone {
+    let sql_service_filter = warp::any().map(move || sql_service.clone());
+    let config_filter = warp::any().map(move || config_obj.clone());
+
+    warp::post()
+        .and(warp::path!("query"))
+        .and(warp::header::optional::<String>("authorization"))
+        .and(warp::header::optional::<String>("accept"))
+        .and(warp::body::json())
+        .and(sql_service_filter)
+        .and(config_filter)
+        .and_then(post_query)
+}
+
+// curl -X POST -d '{"query":"select 1"}' -H "Content-Type: application/json" http://127.0.0.1:3030/query
 pub async fn post_query(
+    authorization: Option<String>,
+    accept: Option<String>,
     query_body: SqlQueryBody,
     sql_service: Arc<dyn SqlService>,
-) -> Result<String, Rejection> {
-    let res = sql_service.exec_query(&query_body.query).await?;
-    debug!("Query result is {:?}", res);
+    config_obj: Arc<dyn ConfigObj>,
+) -> Result<Response<Body>, Rejection> {
     debug!("Post query: {:?}", query_body);
-    Ok(format!("{:?}", res))
+    let context = match authenticate(config_obj.as_ref(), authorization.as_deref()) {
+        Ok(context) => context,
+        Err(e) => {
+            return Ok(Response::builder()
+                .status(StatusCode::UNAUTHORIZED)
+                .header(WWW_AUTHENTICATE, "Basic realm=\"Cube Store\"")
+                .header(CONTENT_TYPE, ResultFormat::Json.content_type())
+                .body(Body::from(error_json(&e).to_string()))
+                .unwrap());
+        }
+    };
+    let format = ResultFormat::from_accept(accept.as_deref());
+    // Errors of the first batch are still reported with an error status.
+    let res = async {
+        let mut data_stream = sql_service
+            .exec_query_stream(context, &query_body.query)
+            .await?;
+        data_stream.prefetch().await?;
+        Ok::<_, CubeError>(data_stream)
+    }
+    .await;
+    Ok(match res {
+        Ok(data_stream) => Response::builder()
+            .header(CONTENT_TYPE, format.content_type())
+            .body(result_body(format, data_stream))
+            .unwrap(),
+        Err(e) => Response::builder()
+            .status(match e.cause {
+                CubeErrorCauseType::User => StatusCode::BAD_REQUEST,
+                CubeErrorCauseType::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+            })
+            .header(CONTENT_TYPE, ResultFormat::Json.content_type())
+            .body(Body::from(error_json(&e).to_string()))
+            .unwrap(),
+    })
+}
+
+/// Checks HTTP basic credentials against configured users, any request is accepted if there are none.
+pub fn authenticate(
+    config_obj: &dyn ConfigObj,
+    authorization: Option<&str>,
+) -> Result<SqlQueryContext, CubeError> {
+    let users = config_obj.users();
+    if users.is_empty() {
+        return Ok(SqlQueryContext::default());
+    }
+    let credentials = authorization
+        .and_then(|a| a.strip_prefix("Basic "))
+        .and_then(|c| base64::decode(c.trim()).ok())
+        .and_then(|c| String::from_utf8(c).ok())
+        .ok_or_else(|| CubeError::user("Basic authorization is required".to_string()))?;
+    let mut parts = credentials.splitn(2, ':');
+    let name = parts.next().unwrap_or("");
+    let password = parts.next().unwrap_or("");
+    match users
+        .iter()
+        .find(|u| u.name == name && constant_time_eq(u.password.as_bytes(), password.as_bytes()))
+    {
+        Some(user) => Ok(SqlQueryContext {
+            connection_id: None,
+            user: Some(user.name.clone()),
+            allowed_schemas: user.allowed_schemas.clone(),
+            default_schema: None,
+        }),
+        None => Err(CubeError::user(format!(
+            "Authentication failed for user {}",
+            name
+        ))),
+    }
+}
+
+fn error_json(e: &CubeError) -> Value {
+    json!({ "error": e.message, "type": e.cause })
+}
+
+fn result_body(format: ResultFormat, data_stream: DataStream) -> Body {
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        if let Err(e) = write_result(format, data_stream, &mut sender).await {
+            error!("Error sending query results: {}", e);
+            sender.abort();
+        }
+    });
+    body
+}
+
+async fn write_result(
+    format: ResultFormat,
+    mut data_stream: DataStream,
+    sender: &mut Sender,
+) -> Result<(), CubeError> {
+    if format == ResultFormat::ArrowIpc {
+        let buffer = SharedBuffer::default();
+        let mut writer = StreamWriter::try_new(buffer.clone(), data_stream.schema().as_ref())?;
+        while let Some(batch) = data_stream.next().await {
+            writer.write(&batch?)?;
+            send(sender, buffer.take()).await?;
+        }
+        writer.finish()?;
+        return send(sender, buffer.take()).await;
+    }
+    send(sender, encode_header(format, &data_stream)?).await?;
+    let mut rows_sent = 0;
+    while let Some(batch) = data_stream.next().await {
+        let data_frame = batch_to_dataframe(&vec![batch?])?;
+        send(
+            sender,
+            encode_rows(format, data_frame.get_rows(), rows_sent)?,
+        )
+        .await?;
+        rows_sent += data_frame.len();
+    }
+    if format == ResultFormat::Json {
+        send(sender, b"]}".to_vec()).await?;
+    }
+    Ok(())
+}
+
+async fn send(sender: &mut Sender, data: Vec<u8>) -> Result<(), CubeError> {
+    if data.is_empty() {
+        return Ok(());
+    }
+    sender
+        .send_data(Bytes::from(data))
+        .await
+        .map_err(|e| CubeError::internal(e.to_string()))
+}
+
+/// Arrow IPC writer output which is sent after each batch.
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuffer {
+    fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn encode_header(format: ResultFormat, data_stream: &DataStream) -> Result<Vec<u8>, CubeError> {
+    let schema = data_stream.schema();
+    match format {
+        ResultFormat::Json => {
+            let columns = schema
+                .fields()
+                .iter()
+                .enumerate()
+                .map(|(i, f)| {
+                    Ok(json!({
+                        "name": f.name(),
+                        "type": arrow_to_column_type(f.data_type().clone())?.to_string(),
+                        "table": data_stream.get_source_table(i).map(|t| t.table_name()),
+                    }))
+                })
+                .collect::<Result<Vec<_>, CubeError>>()?;
+            let header = serde_json::to_string(&columns)?;
+            Ok(format!("{{\"columns\":{},\"data\":[", header).into_bytes())
+        }
+        _ => {
+            let mut writer = csv::Writer::from_writer(Vec::new());
+            writer.write_record(schema.fields().iter().map(|f| f.name()))?;
+            writer.into_inner().map_err(|e| CubeError::from_error(e))
+        }
+    }
+}
+
+/// `rows_sent` is the number of rows preceding `rows` in the result.
+fn encode_rows(format: ResultFormat, rows: &[Row], rows_sent: usize) -> Result<Vec<u8>, CubeError> {
+    match format {
+        ResultFormat::Json => {
+            let mut res = Vec::new();
+            for (i, row) in rows.iter().enumerate() {
+                if rows_sent + i > 0 {
+                    res.push(b',');
+                }
+                let values = row.values().iter().map(json_value).collect::<Vec<_>>();
+                serde_json::to_writer(&mut res, &values)?;
+            }
+            Ok(res)
+        }
+        _ => {
+            let mut writer = csv::Writer::from_writer(Vec::new());
+            for row in rows.iter() {
+                writer.write_record(row.values().iter().map(csv_value))?;
+            }
+            writer.into_inner().map_err(|e| CubeError::from_error(e))
+        }
+    }
+}
+
+fn json_value(value: &TableValue) -> Value {
+    match value {
+        TableValue::Null => Value::Null,
+        TableValue::String(s) => Value::String(s.to_string()),
+        TableValue::Int(i) => Value::from(*i),
+        // Decimals are kept as strings to preserve precision.
+        TableValue::Decimal(d) => Value::String(d.to_string()),
+        TableValue::Float(f) => f
+            .parse::<f64>()
+            .ok()
+            .and_then(Number::from_f64)
+            .map(Value::Number)
+            .unwrap_or_else(|| Value::String(f.to_string())),
+        TableValue::Bytes(b) => Value::String(b.encode_hex()),
+        TableValue::Timestamp(t) => Value::String(t.to_string()),
+        TableValue::Boolean(b) => Value::Bool(*b),
+    }
+}
+
+fn csv_value(value: &TableValue) -> String {
+    match value {
+        TableValue::Null => "".to_string(),
+        TableValue::String(s) => s.to_string(),
+        TableValue::Int(i) => i.to_string(),
+        TableValue::Decimal(d) => d.to_string(),
+        TableValue::Float(f) => f.to_string(),
+        TableValue::Bytes(b) => b.encode_hex(),
+        TableValue::Timestamp(t) => t.to_string(),
+        TableValue::Boolean(b) => b.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, UserConfig};
+    use arrow::array::Int64Array;
+    use arrow::datatypes::{DataType, Field, Schema};
+    use arrow::ipc::reader::StreamReader;
+    use arrow::record_batch::RecordBatch;
+    use std::io::Cursor;
+    use tokio::sync::mpsc;
+
+    async fn query(
+        routes: &(impl Filter<Extract = (Response<Body>,), Error = Rejection> + Clone + 'static),
+        query: &str,
+        accept: &str,
+        authorization: Option<&str>,
+    ) -> warp::http::Response<bytes::Bytes> {
+        let mut request = warp::test::request()
+            .method("POST")
+            .path("/query")
+            .header("accept", accept)
+            .json(&json!({ "query": query }));
+        if let Some(authorization) = authorization {
+            request = request.header("authorization", authorization);
+        }
+        request.reply(routes).await
+    }
+
+    #[tokio::test]
+    async fn http_query() {
+        let config = Config::test("http_query").update_config(|mut c| {
+            c.users = vec![UserConfig {
+                name: "analyst".to_string(),
+                password: "secret".to_string(),
+                allowed_schemas: Some(vec!["foo".to_string()]),
+            }];
+            c
+        });
+        let config_obj = config.config_obj();
+        config.start_test(async move |services| {
+            let service = services.sql_service;
+            service.exec_query("CREATE SCHEMA foo").await.unwrap();
+            service.exec_query("CREATE TABLE foo.orders (id int, city text, amount decimal(10, 2), created timestamp)").await.unwrap();
+            service.exec_query("INSERT INTO foo.orders (id, city, amount, created) VALUES (1, 'New York', 10.5, '2021-01-01T00:00:00.000Z'), (2, NULL, 5.25, '2021-01-02T00:00:00.000Z')").await.unwrap();
+
+            let routes = routes(service, config_obj);
+            let select = "SELECT id, city, amount, created FROM foo.orders ORDER BY id";
+            // analyst:secret
+            let authorization = Some("Basic YW5hbHlzdDpzZWNyZXQ=");
+
+            let res = query(&routes, select, "application/json", None).await;
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+            let res = query(&routes, select, "application/json", authorization).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            let body: Value = serde_json::from_slice(res.body()).unwrap();
+            assert_eq!(body["columns"][1], json!({ "name": "city", "type": "STRING", "table": "foo.orders" }));
+            assert_eq!(body["data"], json!([
+                [1, "New York", "10.5", "2021-01-01T00:00:00.000Z"],
+                [2, null, "5.25", "2021-01-02T00:00:00.000Z"]
+            ]));
+
+            let res = query(&routes, select, "text/csv", authorization).await;
+            assert_eq!(res.headers()[CONTENT_TYPE], "text/csv");
+            assert_eq!(
+                std::str::from_utf8(res.body()).unwrap(),
+                "id,city,amount,created\n1,New York,10.5,2021-01-01T00:00:00.000Z\n2,,5.25,2021-01-02T00:00:00.000Z\n"
+            );
+
+            let res = query(&routes, select, "application/vnd.apache.arrow.stream", authorization).await;
+            let batches = StreamReader::try_new(Cursor::new(res.body().to_vec())).unwrap().collect::<Result<Vec<_>, _>>().unwrap();
+            assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 2);
+
+            let res = query(&routes, "SELECT * FROM bar.secrets", "application/json", authorization).await;
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+            let body: Value = serde_json::from_slice(res.body()).unwrap();
+            assert_eq!(body["type"], "User");
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn result_body_is_sent_per_batch() {
+        let schema = Arc::new(Schema::new(vec![Field::new("id", DataType::Int64, false)]));
+        let (mut sender, receiver) = mpsc::channel(1);
+        let mut body = result_body(
+            ResultFormat::Csv,
+            DataStream::new(schema.clone(), Box::pin(receiver)),
+        );
+        assert_eq!(body.next().await.unwrap().unwrap(), Bytes::from("id\n"));
+
+        let batch = |id: i64| {
+            Ok(
+                RecordBatch::try_new(schema.clone(), vec![Arc::new(Int64Array::from(vec![id]))])
+                    .unwrap(),
+            )
+        };
+        // The first batch is sent while the query still produces the second one.
+        sender.send(batch(1)).await.unwrap();
+        assert_eq!(body.next().await.unwrap().unwrap(), Bytes::from("1\n"));
+        sender.send(batch(2)).await.unwrap();
+        assert_eq!(body.next().await.unwrap().unwrap(), Bytes::from("2\n"));
+        drop(sender);
+        assert!(body.next().await.is_none());
+    }
 }
@@ -1,69 +1,525 @@
-use crate::metastore::{Column, ColumnType, ImportFormat, MetaStore};
-use crate::store::{DataFrame, WALDataStore};
-use crate::table::{Row, TableValue};
+use crate::config::{ConfigObj, FileStoreProvider};
+use crate::metastore::job::{JobProgress, JobType};
+use crate::metastore::table::Table;
+use crate::metastore::{
+    Column, ColumnType, CsvOptions, IdRow, ImportFormat, MetaStore, RowKey, TableId,
+};
+use crate::queryplanner::query_executor::batch_to_dataframe;
+use crate::remotefs::s3::S3RemoteFs;
+use crate::remotefs::{LocalDirRemoteFs, RemoteFs};
+use crate::store::{RecordBatchBuilder, WALDataStore};
+use crate::table::{Row, TableValue, TimestampValue};
 use crate::CubeError;
+use arrow::array::{Array, ArrayRef, Date32Array, Date64Array, TimestampMicrosecondArray};
+use arrow::compute::kernels::cast::cast;
+use arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+use arrow::error::ArrowError;
+use arrow::ipc::reader::FileReader;
+use arrow::record_batch::RecordBatch;
 use async_trait::async_trait;
 use bigdecimal::{BigDecimal, Num};
 use core::mem;
-use futures::StreamExt;
+use datafusion::physical_plan::datetime_expressions::string_to_timestamp_nanos;
+use flate2::read::MultiGzDecoder;
+use futures::{StreamExt, TryStreamExt};
+use hex::FromHex;
+use log::{error, warn};
 use mockall::automock;
+use parquet::arrow::{ArrowReader, ParquetFileArrowReader};
+use parquet::file::reader::SerializedFileReader;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use tokio::fs;
 use tokio::fs::File;
-use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::io::AsyncWriteExt;
 use tokio::stream::Stream;
+use tokio::sync::mpsc;
+use uuid::Uuid;
 
 impl ImportFormat {
     async fn row_stream(
         &self,
         location: String,
+        file: String,
         columns: Vec<Column>,
-    ) -> Result<Pin<Box<dyn Stream<Item = Result<Row, CubeError>> + Send>>, CubeError> {
-        match self {
-            ImportFormat::CSV => {
-                let file = File::open(location).await?;
-                let lines = BufReader::new(file).lines();
-                let rows = lines.map(move |line| -> Result<Row, CubeError> {
-                    let str = line?;
-                    let mut remaining: &str = str.as_str();
-                    let mut row = Vec::with_capacity(columns.len());
-                    for column in columns.iter() {
-                        let value = if remaining.chars().nth(0) == Some('"') {
-                            let closing_index = remaining
-                                .find("\"")
-                                .ok_or(CubeError::user(format!("Malformed CSV string: {}", str)))?;
-                            let res: &str = remaining[1..closing_index].as_ref();
-                            remaining = remaining[closing_index..].as_ref();
-                            res
+        bytes_read: Arc<AtomicU64>,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<Vec<Row>, CubeError>> + Send>>, CubeError> {
+        let format = self.clone();
+        let (tx, rx) = mpsc::channel(ROW_CHUNKS_IN_FLIGHT);
+        tokio::task::spawn_blocking(move || {
+            let mut sender = RowSender::new(tx);
+            let result = match format {
+                ImportFormat::CSV => read_csv(
+                    &CsvOptions::default(),
+                    &location,
+                    &file,
+                    &columns,
+                    bytes_read,
+                    &mut sender,
+                ),
+                ImportFormat::CSVWithOptions(options) => read_csv(
+                    &options,
+                    &location,
+                    &file,
+                    &columns,
+                    bytes_read,
+                    &mut sender,
+                ),
+                ImportFormat::Parquet => {
+                    read_parquet(&location, &file, &columns, bytes_read, &mut sender)
+                }
+                ImportFormat::ArrowIpc => {
+                    read_arrow_ipc(&location, &file, &columns, bytes_read, &mut sender)
+                }
+            };
+            match result {
+                Ok(()) => sender.finish(),
+                Err(e) => sender.fail(e),
+            }
+        });
+        Ok(rx.boxed())
+    }
+}
+
+const ROW_CHUNK_SIZE: usize = 4096;
+const ROW_CHUNKS_IN_FLIGHT: usize = 4;
+
+/// Passes rows read on a blocking thread to the import task in chunks.
+/// Channel is bounded so reading is paused while the import task is busy writing WALs.
+struct RowSender {
+    tx: mpsc::Sender<Result<Vec<Row>, CubeError>>,
+    chunk: Vec<Row>,
+}
+
+impl RowSender {
+    fn new(tx: mpsc::Sender<Result<Vec<Row>, CubeError>>) -> RowSender {
+        RowSender {
+            tx,
+            chunk: Vec::with_capacity(ROW_CHUNK_SIZE),
+        }
+    }
+
+    fn push(&mut self, row: Row) -> Result<(), CubeError> {
+        self.chunk.push(row);
+        if self.chunk.len() >= ROW_CHUNK_SIZE {
+            let chunk = mem::replace(&mut self.chunk, Vec::with_capacity(ROW_CHUNK_SIZE));
+            self.send(Ok(chunk))?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<(), CubeError> {
+        let chunk = mem::take(&mut self.chunk);
+        if !chunk.is_empty() {
+            self.send(Ok(chunk))?;
+        }
+        Ok(())
+    }
+
+    fn fail(mut self, e: CubeError) -> Result<(), CubeError> {
+        self.send(Err(e))
+    }
+
+    fn send(&mut self, item: Result<Vec<Row>, CubeError>) -> Result<(), CubeError> {
+        futures::executor::block_on(self.tx.send(item))
+            .map_err(|_| CubeError::internal("Import row receiver is closed".to_string()))
+    }
+}
+
+struct CountingReader<R> {
+    inner: R,
+    bytes_read: Arc<AtomicU64>,
+}
+
+impl<R> CountingReader<R> {
+    fn new(inner: R, bytes_read: Arc<AtomicU64>) -> CountingReader<R> {
+        CountingReader { inner, bytes_read }
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.bytes_read.fetch_add(read as u64, Ordering::Relaxed);
+        Ok(read)
+    }
+}
+
+impl<R: Seek> Seek for CountingReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+fn open_binary(location: &str, file: &str) -> Result<std::fs::File, CubeError> {
+    if is_gzip(location) {
+        return Err(CubeError::user(format!(
+            "Compressed files are supported only for CSV format: {}",
+            location
+        )));
+    }
+    Ok(std::fs::File::open(file)?)
+}
+
+fn read_parquet(
+    location: &str,
+    file: &str,
+    columns: &[Column],
+    bytes_read: Arc<AtomicU64>,
+    sender: &mut RowSender,
+) -> Result<(), CubeError> {
+    let input = open_binary(location, file)?;
+    let file_size = input.metadata()?.len();
+    let reader = SerializedFileReader::new(input)?;
+    let mut arrow_reader = ParquetFileArrowReader::new(Rc::new(reader));
+    let batches = arrow_reader.get_record_reader(PARQUET_BATCH_SIZE)?;
+    read_record_batches(location, batches, columns, sender)?;
+    // Parquet reader reads column chunks at random positions so file is accounted as a whole.
+    bytes_read.fetch_add(file_size, Ordering::Relaxed);
+    Ok(())
+}
+
+const PARQUET_BATCH_SIZE: usize = 16384;
+
+fn read_arrow_ipc(
+    location: &str,
+    file: &str,
+    columns: &[Column],
+    bytes_read: Arc<AtomicU64>,
+    sender: &mut RowSender,
+) -> Result<(), CubeError> {
+    let input = CountingReader::new(open_binary(location, file)?, bytes_read);
+    let batches = FileReader::try_new(input)?;
+    read_record_batches(location, batches, columns, sender)
+}
+
+fn read_record_batches(
+    location: &str,
+    batches: impl Iterator<Item = Result<RecordBatch, ArrowError>>,
+    columns: &[Column],
+    sender: &mut RowSender,
+) -> Result<(), CubeError> {
+    let mut row_number = 0;
+    for batch in batches {
+        let batch = batch?;
+        let schema = batch.schema();
+        let mut fields = Vec::with_capacity(columns.len());
+        let mut arrays = Vec::with_capacity(columns.len());
+        for column in columns.iter() {
+            let index = schema
+                .fields()
+                .iter()
+                .position(|f| f.name().eq_ignore_ascii_case(column.get_name()))
+                .ok_or_else(|| {
+                    CubeError::user(format!(
+                        "Column '{}' is not found in {}",
+                        column.get_name(),
+                        location
+                    ))
+                })?;
+            let array = normalize_array(batch.column(index)).map_err(|e| {
+                CubeError::user(format!(
+                    "Can't import column '{}' from {}: {}",
+                    column.get_name(),
+                    location,
+                    e
+                ))
+            })?;
+            fields.push(Field::new(
+                column.get_name(),
+                array.data_type().clone(),
+                true,
+            ));
+            arrays.push(array);
+        }
+        let batch = RecordBatch::try_new(Arc::new(Schema::new(fields)), arrays)?;
+        let data_frame = batch_to_dataframe(&vec![batch])?;
+        for row in data_frame.get_rows().iter() {
+            row_number += 1;
+            let mut values = Vec::with_capacity(columns.len());
+            for (column, value) in columns.iter().zip(row.values().iter()) {
+                values.push(convert_value(column.get_column_type(), value).map_err(|e| {
+                    CubeError::user(format!(
+                        "Can't import {}: row {}, column '{}': {}",
+                        location,
+                        row_number,
+                        column.get_name(),
+                        e.message
+                    ))
+                })?);
+            }
+            sender.push(Row::new(values))?;
+        }
+    }
+    Ok(())
+}
+
+/// Casts arrow array to one of the types supported by `batch_to_dataframe`.
+fn normalize_array(array: &ArrayRef) -> Result<ArrayRef, CubeError> {
+    Ok(match array.data_type() {
+        DataType::Int8
+        | DataType::Int16
+        | DataType::Int32
+        | DataType::UInt8
+        | DataType::UInt16
+        | DataType::UInt32 => cast(array, &DataType::Int64)?,
+        DataType::Float16 | DataType::Float32 => cast(array, &DataType::Float64)?,
+        DataType::LargeUtf8 => cast(array, &DataType::Utf8)?,
+        DataType::Timestamp(TimeUnit::Microsecond, None)
+        | DataType::Timestamp(TimeUnit::Nanosecond, None) => array.clone(),
+        DataType::Timestamp(_, _) => {
+            cast(array, &DataType::Timestamp(TimeUnit::Microsecond, None))?
+        }
+        DataType::Date32 => {
+            let a = array.as_any().downcast_ref::<Date32Array>().unwrap();
+            Arc::new(TimestampMicrosecondArray::from(
+                (0..a.len())
+                    .map(|i| {
+                        if a.is_null(i) {
+                            None
+                        } else {
+                            Some(a.value(i) as i64 * 86_400_000_000)
+                        }
+                    })
+                    .collect::<Vec<_>>(),
+            ))
+        }
+        DataType::Date64 => {
+            let a = array.as_any().downcast_ref::<Date64Array>().unwrap();
+            Arc::new(TimestampMicrosecondArray::from(
+                (0..a.len())
+                    .map(|i| {
+                        if a.is_null(i) {
+                            None
                         } else {
-                            let next_comma = remaining.find(",").unwrap_or(remaining.len());
-                            let res: &str = remaining[0..next_comma].as_ref();
-                            remaining = remaining[next_comma..].as_ref();
-                            res
-                        };
-
-                        row.push(match column.get_column_type() {
-                            ColumnType::String => TableValue::String(value.to_string()),
-                            ColumnType::Int => value
-                                .parse()
-                                .map(|v| TableValue::Int(v))
-                                .unwrap_or(TableValue::Null),
-                            ColumnType::Decimal { .. } => BigDecimal::from_str_radix(value, 10)
-                                .map(|d| TableValue::Decimal(d.to_string()))
-                                .unwrap_or(TableValue::Null),
-                            x => panic!("CSV import for {:?} is not implemented", x),
-                        });
-
-                        if remaining.chars().nth(0) == Some(',') {
-                            remaining = remaining[1..].as_ref()
+                            Some(a.value(i) * 1000)
                         }
+                    })
+                    .collect::<Vec<_>>(),
+            ))
+        }
+        DataType::UInt64
+        | DataType::Int64
+        | DataType::Float64
+        | DataType::Int64Decimal(0..=5)
+        | DataType::Int64Decimal(10)
+        | DataType::Binary
+        | DataType::Utf8
+        | DataType::Boolean => array.clone(),
+        x => return Err(CubeError::user(format!("unsupported type {:?}", x))),
+    })
+}
+
+fn convert_value(column_type: &ColumnType, value: &TableValue) -> Result<TableValue, CubeError> {
+    Ok(match (column_type, value) {
+        (_, TableValue::Null) => TableValue::Null,
+        (ColumnType::String, TableValue::String(_))
+        | (ColumnType::Int, TableValue::Int(_))
+        | (ColumnType::Decimal { .. }, TableValue::Decimal(_))
+        | (ColumnType::Float, TableValue::Float(_))
+        | (ColumnType::Timestamp, TableValue::Timestamp(_))
+        | (ColumnType::Boolean, TableValue::Boolean(_))
+        | (ColumnType::Bytes, TableValue::Bytes(_)) => value.clone(),
+        (ColumnType::Decimal { .. }, TableValue::Int(v)) => TableValue::Decimal(v.to_string()),
+        (ColumnType::Decimal { .. }, TableValue::Float(v)) => {
+            TableValue::Decimal(BigDecimal::from_str_radix(v, 10)?.to_string())
+        }
+        (ColumnType::Float, TableValue::Int(v)) => TableValue::Float(v.to_string()),
+        (ColumnType::Float, TableValue::Decimal(v)) => TableValue::Float(v.to_string()),
+        (ColumnType::String, TableValue::Int(v)) => TableValue::String(v.to_string()),
+        (ColumnType::String, TableValue::Decimal(v))
+        | (ColumnType::String, TableValue::Float(v)) => TableValue::String(v.to_string()),
+        (ColumnType::String, TableValue::Boolean(v)) => TableValue::String(v.to_string()),
+        (ColumnType::HyperLogLog, TableValue::Bytes(v)) => {
+            cubehll::HllSketch::read(v)?;
+            value.clone()
+        }
+        (column_type, TableValue::String(v)) => {
+            parse_csv_value(column_type, v, &CsvOptions::default())?
+        }
+        (column_type, value) => {
+            return Err(CubeError::user(format!(
+                "can't convert {:?} to {:?}",
+                value, column_type
+            )))
+        }
+    })
+}
+
+fn read_csv(
+    options: &CsvOptions,
+    location: &str,
+    file: &str,
+    columns: &[Column],
+    bytes_read: Arc<AtomicU64>,
+    sender: &mut RowSender,
+) -> Result<(), CubeError> {
+    let file = CountingReader::new(std::fs::File::open(file)?, bytes_read);
+    let input: Box<dyn Read> = if is_gzip(location) {
+        Box::new(MultiGzDecoder::new(file))
+    } else {
+        Box::new(file)
+    };
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(options.delimiter)
+        .has_headers(options.has_header)
+        .flexible(true)
+        .from_reader(input);
+
+    let positions = if options.has_header {
+        let headers = reader.headers()?.clone();
+        columns
+            .iter()
+            .map(|c| {
+                headers
+                    .iter()
+                    .position(|h| h.trim().eq_ignore_ascii_case(c.get_name()))
+                    .ok_or_else(|| {
+                        CubeError::user(format!(
+                            "Column '{}' is not found in CSV header of {}",
+                            c.get_name(),
+                            location
+                        ))
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()?
+    } else {
+        (0..columns.len()).collect::<Vec<_>>()
+    };
+
+    let mut row_number = 0;
+    let mut rows_with_nulls = 0;
+    let mut first_error = None;
+    let mut record = csv::StringRecord::new();
+    while reader
+        .read_record(&mut record)
+        .map_err(|e| CubeError::user(format!("Malformed CSV in {}: {}", location, e)))?
+    {
+        row_number += 1;
+        let mut row = Vec::with_capacity(columns.len());
+        let mut has_nulls = false;
+        for (column, position) in columns.iter().zip(positions.iter()) {
+            let value = match record.get(*position) {
+                Some(value) => parse_csv_value(column.get_column_type(), value, options),
+                None => Err(CubeError::user("value is missing".to_string())),
+            };
+            row.push(match value {
+                Ok(value) => value,
+                Err(e) if options.strict => {
+                    return Err(CubeError::user(format!(
+                        "Can't import {}: row {}{}, column '{}': {}",
+                        location,
+                        row_number,
+                        record
+                            .position()
+                            .map(|p| format!(" (line {})", p.line()))
+                            .unwrap_or_default(),
+                        column.get_name(),
+                        e.message
+                    )))
+                }
+                Err(e) => {
+                    has_nulls = true;
+                    if first_error.is_none() {
+                        first_error = Some(format!(
+                            "row {}, column '{}': {}",
+                            row_number,
+                            column.get_name(),
+                            e.message
+                        ));
                     }
-                    Ok(Row::new(row))
-                });
-                Ok(rows.boxed())
-            }
+                    TableValue::Null
+                }
+            });
+        }
+        if has_nulls {
+            rows_with_nulls += 1;
         }
+        sender.push(Row::new(row))?;
     }
+    if let Some(first_error) = first_error {
+        warn!(
+            "{} of {} rows imported from {} have values which can't be parsed and were replaced \
+             with NULL, first one is at {}. Use strict = true to fail the import instead.",
+            rows_with_nulls, row_number, location, first_error
+        );
+    }
+    Ok(())
+}
+
+/// Remote locations are downloaded by the importing node, others are paths on its local disk.
+pub fn is_remote_location(location: &str) -> bool {
+    location.starts_with("http://")
+        || location.starts_with("https://")
+        || location.starts_with("s3://")
+}
+
+fn is_gzip(location: &str) -> bool {
+    let path = location.split('?').next().unwrap();
+    path.ends_with(".gz")
+}
+
+fn parse_csv_value(
+    column_type: &ColumnType,
+    value: &str,
+    options: &CsvOptions,
+) -> Result<TableValue, CubeError> {
+    if options.null_values.iter().any(|n| n == value) {
+        return Ok(TableValue::Null);
+    }
+    if value.is_empty() && column_type != &ColumnType::String {
+        return Ok(TableValue::Null);
+    }
+    let invalid =
+        |type_name: &str| CubeError::user(format!("can't parse {} from '{}'", type_name, value));
+    Ok(match column_type {
+        ColumnType::String => TableValue::String(value.to_string()),
+        ColumnType::Int => TableValue::Int(value.trim().parse().map_err(|_| invalid("int"))?),
+        ColumnType::Decimal { .. } => TableValue::Decimal(
+            BigDecimal::from_str_radix(value.trim(), 10)
+                .map_err(|_| invalid("decimal"))?
+                .to_string(),
+        ),
+        ColumnType::Float => TableValue::Float(
+            value
+                .trim()
+                .parse::<f64>()
+                .map_err(|_| invalid("float"))?
+                .to_string(),
+        ),
+        ColumnType::Timestamp => TableValue::Timestamp(TimestampValue::new(
+            string_to_timestamp_nanos(value.trim()).map_err(|_| invalid("timestamp"))?,
+        )),
+        ColumnType::Boolean => match value.trim().to_lowercase().as_str() {
+            "true" | "t" | "yes" | "y" | "1" => TableValue::Boolean(true),
+            "false" | "f" | "no" | "n" | "0" => TableValue::Boolean(false),
+            _ => return Err(invalid("boolean")),
+        },
+        ColumnType::Bytes => {
+            TableValue::Bytes(parse_csv_bytes(value).ok_or_else(|| invalid("bytes"))?)
+        }
+        ColumnType::HyperLogLog => {
+            let bytes = parse_csv_bytes(value).ok_or_else(|| invalid("hyperloglog"))?;
+            if cubehll::HllSketch::read(&bytes).is_err() {
+                return Err(invalid("hyperloglog"));
+            }
+            TableValue::Bytes(bytes)
+        }
+    })
+}
+
+/// Binary values are expected to be hex encoded with an optional `0x` or `\x` prefix.
+fn parse_csv_bytes(value: &str) -> Option<Vec<u8>> {
+    let value = value.trim();
+    let hex = value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("\\x"))
+        .unwrap_or(value);
+    Vec::from_hex(hex.as_bytes()).ok()
 }
 
 #[automock]
@@ -75,18 +531,164 @@ pub trait ImportService: Send + Sync {
 pub struct ImportServiceImpl {
     meta_store: Arc<dyn MetaStore>,
     wal_store: Arc<dyn WALDataStore>,
+    remote_fs: Arc<dyn RemoteFs>,
+    config_obj: Arc<dyn ConfigObj>,
 }
 
 impl ImportServiceImpl {
     pub fn new(
         meta_store: Arc<dyn MetaStore>,
         wal_store: Arc<dyn WALDataStore>,
+        remote_fs: Arc<dyn RemoteFs>,
+        config_obj: Arc<dyn ConfigObj>,
     ) -> Arc<ImportServiceImpl> {
         Arc::new(ImportServiceImpl {
             meta_store,
             wal_store,
+            remote_fs,
+            config_obj,
         })
     }
+
+    async fn import_location(
+        &self,
+        table: &IdRow<Table>,
+        format: &ImportFormat,
+        location: &str,
+        progress: &ImportProgress,
+    ) -> Result<(), CubeError> {
+        if is_remote_location(location) {
+            let temp_dir = PathBuf::from(self.remote_fs.local_path().await)
+                .join("import")
+                .join(Uuid::new_v4().to_string());
+            fs::create_dir_all(&temp_dir).await?;
+            let result = match self.download(location, &temp_dir).await {
+                Ok(file) => {
+                    self.import_file(table, format, location, file, progress)
+                        .await
+                }
+                Err(e) => Err(e),
+            };
+            if let Err(e) = fs::remove_dir_all(&temp_dir).await {
+                error!("Error removing import dir {:?}: {}", temp_dir, e);
+            }
+            result
+        } else {
+            self.import_file(table, format, location, location.to_string(), progress)
+                .await
+        }
+    }
+
+    async fn download(&self, location: &str, temp_dir: &Path) -> Result<String, CubeError> {
+        if let Some(path) = location.strip_prefix("s3://") {
+            let mut parts = path.splitn(2, '/');
+            let bucket = parts.next().unwrap();
+            let key = parts.next().filter(|k| !k.is_empty()).ok_or_else(|| {
+                CubeError::user(format!("S3 location should contain a key: {}", location))
+            })?;
+            let remote_fs: Arc<dyn RemoteFs> = match self.config_obj.store_provider() {
+                FileStoreProvider::S3 { region, .. } => S3RemoteFs::new(
+                    temp_dir.to_path_buf(),
+                    region.to_string(),
+                    bucket.to_string(),
+                    None,
+                )?,
+                // Buckets are emulated by directories inside remote dir for filesystem based store
+                FileStoreProvider::Filesystem { remote_dir } => {
+                    LocalDirRemoteFs::new(remote_dir.join(bucket), temp_dir.to_path_buf())
+                }
+                FileStoreProvider::Local => {
+                    return Err(CubeError::user(format!(
+                        "S3 locations are not supported by local store: {}",
+                        location
+                    )))
+                }
+            };
+            remote_fs.download_file(key).await
+        } else {
+            let file_path = temp_dir.join("download");
+            let mut response = reqwest::get(location).await?;
+            if !response.status().is_success() {
+                return Err(CubeError::user(format!(
+                    "Can't download {}: {}",
+                    location,
+                    response.status()
+                )));
+            }
+            let mut file = File::create(&file_path).await?;
+            while let Some(chunk) = response.chunk().await? {
+                file.write_all(&chunk).await?;
+            }
+            file.flush().await?;
+            Ok(file_path.to_str().unwrap().to_string())
+        }
+    }
+
+    async fn import_file(
+        &self,
+        table: &IdRow<Table>,
+        format: &ImportFormat,
+        location: &str,
+        file: String,
+        progress: &ImportProgress,
+    ) -> Result<(), CubeError> {
+        let mut row_stream = format
+            .row_stream(
+                location.to_string(),
+                file,
+                table.get_row().get_columns().clone(),
+                progress.bytes.clone(),
+            )
+            .await?;
+        let batch_size = self.config_obj.import_batch_size();
+        let mut builder =
+            RecordBatchBuilder::new(table.get_row().get_columns().clone(), batch_size);
+        while let Some(chunk) = row_stream.next().await {
+            for row in chunk? {
+                builder.append(&row)?;
+                if builder.len() >= batch_size {
+                    self.add_wal(table, builder.flush()?, progress).await?;
+                }
+            }
+        }
+
+        if builder.len() > 0 {
+            self.add_wal(table, builder.flush()?, progress).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn add_wal(
+        &self,
+        table: &IdRow<Table>,
+        batch: RecordBatch,
+        progress: &ImportProgress,
+    ) -> Result<(), CubeError> {
+        let rows_count = batch.num_rows() as u64;
+        self.wal_store
+            .add_wal_batches(table.clone(), vec![batch])
+            .await?;
+        let rows_imported = progress.rows.fetch_add(rows_count, Ordering::Relaxed) + rows_count;
+        if let Some(job_id) = progress.job_id {
+            self.meta_store
+                .update_job_progress(
+                    job_id,
+                    JobProgress {
+                        rows: rows_imported,
+                        bytes: progress.bytes.load(Ordering::Relaxed),
+                    },
+                )
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+struct ImportProgress {
+    job_id: Option<u64>,
+    rows: AtomicU64,
+    bytes: Arc<AtomicU64>,
 }
 
 #[async_trait]
@@ -101,38 +703,33 @@ impl ImportService for ImportServiceImpl {
                 "Trying to import table without import format: {:?}",
                 table
             )))?;
-        let location = table
+        let locations = table
             .get_row()
-            .location()
-            .as_ref()
+            .locations()
             .ok_or(CubeError::internal(format!(
                 "Trying to import table without location: {:?}",
                 table
             )))?;
-        let mut row_stream = format
-            .row_stream(location.to_string(), table.get_row().get_columns().clone())
-            .await?;
-        let mut rows = Vec::new();
-        while let Some(row) = row_stream.next().await {
-            rows.push(row?);
-            if rows.len() >= 500000 {
-                let mut to_add = Vec::new();
-                mem::swap(&mut rows, &mut to_add);
-                self.wal_store
-                    .add_wal(
-                        table.clone(),
-                        DataFrame::new(table.get_row().get_columns().clone(), to_add),
-                    )
-                    .await?;
-            }
-        }
-
-        self.wal_store
-            .add_wal(
-                table.clone(),
-                DataFrame::new(table.get_row().get_columns().clone(), rows),
+        let job = self
+            .meta_store
+            .get_job_by_ref(
+                RowKey::Table(TableId::Tables, table_id),
+                JobType::TableImport,
             )
             .await?;
+        let progress = ImportProgress {
+            job_id: job.map(|j| j.get_id()),
+            rows: AtomicU64::new(0),
+            bytes: Arc::new(AtomicU64::new(0)),
+        };
+        futures::stream::iter(
+            locations
+                .into_iter()
+                .map(|location| self.import_location(&table, format, location, &progress)),
+        )
+        .buffer_unordered(self.config_obj.import_concurrency())
+        .try_collect::<Vec<_>>()
+        .await?;
 
         Ok(())
     }
@@ -27,6 +27,7 @@ use cubehll::HllError;
 
 pub mod cluster;
 pub mod config;
+pub mod flight;
 pub mod http;
 pub mod import;
 pub mod metastore;
@@ -87,6 +88,8 @@ impl fmt::Display for CubeError {
     }
 }
 
+impl std::error::Error for CubeError {}
+
 impl From<flexbuffers::DeserializationError> for CubeError {
     fn from(v: DeserializationError) -> Self {
         CubeError::internal(v.to_string())
@@ -308,6 +311,18 @@ impl From<hex::FromHexError> for CubeError {
     }
 }
 
+impl From<csv::Error> for CubeError {
+    fn from(v: csv::Error) -> Self {
+        CubeError::from_error(v)
+    }
+}
+
+impl From<tokio_rustls::rustls::TLSError> for CubeError {
+    fn from(v: tokio_rustls::rustls::TLSError) -> Self {
+        CubeError::from_error(v)
+    }
+}
+
 impl From<HllError> for CubeError {
     fn from(v: HllError) -> Self { return CubeError::from_error(v) }
 }
@@ -0,0 +1,32 @@
+use super::{BaseRocksSecondaryIndex, FileDeletion, IndexId, RocksTable, TableId};
+use crate::metastore::{IdRow, MetaStoreEvent};
+use crate::rocks_table_impl;
+use chrono::{DateTime, Utc};
+use rocksdb::DB;
+use serde::{Deserialize, Deserializer};
+
+impl FileDeletion {
+    pub fn new(remote_path: String, deadline: DateTime<Utc>) -> FileDeletion {
+        FileDeletion {
+            remote_path,
+            deadline,
+        }
+    }
+
+    pub fn remote_path(&self) -> &String {
+        &self.remote_path
+    }
+
+    /// File is deleted once this time passes.
+    pub fn deadline(&self) -> &DateTime<Utc> {
+        &self.deadline
+    }
+}
+
+rocks_table_impl!(
+    FileDeletion,
+    FileDeletionRocksTable,
+    TableId::FileDeletions,
+    { vec![] },
+    DeleteFileDeletion
+);
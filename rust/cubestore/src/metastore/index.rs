@@ -1,6 +1,7 @@
 use super::{
     BaseRocksSecondaryIndex, Column, Index, IndexId, RocksSecondaryIndex, RocksTable, TableId,
 };
+use crate::metastore::table::{self, Table};
 use crate::metastore::{IdRow, MetaStoreEvent};
 use crate::{rocks_table_impl, CubeError};
 use byteorder::{BigEndian, WriteBytesExt};
@@ -26,6 +27,7 @@ impl Index {
             table_id,
             columns,
             sort_key_size,
+            storage_names: Vec::new(),
         })
     }
 
@@ -45,8 +47,61 @@ impl Index {
     pub fn sort_key_size(&self) -> u64 {
         self.sort_key_size
     }
+
+    /// Name of the column in data files. Differs from the column name after ALTER TABLE.
+    pub fn storage_name<'b>(&'b self, column: &'b Column) -> &'b String {
+        table::storage_name(&self.columns, &self.storage_names, column)
+    }
+
+    pub fn has_evolved_schema(&self) -> bool {
+        table::has_evolved_schema(&self.storage_names)
+    }
+
+    /// Follows column changes of the table. Dropped columns of the sort key are kept
+    /// under a hidden name as existing partitions are ordered by them.
+    pub fn update_columns(&self, table: &Table) -> Index {
+        let mut columns = Vec::with_capacity(table.get_columns().len());
+        for column in self.columns[0..self.sort_key_size as usize].iter() {
+            let storage_name = self.storage_name(column);
+            let table_column = table
+                .get_columns()
+                .iter()
+                .find(|c| table.storage_name(c) == storage_name);
+            columns.push(match table_column {
+                Some(c) => (c.clone(), storage_name.clone()),
+                None if column.get_name().starts_with(DROPPED_COLUMN_PREFIX) => {
+                    (column.clone(), storage_name.clone())
+                }
+                None => (
+                    Column::new(
+                        format!("{}{}", DROPPED_COLUMN_PREFIX, storage_name),
+                        column.get_column_type().clone(),
+                        0,
+                    ),
+                    storage_name.clone(),
+                ),
+            });
+        }
+        for column in table.get_columns().iter() {
+            let storage_name = table.storage_name(column);
+            if columns.iter().all(|(_, s)| s != storage_name) {
+                columns.push((column.clone(), storage_name.clone()));
+            }
+        }
+        Index {
+            storage_names: columns.iter().map(|(_, s)| s.clone()).collect(),
+            columns: columns
+                .into_iter()
+                .enumerate()
+                .map(|(i, (c, _))| c.replace_index(i))
+                .collect(),
+            ..self.clone()
+        }
+    }
 }
 
+const DROPPED_COLUMN_PREFIX: &str = "#dropped#";
+
 #[derive(Clone, Copy, Debug)]
 pub(crate) enum IndexRocksIndex {
     Name = 1,
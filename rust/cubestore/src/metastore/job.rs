@@ -25,12 +25,20 @@ pub enum JobStatus {
     Error(String),
 }
 
+#[derive(Clone, Serialize, Deserialize, Debug, Hash, Default, Eq, PartialEq)]
+pub struct JobProgress {
+    pub rows: u64,
+    pub bytes: u64,
+}
+
 #[derive(Clone, Serialize, Deserialize, Debug, Hash)]
 pub struct Job {
     row_reference: RowKey,
     job_type: JobType,
     last_heart_beat: DateTime<Utc>,
     status: JobStatus,
+    #[serde(default)]
+    progress: Option<JobProgress>,
 }
 
 impl Job {
@@ -40,6 +48,7 @@ impl Job {
             job_type,
             last_heart_beat: Utc::now(),
             status: JobStatus::Scheduled(shard),
+            progress: None,
         }
     }
 
@@ -59,12 +68,27 @@ impl Job {
         &self.status
     }
 
+    pub fn progress(&self) -> &Option<JobProgress> {
+        &self.progress
+    }
+
     pub fn update_status(&self, status: JobStatus) -> Job {
         Job {
             row_reference: self.row_reference.clone(),
             job_type: self.job_type.clone(),
             last_heart_beat: Utc::now(),
             status,
+            progress: self.progress.clone(),
+        }
+    }
+
+    pub fn update_progress(&self, progress: JobProgress) -> Job {
+        Job {
+            row_reference: self.row_reference.clone(),
+            job_type: self.job_type.clone(),
+            last_heart_beat: Utc::now(),
+            status: self.status.clone(),
+            progress: Some(progress),
         }
     }
 
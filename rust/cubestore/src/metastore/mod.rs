@@ -1,8 +1,10 @@
 pub mod chunks;
+pub mod file_deletion;
 pub mod index;
 pub mod job;
 pub mod listener;
 pub mod partition;
+pub mod rpc;
 pub mod schema;
 pub mod table;
 pub mod wal;
@@ -23,10 +25,13 @@ use tokio::sync::{Notify, RwLock};
 use crate::config::{Config, ConfigObj};
 use crate::metastore::chunks::{ChunkIndexKey, ChunkRocksIndex};
 use crate::metastore::index::IndexIndexKey;
-use crate::metastore::job::{Job, JobIndexKey, JobRocksIndex, JobRocksTable, JobStatus};
+use crate::metastore::job::{
+    Job, JobIndexKey, JobProgress, JobRocksIndex, JobRocksTable, JobStatus, JobType,
+};
 use crate::metastore::partition::PartitionIndexKey;
 use crate::metastore::table::{TableIndexKey, TablePath};
 use crate::metastore::wal::{WALIndexKey, WALRocksIndex};
+use crate::queryplanner::partition_filter::RowFilter;
 use crate::remotefs::{LocalDirRemoteFs, RemoteFs};
 use crate::store::DataFrame;
 use crate::table::{Row, TableValue};
@@ -36,6 +41,7 @@ use arrow::datatypes::{DataType, Field};
 use chrono::{DateTime, Utc};
 use chunks::ChunkRocksTable;
 use core::{fmt, mem};
+use file_deletion::FileDeletionRocksTable;
 use futures::future::join_all;
 use index::{IndexRocksIndex, IndexRocksTable};
 use itertools::Itertools;
@@ -60,7 +66,7 @@ use table::Table;
 use table::{TableRocksIndex, TableRocksTable};
 use tokio::fs::File;
 use tokio::sync::broadcast::Sender;
-use tokio::time::{Duration, delay_for};
+use tokio::time::{delay_for, Duration};
 use wal::WALRocksTable;
 
 #[macro_export]
@@ -218,6 +224,12 @@ impl DataFrameValue<String> for Vec<Column> {
     }
 }
 
+impl DataFrameValue<String> for Vec<String> {
+    fn value(v: &Self) -> String {
+        v.join(", ")
+    }
+}
+
 impl DataFrameValue<String> for Option<String> {
     fn value(v: &Self) -> String {
         v.as_ref()
@@ -226,6 +238,14 @@ impl DataFrameValue<String> for Option<String> {
     }
 }
 
+impl DataFrameValue<String> for Option<Vec<String>> {
+    fn value(v: &Self) -> String {
+        v.as_ref()
+            .map(|v| v.join(", "))
+            .unwrap_or("NULL".to_string())
+    }
+}
+
 impl DataFrameValue<String> for Option<DateTime<Utc>> {
     fn value(v: &Self) -> String {
         v.as_ref()
@@ -234,6 +254,12 @@ impl DataFrameValue<String> for Option<DateTime<Utc>> {
     }
 }
 
+impl DataFrameValue<String> for Vec<PartitionDelete> {
+    fn value(v: &Self) -> String {
+        serde_json::to_string(v).unwrap()
+    }
+}
+
 impl DataFrameValue<String> for Option<ImportFormat> {
     fn value(v: &Self) -> String {
         v.as_ref()
@@ -387,9 +413,9 @@ impl Into<Field> for Column {
     }
 }
 
-impl fmt::Display for Column {
+impl fmt::Display for ColumnType {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        let column_type = match &self.column_type {
+        let column_type = match self {
             ColumnType::String => "STRING".to_string(),
             ColumnType::Int => "INT".to_string(),
             ColumnType::Timestamp => "TIMESTAMP".to_string(),
@@ -401,13 +427,41 @@ impl fmt::Display for Column {
             ColumnType::HyperLogLog => "HYPERLOGLOG".to_string(),
             ColumnType::Float => "FLOAT".to_string(),
         };
-        f.write_fmt(format_args!("{} {}", self.name, column_type))
+        f.write_str(&column_type)
+    }
+}
+
+impl fmt::Display for Column {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!("{} {}", self.name, self.column_type))
     }
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug, Eq, PartialEq, Hash)]
 pub enum ImportFormat {
     CSV,
+    CSVWithOptions(CsvOptions),
+    Parquet,
+    ArrowIpc,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Eq, PartialEq, Hash)]
+pub struct CsvOptions {
+    pub delimiter: u8,
+    pub has_header: bool,
+    pub null_values: Vec<String>,
+    pub strict: bool,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        CsvOptions {
+            delimiter: b',',
+            has_header: false,
+            null_values: Vec::new(),
+            strict: false,
+        }
+    }
 }
 
 data_frame_from! {
@@ -423,7 +477,9 @@ pub struct Index {
     name: String,
     table_id: u64,
     columns: Vec<Column>,
-    sort_key_size: u64
+    sort_key_size: u64,
+    #[serde(default)]
+    storage_names: Vec<String>
 }
 }
 
@@ -443,8 +499,18 @@ pub struct Partition {
     active: bool,
     main_table_row_count: u64,
     #[serde(default)]
-    last_used: Option<DateTime<Utc>>
+    last_used: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pending_deletes: Vec<PartitionDelete>
+}
 }
+
+/// Rows matching `filter` in the partition file and in chunks up to `max_chunk_id` are removed
+/// by the next compaction of the partition.
+#[derive(Clone, Serialize, Deserialize, Debug, Eq, PartialEq)]
+pub struct PartitionDelete {
+    pub filter: RowFilter,
+    pub max_chunk_id: u64,
 }
 
 data_frame_from! {
@@ -464,6 +530,17 @@ pub struct WAL {
     table_id: u64,
     row_count: u64,
     uploaded: bool,
+    #[serde(default)]
+    columnar: bool,
+    #[serde(default)]
+    written: bool,
+}
+
+/// Remote file which is deleted after queries which could read it are finished.
+#[derive(Clone, Serialize, Deserialize, Debug, Eq, PartialEq, Hash)]
+pub struct FileDeletion {
+    remote_path: String,
+    deadline: DateTime<Utc>,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug, Eq, PartialEq)]
@@ -591,7 +668,7 @@ meta_store_table_impl!(TableMetaStoreTable, Table, TableRocksTable);
 #[async_trait]
 pub trait MetaStore: Send + Sync {
     async fn wait_for_current_seq_to_sync(&self) -> Result<(), CubeError>;
-    fn schemas_table(&self) -> SchemaMetaStoreTable;
+    fn schemas_table(&self) -> Box<dyn MetaStoreTable<T = Schema>>;
     async fn create_schema(
         &self,
         schema_name: String,
@@ -616,13 +693,13 @@ pub trait MetaStore: Send + Sync {
     async fn delete_schema(&self, schema_name: String) -> Result<(), CubeError>;
     async fn delete_schema_by_id(&self, schema_id: u64) -> Result<(), CubeError>;
 
-    fn tables_table(&self) -> TableMetaStoreTable;
+    fn tables_table(&self) -> Box<dyn MetaStoreTable<T = Table>>;
     async fn create_table(
         &self,
         schema_name: String,
         table_name: String,
         columns: Vec<Column>,
-        location: Option<String>,
+        locations: Option<Vec<String>>,
         import_format: Option<ImportFormat>,
         indexes: Vec<IndexDef>,
     ) -> Result<IdRow<Table>, CubeError>;
@@ -635,8 +712,39 @@ pub trait MetaStore: Send + Sync {
     async fn get_tables(&self) -> Result<Vec<IdRow<Table>>, CubeError>;
     async fn get_tables_with_path(&self) -> Result<Vec<TablePath>, CubeError>;
     async fn drop_table(&self, table_id: u64) -> Result<IdRow<Table>, CubeError>;
+    async fn alter_table_add_column(
+        &self,
+        table_id: u64,
+        column_name: String,
+        column_type: ColumnType,
+    ) -> Result<IdRow<Table>, CubeError>;
+    async fn alter_table_drop_column(
+        &self,
+        table_id: u64,
+        column_name: String,
+    ) -> Result<IdRow<Table>, CubeError>;
+    async fn alter_table_rename_column(
+        &self,
+        table_id: u64,
+        old_column_name: String,
+        new_column_name: String,
+    ) -> Result<IdRow<Table>, CubeError>;
+    async fn rename_table(
+        &self,
+        table_id: u64,
+        schema_name: String,
+        table_name: String,
+    ) -> Result<IdRow<Table>, CubeError>;
+    /// Drops a table named `schema_name.table_name` if any and renames the table to this name
+    /// within the same write.
+    async fn replace_table(
+        &self,
+        table_id: u64,
+        schema_name: String,
+        table_name: String,
+    ) -> Result<IdRow<Table>, CubeError>;
 
-    fn partition_table(&self) -> PartitionMetaStoreTable;
+    fn partition_table(&self) -> Box<dyn MetaStoreTable<T = Partition>>;
     async fn create_partition(&self, partition: Partition) -> Result<IdRow<Partition>, CubeError>;
     async fn get_partition(&self, partition_id: u64) -> Result<IdRow<Partition>, CubeError>;
     async fn get_partition_for_compaction(
@@ -650,10 +758,27 @@ pub trait MetaStore: Send + Sync {
         new_active: Vec<u64>,
         compacted_chunk_ids: Vec<u64>,
         new_active_min_max: Vec<(u64, (Option<Row>, Option<Row>))>,
+        deleted_row_count: u64,
+        applied_delete_count: usize,
     ) -> Result<(), CubeError>;
     async fn is_partition_used(&self, partition_id: u64) -> Result<bool, CubeError>;
+    /// Records a delete of rows matching `filter` on partitions of the index which can contain
+    /// them, inactive ones included while they have chunks to repartition. Partitions and chunks
+    /// which match as a whole are deactivated right away, an empty partition takes the place of
+    /// an active one. Returns tagged and new partitions.
+    async fn add_partition_delete(
+        &self,
+        index_id: u64,
+        filter: RowFilter,
+    ) -> Result<Vec<IdRow<Partition>>, CubeError>;
+    /// Active partitions of the index with deletes to compact and inactive ones with chunks which
+    /// should be repartitioned to apply deletes.
+    async fn get_partitions_with_pending_deletes(
+        &self,
+        index_id: u64,
+    ) -> Result<Vec<IdRow<Partition>>, CubeError>;
 
-    fn index_table(&self) -> IndexMetaStoreTable;
+    fn index_table(&self) -> Box<dyn MetaStoreTable<T = Index>>;
     async fn create_index(
         &self,
         schema_name: String,
@@ -672,7 +797,7 @@ pub trait MetaStore: Send + Sync {
         index_id: u64,
     ) -> Result<Vec<(IdRow<Partition>, Vec<IdRow<Chunk>>)>, CubeError>;
 
-    fn chunks_table(&self) -> ChunkMetaStoreTable;
+    fn chunks_table(&self) -> Box<dyn MetaStoreTable<T = Chunk>>;
     async fn create_chunk(
         &self,
         partition_id: u64,
@@ -690,6 +815,7 @@ pub trait MetaStore: Send + Sync {
         &self,
         deactivate_ids: Vec<u64>,
         uploaded_ids: Vec<u64>,
+        deleted_row_count: u64,
     ) -> Result<(), CubeError>;
     async fn activate_wal(
         &self,
@@ -703,11 +829,27 @@ pub trait MetaStore: Send + Sync {
     async fn create_wal(&self, table_id: u64, row_count: usize) -> Result<IdRow<WAL>, CubeError>;
     async fn get_wal(&self, wal_id: u64) -> Result<IdRow<WAL>, CubeError>;
     async fn delete_wal(&self, wal_id: u64) -> Result<(), CubeError>;
+    async fn wal_written(&self, wal_id: u64) -> Result<IdRow<WAL>, CubeError>;
     async fn wal_uploaded(&self, wal_id: u64) -> Result<IdRow<WAL>, CubeError>;
     async fn get_wals_for_table(&self, table_id: u64) -> Result<Vec<IdRow<WAL>>, CubeError>;
+    async fn get_all_wals(&self) -> Result<Vec<IdRow<WAL>>, CubeError>;
+
+    async fn add_file_deletion(
+        &self,
+        remote_path: String,
+        deadline: DateTime<Utc>,
+    ) -> Result<IdRow<FileDeletion>, CubeError>;
+    async fn get_file_deletions(&self) -> Result<Vec<IdRow<FileDeletion>>, CubeError>;
+    async fn delete_file_deletion(&self, id: u64) -> Result<(), CubeError>;
 
     async fn add_job(&self, job: Job) -> Result<Option<IdRow<Job>>, CubeError>;
     async fn get_job(&self, job_id: u64) -> Result<IdRow<Job>, CubeError>;
+    async fn get_all_jobs(&self) -> Result<Vec<IdRow<Job>>, CubeError>;
+    async fn get_job_by_ref(
+        &self,
+        row_reference: RowKey,
+        job_type: JobType,
+    ) -> Result<Option<IdRow<Job>>, CubeError>;
     async fn delete_job(&self, job_id: u64) -> Result<IdRow<Job>, CubeError>;
     async fn start_processing_job(
         &self,
@@ -715,6 +857,11 @@ pub trait MetaStore: Send + Sync {
     ) -> Result<Option<IdRow<Job>>, CubeError>;
     async fn update_status(&self, job_id: u64, status: JobStatus) -> Result<IdRow<Job>, CubeError>;
     async fn update_heart_beat(&self, job_id: u64) -> Result<IdRow<Job>, CubeError>;
+    async fn update_job_progress(
+        &self,
+        job_id: u64,
+        progress: JobProgress,
+    ) -> Result<IdRow<Job>, CubeError>;
 }
 
 #[derive(Clone, Debug)]
@@ -729,6 +876,7 @@ pub enum MetaStoreEvent {
     DeleteSchema(IdRow<Schema>),
     DeleteTable(IdRow<Table>),
     DeleteWal(IdRow<WAL>),
+    DeleteFileDeletion(IdRow<FileDeletion>),
 }
 
 type SecondaryKey = Vec<u8>;
@@ -835,7 +983,8 @@ enum_from_primitive! {
         Partitions = 0x0400,
         Chunks = 0x0500,
         WALs = 0x0600,
-        Jobs = 0x0700
+        Jobs = 0x0700,
+        FileDeletions = 0x0800
     }
 }
 
@@ -1853,6 +2002,98 @@ impl RocksMetaStore {
 }
 
 impl RocksMetaStore {
+    fn delete_table(
+        db_ref: DbTableRef,
+        batch_pipe: &mut BatchPipe,
+        table_id: u64,
+    ) -> Result<IdRow<Table>, CubeError> {
+        let tables_table = TableRocksTable::new(db_ref.clone());
+        let indexes_table = IndexRocksTable::new(db_ref.clone());
+        let partitions_table = PartitionRocksTable::new(db_ref.clone());
+        let chunks_table = ChunkRocksTable::new(db_ref);
+
+        let indexes = indexes_table
+            .get_rows_by_index(&IndexIndexKey::TableId(table_id), &IndexRocksIndex::TableID)?;
+        for index in indexes.into_iter() {
+            let partitions = partitions_table.get_rows_by_index(
+                &PartitionIndexKey::ByIndexId(index.get_id()),
+                &PartitionRocksIndex::IndexId,
+            )?;
+            for partition in partitions.into_iter() {
+                let chunks = chunks_table.get_rows_by_index(
+                    &ChunkIndexKey::ByPartitionId(partition.get_id()),
+                    &ChunkRocksIndex::PartitionId,
+                )?;
+                for chunk in chunks.into_iter() {
+                    chunks_table.delete(chunk.get_id(), batch_pipe)?;
+                }
+                partitions_table.delete(partition.get_id(), batch_pipe)?;
+            }
+            indexes_table.delete(index.get_id(), batch_pipe)?;
+        }
+        Ok(tables_table.delete(table_id, batch_pipe)?)
+    }
+
+    fn rename_table_row(
+        db_ref: DbTableRef,
+        batch_pipe: &mut BatchPipe,
+        table_id: u64,
+        schema_name: String,
+        table_name: String,
+        replace: bool,
+    ) -> Result<IdRow<Table>, CubeError> {
+        let tables_table = TableRocksTable::new(db_ref.clone());
+        let schemas_table = SchemaRocksTable::new(db_ref.clone());
+        let schema =
+            schemas_table.get_single_row_by_index(&schema_name, &SchemaRocksIndex::Name)?;
+        let existing_tables = tables_table.get_rows_by_index(
+            &TableIndexKey::ByName(schema.get_id(), table_name.to_string()),
+            &TableRocksIndex::Name,
+        )?;
+        for existing in existing_tables.into_iter() {
+            if existing.get_id() == table_id {
+                continue;
+            }
+            if !replace {
+                return Err(CubeError::user(format!(
+                    "Table {}.{} already exists",
+                    schema_name, table_name
+                )));
+            }
+            RocksMetaStore::delete_table(db_ref.clone(), batch_pipe, existing.get_id())?;
+        }
+        let table = tables_table.get_row_or_not_found(table_id)?;
+        tables_table.update(
+            table_id,
+            table.get_row().rename(schema.get_id(), table_name),
+            table.get_row(),
+            batch_pipe,
+        )
+    }
+
+    fn alter_table_columns(
+        db_ref: DbTableRef,
+        batch_pipe: &mut BatchPipe,
+        table_id: u64,
+        alter: impl FnOnce(&Table) -> Result<Table, CubeError>,
+    ) -> Result<IdRow<Table>, CubeError> {
+        let tables_table = TableRocksTable::new(db_ref.clone());
+        let indexes_table = IndexRocksTable::new(db_ref);
+        let table = tables_table.get_row_or_not_found(table_id)?;
+        let new_table = alter(table.get_row())?;
+        let indexes = indexes_table
+            .get_rows_by_index(&IndexIndexKey::TableId(table_id), &IndexRocksIndex::TableID)?;
+        for index in indexes.into_iter() {
+            indexes_table.update(
+                index.get_id(),
+                index.get_row().update_columns(&new_table),
+                index.get_row(),
+                batch_pipe,
+            )?;
+        }
+        tables_table.update(table_id, new_table, table.get_row(), batch_pipe)
+    }
+
     fn add_index(
         batch_pipe: &mut BatchPipe,
         rocks_index: &IndexRocksTable,
@@ -1893,6 +2134,11 @@ impl RocksMetaStore {
                 .collect::<Vec<_>>(),
             sorted_key_size,
         )?;
+        let index = if table_id.get_row().has_evolved_schema() {
+            index.update_columns(table_id.get_row())
+        } else {
+            index
+        };
         let index_id = rocks_index.insert(index, batch_pipe)?;
         let partition = Partition::new(index_id.id, None, None);
         let _ = rocks_partition.insert(partition, batch_pipe)?;
@@ -1912,6 +2158,29 @@ impl RocksMetaStore {
         Ok(table)
     }
 
+    fn has_non_repartitioned_parent_chunks(
+        partition: &IdRow<Partition>,
+        table: &ChunkRocksTable,
+        partition_table: &PartitionRocksTable,
+    ) -> Result<bool, CubeError> {
+        let mut current_partition = partition.clone();
+        while let Some(parent_id) = current_partition.get_row().parent_partition_id() {
+            let parent = partition_table.get_row_or_not_found(*parent_id)?;
+            if table
+                .get_rows_by_index(
+                    &ChunkIndexKey::ByPartitionId(parent.get_id()),
+                    &ChunkRocksIndex::PartitionId,
+                )?
+                .iter()
+                .any(|c| c.get_row().active() || !c.get_row().uploaded())
+            {
+                return Ok(true);
+            }
+            current_partition = parent;
+        }
+        Ok(false)
+    }
+
     fn chunks_by_partitioned_with_non_repartitioned(
         partition_id: u64,
         table: &ChunkRocksTable,
@@ -1957,10 +2226,10 @@ impl MetaStore for RocksMetaStore {
         Ok(())
     }
 
-    fn schemas_table(&self) -> SchemaMetaStoreTable {
-        SchemaMetaStoreTable {
+    fn schemas_table(&self) -> Box<dyn MetaStoreTable<T = Schema>> {
+        Box::new(SchemaMetaStoreTable {
             rocks_meta_store: self.clone(),
-        }
+        })
     }
 
     async fn create_schema(
@@ -2081,10 +2350,10 @@ impl MetaStore for RocksMetaStore {
         .await
     }
 
-    fn tables_table(&self) -> TableMetaStoreTable {
-        TableMetaStoreTable {
+    fn tables_table(&self) -> Box<dyn MetaStoreTable<T = Table>> {
+        Box::new(TableMetaStoreTable {
             rocks_meta_store: self.clone(),
-        }
+        })
     }
 
     async fn create_table(
@@ -2092,7 +2361,7 @@ impl MetaStore for RocksMetaStore {
         schema_name: String,
         table_name: String,
         columns: Vec<Column>,
-        location: Option<String>,
+        locations: Option<Vec<String>>,
         import_format: Option<ImportFormat>,
         indexes: Vec<IndexDef>,
     ) -> Result<IdRow<Table>, CubeError> {
@@ -2109,7 +2378,7 @@ impl MetaStore for RocksMetaStore {
                 table_name,
                 schema_id.get_id(),
                 columns,
-                location,
+                locations,
                 import_format,
             );
             let table_id = rocks_table.insert(table, batch_pipe)?;
@@ -2181,13 +2450,23 @@ impl MetaStore for RocksMetaStore {
     }
 
     async fn get_tables(&self) -> Result<Vec<IdRow<Table>>, CubeError> {
-        self.read_operation(|db_ref| TableRocksTable::new(db_ref).all_rows())
-            .await
+        self.read_operation(|db_ref| {
+            Ok(TableRocksTable::new(db_ref)
+                .all_rows()?
+                .into_iter()
+                .filter(|t| !t.get_row().is_replacement())
+                .collect())
+        })
+        .await
     }
 
     async fn get_tables_with_path(&self) -> Result<Vec<TablePath>, CubeError> {
         self.read_operation(|db_ref| {
-            let tables = TableRocksTable::new(db_ref.clone()).all_rows()?;
+            let tables: Vec<IdRow<Table>> = TableRocksTable::new(db_ref.clone())
+                .all_rows()?
+                .into_iter()
+                .filter(|t| !t.get_row().is_replacement())
+                .collect();
             let schemas = SchemaRocksTable::new(db_ref);
             Ok(schemas.build_path_rows(
                 tables,
@@ -2200,39 +2479,94 @@ impl MetaStore for RocksMetaStore {
 
     async fn drop_table(&self, table_id: u64) -> Result<IdRow<Table>, CubeError> {
         self.write_operation(move |db_ref, batch_pipe| {
-            let tables_table = TableRocksTable::new(db_ref.clone());
-            let indexes_table = IndexRocksTable::new(db_ref.clone());
-            let partitions_table = PartitionRocksTable::new(db_ref.clone());
-            let chunks_table = ChunkRocksTable::new(db_ref);
-
-            let indexes = indexes_table
-                .get_rows_by_index(&IndexIndexKey::TableId(table_id), &IndexRocksIndex::TableID)?;
-            for index in indexes.into_iter() {
-                let partitions = partitions_table.get_rows_by_index(
-                    &PartitionIndexKey::ByIndexId(index.get_id()),
-                    &PartitionRocksIndex::IndexId,
-                )?;
-                for partition in partitions.into_iter() {
-                    let chunks = chunks_table.get_rows_by_index(
-                        &ChunkIndexKey::ByPartitionId(partition.get_id()),
-                        &ChunkRocksIndex::PartitionId,
-                    )?;
-                    for chunk in chunks.into_iter() {
-                        chunks_table.delete(chunk.get_id(), batch_pipe)?;
-                    }
-                    partitions_table.delete(partition.get_id(), batch_pipe)?;
-                }
-                indexes_table.delete(index.get_id(), batch_pipe)?;
-            }
-            Ok(tables_table.delete(table_id, batch_pipe)?)
+            RocksMetaStore::delete_table(db_ref, batch_pipe, table_id)
+        })
+        .await
+    }
+
+    async fn alter_table_add_column(
+        &self,
+        table_id: u64,
+        column_name: String,
+        column_type: ColumnType,
+    ) -> Result<IdRow<Table>, CubeError> {
+        self.write_operation(move |db_ref, batch_pipe| {
+            RocksMetaStore::alter_table_columns(db_ref, batch_pipe, table_id, |t| {
+                t.add_column(column_name, column_type)
+            })
+        })
+        .await
+    }
+
+    async fn alter_table_drop_column(
+        &self,
+        table_id: u64,
+        column_name: String,
+    ) -> Result<IdRow<Table>, CubeError> {
+        self.write_operation(move |db_ref, batch_pipe| {
+            RocksMetaStore::alter_table_columns(db_ref, batch_pipe, table_id, |t| {
+                t.drop_column(&column_name)
+            })
+        })
+        .await
+    }
+
+    async fn alter_table_rename_column(
+        &self,
+        table_id: u64,
+        old_column_name: String,
+        new_column_name: String,
+    ) -> Result<IdRow<Table>, CubeError> {
+        self.write_operation(move |db_ref, batch_pipe| {
+            RocksMetaStore::alter_table_columns(db_ref, batch_pipe, table_id, |t| {
+                t.rename_column(&old_column_name, new_column_name)
+            })
+        })
+        .await
+    }
+
+    async fn rename_table(
+        &self,
+        table_id: u64,
+        schema_name: String,
+        table_name: String,
+    ) -> Result<IdRow<Table>, CubeError> {
+        self.write_operation(move |db_ref, batch_pipe| {
+            RocksMetaStore::rename_table_row(
+                db_ref,
+                batch_pipe,
+                table_id,
+                schema_name,
+                table_name,
+                false,
+            )
+        })
+        .await
+    }
+
+    async fn replace_table(
+        &self,
+        table_id: u64,
+        schema_name: String,
+        table_name: String,
+    ) -> Result<IdRow<Table>, CubeError> {
+        self.write_operation(move |db_ref, batch_pipe| {
+            RocksMetaStore::rename_table_row(
+                db_ref,
+                batch_pipe,
+                table_id,
+                schema_name,
+                table_name,
+                true,
+            )
         })
         .await
     }
 
-    fn partition_table(&self) -> PartitionMetaStoreTable {
-        PartitionMetaStoreTable {
+    fn partition_table(&self) -> Box<dyn MetaStoreTable<T = Partition>> {
+        Box::new(PartitionMetaStoreTable {
             rocks_meta_store: self.clone(),
-        }
+        })
     }
 
     async fn create_partition(&self, partition: Partition) -> Result<IdRow<Partition>, CubeError> {
@@ -2291,12 +2625,15 @@ impl MetaStore for RocksMetaStore {
         new_active: Vec<u64>,
         compacted_chunk_ids: Vec<u64>,
         new_active_min_max: Vec<(u64, (Option<Row>, Option<Row>))>,
+        deleted_row_count: u64,
+        applied_delete_count: usize,
     ) -> Result<(), CubeError> {
         trace!(
-            "Swapping partitions: deactivating ({}), deactivating chunks ({}), activating ({})",
+            "Swapping partitions: deactivating ({}), deactivating chunks ({}), activating ({}), deleting {} rows",
             current_active.iter().join(", "),
             compacted_chunk_ids.iter().join(", "),
-            new_active.iter().join(", ")
+            new_active.iter().join(", "),
+            deleted_row_count
         );
         self.write_operation(move |db_ref, batch_pipe| {
             let table = PartitionRocksTable::new(db_ref.clone());
@@ -2304,6 +2641,8 @@ impl MetaStore for RocksMetaStore {
 
             let mut deactivated_row_count = 0;
             let mut activated_row_count = 0;
+            // Deletes added during compaction are applied to new partitions by their compaction.
+            let mut not_applied_deletes = Vec::new();
 
             for current in current_active.iter() {
                 let current_partition =
@@ -2326,7 +2665,15 @@ impl MetaStore for RocksMetaStore {
                     current_partition.get_row(),
                     batch_pipe,
                 )?;
-                deactivated_row_count += current_partition.get_row().main_table_row_count()
+                deactivated_row_count += current_partition.get_row().main_table_row_count();
+                not_applied_deletes.extend(
+                    current_partition
+                        .get_row()
+                        .pending_deletes()
+                        .iter()
+                        .skip(applied_delete_count)
+                        .cloned(),
+                );
             }
 
             for (new, (count, (min_value, max_value))) in
@@ -2347,7 +2694,8 @@ impl MetaStore for RocksMetaStore {
                     new_partition
                         .get_row()
                         .to_active(true)
-                        .update_min_max_and_row_count(min_value, max_value, count),
+                        .update_min_max_and_row_count(min_value, max_value, count)
+                        .set_pending_deletes(not_applied_deletes.clone()),
                     new_partition.get_row(),
                     batch_pipe,
                 )?;
@@ -2359,11 +2707,12 @@ impl MetaStore for RocksMetaStore {
                 chunk_table.update_with_fn(*chunk_id, |row| row.deactivate(), batch_pipe)?;
             }
 
-            if activated_row_count != deactivated_row_count {
+            if activated_row_count + deleted_row_count != deactivated_row_count {
                 return Err(CubeError::internal(format!(
-                    "Deactivated row count ({}) doesn't match activated row count ({}) during swap of partition ({}) and ({}) chunks to new partitions ({})",
+                    "Deactivated row count ({}) doesn't match activated ({}) and deleted ({}) row count during swap of partition ({}) and ({}) chunks to new partitions ({})",
                     deactivated_row_count,
                     activated_row_count,
+                    deleted_row_count,
                     current_active.iter().join(", "),
                     compacted_chunk_ids.iter().join(", "),
                     new_active.iter().join(", ")
@@ -2385,10 +2734,141 @@ impl MetaStore for RocksMetaStore {
         .await
     }
 
-    fn index_table(&self) -> IndexMetaStoreTable {
-        IndexMetaStoreTable {
+    async fn add_partition_delete(
+        &self,
+        index_id: u64,
+        filter: RowFilter,
+    ) -> Result<Vec<IdRow<Partition>>, CubeError> {
+        self.write_operation(move |db_ref, batch_pipe| {
+            let index = IndexRocksTable::new(db_ref.clone()).get_row_or_not_found(index_id)?;
+            let partition_filter = filter.partition_filter(index.get_row());
+            let table = PartitionRocksTable::new(db_ref.clone());
+            let chunk_table = ChunkRocksTable::new(db_ref);
+            let partitions = table
+                .get_rows_by_index(
+                    &PartitionIndexKey::ByIndexId(index_id),
+                    &PartitionRocksIndex::IndexId,
+                )?
+                .into_iter()
+                .filter(|p| {
+                    partition_filter.can_match(p.get_row().get_min_val(), p.get_row().get_max_val())
+                })
+                .collect::<Vec<_>>();
+            let mut result = Vec::new();
+            for partition in partitions {
+                let row = partition.get_row();
+                let chunks = chunk_table.get_rows_by_index(
+                    &ChunkIndexKey::ByPartitionId(partition.get_id()),
+                    &ChunkRocksIndex::PartitionId,
+                )?;
+                let active_chunk_ids = chunks
+                    .iter()
+                    .filter(|c| c.get_row().uploaded() && c.get_row().active())
+                    .map(|c| c.get_id())
+                    .collect::<Vec<_>>();
+                // Chunks of WALs being activated become active later.
+                let has_pending_chunks = chunks.iter().any(|c| !c.get_row().uploaded());
+                if row.is_active() {
+                    if row.main_table_row_count() == 0 && chunks.is_empty() {
+                        continue;
+                    }
+                } else if active_chunk_ids.is_empty() && !has_pending_chunks {
+                    continue;
+                }
+                // Queries of the replacement partition wouldn't see chunks of parents.
+                let deactivate = !has_pending_chunks
+                    && filter.matches_partition(
+                        index.get_row(),
+                        row.get_min_val(),
+                        row.get_max_val(),
+                    )
+                    && (!row.is_active()
+                        || !RocksMetaStore::has_non_repartitioned_parent_chunks(
+                            &partition,
+                            &chunk_table,
+                            &table,
+                        )?);
+                if deactivate {
+                    for chunk_id in active_chunk_ids {
+                        chunk_table.update_with_fn(chunk_id, |c| c.deactivate(), batch_pipe)?;
+                    }
+                    if row.is_active() {
+                        table.update(partition.get_id(), row.to_active(false), row, batch_pipe)?;
+                        result.push(table.insert(
+                            Partition::new(
+                                index_id,
+                                row.get_min_val().clone(),
+                                row.get_max_val().clone(),
+                            ),
+                            batch_pipe,
+                        )?);
+                    }
+                    continue;
+                }
+                // Chunks created later hold rows inserted after the delete
+                let max_chunk_id = chunks.iter().map(|c| c.get_id()).max().unwrap_or(0);
+                result.push(table.update(
+                    partition.get_id(),
+                    row.add_pending_delete(PartitionDelete {
+                        filter: filter.clone(),
+                        max_chunk_id,
+                    }),
+                    row,
+                    batch_pipe,
+                )?);
+            }
+            Ok(result)
+        })
+        .await
+    }
+
+    async fn get_partitions_with_pending_deletes(
+        &self,
+        index_id: u64,
+    ) -> Result<Vec<IdRow<Partition>>, CubeError> {
+        self.read_operation(move |db_ref| {
+            let table = PartitionRocksTable::new(db_ref.clone());
+            let chunk_table = ChunkRocksTable::new(db_ref);
+            let mut result = Vec::new();
+            let partitions = table.get_rows_by_index(
+                &PartitionIndexKey::ByIndexId(index_id),
+                &PartitionRocksIndex::IndexId,
+            )?;
+            for partition in partitions {
+                let max_chunk_id = partition
+                    .get_row()
+                    .pending_deletes()
+                    .iter()
+                    .map(|d| d.max_chunk_id)
+                    .max();
+                let pending = match max_chunk_id {
+                    None => false,
+                    Some(_) if partition.get_row().is_active() => true,
+                    Some(max_chunk_id) => chunk_table
+                        .get_rows_by_index(
+                            &ChunkIndexKey::ByPartitionId(partition.get_id()),
+                            &ChunkRocksIndex::PartitionId,
+                        )?
+                        .iter()
+                        .any(|c| {
+                            c.get_row().uploaded()
+                                && c.get_row().active()
+                                && c.get_id() <= max_chunk_id
+                        }),
+                };
+                if pending {
+                    result.push(partition);
+                }
+            }
+            Ok(result)
+        })
+        .await
+    }
+
+    fn index_table(&self) -> Box<dyn MetaStoreTable<T = Index>> {
+        Box::new(IndexMetaStoreTable {
             rocks_meta_store: self.clone(),
-        }
+        })
     }
 
     async fn create_index(
@@ -2633,6 +3113,7 @@ impl MetaStore for RocksMetaStore {
         &self,
         deactivate_ids: Vec<u64>,
         uploaded_ids: Vec<u64>,
+        deleted_row_count: u64,
     ) -> Result<(), CubeError> {
         trace!(
             "Swapping chunks: deactivating ({}), activating ({})",
@@ -2651,11 +3132,12 @@ impl MetaStore for RocksMetaStore {
                 activated_row_count += table.get_row_or_not_found(*id)?.get_row().get_row_count();
                 table.update_with_fn(*id, |row| row.set_uploaded(true), batch_pipe)?;
             }
-            if deactivate_ids.len() > 0 && activated_row_count != deactivated_row_count {
+            if deactivate_ids.len() > 0 && activated_row_count + deleted_row_count != deactivated_row_count {
                 return Err(CubeError::internal(format!(
-                    "Deactivated row count ({}) doesn't match activated row count ({}) during swap of ({}) to ({}) chunks",
+                    "Deactivated row count ({}) doesn't match activated ({}) and deleted ({}) row count during swap of ({}) to ({}) chunks",
                     deactivated_row_count,
                     activated_row_count,
+                    deleted_row_count,
                     deactivate_ids.iter().join(", "),
                     uploaded_ids.iter().join(", ")
                 )))
@@ -2699,10 +3181,10 @@ impl MetaStore for RocksMetaStore {
         .await
     }
 
-    fn chunks_table(&self) -> ChunkMetaStoreTable {
-        ChunkMetaStoreTable {
+    fn chunks_table(&self) -> Box<dyn MetaStoreTable<T = Chunk>> {
+        Box::new(ChunkMetaStoreTable {
             rocks_meta_store: self.clone(),
-        }
+        })
     }
 
     async fn create_wal(&self, table_id: u64, row_count: usize) -> Result<IdRow<WAL>, CubeError> {
@@ -2735,6 +3217,36 @@ impl MetaStore for RocksMetaStore {
         .await
     }
 
+    async fn get_all_wals(&self) -> Result<Vec<IdRow<WAL>>, CubeError> {
+        self.read_operation(|db_ref| WALRocksTable::new(db_ref).all_rows())
+            .await
+    }
+
+    async fn add_file_deletion(
+        &self,
+        remote_path: String,
+        deadline: DateTime<Utc>,
+    ) -> Result<IdRow<FileDeletion>, CubeError> {
+        self.write_operation(move |db_ref, batch_pipe| {
+            FileDeletionRocksTable::new(db_ref)
+                .insert(FileDeletion::new(remote_path, deadline), batch_pipe)
+        })
+        .await
+    }
+
+    async fn get_file_deletions(&self) -> Result<Vec<IdRow<FileDeletion>>, CubeError> {
+        self.read_operation(|db_ref| FileDeletionRocksTable::new(db_ref).all_rows())
+            .await
+    }
+
+    async fn delete_file_deletion(&self, id: u64) -> Result<(), CubeError> {
+        self.write_operation(move |db_ref, batch_pipe| {
+            FileDeletionRocksTable::new(db_ref).delete(id, batch_pipe)?;
+            Ok(())
+        })
+        .await
+    }
+
     async fn delete_wal(&self, wal_id: u64) -> Result<(), CubeError> {
         self.write_operation(move |db_ref, batch_pipe| {
             WALRocksTable::new(db_ref.clone()).delete(wal_id, batch_pipe)?;
@@ -2743,6 +3255,22 @@ impl MetaStore for RocksMetaStore {
         .await
     }
 
+    async fn wal_written(&self, wal_id: u64) -> Result<IdRow<WAL>, CubeError> {
+        self.write_operation(move |db_ref, batch_pipe| {
+            let table = WALRocksTable::new(db_ref.clone());
+            let row = table.get_row_or_not_found(wal_id)?;
+            let id_row = table.update(
+                wal_id,
+                row.get_row().set_written(true),
+                row.get_row(),
+                batch_pipe,
+            )?;
+
+            Ok(id_row)
+        })
+        .await
+    }
+
     async fn wal_uploaded(&self, wal_id: u64) -> Result<IdRow<WAL>, CubeError> {
         self.write_operation(move |db_ref, batch_pipe| {
             let table = WALRocksTable::new(db_ref.clone());
@@ -2785,6 +3313,28 @@ impl MetaStore for RocksMetaStore {
         .await
     }
 
+    async fn get_all_jobs(&self) -> Result<Vec<IdRow<Job>>, CubeError> {
+        self.read_operation(|db_ref| JobRocksTable::new(db_ref).all_rows())
+            .await
+    }
+
+    async fn get_job_by_ref(
+        &self,
+        row_reference: RowKey,
+        job_type: JobType,
+    ) -> Result<Option<IdRow<Job>>, CubeError> {
+        self.read_operation(move |db_ref| {
+            Ok(JobRocksTable::new(db_ref)
+                .get_rows_by_index(
+                    &JobIndexKey::RowReference(row_reference, job_type),
+                    &JobRocksIndex::RowReference,
+                )?
+                .into_iter()
+                .nth(0))
+        })
+        .await
+    }
+
     async fn delete_job(&self, job_id: u64) -> Result<IdRow<Job>, CubeError> {
         self.write_operation(move |db_ref, batch_pipe| {
             Ok(JobRocksTable::new(db_ref.clone()).delete(job_id, batch_pipe)?)
@@ -2835,6 +3385,21 @@ impl MetaStore for RocksMetaStore {
         .await
     }
 
+    async fn update_job_progress(
+        &self,
+        job_id: u64,
+        progress: JobProgress,
+    ) -> Result<IdRow<Job>, CubeError> {
+        self.write_operation(move |db_ref, batch_pipe| {
+            Ok(JobRocksTable::new(db_ref).update_with_fn(
+                job_id,
+                |row| row.update_progress(progress),
+                batch_pipe,
+            )?)
+        })
+        .await
+    }
+
     async fn update_status(&self, job_id: u64, status: JobStatus) -> Result<IdRow<Job>, CubeError> {
         self.write_operation(move |db_ref, batch_pipe| {
             Ok(JobRocksTable::new(db_ref).update_with_fn(
@@ -3182,32 +3747,50 @@ mod tests {
 
         {
             {
-                let services = config.configure().await;
+                let services = config.configure().await.unwrap();
                 services.start_processing_loops().await.unwrap();
                 services
                     .meta_store
                     .create_schema("foo1".to_string(), false)
                     .await
                     .unwrap();
-                services.meta_store.run_upload().await.unwrap();
+                services
+                    .rocks_meta_store
+                    .as_ref()
+                    .unwrap()
+                    .run_upload()
+                    .await
+                    .unwrap();
                 services
                     .meta_store
                     .create_schema("foo".to_string(), false)
                     .await
                     .unwrap();
-                services.meta_store.upload_check_point().await.unwrap();
+                services
+                    .rocks_meta_store
+                    .as_ref()
+                    .unwrap()
+                    .upload_check_point()
+                    .await
+                    .unwrap();
                 services
                     .meta_store
                     .create_schema("bar".to_string(), false)
                     .await
                     .unwrap();
-                services.meta_store.run_upload().await.unwrap();
+                services
+                    .rocks_meta_store
+                    .as_ref()
+                    .unwrap()
+                    .run_upload()
+                    .await
+                    .unwrap();
                 services.stop_processing_loops().await.unwrap();
             }
             tokio::time::delay_for(Duration::from_millis(1000)).await; // TODO logger init conflict
             fs::remove_dir_all(config.local_dir()).unwrap();
 
-            let services2 = config.configure().await;
+            let services2 = config.configure().await.unwrap();
             services2
                 .meta_store
                 .get_schema("foo1".to_string())
@@ -3229,6 +3812,57 @@ mod tests {
         fs::remove_dir_all(config.remote_dir()).unwrap();
     }
 
+    #[tokio::test]
+    async fn job_progress() {
+        let config = Config::test("job_progress");
+        let store_path = env::current_dir().unwrap().join("job_progress-local");
+        let remote_store_path = env::current_dir().unwrap().join("job_progress-remote");
+        let _ = fs::remove_dir_all(store_path.clone());
+        let _ = fs::remove_dir_all(remote_store_path.clone());
+        let remote_fs = LocalDirRemoteFs::new(store_path.clone(), remote_store_path.clone());
+        {
+            let meta_store = RocksMetaStore::new(
+                store_path.join("metastore").as_path(),
+                remote_fs,
+                config.config_obj(),
+            );
+
+            let row_key = RowKey::Table(TableId::Tables, 1);
+            let job = meta_store
+                .add_job(Job::new(
+                    row_key.clone(),
+                    JobType::TableImport,
+                    "localhost".to_string(),
+                ))
+                .await
+                .unwrap()
+                .unwrap();
+            let progress = JobProgress {
+                rows: 10,
+                bytes: 100,
+            };
+            meta_store
+                .update_job_progress(job.get_id(), progress.clone())
+                .await
+                .unwrap();
+            meta_store.update_heart_beat(job.get_id()).await.unwrap();
+
+            let job = meta_store
+                .get_job_by_ref(row_key, JobType::TableImport)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(job.get_row().progress(), &Some(progress));
+            assert!(meta_store
+                .get_job_by_ref(RowKey::Table(TableId::Tables, 2), JobType::TableImport)
+                .await
+                .unwrap()
+                .is_none());
+        }
+        let _ = fs::remove_dir_all(store_path.clone());
+        let _ = fs::remove_dir_all(remote_store_path.clone());
+    }
+
     #[tokio::test]
     async fn discard_logs() {
         let config = Config::test("discard_logs");
@@ -3238,26 +3872,44 @@ mod tests {
 
         {
             {
-                let services = config.configure().await;
+                let services = config.configure().await.unwrap();
                 services.start_processing_loops().await.unwrap();
                 services
                     .meta_store
                     .create_schema("foo1".to_string(), false)
                     .await
                     .unwrap();
-                services.meta_store.run_upload().await.unwrap();
+                services
+                    .rocks_meta_store
+                    .as_ref()
+                    .unwrap()
+                    .run_upload()
+                    .await
+                    .unwrap();
                 services
                     .meta_store
                     .create_schema("foo".to_string(), false)
                     .await
                     .unwrap();
-                services.meta_store.upload_check_point().await.unwrap();
+                services
+                    .rocks_meta_store
+                    .as_ref()
+                    .unwrap()
+                    .upload_check_point()
+                    .await
+                    .unwrap();
                 services
                     .meta_store
                     .create_schema("bar".to_string(), false)
                     .await
                     .unwrap();
-                services.meta_store.run_upload().await.unwrap();
+                services
+                    .rocks_meta_store
+                    .as_ref()
+                    .unwrap()
+                    .run_upload()
+                    .await
+                    .unwrap();
                 services.stop_processing_loops().await.unwrap();
             }
             tokio::time::delay_for(Duration::from_millis(1000)).await; // TODO logger init conflict
@@ -3289,7 +3941,7 @@ mod tests {
             println!("Size {}", file.metadata().unwrap().len());
             file.set_len(50).unwrap();
 
-            let services2 = config.configure().await;
+            let services2 = config.configure().await.unwrap();
             services2
                 .meta_store
                 .get_schema("foo1".to_string())
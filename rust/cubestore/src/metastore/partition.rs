@@ -1,5 +1,6 @@
 use super::{
-    BaseRocksSecondaryIndex, IndexId, Partition, RocksSecondaryIndex, RocksTable, TableId,
+    BaseRocksSecondaryIndex, IndexId, Partition, PartitionDelete, RocksSecondaryIndex, RocksTable,
+    TableId,
 };
 use crate::base_rocks_secondary_index;
 use crate::metastore::{IdRow, MetaStoreEvent};
@@ -21,6 +22,7 @@ impl Partition {
             active: true,
             main_table_row_count: 0,
             last_used: None,
+            pending_deletes: Vec::new(),
         }
     }
 
@@ -33,6 +35,7 @@ impl Partition {
             active: false,
             main_table_row_count: 0,
             last_used: None,
+            pending_deletes: Vec::new(),
         }
     }
 
@@ -58,6 +61,7 @@ impl Partition {
             active,
             main_table_row_count: self.main_table_row_count,
             last_used: self.last_used.clone(),
+            pending_deletes: self.pending_deletes.clone(),
         }
     }
 
@@ -75,9 +79,22 @@ impl Partition {
             active: self.active,
             main_table_row_count,
             last_used: self.last_used.clone(),
+            pending_deletes: self.pending_deletes.clone(),
         }
     }
 
+    pub fn add_pending_delete(&self, delete: PartitionDelete) -> Self {
+        let mut new = self.clone();
+        new.pending_deletes.push(delete);
+        new
+    }
+
+    pub fn set_pending_deletes(&self, pending_deletes: Vec<PartitionDelete>) -> Self {
+        let mut new = self.clone();
+        new.pending_deletes = pending_deletes;
+        new
+    }
+
     pub fn update_last_used(&self) -> Self {
         let mut new = self.clone();
         new.last_used = Some(Utc::now());
@@ -100,6 +117,10 @@ impl Partition {
         self.main_table_row_count
     }
 
+    pub fn pending_deletes(&self) -> &Vec<PartitionDelete> {
+        &self.pending_deletes
+    }
+
     pub fn is_used(&self, timeout: u64) -> bool {
         self.last_used
             .map(|time| Utc::now().sub(time.clone()).num_seconds() < timeout as i64)
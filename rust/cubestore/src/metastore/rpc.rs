@@ -0,0 +1,401 @@
+use crate::cluster::connector::NodeConnector;
+use crate::cluster::message::NetworkMessage;
+use crate::metastore::job::{Job, JobProgress, JobStatus, JobType};
+use crate::metastore::table::{Table, TablePath};
+use crate::metastore::{
+    Chunk, Column, ColumnType, FileDeletion, IdRow, ImportFormat, Index, IndexDef, MetaStore,
+    MetaStoreTable, Partition, RowKey, Schema, TableId, WAL,
+};
+use crate::queryplanner::partition_filter::RowFilter;
+use crate::table::Row;
+use crate::CubeError;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+/// Generates `MetaStoreRpcMethodCall` and `MetaStoreRpcMethodResult` with a variant per
+/// `MetaStore` method and implements `MetaStore` for `MetaStoreRpcClient` by sending them.
+macro_rules! meta_store_rpc {
+    (
+        $( fn $name:ident(&self $(, $arg:ident: $arg_ty:ty)*) -> $res:ty; )*
+    ) => {
+        #[allow(non_camel_case_types)]
+        #[derive(Serialize, Deserialize, Debug)]
+        pub enum MetaStoreRpcMethodCall {
+            Table { table: TableId, call: MetaStoreTableCall },
+            $( $name { $( $arg: $arg_ty ),* }, )*
+        }
+
+        #[allow(non_camel_case_types)]
+        #[derive(Serialize, Deserialize, Debug)]
+        pub enum MetaStoreRpcMethodResult {
+            /// Rows serialized with flexbuffers as their type depends on the table.
+            Table(Result<Vec<u8>, CubeError>),
+            $( $name($res), )*
+        }
+
+        impl MetaStoreRpcMethodCall {
+            pub async fn invoke(self, meta_store: &dyn MetaStore) -> MetaStoreRpcMethodResult {
+                match self {
+                    MetaStoreRpcMethodCall::Table { table, call } => {
+                        MetaStoreRpcMethodResult::Table(call.invoke_on(table, meta_store).await)
+                    }
+                    $(
+                        MetaStoreRpcMethodCall::$name { $( $arg ),* } => {
+                            MetaStoreRpcMethodResult::$name(meta_store.$name($( $arg ),*).await)
+                        }
+                    )*
+                }
+            }
+        }
+
+        #[async_trait]
+        impl MetaStore for MetaStoreRpcClient {
+            fn schemas_table(&self) -> Box<dyn MetaStoreTable<T = Schema>> {
+                self.table(TableId::Schemas)
+            }
+
+            fn tables_table(&self) -> Box<dyn MetaStoreTable<T = Table>> {
+                self.table(TableId::Tables)
+            }
+
+            fn partition_table(&self) -> Box<dyn MetaStoreTable<T = Partition>> {
+                self.table(TableId::Partitions)
+            }
+
+            fn index_table(&self) -> Box<dyn MetaStoreTable<T = Index>> {
+                self.table(TableId::Indexes)
+            }
+
+            fn chunks_table(&self) -> Box<dyn MetaStoreTable<T = Chunk>> {
+                self.table(TableId::Chunks)
+            }
+
+            $(
+                async fn $name(&self $(, $arg: $arg_ty)*) -> $res {
+                    match self.call(MetaStoreRpcMethodCall::$name { $( $arg ),* }).await? {
+                        MetaStoreRpcMethodResult::$name(res) => res,
+                        res => Err(CubeError::internal(format!(
+                            "Unexpected metastore response to {}: {:?}",
+                            stringify!($name),
+                            res
+                        ))),
+                    }
+                }
+            )*
+        }
+    };
+}
+
+meta_store_rpc! {
+    fn wait_for_current_seq_to_sync(&self) -> Result<(), CubeError>;
+    fn create_schema(
+        &self,
+        schema_name: String,
+        if_not_exists: bool
+    ) -> Result<IdRow<Schema>, CubeError>;
+    fn get_schemas(&self) -> Result<Vec<IdRow<Schema>>, CubeError>;
+    fn get_schema_by_id(&self, schema_id: u64) -> Result<IdRow<Schema>, CubeError>;
+    fn get_schema_id(&self, schema_name: String) -> Result<u64, CubeError>;
+    fn get_schema(&self, schema_name: String) -> Result<IdRow<Schema>, CubeError>;
+    fn rename_schema(
+        &self,
+        old_schema_name: String,
+        new_schema_name: String
+    ) -> Result<IdRow<Schema>, CubeError>;
+    fn rename_schema_by_id(
+        &self,
+        schema_id: u64,
+        new_schema_name: String
+    ) -> Result<IdRow<Schema>, CubeError>;
+    fn delete_schema(&self, schema_name: String) -> Result<(), CubeError>;
+    fn delete_schema_by_id(&self, schema_id: u64) -> Result<(), CubeError>;
+    fn create_table(
+        &self,
+        schema_name: String,
+        table_name: String,
+        columns: Vec<Column>,
+        locations: Option<Vec<String>>,
+        import_format: Option<ImportFormat>,
+        indexes: Vec<IndexDef>
+    ) -> Result<IdRow<Table>, CubeError>;
+    fn get_table(
+        &self,
+        schema_name: String,
+        table_name: String
+    ) -> Result<IdRow<Table>, CubeError>;
+    fn get_table_by_id(&self, table_id: u64) -> Result<IdRow<Table>, CubeError>;
+    fn get_tables(&self) -> Result<Vec<IdRow<Table>>, CubeError>;
+    fn get_tables_with_path(&self) -> Result<Vec<TablePath>, CubeError>;
+    fn drop_table(&self, table_id: u64) -> Result<IdRow<Table>, CubeError>;
+    fn alter_table_add_column(
+        &self,
+        table_id: u64,
+        column_name: String,
+        column_type: ColumnType
+    ) -> Result<IdRow<Table>, CubeError>;
+    fn alter_table_drop_column(
+        &self,
+        table_id: u64,
+        column_name: String
+    ) -> Result<IdRow<Table>, CubeError>;
+    fn alter_table_rename_column(
+        &self,
+        table_id: u64,
+        old_column_name: String,
+        new_column_name: String
+    ) -> Result<IdRow<Table>, CubeError>;
+    fn rename_table(
+        &self,
+        table_id: u64,
+        schema_name: String,
+        table_name: String
+    ) -> Result<IdRow<Table>, CubeError>;
+    fn replace_table(
+        &self,
+        table_id: u64,
+        schema_name: String,
+        table_name: String
+    ) -> Result<IdRow<Table>, CubeError>;
+    fn create_partition(&self, partition: Partition) -> Result<IdRow<Partition>, CubeError>;
+    fn get_partition(&self, partition_id: u64) -> Result<IdRow<Partition>, CubeError>;
+    fn get_partition_for_compaction(
+        &self,
+        partition_id: u64
+    ) -> Result<(IdRow<Partition>, IdRow<Index>), CubeError>;
+    fn get_partition_chunk_sizes(&self, partition_id: u64) -> Result<u64, CubeError>;
+    fn swap_active_partitions(
+        &self,
+        current_active: Vec<u64>,
+        new_active: Vec<u64>,
+        compacted_chunk_ids: Vec<u64>,
+        new_active_min_max: Vec<(u64, (Option<Row>, Option<Row>))>,
+        deleted_row_count: u64,
+        applied_delete_count: usize
+    ) -> Result<(), CubeError>;
+    fn is_partition_used(&self, partition_id: u64) -> Result<bool, CubeError>;
+    fn add_partition_delete(
+        &self,
+        index_id: u64,
+        filter: RowFilter
+    ) -> Result<Vec<IdRow<Partition>>, CubeError>;
+    fn get_partitions_with_pending_deletes(
+        &self,
+        index_id: u64
+    ) -> Result<Vec<IdRow<Partition>>, CubeError>;
+    fn create_index(
+        &self,
+        schema_name: String,
+        table_name: String,
+        index_def: IndexDef
+    ) -> Result<IdRow<Index>, CubeError>;
+    fn get_default_index(&self, table_id: u64) -> Result<IdRow<Index>, CubeError>;
+    fn get_table_indexes(&self, table_id: u64) -> Result<Vec<IdRow<Index>>, CubeError>;
+    fn get_active_partitions_by_index_id(
+        &self,
+        index_id: u64
+    ) -> Result<Vec<IdRow<Partition>>, CubeError>;
+    fn get_active_partitions_and_chunks_by_index_id_for_select(
+        &self,
+        index_id: u64
+    ) -> Result<Vec<(IdRow<Partition>, Vec<IdRow<Chunk>>)>, CubeError>;
+    fn create_chunk(
+        &self,
+        partition_id: u64,
+        row_count: usize
+    ) -> Result<IdRow<Chunk>, CubeError>;
+    fn get_chunk(&self, chunk_id: u64) -> Result<IdRow<Chunk>, CubeError>;
+    fn get_chunks_by_partition(
+        &self,
+        partition_id: u64,
+        include_inactive: bool
+    ) -> Result<Vec<IdRow<Chunk>>, CubeError>;
+    fn chunk_uploaded(&self, chunk_id: u64) -> Result<IdRow<Chunk>, CubeError>;
+    fn deactivate_chunk(&self, chunk_id: u64) -> Result<(), CubeError>;
+    fn swap_chunks(
+        &self,
+        deactivate_ids: Vec<u64>,
+        uploaded_ids: Vec<u64>,
+        deleted_row_count: u64
+    ) -> Result<(), CubeError>;
+    fn activate_wal(
+        &self,
+        wal_id_to_delete: u64,
+        uploaded_ids: Vec<u64>,
+        index_count: u64
+    ) -> Result<(), CubeError>;
+    fn is_chunk_used(&self, chunk_id: u64) -> Result<bool, CubeError>;
+    fn delete_chunk(&self, chunk_id: u64) -> Result<IdRow<Chunk>, CubeError>;
+    fn create_wal(&self, table_id: u64, row_count: usize) -> Result<IdRow<WAL>, CubeError>;
+    fn get_wal(&self, wal_id: u64) -> Result<IdRow<WAL>, CubeError>;
+    fn delete_wal(&self, wal_id: u64) -> Result<(), CubeError>;
+    fn wal_written(&self, wal_id: u64) -> Result<IdRow<WAL>, CubeError>;
+    fn wal_uploaded(&self, wal_id: u64) -> Result<IdRow<WAL>, CubeError>;
+    fn get_wals_for_table(&self, table_id: u64) -> Result<Vec<IdRow<WAL>>, CubeError>;
+    fn get_all_wals(&self) -> Result<Vec<IdRow<WAL>>, CubeError>;
+    fn add_file_deletion(
+        &self,
+        remote_path: String,
+        deadline: DateTime<Utc>
+    ) -> Result<IdRow<FileDeletion>, CubeError>;
+    fn get_file_deletions(&self) -> Result<Vec<IdRow<FileDeletion>>, CubeError>;
+    fn delete_file_deletion(&self, id: u64) -> Result<(), CubeError>;
+    fn add_job(&self, job: Job) -> Result<Option<IdRow<Job>>, CubeError>;
+    fn get_job(&self, job_id: u64) -> Result<IdRow<Job>, CubeError>;
+    fn get_all_jobs(&self) -> Result<Vec<IdRow<Job>>, CubeError>;
+    fn get_job_by_ref(
+        &self,
+        row_reference: RowKey,
+        job_type: JobType
+    ) -> Result<Option<IdRow<Job>>, CubeError>;
+    fn delete_job(&self, job_id: u64) -> Result<IdRow<Job>, CubeError>;
+    fn start_processing_job(
+        &self,
+        server_name: String
+    ) -> Result<Option<IdRow<Job>>, CubeError>;
+    fn update_status(&self, job_id: u64, status: JobStatus) -> Result<IdRow<Job>, CubeError>;
+    fn update_heart_beat(&self, job_id: u64) -> Result<IdRow<Job>, CubeError>;
+    fn update_job_progress(
+        &self,
+        job_id: u64,
+        progress: JobProgress
+    ) -> Result<IdRow<Job>, CubeError>;
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum MetaStoreTableCall {
+    AllRows,
+    RowByIdOrNotFound(u64),
+    Delete(u64),
+}
+
+impl MetaStoreTableCall {
+    async fn invoke_on(
+        self,
+        table: TableId,
+        meta_store: &dyn MetaStore,
+    ) -> Result<Vec<u8>, CubeError> {
+        match table {
+            TableId::Schemas => self.invoke(meta_store.schemas_table()).await,
+            TableId::Tables => self.invoke(meta_store.tables_table()).await,
+            TableId::Indexes => self.invoke(meta_store.index_table()).await,
+            TableId::Partitions => self.invoke(meta_store.partition_table()).await,
+            TableId::Chunks => self.invoke(meta_store.chunks_table()).await,
+            TableId::WALs | TableId::Jobs | TableId::FileDeletions => Err(CubeError::internal(
+                format!("{:?} table isn't exposed by the metastore", table),
+            )),
+        }
+    }
+
+    async fn invoke<T: Serialize + Clone + Debug + Send + Sync + 'static>(
+        self,
+        table: Box<dyn MetaStoreTable<T = T>>,
+    ) -> Result<Vec<u8>, CubeError> {
+        match self {
+            MetaStoreTableCall::AllRows => serialize(&table.all_rows().await?),
+            MetaStoreTableCall::RowByIdOrNotFound(id) => {
+                serialize(&table.row_by_id_or_not_found(id).await?)
+            }
+            MetaStoreTableCall::Delete(id) => serialize(&table.delete(id).await?),
+        }
+    }
+}
+
+/// `MetaStore` of the router used by workers which run jobs. Opens a connection to the router
+/// metastore port for every call.
+#[derive(Clone)]
+pub struct MetaStoreRpcClient {
+    address: String,
+    connector: Arc<NodeConnector>,
+}
+
+impl MetaStoreRpcClient {
+    pub fn new(address: String, connector: Arc<NodeConnector>) -> Arc<MetaStoreRpcClient> {
+        Arc::new(MetaStoreRpcClient { address, connector })
+    }
+
+    async fn call(
+        &self,
+        call: MetaStoreRpcMethodCall,
+    ) -> Result<MetaStoreRpcMethodResult, CubeError> {
+        let mut stream = self.connector.connect(&self.address).await?;
+        NetworkMessage::MetaStoreCall(call)
+            .send(&mut stream)
+            .await?;
+        match NetworkMessage::receive(&mut stream).await? {
+            NetworkMessage::MetaStoreResult(res) => Ok(res),
+            message => Err(CubeError::internal(format!(
+                "Unexpected metastore response: {:?}",
+                message
+            ))),
+        }
+    }
+
+    fn table<T>(&self, table: TableId) -> Box<dyn MetaStoreTable<T = T>>
+    where
+        T: Serialize + DeserializeOwned + Clone + Debug + Send + Sync + 'static,
+    {
+        Box::new(RpcMetaStoreTable {
+            client: self.clone(),
+            table,
+            row: PhantomData,
+        })
+    }
+}
+
+struct RpcMetaStoreTable<T> {
+    client: MetaStoreRpcClient,
+    table: TableId,
+    row: PhantomData<T>,
+}
+
+impl<T> RpcMetaStoreTable<T> {
+    async fn call<R: DeserializeOwned>(&self, call: MetaStoreTableCall) -> Result<R, CubeError> {
+        let table = self.table;
+        match self
+            .client
+            .call(MetaStoreRpcMethodCall::Table { table, call })
+            .await?
+        {
+            MetaStoreRpcMethodResult::Table(res) => {
+                let buffer = res?;
+                let r = flexbuffers::Reader::get_root(&buffer)?;
+                Ok(R::deserialize(r)?)
+            }
+            res => Err(CubeError::internal(format!(
+                "Unexpected metastore response to {:?} call: {:?}",
+                table, res
+            ))),
+        }
+    }
+}
+
+#[async_trait]
+impl<T> MetaStoreTable for RpcMetaStoreTable<T>
+where
+    T: Serialize + DeserializeOwned + Clone + Debug + Send + Sync + 'static,
+{
+    type T = T;
+
+    async fn all_rows(&self) -> Result<Vec<IdRow<Self::T>>, CubeError> {
+        self.call(MetaStoreTableCall::AllRows).await
+    }
+
+    async fn row_by_id_or_not_found(&self, id: u64) -> Result<IdRow<Self::T>, CubeError> {
+        self.call(MetaStoreTableCall::RowByIdOrNotFound(id)).await
+    }
+
+    async fn delete(&self, id: u64) -> Result<IdRow<Self::T>, CubeError> {
+        self.call(MetaStoreTableCall::Delete(id)).await
+    }
+}
+
+fn serialize<T: Serialize>(value: &T) -> Result<Vec<u8>, CubeError> {
+    let mut ser = flexbuffers::FlexbufferSerializer::new();
+    value.serialize(&mut ser)?;
+    Ok(ser.take_buffer())
+}
@@ -9,6 +9,7 @@ use crate::metastore::{IdRow, ImportFormat, MetaStoreEvent, Schema};
 use crate::rocks_table_impl;
 use crate::store::DataFrame;
 use crate::table::Row;
+use crate::CubeError;
 use byteorder::{BigEndian, WriteBytesExt};
 use rocksdb::DB;
 use serde::{Deserialize, Deserializer, Serialize};
@@ -24,10 +25,19 @@ pub struct Table {
     location: Option<String>,
     import_format: Option<ImportFormat>,
     #[serde(default)]
-    has_data: bool
+    has_data: bool,
+    #[serde(default)]
+    locations: Option<Vec<String>>,
+    #[serde(default)]
+    storage_names: Vec<String>,
+    #[serde(default)]
+    schema_version: u64
 }
 }
 
+/// Marks tables built by `CREATE OR REPLACE TABLE` before they replace the original table.
+pub const REPLACEMENT_MARKER: &str = "#replace#";
+
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct TablePath {
     pub table: IdRow<Table>,
@@ -47,16 +57,19 @@ impl Table {
         table_name: String,
         schema_id: u64,
         columns: Vec<Column>,
-        location: Option<String>,
+        locations: Option<Vec<String>>,
         import_format: Option<ImportFormat>,
     ) -> Table {
         Table {
             table_name,
             schema_id,
             columns,
-            location,
+            location: None,
             import_format,
             has_data: false,
+            locations,
+            storage_names: Vec::new(),
+            schema_version: 0,
         }
     }
     pub fn get_columns(&self) -> &Vec<Column> {
@@ -71,28 +84,157 @@ impl Table {
         &self.import_format
     }
 
-    pub fn location(&self) -> &Option<String> {
-        &self.location
+    pub fn locations(&self) -> Option<Vec<&String>> {
+        self.locations
+            .as_ref()
+            .map(|locations| locations.iter().collect())
+            .or_else(|| self.location.as_ref().map(|location| vec![location]))
     }
 
     pub fn get_table_name(&self) -> &String {
         &self.table_name
     }
 
+    /// Replacement tables are hidden from listings and dropped on startup if a crash left them.
+    pub fn is_replacement(&self) -> bool {
+        self.table_name.contains(REPLACEMENT_MARKER)
+    }
+
     pub fn has_data(&self) -> &bool {
         &self.has_data
     }
 
     pub fn update_has_data(&self, has_data: bool) -> Self {
         Self {
-            table_name: self.table_name.clone(),
-            schema_id: self.schema_id,
-            columns: self.columns.clone(),
-            location: self.location.clone(),
-            import_format: self.import_format.clone(),
             has_data,
+            ..self.clone()
+        }
+    }
+
+    pub fn rename(&self, schema_id: u64, table_name: String) -> Self {
+        Self {
+            schema_id,
+            table_name,
+            ..self.clone()
+        }
+    }
+
+    /// Name of the column in data files. Differs from the column name after ALTER TABLE.
+    pub fn storage_name<'b>(&'b self, column: &'b Column) -> &'b String {
+        storage_name(&self.columns, &self.storage_names, column)
+    }
+
+    pub fn has_evolved_schema(&self) -> bool {
+        has_evolved_schema(&self.storage_names)
+    }
+
+    pub fn add_column(&self, name: String, column_type: ColumnType) -> Result<Table, CubeError> {
+        if self.find_column(&name).is_some() {
+            return Err(CubeError::user(format!(
+                "Column '{}' already exists in table '{}'",
+                name, self.table_name
+            )));
+        }
+        let schema_version = self.schema_version + 1;
+        let mut storage_names = self.storage_names();
+        storage_names.push(format!("{}#{}", name, schema_version));
+        let mut columns = self.columns.clone();
+        columns.push(Column::new(name, column_type, columns.len()));
+        Ok(self.with_columns(columns, storage_names, schema_version))
+    }
+
+    pub fn drop_column(&self, name: &str) -> Result<Table, CubeError> {
+        let position = self.column_position(name)?;
+        if self.columns.len() == 1 {
+            return Err(CubeError::user(format!(
+                "Can't drop the only column '{}' of table '{}'",
+                name, self.table_name
+            )));
+        }
+        let mut storage_names = self.storage_names();
+        storage_names.remove(position);
+        let mut columns = self.columns.clone();
+        columns.remove(position);
+        let columns = columns
+            .into_iter()
+            .enumerate()
+            .map(|(i, c)| c.replace_index(i))
+            .collect();
+        Ok(self.with_columns(columns, storage_names, self.schema_version + 1))
+    }
+
+    pub fn rename_column(&self, old_name: &str, new_name: String) -> Result<Table, CubeError> {
+        let position = self.column_position(old_name)?;
+        if self.find_column(&new_name).is_some() {
+            return Err(CubeError::user(format!(
+                "Column '{}' already exists in table '{}'",
+                new_name, self.table_name
+            )));
         }
+        let storage_names = self.storage_names();
+        let mut columns = self.columns.clone();
+        columns[position] = Column::new(
+            new_name,
+            columns[position].get_column_type().clone(),
+            position,
+        );
+        Ok(self.with_columns(columns, storage_names, self.schema_version + 1))
+    }
+
+    fn find_column(&self, name: &str) -> Option<&Column> {
+        self.columns.iter().find(|c| c.get_name() == name)
     }
+
+    fn column_position(&self, name: &str) -> Result<usize, CubeError> {
+        self.columns
+            .iter()
+            .position(|c| c.get_name() == name)
+            .ok_or_else(|| {
+                CubeError::user(format!(
+                    "Column '{}' not found in table '{}'",
+                    name, self.table_name
+                ))
+            })
+    }
+
+    fn storage_names(&self) -> Vec<String> {
+        self.columns
+            .iter()
+            .map(|c| self.storage_name(c).clone())
+            .collect()
+    }
+
+    fn with_columns(
+        &self,
+        columns: Vec<Column>,
+        storage_names: Vec<String>,
+        schema_version: u64,
+    ) -> Table {
+        Table {
+            columns,
+            storage_names,
+            schema_version,
+            ..self.clone()
+        }
+    }
+}
+
+/// `storage_names` are either empty or match `columns` by position.
+/// Shared by tables and indexes which both follow column renames.
+pub(crate) fn storage_name<'b>(
+    columns: &'b [Column],
+    storage_names: &'b [String],
+    column: &'b Column,
+) -> &'b String {
+    columns
+        .iter()
+        .position(|c| c.get_name() == column.get_name())
+        .and_then(|p| storage_names.get(p))
+        .unwrap_or(column.get_name())
+}
+
+pub(crate) fn has_evolved_schema(storage_names: &[String]) -> bool {
+    !storage_names.is_empty()
 }
 
 impl Column {
@@ -12,6 +12,8 @@ impl WAL {
             table_id,
             row_count: row_count as u64,
             uploaded: false,
+            columnar: true,
+            written: false,
         }
     }
 
@@ -25,9 +27,15 @@ impl WAL {
 
     pub fn set_uploaded(&self, uploaded: bool) -> WAL {
         WAL {
-            table_id: self.table_id,
-            row_count: self.row_count,
             uploaded,
+            ..self.clone()
+        }
+    }
+
+    pub fn set_written(&self, written: bool) -> WAL {
+        WAL {
+            written,
+            ..self.clone()
         }
     }
 
@@ -35,9 +43,21 @@ impl WAL {
         self.table_id
     }
 
+    /// WAL file is stored in the remote storage.
     pub fn uploaded(&self) -> bool {
         self.uploaded
     }
+
+    /// WAL file is completely written to the local disk of the node which accepted it.
+    /// WALs created before this flag was introduced were only marked as uploaded.
+    pub fn written(&self) -> bool {
+        self.written || self.uploaded
+    }
+
+    /// WALs written before Arrow IPC format was introduced are bincode serialized `DataFrame`s.
+    pub fn columnar(&self) -> bool {
+        self.columnar
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -1,44 +1,185 @@
-use crate::sql::SqlService;
-use crate::table::TableValue;
+use crate::cluster::tls;
+use crate::config::{constant_time_eq, ConfigObj, UserConfig};
+use crate::metastore::table::TablePath;
+use crate::queryplanner::query_executor::{arrow_to_column_type, batch_to_dataframe};
+use crate::sql::{PreparedStatement, SqlQueryContext, SqlService};
+use crate::store::DataStream;
+use crate::table::{TableValue, TimestampValue};
 use crate::{metastore, CubeError};
 use async_trait::async_trait;
-use hex::ToHex;
+use chrono::{NaiveDate, TimeZone, Utc};
+use futures::StreamExt;
 use log::{error, info, warn};
 use msql_srv::*;
+use rand::Rng;
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
 use std::io;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 use std::time::SystemTime;
-use tokio::net::TcpListener;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::TlsAcceptor;
 
 struct Backend {
+    connection_id: u32,
     sql_service: Arc<dyn SqlService>,
+    config_obj: Arc<dyn ConfigObj>,
+    salt: [u8; 20],
+    user: RwLock<Option<UserConfig>>,
+    default_schema: Option<String>,
+    statements: HashMap<u32, PreparedStatement>,
+    next_statement_id: u32,
+}
+
+impl Backend {
+    fn context(&self) -> SqlQueryContext {
+        match self.user.read().unwrap().as_ref() {
+            Some(user) => SqlQueryContext {
+                connection_id: Some(self.connection_id),
+                user: Some(user.name.clone()),
+                allowed_schemas: user.allowed_schemas.clone(),
+                default_schema: self.default_schema.clone(),
+            },
+            None => SqlQueryContext {
+                connection_id: Some(self.connection_id),
+                default_schema: self.default_schema.clone(),
+                ..SqlQueryContext::default()
+            },
+        }
+    }
 }
 
 #[async_trait]
 impl<W: io::Write + Send> AsyncMysqlShim<W> for Backend {
     type Error = io::Error;
 
+    fn salt(&self) -> [u8; 20] {
+        self.salt
+    }
+
+    async fn authenticate(
+        &self,
+        auth_plugin: &str,
+        username: &[u8],
+        salt: &[u8],
+        auth_data: &[u8],
+    ) -> bool {
+        let users = self.config_obj.users();
+        if users.is_empty() {
+            return true;
+        }
+        let name = String::from_utf8_lossy(username);
+        let user = match users.iter().find(|u| u.name == name) {
+            Some(user) => user,
+            None => {
+                warn!("Authentication failed: unknown user {}", name);
+                return false;
+            }
+        };
+        let authenticated = auth_plugin == "mysql_native_password"
+            && if user.password.is_empty() {
+                auth_data.is_empty()
+            } else {
+                constant_time_eq(
+                    auth_data,
+                    &native_password_scramble(user.password.as_bytes(), salt),
+                )
+            };
+        if authenticated {
+            *self.user.write().unwrap() = Some(user.clone());
+        } else {
+            warn!("Authentication failed for user {}", name);
+        }
+        authenticated
+    }
+
+    async fn on_init<'a>(
+        &'a mut self,
+        database: &'a str,
+        w: InitWriter<'a, W>,
+    ) -> Result<(), Self::Error> {
+        let allowed = match self.user.read().unwrap().as_ref() {
+            Some(UserConfig {
+                allowed_schemas: Some(schemas),
+                ..
+            }) => schemas.iter().any(|s| s.eq_ignore_ascii_case(database)),
+            _ => true,
+        };
+        if !allowed {
+            return w.error(
+                ErrorKind::ER_DBACCESS_DENIED_ERROR,
+                format!("Access denied to schema '{}'", database).as_bytes(),
+            );
+        }
+        self.default_schema = Some(database.to_string());
+        w.ok()
+    }
+
     async fn on_prepare<'a>(
         &'a mut self,
-        _query: &'a str,
+        query: &'a str,
         info: StatementMetaWriter<'a, W>,
     ) -> Result<(), Self::Error> {
-        info.reply(42, &[], &[])
+        let statement = match self.sql_service.prepare(query) {
+            Ok(statement) => statement,
+            Err(e) => {
+                error!("Error during preparing {}: {}", query, e.message);
+                return info.error(ErrorKind::ER_PARSE_ERROR, e.message.as_bytes());
+            }
+        };
+        let params = (0..statement.params_count())
+            .map(|_| Column {
+                table: "".to_string(),
+                column: "?".to_string(),
+                coltype: ColumnType::MYSQL_TYPE_VAR_STRING,
+                colflags: ColumnFlags::empty(),
+            })
+            .collect::<Vec<_>>();
+        let id = self.next_statement_id;
+        self.next_statement_id = self.next_statement_id.wrapping_add(1);
+        self.statements.insert(id, statement);
+        info.reply(id, &params, &[])
     }
 
     async fn on_execute<'a>(
         &'a mut self,
-        _id: u32,
-        _params: ParamParser<'a>,
+        id: u32,
+        params: ParamParser<'a>,
         results: QueryResultWriter<'a, W>,
     ) -> Result<(), Self::Error> {
-        results.completed(0, 0)
+        let statement = match self.statements.get(&id) {
+            Some(statement) => statement,
+            None => {
+                return results.error(
+                    ErrorKind::ER_UNKNOWN_STMT_HANDLER,
+                    format!("Unknown prepared statement: {}", id).as_bytes(),
+                )
+            }
+        };
+        let values = match params
+            .into_iter()
+            .map(|p| param_value(p.value.into_inner(), p.coltype))
+            .collect::<Result<Vec<_>, _>>()
+        {
+            Ok(values) => values,
+            Err(e) => {
+                return results.error(ErrorKind::ER_WRONG_ARGUMENTS, e.message.as_bytes());
+            }
+        };
+        let start = SystemTime::now();
+        let res = self
+            .sql_service
+            .exec_prepared_stream(self.context(), statement, values)
+            .await;
+        write_result(statement.query(), start, res, results).await
     }
 
-    async fn on_close<'a>(&'a mut self, _stmt: u32)
+    async fn on_close<'a>(&'a mut self, stmt: u32)
     where
         W: 'async_trait,
     {
+        self.statements.remove(&stmt);
     }
 
     async fn on_query<'a>(
@@ -47,91 +188,809 @@ impl<W: io::Write + Send> AsyncMysqlShim<W> for Backend {
         results: QueryResultWriter<'a, W>,
     ) -> Result<(), Self::Error> {
         let start = SystemTime::now();
-        let res = self.sql_service.exec_query(query).await;
-        if let Err(e) = res {
+        let res = self
+            .sql_service
+            .exec_query_stream(self.context(), query)
+            .await;
+        write_result(query, start, res, results).await
+    }
+}
+
+async fn write_result<W: io::Write>(
+    query: &str,
+    start: SystemTime,
+    res: Result<DataStream, CubeError>,
+    results: QueryResultWriter<'_, W>,
+) -> Result<(), io::Error> {
+    // Errors are reported before column definitions are sent if they happen in the first batch.
+    let res = match res {
+        Ok(mut data_stream) => data_stream.prefetch().await.and_then(|_| {
+            let column_types = data_stream
+                .schema()
+                .fields()
+                .iter()
+                .map(|f| arrow_to_column_type(f.data_type().clone()))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok((data_stream, column_types))
+        }),
+        Err(e) => Err(e),
+    };
+    let (mut data_stream, column_types) = match res {
+        Ok(res) => res,
+        Err(e) => {
             error!("Error during processing {}: {}", query, e.message);
             results.error(ErrorKind::ER_INTERNAL_ERROR, e.message.as_bytes())?;
             return Ok(());
         }
-        let data_frame = res.unwrap();
-        let columns = data_frame
-            .get_columns()
-            .iter()
-            .map(|c| Column {
-                table: "result".to_string(), // TODO
-                column: c.get_name().to_string(),
-                coltype: match c.get_column_type() {
-                    metastore::ColumnType::String => ColumnType::MYSQL_TYPE_STRING,
-                    metastore::ColumnType::Timestamp => ColumnType::MYSQL_TYPE_STRING,
-                    metastore::ColumnType::Int => ColumnType::MYSQL_TYPE_LONGLONG,
-                    metastore::ColumnType::Decimal { .. } => ColumnType::MYSQL_TYPE_DECIMAL,
-                    metastore::ColumnType::Boolean => ColumnType::MYSQL_TYPE_STRING,
-                    metastore::ColumnType::Bytes => ColumnType::MYSQL_TYPE_STRING,
-                    metastore::ColumnType::HyperLogLog => ColumnType::MYSQL_TYPE_STRING,
-                    metastore::ColumnType::Float => ColumnType::MYSQL_TYPE_STRING,
-                },
-                colflags: ColumnFlags::empty(),
-            })
-            .collect::<Vec<_>>();
+    };
+    let columns = data_stream
+        .schema()
+        .fields()
+        .iter()
+        .zip(column_types.iter())
+        .enumerate()
+        .map(|(i, (f, column_type))| {
+            column_definition(f.name(), data_stream.get_source_table(i), column_type)
+        })
+        .collect::<Vec<_>>();
 
-        let mut rw = results.start(&columns)?;
+    let mut rw = results.start(&columns)?;
+    while let Some(batch) = data_stream.next().await {
+        // MySQL protocol is row based so batches are converted as they arrive.
+        let data_frame = match batch.and_then(|b| batch_to_dataframe(&vec![b])) {
+            Ok(data_frame) => data_frame,
+            Err(e) => {
+                error!("Error during processing {}: {}", query, e.message);
+                return Err(io::Error::new(io::ErrorKind::Other, e.message));
+            }
+        };
         for row in data_frame.get_rows().iter() {
-            for value in row.values().iter() {
+            for (value, column_type) in row.values().iter().zip(column_types.iter()) {
                 match value {
                     TableValue::String(s) => rw.write_col(s)?,
-                    TableValue::Timestamp(s) => rw.write_col(s.to_string())?,
-                    TableValue::Int(i) => rw.write_col(i)?,
-                    TableValue::Decimal(v) => rw.write_col(v.to_string())?,
-                    TableValue::Boolean(v) => rw.write_col(v.to_string())?,
-                    TableValue::Float(v) => rw.write_col(v.to_string())?,
-                    TableValue::Bytes(b) => {
-                        rw.write_col(format!("0x{}", b.encode_hex_upper::<String>()))?
+                    TableValue::Timestamp(t) => {
+                        rw.write_col(Utc.timestamp_nanos(t.get_time_stamp()).naive_utc())?
                     }
+                    TableValue::Int(i) => rw.write_col(i)?,
+                    TableValue::Decimal(v) => match column_type {
+                        metastore::ColumnType::Decimal { scale, .. } => {
+                            rw.write_col(decimal_with_scale(v, *scale as usize))?
+                        }
+                        _ => rw.write_col(v)?,
+                    },
+                    TableValue::Boolean(v) => rw.write_col(*v as i8)?,
+                    TableValue::Float(v) => match v.parse::<f64>() {
+                        Ok(f) => rw.write_col(f)?,
+                        Err(_) => rw.write_col(v)?,
+                    },
+                    TableValue::Bytes(b) => rw.write_col(&b[..])?,
                     TableValue::Null => rw.write_col(Option::<String>::None)?,
                 }
             }
             rw.end_row()?;
         }
-        rw.finish()?;
-        if start.elapsed().unwrap().as_millis() > 200 && query.to_lowercase().starts_with("select")
-        {
-            warn!(
-                "Slow Query SQL ({:?}):\n{}",
-                start.elapsed().unwrap(),
-                query
-            );
+    }
+    rw.finish()?;
+    if start.elapsed().unwrap().as_millis() > 200 && query.to_lowercase().starts_with("select") {
+        warn!(
+            "Slow Query SQL ({:?}):\n{}",
+            start.elapsed().unwrap(),
+            query
+        );
+    }
+    Ok(())
+}
+
+/// msql-srv doesn't send the schema and decimal digits of columns, so decimal values are
+/// padded to their scale by `decimal_with_scale` instead.
+fn column_definition(
+    name: &str,
+    source_table: Option<&TablePath>,
+    column_type: &metastore::ColumnType,
+) -> Column {
+    Column {
+        table: source_table
+            .map(|t| t.table.get_row().get_table_name().to_string())
+            .unwrap_or_default(),
+        column: name.to_string(),
+        coltype: match column_type {
+            metastore::ColumnType::String => ColumnType::MYSQL_TYPE_STRING,
+            metastore::ColumnType::Timestamp => ColumnType::MYSQL_TYPE_DATETIME,
+            metastore::ColumnType::Int => ColumnType::MYSQL_TYPE_LONGLONG,
+            metastore::ColumnType::Decimal { .. } => ColumnType::MYSQL_TYPE_NEWDECIMAL,
+            metastore::ColumnType::Boolean => ColumnType::MYSQL_TYPE_TINY,
+            metastore::ColumnType::Bytes => ColumnType::MYSQL_TYPE_BLOB,
+            metastore::ColumnType::HyperLogLog => ColumnType::MYSQL_TYPE_BLOB,
+            metastore::ColumnType::Float => ColumnType::MYSQL_TYPE_DOUBLE,
+        },
+        colflags: match column_type {
+            metastore::ColumnType::Bytes | metastore::ColumnType::HyperLogLog => {
+                ColumnFlags::BINARY_FLAG | ColumnFlags::BLOB_FLAG
+            }
+            _ => ColumnFlags::empty(),
+        },
+    }
+}
+
+/// Pads fractional digits with zeros so clients see values with the declared scale.
+fn decimal_with_scale(value: &str, scale: usize) -> String {
+    let (integer, fraction) = match value.find('.') {
+        Some(i) => (&value[..i], &value[i + 1..]),
+        None => (value, ""),
+    };
+    if scale == 0 && fraction.is_empty() {
+        return integer.to_string();
+    }
+    format!("{}.{:0<width$}", integer, fraction, width = scale)
+}
+
+/// `mysql_native_password` auth response: SHA1(password) XOR SHA1(salt + SHA1(SHA1(password))).
+fn native_password_scramble(password: &[u8], salt: &[u8]) -> Vec<u8> {
+    let password_hash = Sha1::digest(password);
+    let double_hash = Sha1::digest(&password_hash);
+    let mut hasher = Sha1::new();
+    hasher.update(salt);
+    hasher.update(&double_hash);
+    let salted_hash = hasher.finalize();
+    password_hash
+        .iter()
+        .zip(salted_hash.iter())
+        .map(|(a, b)| a ^ b)
+        .collect()
+}
+
+fn random_salt() -> [u8; 20] {
+    let mut rng = rand::thread_rng();
+    let mut salt = [0u8; 20];
+    for b in salt.iter_mut() {
+        // Printable ASCII as some clients don't expect zeros or high bytes in the scramble.
+        *b = rng.gen_range(33..127);
+    }
+    salt
+}
+
+/// Strings and binary values are both sent as bytes. Bytes of blob parameters and bytes which
+/// aren't valid UTF-8 are bound as binary values.
+fn param_value(value: ValueInner, coltype: ColumnType) -> Result<TableValue, CubeError> {
+    Ok(match value {
+        ValueInner::NULL => TableValue::Null,
+        ValueInner::Bytes(b) => match std::str::from_utf8(b) {
+            Ok(s) if !is_blob_type(coltype) => TableValue::String(s.to_string()),
+            _ => TableValue::Bytes(b.to_vec()),
+        },
+        ValueInner::Int(i) => TableValue::Int(i),
+        ValueInner::UInt(u) => TableValue::Int(u as i64),
+        ValueInner::Double(f) => TableValue::Float(f.to_string()),
+        ValueInner::Date(b) | ValueInner::Datetime(b) => parse_binary_datetime(b)?,
+        ValueInner::Time(_) => {
+            return Err(CubeError::user(
+                "TIME parameters are not supported".to_string(),
+            ))
         }
-        Ok(())
+    })
+}
+
+fn is_blob_type(coltype: ColumnType) -> bool {
+    match coltype {
+        ColumnType::MYSQL_TYPE_TINY_BLOB
+        | ColumnType::MYSQL_TYPE_MEDIUM_BLOB
+        | ColumnType::MYSQL_TYPE_LONG_BLOB
+        | ColumnType::MYSQL_TYPE_BLOB => true,
+        _ => false,
     }
 }
 
+/// Decodes DATE and DATETIME values of the binary protocol: year, month, day, hour, minute,
+/// second and microseconds where trailing zero parts may be omitted.
+fn parse_binary_datetime(b: &[u8]) -> Result<TableValue, CubeError> {
+    let (year, month, day) = if b.len() >= 4 {
+        (
+            u16::from_le_bytes([b[0], b[1]]) as i32,
+            b[2] as u32,
+            b[3] as u32,
+        )
+    } else {
+        (0, 0, 0)
+    };
+    let (hour, minute, second) = if b.len() >= 7 {
+        (b[4] as u32, b[5] as u32, b[6] as u32)
+    } else {
+        (0, 0, 0)
+    };
+    let micros = if b.len() >= 11 {
+        u32::from_le_bytes([b[7], b[8], b[9], b[10]])
+    } else {
+        0
+    };
+    let date_time = NaiveDate::from_ymd_opt(year, month, day)
+        .and_then(|d| d.and_hms_micro_opt(hour, minute, second, micros))
+        .ok_or_else(|| CubeError::user(format!("Invalid date time parameter: {:?}", b)))?;
+    Ok(TableValue::Timestamp(TimestampValue::new(
+        date_time.timestamp_nanos(),
+    )))
+}
+
 pub struct MySqlServer;
 
 impl MySqlServer {
     pub async fn listen(
         address: String,
         sql_service: Arc<dyn SqlService>,
+        config_obj: Arc<dyn ConfigObj>,
     ) -> Result<(), CubeError> {
-        let mut listener = TcpListener::bind(address.clone()).await?;
+        let listener = TcpListener::bind(address.clone()).await?;
 
         info!("MySQL port open on {}", address);
 
+        Self::serve(listener, sql_service, config_obj).await
+    }
+
+    pub async fn serve(
+        mut listener: TcpListener,
+        sql_service: Arc<dyn SqlService>,
+        config_obj: Arc<dyn ConfigObj>,
+    ) -> Result<(), CubeError> {
+        let tls_acceptor = match config_obj.mysql_tls() {
+            Some(tls_config) => Some((tls::mysql_tls_acceptor(tls_config)?, tls_config.required)),
+            None => None,
+        };
+        let mut next_connection_id: u32 = 1;
         loop {
             let (socket, _) = listener.accept().await?;
+            let connection_id = next_connection_id;
+            next_connection_id = next_connection_id.wrapping_add(1);
 
-            let sql_service_clone = sql_service.clone();
+            let backend = Backend {
+                connection_id,
+                sql_service: sql_service.clone(),
+                config_obj: config_obj.clone(),
+                salt: random_salt(),
+                user: RwLock::new(None),
+                default_schema: None,
+                statements: HashMap::new(),
+                next_statement_id: 1,
+            };
+            let tls_acceptor = tls_acceptor.clone();
             tokio::spawn(async move {
-                if let Err(e) = AsyncMysqlIntermediary::run_on(
-                    Backend {
-                        sql_service: sql_service_clone,
-                    },
-                    socket,
-                )
-                .await
-                {
+                let res = match tls_acceptor {
+                    Some((tls_acceptor, required)) => {
+                        run_with_tls(backend, socket, tls_acceptor, required).await
+                    }
+                    None => AsyncMysqlIntermediary::run_on(backend, socket)
+                        .await
+                        .map_err(|e| e.into()),
+                };
+                if let Err(e) = res {
                     error!("Error during processing MySQL connection: {}", e);
                 }
             });
         }
     }
 }
+
+const CLIENT_SSL: u32 = 0x800;
+
+/// msql-srv doesn't implement the SSL upgrade of the MySQL protocol, so it's done here: the
+/// server greeting advertises SSL and if the client asks for it, TLS is terminated here and
+/// msql-srv gets the decrypted stream over a loopback connection. Clients which don't ask for
+/// SSL get an error if `required` is set.
+async fn run_with_tls(
+    backend: Backend,
+    mut socket: TcpStream,
+    tls_acceptor: TlsAcceptor,
+    required: bool,
+) -> Result<(), CubeError> {
+    let (mut relay_stream, backend_stream) = loopback_pair().await?;
+    let intermediary = tokio::spawn(AsyncMysqlIntermediary::run_on(backend, backend_stream));
+
+    let mut greeting = read_packet(&mut relay_stream).await?;
+    // Protocol version, server version, connection id, salt and filler precede capabilities.
+    let version_end = 5 + greeting[5..]
+        .iter()
+        .position(|b| *b == 0)
+        .ok_or_else(|| CubeError::internal("Malformed MySQL server greeting".to_string()))?;
+    greeting[version_end + 1 + 4 + 8 + 1 + 1] |= (CLIENT_SSL >> 8) as u8;
+    socket.write_all(&greeting).await?;
+
+    let response = read_packet(&mut socket).await?;
+    // SSL request is the fixed part of the handshake response without the user name.
+    let is_ssl_request = response.len() == 4 + 32
+        && u32::from_le_bytes([response[4], response[5], response[6], response[7]]) & CLIENT_SSL
+            != 0;
+    if is_ssl_request {
+        let tls_stream = tls_acceptor.accept(socket).await?;
+        relay(tls_stream, relay_stream).await?;
+    } else if required {
+        write_error_packet(
+            &mut socket,
+            response[3].wrapping_add(1),
+            ErrorKind::ER_ACCESS_DENIED_ERROR,
+            b"28000",
+            "Connections without TLS are not allowed",
+        )
+        .await?;
+        return Ok(());
+    } else {
+        relay_stream.write_all(&response).await?;
+        relay(socket, relay_stream).await?;
+    }
+    intermediary.await??;
+    Ok(())
+}
+
+/// Reads a packet including its header.
+async fn read_packet(stream: &mut (impl AsyncRead + Unpin)) -> Result<Vec<u8>, CubeError> {
+    let mut packet = vec![0u8; 4];
+    stream.read_exact(&mut packet).await?;
+    let len = u32::from_le_bytes([packet[0], packet[1], packet[2], 0]) as usize;
+    packet.resize(4 + len, 0);
+    stream.read_exact(&mut packet[4..]).await?;
+    Ok(packet)
+}
+
+async fn write_error_packet(
+    stream: &mut (impl AsyncWrite + Unpin),
+    sequence_id: u8,
+    kind: ErrorKind,
+    sql_state: &[u8; 5],
+    message: &str,
+) -> Result<(), CubeError> {
+    let mut payload = vec![0xff];
+    payload.extend_from_slice(&(kind as u16).to_le_bytes());
+    payload.push(b'#');
+    payload.extend_from_slice(sql_state);
+    payload.extend_from_slice(message.as_bytes());
+    let mut packet = (payload.len() as u32).to_le_bytes().to_vec();
+    packet[3] = sequence_id;
+    packet.extend_from_slice(&payload);
+    stream.write_all(&packet).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+async fn loopback_pair() -> Result<(TcpStream, TcpStream), CubeError> {
+    let mut listener = TcpListener::bind("127.0.0.1:0").await?;
+    let client = TcpStream::connect(listener.local_addr()?).await?;
+    let (server, peer) = listener.accept().await?;
+    if peer != client.local_addr()? {
+        return Err(CubeError::internal(format!(
+            "Unexpected loopback connection from {}",
+            peer
+        )));
+    }
+    Ok((client, server))
+}
+
+/// Copies data both ways until both sides are closed.
+async fn relay(
+    a: impl AsyncRead + AsyncWrite,
+    b: impl AsyncRead + AsyncWrite,
+) -> Result<(), CubeError> {
+    let (mut a_read, mut a_write) = tokio::io::split(a);
+    let (mut b_read, mut b_write) = tokio::io::split(b);
+    let a_to_b = async {
+        tokio::io::copy(&mut a_read, &mut b_write).await?;
+        b_write.shutdown().await
+    };
+    let b_to_a = async {
+        tokio::io::copy(&mut b_read, &mut a_write).await?;
+        a_write.shutdown().await
+    };
+    futures::future::try_join(a_to_b, b_to_a).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cluster::tls::tests::TestCa;
+    use crate::config::Config;
+    use crate::config::MySqlTlsConfig;
+    use hex::ToHex;
+    use std::fs::File;
+    use std::io::{BufReader, Cursor};
+    use std::mem;
+    use std::net::SocketAddr;
+    use tokio_rustls::rustls::ClientConfig;
+    use tokio_rustls::TlsConnector;
+
+    #[test]
+    fn native_password() {
+        let salt = b"A;3#xu!0P]aQ%k6Zi~8,";
+        assert_eq!(
+            native_password_scramble(b"secret", salt).encode_hex::<String>(),
+            "f4a0c362d576678375ebce0b48b65fa68a26f605"
+        );
+        assert_ne!(
+            native_password_scramble(b"secret", salt),
+            native_password_scramble(b"secret", b"B;3#xu!0P]aQ%k6Zi~8,")
+        );
+    }
+
+    #[test]
+    fn column_types() {
+        let types = vec![
+            metastore::ColumnType::String,
+            metastore::ColumnType::Int,
+            metastore::ColumnType::Decimal {
+                scale: 2,
+                precision: 10,
+            },
+            metastore::ColumnType::Timestamp,
+            metastore::ColumnType::HyperLogLog,
+        ];
+        let columns = types
+            .iter()
+            .map(|t| column_definition("c", None, t))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            columns.iter().map(|c| c.coltype).collect::<Vec<_>>(),
+            vec![
+                ColumnType::MYSQL_TYPE_STRING,
+                ColumnType::MYSQL_TYPE_LONGLONG,
+                ColumnType::MYSQL_TYPE_NEWDECIMAL,
+                ColumnType::MYSQL_TYPE_DATETIME,
+                ColumnType::MYSQL_TYPE_BLOB,
+            ]
+        );
+        assert_eq!(columns[2].colflags, ColumnFlags::empty());
+        assert_eq!(
+            columns[4].colflags,
+            ColumnFlags::BINARY_FLAG | ColumnFlags::BLOB_FLAG
+        );
+        assert!(columns.iter().all(|c| c.table.is_empty()));
+        assert_eq!(decimal_with_scale("1.5", 2), "1.50");
+        assert_eq!(decimal_with_scale("-3", 2), "-3.00");
+    }
+
+    #[test]
+    fn bytes_params() {
+        assert_eq!(
+            param_value(ValueInner::Bytes(b"abc"), ColumnType::MYSQL_TYPE_VAR_STRING).unwrap(),
+            TableValue::String("abc".to_string())
+        );
+        assert_eq!(
+            param_value(ValueInner::Bytes(b"abc"), ColumnType::MYSQL_TYPE_BLOB).unwrap(),
+            TableValue::Bytes(b"abc".to_vec())
+        );
+        assert_eq!(
+            param_value(
+                ValueInner::Bytes(&[0, 159, 146, 150]),
+                ColumnType::MYSQL_TYPE_VAR_STRING
+            )
+            .unwrap(),
+            TableValue::Bytes(vec![0, 159, 146, 150])
+        );
+    }
+
+    #[tokio::test]
+    async fn users_over_mysql_protocol() {
+        let config = Config::test("mysql_users").update_config(|mut c| {
+            c.users = vec![
+                UserConfig {
+                    name: "admin".to_string(),
+                    password: "admin_secret".to_string(),
+                    allowed_schemas: None,
+                },
+                UserConfig {
+                    name: "reader".to_string(),
+                    password: "secret".to_string(),
+                    allowed_schemas: Some(vec!["foo".to_string()]),
+                },
+            ];
+            c
+        });
+        let config_obj = config.config_obj();
+        config
+            .start_test(async move |services| {
+                let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+                let address = listener.local_addr().unwrap();
+                tokio::spawn(MySqlServer::serve(
+                    listener,
+                    services.sql_service.clone(),
+                    config_obj,
+                ));
+
+                let mut admin = TestClient::connect(address, "admin", "admin_secret", None)
+                    .await
+                    .unwrap();
+                for q in vec![
+                    "CREATE SCHEMA foo",
+                    "CREATE SCHEMA bar",
+                    "CREATE TABLE foo.orders (id int, amount int)",
+                    "CREATE TABLE bar.secrets (id int, secret text)",
+                    "INSERT INTO foo.orders (id, amount) VALUES (1, 10)",
+                ] {
+                    admin.query(q).await.unwrap();
+                }
+
+                assert!(TestClient::connect(address, "reader", "wrong", None)
+                    .await
+                    .is_err());
+                assert!(TestClient::connect(address, "unknown", "secret", None)
+                    .await
+                    .is_err());
+
+                let mut reader = TestClient::connect(address, "reader", "secret", None)
+                    .await
+                    .unwrap();
+                assert!(reader.query("SELECT id FROM orders").await.is_err());
+                assert!(reader.init_db("bar").await.is_err());
+                reader.init_db("foo").await.unwrap();
+                assert_eq!(
+                    reader.query("SELECT id, amount FROM orders").await.unwrap(),
+                    vec![vec![Some("1".to_string()), Some("10".to_string())]]
+                );
+                assert_eq!(
+                    reader
+                        .query("SELECT id FROM foo.orders WHERE id = 2")
+                        .await
+                        .unwrap(),
+                    Vec::<Vec<Option<String>>>::new()
+                );
+                let error = reader
+                    .query("SELECT id FROM bar.secrets")
+                    .await
+                    .unwrap_err();
+                assert!(error.contains("Access denied"), "{}", error);
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn tls() {
+        let ca = TestCa::new("mysql_tls");
+        let server_tls = ca.config("server");
+        let config = Config::test("mysql_tls").update_config(|mut c| {
+            c.mysql_tls = Some(MySqlTlsConfig {
+                cert_path: server_tls.cert_path,
+                key_path: server_tls.key_path,
+                required: false,
+            });
+            c
+        });
+        let config_obj = config.config_obj();
+        let mut client_config = ClientConfig::new();
+        client_config
+            .root_store
+            .add_pem_file(&mut BufReader::new(File::open(ca.ca_path()).unwrap()))
+            .unwrap();
+        let tls_connector = TlsConnector::from(Arc::new(client_config));
+        config
+            .start_test(async move |services| {
+                let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+                let address = listener.local_addr().unwrap();
+                tokio::spawn(MySqlServer::serve(
+                    listener,
+                    services.sql_service.clone(),
+                    config_obj,
+                ));
+
+                let mut client = TestClient::connect(address, "root", "", Some(&tls_connector))
+                    .await
+                    .unwrap();
+                client.query("CREATE SCHEMA foo").await.unwrap();
+                client
+                    .query("CREATE TABLE foo.orders (id int, city text)")
+                    .await
+                    .unwrap();
+                client
+                    .query("INSERT INTO foo.orders (id, city) VALUES (1, 'San Francisco')")
+                    .await
+                    .unwrap();
+                assert_eq!(
+                    client
+                        .query("SELECT id, city FROM foo.orders")
+                        .await
+                        .unwrap(),
+                    vec![vec![
+                        Some("1".to_string()),
+                        Some("San Francisco".to_string())
+                    ]]
+                );
+
+                // TLS is optional.
+                let mut client = TestClient::connect(address, "root", "", None)
+                    .await
+                    .unwrap();
+                assert_eq!(
+                    client.query("SELECT id FROM foo.orders").await.unwrap(),
+                    vec![vec![Some("1".to_string())]]
+                );
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn tls_required() {
+        let ca = TestCa::new("mysql_tls_required");
+        let server_tls = ca.config("server");
+        let config = Config::test("mysql_tls_required").update_config(|mut c| {
+            c.mysql_tls = Some(MySqlTlsConfig {
+                cert_path: server_tls.cert_path,
+                key_path: server_tls.key_path,
+                required: true,
+            });
+            c
+        });
+        let config_obj = config.config_obj();
+        config
+            .start_test(async move |services| {
+                let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+                let address = listener.local_addr().unwrap();
+                tokio::spawn(MySqlServer::serve(
+                    listener,
+                    services.sql_service.clone(),
+                    config_obj,
+                ));
+
+                let error = TestClient::connect(address, "root", "", None)
+                    .await
+                    .err()
+                    .unwrap();
+                assert!(error.contains("without TLS"), "{}", error);
+            })
+            .await;
+    }
+
+    trait TestStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+    impl<T: AsyncRead + AsyncWrite + Unpin + Send> TestStream for T {}
+
+    /// Minimal client of the MySQL text protocol.
+    struct TestClient {
+        stream: Box<dyn TestStream>,
+        sequence_id: u8,
+    }
+
+    impl TestClient {
+        async fn connect(
+            address: SocketAddr,
+            user: &str,
+            password: &str,
+            tls_connector: Option<&TlsConnector>,
+        ) -> Result<TestClient, String> {
+            let mut client = TestClient {
+                stream: Box::new(TcpStream::connect(address).await.unwrap()),
+                sequence_id: 0,
+            };
+            // Handshake v10: version, server version, connection id, first 8 bytes of the
+            // salt, filler, capabilities, charset, status, capabilities, salt length, reserved
+            // and the rest of the salt.
+            let handshake = client.read_packet().await;
+            let salt_start = handshake.iter().position(|b| *b == 0).unwrap() + 1 + 4;
+            let mut salt = handshake[salt_start..salt_start + 8].to_vec();
+            let capabilities =
+                u16::from_le_bytes([handshake[salt_start + 9], handshake[salt_start + 10]]) as u32;
+            let salt_rest = salt_start + 8 + 1 + 2 + 1 + 2 + 2 + 1 + 10;
+            salt.extend_from_slice(&handshake[salt_rest..salt_rest + 12]);
+
+            // CLIENT_PROTOCOL_41 | CLIENT_SECURE_CONNECTION | CLIENT_PLUGIN_AUTH
+            let mut response = (0x200u32 | 0x8000 | 0x80000).to_le_bytes().to_vec();
+            response.extend_from_slice(&(1u32 << 24).to_le_bytes());
+            response.push(33);
+            response.extend_from_slice(&[0; 23]);
+            if let Some(tls_connector) = tls_connector {
+                assert_ne!(capabilities & CLIENT_SSL, 0);
+                response[1] |= (CLIENT_SSL >> 8) as u8;
+                client.write_packet(&response).await;
+                // The handshake continues over TLS without resetting the sequence id.
+                let stream = mem::replace(&mut client.stream, Box::new(Cursor::new(Vec::new())));
+                client.stream = Box::new(
+                    tls_connector
+                        .connect(tls::dns_name("localhost").unwrap(), stream)
+                        .await
+                        .unwrap(),
+                );
+            }
+
+            let auth_data = native_password_scramble(password.as_bytes(), &salt);
+            response.extend_from_slice(user.as_bytes());
+            response.push(0);
+            response.push(auth_data.len() as u8);
+            response.extend_from_slice(&auth_data);
+            response.extend_from_slice(b"mysql_native_password\0");
+            client.write_packet(&response).await;
+            client.read_ok().await?;
+            Ok(client)
+        }
+
+        async fn init_db(&mut self, schema: &str) -> Result<(), String> {
+            self.sequence_id = 0;
+            self.write_packet(&[&[0x02], schema.as_bytes()].concat())
+                .await;
+            self.read_ok().await
+        }
+
+        async fn query(&mut self, query: &str) -> Result<Vec<Vec<Option<String>>>, String> {
+            self.sequence_id = 0;
+            self.write_packet(&[&[0x03], query.as_bytes()].concat())
+                .await;
+            let packet = self.read_packet().await;
+            match packet[0] {
+                0x00 => return Ok(Vec::new()),
+                0xff => return Err(error_message(&packet)),
+                _ => {}
+            }
+            let (columns, _) = read_length_encoded(&packet);
+            for _ in 0..columns {
+                self.read_packet().await;
+            }
+            assert!(is_eof(&self.read_packet().await));
+            let mut rows = Vec::new();
+            loop {
+                let packet = self.read_packet().await;
+                if is_eof(&packet) {
+                    return Ok(rows);
+                }
+                if packet[0] == 0xff {
+                    return Err(error_message(&packet));
+                }
+                let mut row = Vec::new();
+                let mut rest = &packet[..];
+                while !rest.is_empty() {
+                    if rest[0] == 0xfb {
+                        row.push(None);
+                        rest = &rest[1..];
+                    } else {
+                        let (len, size) = read_length_encoded(rest);
+                        let value = &rest[size..size + len as usize];
+                        row.push(Some(String::from_utf8(value.to_vec()).unwrap()));
+                        rest = &rest[size + len as usize..];
+                    }
+                }
+                rows.push(row);
+            }
+        }
+
+        async fn read_ok(&mut self) -> Result<(), String> {
+            let packet = self.read_packet().await;
+            match packet[0] {
+                0x00 => Ok(()),
+                0xff => Err(error_message(&packet)),
+                x => panic!("Unexpected packet: {}", x),
+            }
+        }
+
+        async fn read_packet(&mut self) -> Vec<u8> {
+            let mut header = [0u8; 4];
+            self.stream.read_exact(&mut header).await.unwrap();
+            self.sequence_id = header[3].wrapping_add(1);
+            let mut payload =
+                vec![0u8; u32::from_le_bytes([header[0], header[1], header[2], 0]) as usize];
+            self.stream.read_exact(&mut payload).await.unwrap();
+            payload
+        }
+
+        async fn write_packet(&mut self, payload: &[u8]) {
+            let mut packet = (payload.len() as u32).to_le_bytes();
+            packet[3] = self.sequence_id;
+            self.sequence_id = self.sequence_id.wrapping_add(1);
+            self.stream.write_all(&packet).await.unwrap();
+            self.stream.write_all(payload).await.unwrap();
+        }
+    }
+
+    fn is_eof(packet: &[u8]) -> bool {
+        packet[0] == 0xfe && packet.len() < 9
+    }
+
+    /// Skips the error header, error code, SQL state marker and SQL state.
+    fn error_message(packet: &[u8]) -> String {
+        String::from_utf8_lossy(&packet[9..]).to_string()
+    }
+
+    /// Returns the length encoded integer and its size.
+    fn read_length_encoded(b: &[u8]) -> (u64, usize) {
+        match b[0] {
+            0xfc => (u16::from_le_bytes([b[1], b[2]]) as u64, 3),
+            0xfd => (u32::from_le_bytes([b[1], b[2], b[3], 0]) as u64, 4),
+            0xfe => {
+                let mut bytes = [0u8; 8];
+                bytes.copy_from_slice(&b[1..9]);
+                (u64::from_le_bytes(bytes), 9)
+            }
+            x => (x as u64, 1),
+        }
+    }
+}
@@ -1,3 +1,4 @@
+pub mod partition_filter;
 pub mod query_executor;
 pub mod serialized_plan;
 pub mod udfs;
@@ -15,7 +16,7 @@ use arrow::datatypes::Field;
 use arrow::{array::Array, datatypes::Schema, datatypes::SchemaRef};
 use arrow::{datatypes::DataType, record_batch::RecordBatch};
 use async_trait::async_trait;
-use datafusion::datasource::datasource::Statistics;
+use datafusion::datasource::datasource::{Statistics, TableProviderFilterPushDown};
 use datafusion::error::DataFusionError;
 use datafusion::logical_plan::{Expr, LogicalPlan};
 use datafusion::physical_plan::udaf::AggregateUDF;
@@ -313,6 +314,14 @@ impl TableProvider for CubeTableLogical {
         panic!("scan has been called on CubeTableLogical: serialized plan wasn't preprocessed for select");
     }
 
+    fn supports_filter_pushdown(
+        &self,
+        _filter: &Expr,
+    ) -> Result<TableProviderFilterPushDown, DataFusionError> {
+        // Filters are used for partition pruning only and are applied again afterwards.
+        Ok(TableProviderFilterPushDown::Inexact)
+    }
+
     fn statistics(&self) -> Statistics {
         // TODO
         Statistics {
@@ -0,0 +1,729 @@
+use crate::metastore::{Column, ColumnType, Index};
+use crate::table::parquet::min_max_from_statistics;
+use crate::table::{Row, TableValue, TimestampValue};
+use arrow::datatypes::DataType;
+use datafusion::logical_plan::{Expr, Operator};
+use datafusion::physical_plan::datetime_expressions::string_to_timestamp_nanos;
+use datafusion::physical_plan::functions::BuiltinScalarFunction;
+use datafusion::scalar::ScalarValue;
+use parquet::file::metadata::RowGroupMetaData;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::mem;
+
+/// Bounds on the sort key prefix extracted from the filters of a table scan.
+/// Used to skip partitions whose `[min_value, max_value)` range can't match.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartitionFilter {
+    /// Inclusive lower bound of the sort key prefix.
+    min: Vec<TableValue>,
+    /// Inclusive upper bound of the sort key prefix.
+    max: Vec<TableValue>,
+    sort_key_size: usize,
+    /// Filters contradict each other, nothing can match.
+    empty: bool,
+}
+
+/// Inclusive range of column values allowed by filters.
+#[derive(Debug, Clone)]
+pub struct ColumnRange {
+    min: Option<TableValue>,
+    max: Option<TableValue>,
+}
+
+impl ColumnRange {
+    fn unbounded() -> ColumnRange {
+        ColumnRange {
+            min: None,
+            max: None,
+        }
+    }
+
+    fn is_unbounded(&self) -> bool {
+        self.min.is_none() && self.max.is_none()
+    }
+
+    fn is_empty(&self) -> bool {
+        match (&self.min, &self.max) {
+            (Some(min), Some(max)) => compare_values(min, max) == Ordering::Greater,
+            _ => false,
+        }
+    }
+
+    /// Checks if any value of `[min, max]` can be within the range.
+    pub fn can_match(&self, min: &TableValue, max: &TableValue) -> bool {
+        self.min
+            .as_ref()
+            .map(|m| compare_values(m, max) != Ordering::Greater)
+            .unwrap_or(true)
+            && self
+                .max
+                .as_ref()
+                .map(|m| compare_values(m, min) != Ordering::Less)
+                .unwrap_or(true)
+    }
+}
+
+/// Column ranges extracted from the filters of a table scan.
+/// Used to skip parquet row groups by their min/max statistics.
+#[derive(Debug, Clone)]
+pub struct RowGroupFilter {
+    /// Position of column in index, its name in files, type and range.
+    ranges: Vec<(usize, String, ColumnType, ColumnRange)>,
+    empty: bool,
+}
+
+impl RowGroupFilter {
+    /// Returns `None` if filters don't restrict any of the index columns.
+    pub fn extract(index: &Index, filters: &[Expr]) -> Option<RowGroupFilter> {
+        let columns = index.get_columns();
+        let mut empty = false;
+        let ranges = column_ranges(columns, filters)
+            .into_iter()
+            .enumerate()
+            .filter(|(_, r)| !r.is_unbounded())
+            .map(|(i, r)| {
+                empty |= r.is_empty();
+                (
+                    i,
+                    index.storage_name(&columns[i]).clone(),
+                    columns[i].get_column_type().clone(),
+                    r,
+                )
+            })
+            .collect::<Vec<_>>();
+        if ranges.is_empty() {
+            return None;
+        }
+        Some(RowGroupFilter { ranges, empty })
+    }
+
+    /// Files written without full statistics have min/max only for the first column:
+    /// first and last values of a row group were stored for the rest of the sort key.
+    pub fn can_match(&self, row_group: &RowGroupMetaData, full_statistics: bool) -> bool {
+        if self.empty {
+            return false;
+        }
+        for (i, storage_name, column_type, range) in self.ranges.iter() {
+            let position = if *i < row_group.num_columns()
+                && row_group.column(*i).column_descr().name() == storage_name.as_str()
+            {
+                Some(*i)
+            } else {
+                // Schema of the table has been altered after the file was written.
+                row_group
+                    .columns()
+                    .iter()
+                    .position(|c| c.column_descr().name() == storage_name.as_str())
+            };
+            let position = match position {
+                Some(position) => position,
+                None => continue,
+            };
+            if position != 0 && !full_statistics {
+                continue;
+            }
+            let min_max = row_group
+                .column(position)
+                .statistics()
+                .and_then(|s| min_max_from_statistics(column_type, s));
+            if let Some((min, max)) = min_max {
+                if !range.can_match(&min, &max) {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+}
+
+impl PartitionFilter {
+    pub fn extract(index: &Index, filters: &[Expr]) -> PartitionFilter {
+        let sort_key_size = index.sort_key_size() as usize;
+        let ranges = column_ranges(&index.get_columns()[0..sort_key_size], filters);
+        PartitionFilter::from_ranges(ranges, sort_key_size)
+    }
+
+    fn from_ranges(ranges: Vec<ColumnRange>, sort_key_size: usize) -> PartitionFilter {
+        let mut min = Vec::new();
+        let mut max = Vec::new();
+        let mut empty = false;
+        for range in ranges.into_iter() {
+            match (range.min, range.max) {
+                (Some(range_min), Some(range_max)) => {
+                    match compare_values(&range_min, &range_max) {
+                        Ordering::Greater => {
+                            empty = true;
+                            break;
+                        }
+                        Ordering::Equal => {
+                            min.push(range_min);
+                            max.push(range_max);
+                        }
+                        Ordering::Less => {
+                            min.push(range_min);
+                            max.push(range_max);
+                            break;
+                        }
+                    }
+                }
+                (range_min, range_max) => {
+                    min.extend(range_min);
+                    max.extend(range_max);
+                    break;
+                }
+            }
+        }
+
+        PartitionFilter {
+            min,
+            max,
+            sort_key_size,
+            empty,
+        }
+    }
+
+    /// Partition rows are within `[min_value, max_value)` by sort key.
+    /// `None` stands for an unbounded side.
+    pub fn can_match(&self, min_value: &Option<Row>, max_value: &Option<Row>) -> bool {
+        if self.empty {
+            return false;
+        }
+        if let (Some(max_value), false) = (max_value, self.min.is_empty()) {
+            match compare_prefix(max_value, &self.min) {
+                Ordering::Less => return false,
+                // Upper bound of partition is exclusive.
+                Ordering::Equal if self.min.len() == self.sort_key_size => return false,
+                _ => {}
+            }
+        }
+        if let Some(min_value) = min_value {
+            if !self.max.is_empty() && compare_prefix(min_value, &self.max) == Ordering::Greater {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Predicate of `DELETE` and `TRUNCATE` statements evaluated against rows of an index.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub enum RowFilter {
+    All,
+    Compare {
+        column: String,
+        op: Operator,
+        value: TableValue,
+    },
+    IsNull {
+        column: String,
+        negated: bool,
+    },
+    InList {
+        column: String,
+        list: Vec<TableValue>,
+        negated: bool,
+    },
+    And(Box<RowFilter>, Box<RowFilter>),
+    Or(Box<RowFilter>, Box<RowFilter>),
+    Not(Box<RowFilter>),
+}
+
+impl RowFilter {
+    /// Rows for which the predicate evaluates to NULL don't match as in SQL `WHERE`.
+    pub fn matches(&self, index: &Index, row: &Row) -> bool {
+        self.evaluate(index, row) == Some(true)
+    }
+
+    fn evaluate(&self, index: &Index, row: &Row) -> Option<bool> {
+        match self {
+            RowFilter::All => Some(true),
+            RowFilter::Compare { column, op, value } => {
+                let ordering = compare_nullable(row_value(index, row, column)?, value)?;
+                match op {
+                    Operator::Eq => Some(ordering == Ordering::Equal),
+                    Operator::NotEq => Some(ordering != Ordering::Equal),
+                    Operator::Lt => Some(ordering == Ordering::Less),
+                    Operator::LtEq => Some(ordering != Ordering::Greater),
+                    Operator::Gt => Some(ordering == Ordering::Greater),
+                    Operator::GtEq => Some(ordering != Ordering::Less),
+                    _ => None,
+                }
+            }
+            RowFilter::IsNull { column, negated } => {
+                let is_null = row_value(index, row, column)
+                    .map(|v| v == &TableValue::Null)
+                    .unwrap_or(true);
+                Some(is_null != *negated)
+            }
+            RowFilter::InList {
+                column,
+                list,
+                negated,
+            } => {
+                let value = row_value(index, row, column)?;
+                let mut res = Some(false);
+                for v in list.iter() {
+                    match compare_nullable(value, v) {
+                        Some(Ordering::Equal) => {
+                            res = Some(true);
+                            break;
+                        }
+                        None => res = None,
+                        _ => {}
+                    }
+                }
+                res.map(|r| r != *negated)
+            }
+            RowFilter::And(left, right) => {
+                match (left.evaluate(index, row), right.evaluate(index, row)) {
+                    (Some(false), _) | (_, Some(false)) => Some(false),
+                    (Some(true), Some(true)) => Some(true),
+                    _ => None,
+                }
+            }
+            RowFilter::Or(left, right) => {
+                match (left.evaluate(index, row), right.evaluate(index, row)) {
+                    (Some(true), _) | (_, Some(true)) => Some(true),
+                    (Some(false), Some(false)) => Some(false),
+                    _ => None,
+                }
+            }
+            RowFilter::Not(filter) => filter.evaluate(index, row).map(|r| !r),
+        }
+    }
+
+    /// Sort key bounds of rows that can match the predicate.
+    pub fn partition_filter(&self, index: &Index) -> PartitionFilter {
+        let sort_key_size = index.sort_key_size() as usize;
+        let columns = &index.get_columns()[0..sort_key_size];
+        let mut ranges = columns
+            .iter()
+            .map(|_| ColumnRange::unbounded())
+            .collect::<Vec<_>>();
+        self.collect_ranges(columns, &mut ranges);
+        PartitionFilter::from_ranges(ranges, sort_key_size)
+    }
+
+    fn collect_ranges(&self, columns: &[Column], ranges: &mut Vec<ColumnRange>) {
+        match self {
+            RowFilter::And(left, right) => {
+                left.collect_ranges(columns, ranges);
+                right.collect_ranges(columns, ranges);
+            }
+            RowFilter::Compare { column, op, value } => {
+                if let Some(i) = columns.iter().position(|c| c.get_name() == column) {
+                    if has_value_order(columns[i].get_column_type()) && value != &TableValue::Null {
+                        apply_op(&mut ranges[i], op, value.clone());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Checks if every row of a partition with `[min_value, max_value)` sort key range matches
+    /// the predicate. Only conditions on the first column of the sort key are considered.
+    pub fn matches_partition(
+        &self,
+        index: &Index,
+        min_value: &Option<Row>,
+        max_value: &Option<Row>,
+    ) -> bool {
+        match self {
+            RowFilter::All => true,
+            RowFilter::And(left, right) => {
+                left.matches_partition(index, min_value, max_value)
+                    && right.matches_partition(index, min_value, max_value)
+            }
+            RowFilter::Or(left, right) => {
+                left.matches_partition(index, min_value, max_value)
+                    || right.matches_partition(index, min_value, max_value)
+            }
+            RowFilter::Compare { column, op, value } => {
+                if index.sort_key_size() == 0 || value == &TableValue::Null {
+                    return false;
+                }
+                let first = &index.get_columns()[0];
+                if first.get_name() != column || !has_value_order(first.get_column_type()) {
+                    return false;
+                }
+                // NULLs go first so rows can't have them if the lower bound doesn't.
+                let min = match min_value.as_ref().map(|r| &r.values()[0]) {
+                    Some(TableValue::Null) | None => return false,
+                    Some(min) => min,
+                };
+                let max = max_value.as_ref().map(|r| &r.values()[0]);
+                match (op, max) {
+                    (Operator::Gt, _) => compare_values(min, value) == Ordering::Greater,
+                    (Operator::GtEq, _) => compare_values(min, value) != Ordering::Less,
+                    (Operator::Eq, Some(max)) => {
+                        compare_values(min, value) == Ordering::Equal
+                            && compare_values(max, value) == Ordering::Equal
+                    }
+                    (Operator::Lt, Some(max)) => match compare_values(max, value) {
+                        Ordering::Less => true,
+                        // Upper bound of partition is exclusive.
+                        Ordering::Equal => index.sort_key_size() == 1,
+                        Ordering::Greater => false,
+                    },
+                    (Operator::LtEq, Some(max)) => compare_values(max, value) != Ordering::Greater,
+                    _ => false,
+                }
+            }
+            _ => false,
+        }
+    }
+}
+
+fn row_value<'a>(index: &Index, row: &'a Row, column: &str) -> Option<&'a TableValue> {
+    index
+        .get_columns()
+        .iter()
+        .position(|c| c.get_name() == column)
+        .and_then(|i| row.values().get(i))
+}
+
+/// Decimals and floats are stored as strings and can't be compared by the sort order.
+fn has_value_order(column_type: &ColumnType) -> bool {
+    match column_type {
+        ColumnType::Decimal { .. } | ColumnType::Float => false,
+        _ => true,
+    }
+}
+
+fn compare_nullable(a: &TableValue, b: &TableValue) -> Option<Ordering> {
+    match (a, b) {
+        (TableValue::Null, _) | (_, TableValue::Null) => None,
+        (TableValue::Decimal(a), TableValue::Decimal(b))
+        | (TableValue::Float(a), TableValue::Float(b)) => {
+            a.parse::<f64>().ok()?.partial_cmp(&b.parse::<f64>().ok()?)
+        }
+        (a, b) if mem::discriminant(a) == mem::discriminant(b) => Some(compare_values(a, b)),
+        _ => None,
+    }
+}
+
+fn column_ranges(columns: &[Column], filters: &[Expr]) -> Vec<ColumnRange> {
+    let mut ranges = columns
+        .iter()
+        .map(|_| ColumnRange::unbounded())
+        .collect::<Vec<_>>();
+    for f in filters {
+        collect_ranges(columns, f, &mut ranges);
+    }
+    ranges
+}
+
+fn collect_ranges(columns: &[Column], e: &Expr, ranges: &mut Vec<ColumnRange>) {
+    match e {
+        Expr::BinaryExpr {
+            left,
+            op: Operator::And,
+            right,
+        } => {
+            collect_ranges(columns, left, ranges);
+            collect_ranges(columns, right, ranges);
+        }
+        Expr::BinaryExpr { left, op, right } => {
+            if let Some((i, v)) = column_and_value(columns, left, right) {
+                apply_op(&mut ranges[i], op, v);
+            } else if let Some((i, v)) = column_and_value(columns, right, left) {
+                let op = match op {
+                    Operator::Lt => Operator::Gt,
+                    Operator::LtEq => Operator::GtEq,
+                    Operator::Gt => Operator::Lt,
+                    Operator::GtEq => Operator::LtEq,
+                    op => op.clone(),
+                };
+                apply_op(&mut ranges[i], &op, v);
+            }
+        }
+        Expr::Between {
+            expr,
+            negated: false,
+            low,
+            high,
+        } => {
+            if let Some((i, v)) = column_and_value(columns, expr, low) {
+                apply_op(&mut ranges[i], &Operator::GtEq, v);
+            }
+            if let Some((i, v)) = column_and_value(columns, expr, high) {
+                apply_op(&mut ranges[i], &Operator::LtEq, v);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn apply_op(range: &mut ColumnRange, op: &Operator, v: TableValue) {
+    // Strict comparisons are treated as non-strict: pruning stays conservative.
+    match op {
+        Operator::Eq => {
+            narrow_min(range, v.clone());
+            narrow_max(range, v);
+        }
+        Operator::Gt | Operator::GtEq => narrow_min(range, v),
+        Operator::Lt | Operator::LtEq => narrow_max(range, v),
+        _ => {}
+    }
+}
+
+fn narrow_min(range: &mut ColumnRange, v: TableValue) {
+    if range
+        .min
+        .as_ref()
+        .map(|m| compare_values(m, &v) == Ordering::Less)
+        .unwrap_or(true)
+    {
+        range.min = Some(v);
+    }
+}
+
+fn narrow_max(range: &mut ColumnRange, v: TableValue) {
+    if range
+        .max
+        .as_ref()
+        .map(|m| compare_values(m, &v) == Ordering::Greater)
+        .unwrap_or(true)
+    {
+        range.max = Some(v);
+    }
+}
+
+fn column_and_value(
+    columns: &[Column],
+    column: &Expr,
+    value: &Expr,
+) -> Option<(usize, TableValue)> {
+    let name = match column {
+        Expr::Column(name, _) => name,
+        _ => return None,
+    };
+    let (i, c) = columns
+        .iter()
+        .enumerate()
+        .find(|(_, c)| c.get_name() == name)?;
+    Some((i, table_value(c.get_column_type(), value)?))
+}
+
+fn table_value(column_type: &ColumnType, e: &Expr) -> Option<TableValue> {
+    match (column_type, e) {
+        (ColumnType::Int, Expr::Literal(v)) => match v {
+            ScalarValue::Int8(Some(v)) => Some(TableValue::Int(*v as i64)),
+            ScalarValue::Int16(Some(v)) => Some(TableValue::Int(*v as i64)),
+            ScalarValue::Int32(Some(v)) => Some(TableValue::Int(*v as i64)),
+            ScalarValue::Int64(Some(v)) => Some(TableValue::Int(*v)),
+            ScalarValue::UInt8(Some(v)) => Some(TableValue::Int(*v as i64)),
+            ScalarValue::UInt16(Some(v)) => Some(TableValue::Int(*v as i64)),
+            ScalarValue::UInt32(Some(v)) => Some(TableValue::Int(*v as i64)),
+            _ => None,
+        },
+        (ColumnType::String, Expr::Literal(v)) => match v {
+            ScalarValue::Utf8(Some(v)) | ScalarValue::LargeUtf8(Some(v)) => {
+                Some(TableValue::String(v.to_string()))
+            }
+            _ => None,
+        },
+        (ColumnType::Boolean, Expr::Literal(ScalarValue::Boolean(Some(v)))) => {
+            Some(TableValue::Boolean(*v))
+        }
+        (ColumnType::Timestamp, Expr::Literal(v)) => match v {
+            ScalarValue::TimestampNanosecond(Some(v)) => {
+                Some(TableValue::Timestamp(TimestampValue::new(*v)))
+            }
+            ScalarValue::TimestampMicrosecond(Some(v)) => {
+                Some(TableValue::Timestamp(TimestampValue::new(*v * 1000)))
+            }
+            ScalarValue::Utf8(Some(v)) => string_to_timestamp_nanos(v)
+                .ok()
+                .map(|v| TableValue::Timestamp(TimestampValue::new(v))),
+            _ => None,
+        },
+        (
+            ColumnType::Timestamp,
+            Expr::ScalarFunction {
+                fun: BuiltinScalarFunction::ToTimestamp,
+                args,
+            },
+        ) if args.len() == 1 => table_value(column_type, &args[0]),
+        (
+            ColumnType::Timestamp,
+            Expr::Cast {
+                expr,
+                data_type: DataType::Timestamp(_, _),
+            },
+        ) => table_value(column_type, expr),
+        // Decimals and floats are stored as strings and can't be compared by value.
+        _ => None,
+    }
+}
+
+fn compare_values(a: &TableValue, b: &TableValue) -> Ordering {
+    let a = Row::new(vec![a.clone()]);
+    let b = Row::new(vec![b.clone()]);
+    a.sort_key(1).cmp(&b.sort_key(1))
+}
+
+fn compare_prefix(row: &Row, prefix: &Vec<TableValue>) -> Ordering {
+    let size = prefix.len().min(row.len());
+    let row_prefix = Row::new(row.values()[0..size].to_vec());
+    let prefix = Row::new(prefix[0..size].to_vec());
+    row_prefix
+        .sort_key(size as u64)
+        .cmp(&prefix.sort_key(size as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index() -> Index {
+        Index::try_new(
+            "by_a_b".to_string(),
+            1,
+            vec![
+                Column::new("a".to_string(), ColumnType::Int, 0),
+                Column::new("b".to_string(), ColumnType::String, 1),
+                Column::new("c".to_string(), ColumnType::Int, 2),
+            ],
+            2,
+        )
+        .unwrap()
+    }
+
+    fn col(name: &str) -> Expr {
+        Expr::Column(name.to_string(), None)
+    }
+
+    fn int(v: i64) -> Expr {
+        Expr::Literal(ScalarValue::Int64(Some(v)))
+    }
+
+    fn string(v: &str) -> Expr {
+        Expr::Literal(ScalarValue::Utf8(Some(v.to_string())))
+    }
+
+    fn bin(left: Expr, op: Operator, right: Expr) -> Expr {
+        Expr::BinaryExpr {
+            left: Box::new(left),
+            op,
+            right: Box::new(right),
+        }
+    }
+
+    fn row(values: Vec<TableValue>) -> Option<Row> {
+        Some(Row::new(values))
+    }
+
+    #[test]
+    fn range_on_first_column() {
+        let filter = PartitionFilter::extract(
+            &index(),
+            &[
+                bin(col("a"), Operator::GtEq, int(10)),
+                bin(int(20), Operator::Gt, col("a")),
+            ],
+        );
+        assert!(!filter.can_match(&None, &row(vec![TableValue::Int(5), TableValue::Null])));
+        assert!(filter.can_match(&None, &row(vec![TableValue::Int(10), TableValue::Null])));
+        assert!(filter.can_match(&row(vec![TableValue::Int(15), TableValue::Null]), &None));
+        assert!(!filter.can_match(&row(vec![TableValue::Int(21), TableValue::Null]), &None));
+        assert!(filter.can_match(&None, &None));
+    }
+
+    #[test]
+    fn equality_prefix() {
+        let filter = PartitionFilter::extract(
+            &index(),
+            &[bin(
+                bin(col("a"), Operator::Eq, int(10)),
+                Operator::And,
+                bin(col("b"), Operator::Eq, string("foo")),
+            )],
+        );
+        let ten = |s: &str| row(vec![TableValue::Int(10), TableValue::String(s.to_string())]);
+        assert!(!filter.can_match(&None, &ten("foo")));
+        assert!(filter.can_match(&None, &ten("foz")));
+        assert!(!filter.can_match(&ten("fop"), &None));
+        assert!(filter.can_match(&ten("foo"), &ten("foz")));
+    }
+
+    #[test]
+    fn ignored_filters() {
+        let filter = PartitionFilter::extract(
+            &index(),
+            &[
+                bin(col("c"), Operator::Eq, int(10)),
+                bin(col("b"), Operator::Eq, string("foo")),
+                bin(col("a"), Operator::NotEq, int(10)),
+            ],
+        );
+        assert!(filter.can_match(&row(vec![TableValue::Int(100), TableValue::Null]), &None));
+        assert!(filter.can_match(&None, &row(vec![TableValue::Int(-100), TableValue::Null])));
+    }
+
+    #[test]
+    fn row_filter() {
+        let compare = |column: &str, op: Operator, value: TableValue| RowFilter::Compare {
+            column: column.to_string(),
+            op,
+            value,
+        };
+        let filter = RowFilter::And(
+            Box::new(compare("a", Operator::GtEq, TableValue::Int(10))),
+            Box::new(RowFilter::Not(Box::new(RowFilter::InList {
+                column: "b".to_string(),
+                list: vec![TableValue::String("foo".to_string())],
+                negated: false,
+            }))),
+        );
+        let index = index();
+        let values = |a: TableValue, b: TableValue| Row::new(vec![a, b, TableValue::Int(1)]);
+        let bar = TableValue::String("bar".to_string());
+        let foo = TableValue::String("foo".to_string());
+        assert!(filter.matches(&index, &values(TableValue::Int(10), bar.clone())));
+        assert!(!filter.matches(&index, &values(TableValue::Int(10), foo.clone())));
+        assert!(!filter.matches(&index, &values(TableValue::Int(5), bar.clone())));
+        assert!(!filter.matches(&index, &values(TableValue::Null, bar.clone())));
+        assert!(!filter.matches(&index, &values(TableValue::Int(10), TableValue::Null)));
+
+        let partition_filter = filter.partition_filter(&index);
+        assert!(!partition_filter.can_match(&None, &row(vec![TableValue::Int(5), bar.clone()])));
+        assert!(partition_filter.can_match(&row(vec![TableValue::Int(5), bar.clone()]), &None));
+
+        let range = RowFilter::And(
+            Box::new(compare("a", Operator::GtEq, TableValue::Int(10))),
+            Box::new(compare("a", Operator::Lt, TableValue::Int(20))),
+        );
+        let bounds = |min: i64, max: i64| {
+            (
+                row(vec![TableValue::Int(min), TableValue::Null]),
+                row(vec![TableValue::Int(max), TableValue::Null]),
+            )
+        };
+        let (min, max) = bounds(10, 15);
+        assert!(range.matches_partition(&index, &min, &max));
+        let (min, max) = bounds(10, 20);
+        assert!(!range.matches_partition(&index, &min, &max));
+        let (min, max) = bounds(5, 15);
+        assert!(!range.matches_partition(&index, &min, &max));
+        assert!(!range.matches_partition(&index, &None, &max));
+        assert!(RowFilter::All.matches_partition(&index, &None, &None));
+        assert!(!filter.matches_partition(&index, &min, &max));
+    }
+
+    #[test]
+    fn contradiction() {
+        let filter = PartitionFilter::extract(
+            &index(),
+            &[Expr::Between {
+                expr: Box::new(col("a")),
+                negated: false,
+                low: Box::new(int(10)),
+                high: Box::new(int(5)),
+            }],
+        );
+        assert!(!filter.can_match(&None, &None));
+    }
+}
@@ -1,8 +1,11 @@
+use crate::cluster::hash_ring::HashRing;
 use crate::cluster::Cluster;
 use crate::metastore::table::Table;
 use crate::metastore::{Column, ColumnType, IdRow, Index, Partition};
+use crate::queryplanner::partition_filter::RowGroupFilter;
 use crate::queryplanner::serialized_plan::{IndexSnapshot, SerializedPlan};
-use crate::store::DataFrame;
+use crate::store::{columns_schema, null_array, DataFrame, DataStream};
+use crate::table::parquet::RowGroupFilteredReader;
 use crate::table::{Row, TableValue, TimestampValue};
 use crate::CubeError;
 use arrow::array::{
@@ -12,6 +15,7 @@ use arrow::array::{
     TimestampNanosecondArray, UInt64Array,
 };
 use arrow::datatypes::{DataType, Schema, SchemaRef, TimeUnit};
+use arrow::error::{ArrowError, Result as ArrowResult};
 use arrow::ipc::reader::StreamReader;
 use arrow::ipc::writer::MemStreamWriter;
 use arrow::record_batch::RecordBatch;
@@ -23,7 +27,7 @@ use datafusion::datasource::TableProvider;
 use datafusion::error::DataFusionError;
 use datafusion::error::Result as DFResult;
 use datafusion::execution::context::{ExecutionConfig, ExecutionContext};
-use datafusion::logical_plan::{DFSchemaRef, Expr, ToDFSchema};
+use datafusion::logical_plan::{DFSchemaRef, Expr, LogicalPlan, ToDFSchema};
 use datafusion::physical_plan::empty::EmptyExec;
 use datafusion::physical_plan::hash_aggregate::HashAggregateExec;
 use datafusion::physical_plan::limit::GlobalLimitExec;
@@ -33,19 +37,26 @@ use datafusion::physical_plan::merge_sort::MergeSortExec;
 use datafusion::physical_plan::parquet::ParquetExec;
 use datafusion::physical_plan::sort::SortExec;
 use datafusion::physical_plan::{collect, ExecutionPlan, Partitioning, RecordBatchStream};
+use futures::{Stream, StreamExt};
 use itertools::Itertools;
 use log::{debug, error, trace, warn};
 use mockall::automock;
 use num::BigInt;
+use parquet::arrow::{ArrowReader, ParquetFileArrowReader};
+use parquet::file::reader::{FileReader, SerializedFileReader};
 use regex::Regex;
 use serde_derive::{Deserialize, Serialize};
 use std::any::Any;
 use std::collections::{HashMap, HashSet};
 use std::fmt::Formatter;
+use std::fs::File;
 use std::io::Cursor;
 use std::pin::Pin;
+use std::rc::Rc;
 use std::sync::Arc;
+use std::task::{Context, Poll};
 use std::time::SystemTime;
+use tokio::sync::mpsc;
 
 #[automock]
 #[async_trait]
@@ -56,6 +67,13 @@ pub trait QueryExecutor: Send + Sync {
         cluster: Arc<dyn Cluster>,
     ) -> Result<DataFrame, CubeError>;
 
+    /// Same as `execute_router_plan` but batches are returned as they're produced.
+    async fn execute_router_plan_stream(
+        &self,
+        plan: SerializedPlan,
+        cluster: Arc<dyn Cluster>,
+    ) -> Result<DataStream, CubeError>;
+
     async fn execute_worker_plan(
         &self,
         plan: SerializedPlan,
@@ -65,6 +83,78 @@ pub trait QueryExecutor: Send + Sync {
 
 pub struct QueryExecutorImpl;
 
+/// `execution_time` is when the execution of the router plan started.
+fn log_router_query(
+    execution_time: SystemTime,
+    plan_to_move: &LogicalPlan,
+    split_plan: &Arc<dyn ExecutionPlan>,
+    failed: bool,
+) -> Result<(), CubeError> {
+    debug!(
+        "Query data processing time: {:?}",
+        execution_time.elapsed()?
+    );
+    if execution_time.elapsed()?.as_millis() > 200 {
+        warn!(
+            "Slow Query ({:?}):\n{:#?}",
+            execution_time.elapsed()?,
+            plan_to_move
+        );
+        debug!(
+            "Slow Query Physical Plan ({:?}): {:#?}",
+            execution_time.elapsed()?,
+            split_plan
+        );
+    }
+    if failed {
+        error!(
+            "Error Query ({:?}):\n{:#?}",
+            execution_time.elapsed()?,
+            plan_to_move
+        );
+        error!(
+            "Error Query Physical Plan ({:?}): {:#?}",
+            execution_time.elapsed()?,
+            split_plan
+        );
+    }
+    Ok(())
+}
+
+/// Logs the query like `execute_router_plan` once its batches end or fail.
+struct LoggedRouterStream {
+    batches: Pin<Box<dyn Stream<Item = Result<RecordBatch, CubeError>> + Send>>,
+    execution_time: SystemTime,
+    plan_to_move: LogicalPlan,
+    split_plan: Arc<dyn ExecutionPlan>,
+    logged: bool,
+}
+
+impl Stream for LoggedRouterStream {
+    type Item = Result<RecordBatch, CubeError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = self.batches.as_mut().poll_next(cx);
+        let failed = match &poll {
+            Poll::Ready(Some(Err(_))) => true,
+            Poll::Ready(None) => false,
+            _ => return poll,
+        };
+        if !self.logged {
+            self.logged = true;
+            if let Err(e) = log_router_query(
+                self.execution_time,
+                &self.plan_to_move,
+                &self.split_plan,
+                failed,
+            ) {
+                error!("Error logging query: {}", e);
+            }
+        }
+        poll
+    }
+}
+
 #[async_trait]
 impl QueryExecutor for QueryExecutorImpl {
     async fn execute_router_plan(
@@ -72,56 +162,45 @@ impl QueryExecutor for QueryExecutorImpl {
         plan: SerializedPlan,
         cluster: Arc<dyn Cluster>,
     ) -> Result<DataFrame, CubeError> {
-        let plan_to_move = plan.logical_plan(&HashMap::new())?;
-        let ctx = self.execution_context()?;
-        let plan_ctx = ctx.clone();
-
-        let serialized_plan = Arc::new(plan);
-        let physical_plan = plan_ctx.create_physical_plan(&plan_to_move.clone())?;
-        let available_nodes = cluster.available_nodes().await?;
-        let split_plan = self.get_router_split_plan(
-            physical_plan,
-            serialized_plan.clone(),
-            cluster,
-            available_nodes,
-        )?;
-
-        trace!("Router Query Physical Plan: {:#?}", &split_plan);
+        let (split_plan, plan_to_move) = self.router_plan(plan, cluster).await?;
 
         let execution_time = SystemTime::now();
         let results = collect(split_plan.clone()).await;
-        debug!(
-            "Query data processing time: {:?}",
-            execution_time.elapsed()?
-        );
-        if execution_time.elapsed()?.as_millis() > 200 {
-            warn!(
-                "Slow Query ({:?}):\n{:#?}",
-                execution_time.elapsed()?,
-                plan_to_move
-            );
-            debug!(
-                "Slow Query Physical Plan ({:?}): {:#?}",
-                execution_time.elapsed()?,
-                &split_plan
-            );
-        }
-        if results.is_err() {
-            error!(
-                "Error Query ({:?}):\n{:#?}",
-                execution_time.elapsed()?,
-                plan_to_move
-            );
-            error!(
-                "Error Query Physical Plan ({:?}): {:#?}",
-                execution_time.elapsed()?,
-                &split_plan
-            );
-        }
+        log_router_query(execution_time, &plan_to_move, &split_plan, results.is_err())?;
         let data_frame = batch_to_dataframe(&results?)?;
         Ok(data_frame)
     }
 
+    async fn execute_router_plan_stream(
+        &self,
+        plan: SerializedPlan,
+        cluster: Arc<dyn Cluster>,
+    ) -> Result<DataStream, CubeError> {
+        let (split_plan, plan_to_move) = self.router_plan(plan, cluster).await?;
+        let schema = split_plan.schema().to_schema_ref();
+        let merged_plan: Arc<dyn ExecutionPlan> =
+            match split_plan.output_partitioning().partition_count() {
+                1 => split_plan.clone(),
+                _ => Arc::new(MergeExec::new(split_plan.clone())),
+            };
+        let execution_time = SystemTime::now();
+        let batches = match merged_plan.execute(0).await {
+            Ok(batches) => batches,
+            Err(e) => {
+                log_router_query(execution_time, &plan_to_move, &split_plan, true)?;
+                return Err(e.into());
+            }
+        };
+        let batches = LoggedRouterStream {
+            batches: Box::pin(batches.map(|b| Ok(b?))),
+            execution_time,
+            plan_to_move,
+            split_plan,
+            logged: false,
+        };
+        Ok(DataStream::new(schema, Box::pin(batches)))
+    }
+
     async fn execute_worker_plan(
         &self,
         plan: SerializedPlan,
@@ -172,6 +251,28 @@ impl QueryExecutor for QueryExecutorImpl {
 }
 
 impl QueryExecutorImpl {
+    async fn router_plan(
+        &self,
+        plan: SerializedPlan,
+        cluster: Arc<dyn Cluster>,
+    ) -> Result<(Arc<dyn ExecutionPlan>, LogicalPlan), CubeError> {
+        let plan_to_move = plan.logical_plan(&HashMap::new())?;
+        let ctx = self.execution_context()?;
+
+        let serialized_plan = Arc::new(plan);
+        let physical_plan = ctx.create_physical_plan(&plan_to_move.clone())?;
+        let available_nodes = cluster.available_nodes().await?;
+        let split_plan = self.get_router_split_plan(
+            physical_plan,
+            serialized_plan.clone(),
+            cluster,
+            available_nodes,
+        )?;
+
+        trace!("Router Query Physical Plan: {:#?}", &split_plan);
+        Ok((split_plan, plan_to_move))
+    }
+
     fn execution_context(&self) -> Result<Arc<ExecutionContext>, CubeError> {
         let ctx = ExecutionContext::with_config(
             ExecutionConfig::new()
@@ -409,10 +510,12 @@ impl CubeTable {
         &self,
         projection: &Option<Vec<usize>>,
         batch_size: usize,
+        filters: &[Expr],
     ) -> Result<Arc<dyn ExecutionPlan>, CubeError> {
         let table = self.index_snapshot.table();
         let index = self.index_snapshot.index();
         let partition_snapshots = self.index_snapshot.partitions();
+        let row_group_filter = RowGroupFilter::extract(index.get_row(), filters);
 
         let mut partition_execs = Vec::<Arc<dyn ExecutionPlan>>::new();
 
@@ -437,13 +540,13 @@ impl CubeTable {
                     .remote_to_local_names
                     .get(remote_path.as_str())
                     .expect(format!("Missing remote path {}", remote_path).as_str());
-                let arc: Arc<dyn ExecutionPlan> = Arc::new(ParquetExec::try_from_path(
+                partition_execs.push(Self::parquet_exec(
+                    index.get_row(),
                     &local_path,
                     mapped_projection.clone(),
                     batch_size,
-                    1,
+                    &row_group_filter,
                 )?);
-                partition_execs.push(arc);
             }
 
             let chunks = partition_snapshot.chunks();
@@ -453,13 +556,13 @@ impl CubeTable {
                     .remote_to_local_names
                     .get(&remote_path)
                     .expect(format!("Missing remote path {}", remote_path).as_str());
-                let node = Arc::new(ParquetExec::try_from_path(
+                partition_execs.push(Self::parquet_exec(
+                    index.get_row(),
                     local_path,
                     mapped_projection.clone(),
                     batch_size,
-                    1,
+                    &row_group_filter,
                 )?);
-                partition_execs.push(node);
             }
         }
 
@@ -501,6 +604,35 @@ impl CubeTable {
         Ok(plan)
     }
 
+    fn parquet_exec(
+        index: &Index,
+        local_path: &str,
+        projection: Option<Vec<usize>>,
+        batch_size: usize,
+        row_group_filter: &Option<RowGroupFilter>,
+    ) -> Result<Arc<dyn ExecutionPlan>, CubeError> {
+        if index.has_evolved_schema() {
+            return Ok(Arc::new(FilteredParquetExec::try_new_for_index(
+                local_path,
+                index,
+                projection,
+                batch_size,
+                row_group_filter.clone(),
+            )?));
+        }
+        Ok(match row_group_filter {
+            Some(filter) => Arc::new(FilteredParquetExec::try_new(
+                local_path,
+                projection,
+                batch_size,
+                filter.clone(),
+            )?),
+            None => Arc::new(ParquetExec::try_from_path(
+                local_path, projection, batch_size, 1,
+            )?),
+        })
+    }
+
     pub fn project_to_index_positions(
         projection_columns: &Vec<Column>,
         i: &IdRow<Index>,
@@ -572,11 +704,224 @@ impl ExecutionPlan for CubeTableExec {
     }
 }
 
+/// Reads only row groups of a parquet file that can match the filter.
+#[derive(Debug, Clone)]
+pub struct FilteredParquetExec {
+    path: String,
+    projection: Option<Vec<usize>>,
+    batch_size: usize,
+    row_group_filter: Option<RowGroupFilter>,
+    /// Output columns with their positions in read batches, `None` for columns missing in file.
+    /// Set for indexes altered after the file was written.
+    column_mapping: Option<Vec<(Column, Option<usize>)>>,
+    schema: DFSchemaRef,
+}
+
+impl FilteredParquetExec {
+    pub fn try_new(
+        path: &str,
+        projection: Option<Vec<usize>>,
+        batch_size: usize,
+        row_group_filter: RowGroupFilter,
+    ) -> Result<Self, CubeError> {
+        let schema = ParquetExec::try_from_path(path, projection.clone(), batch_size, 1)?.schema();
+        Ok(Self {
+            path: path.to_string(),
+            projection,
+            batch_size,
+            row_group_filter: Some(row_group_filter),
+            column_mapping: None,
+            schema,
+        })
+    }
+
+    /// Reads file written before ALTER TABLE of the index: columns are matched by their
+    /// storage names and ones missing in the file are filled with NULLs.
+    pub fn try_new_for_index(
+        path: &str,
+        index: &Index,
+        projection: Option<Vec<usize>>,
+        batch_size: usize,
+        row_group_filter: Option<RowGroupFilter>,
+    ) -> Result<Self, CubeError> {
+        let reader = SerializedFileReader::new(File::open(path)?)?;
+        let file_columns = reader
+            .metadata()
+            .file_metadata()
+            .schema_descr()
+            .columns()
+            .iter()
+            .map(|c| c.name().to_string())
+            .collect::<Vec<_>>();
+        // Columns are returned in index order as `ParquetExec` does.
+        let columns = match &projection {
+            Some(projection) => projection
+                .iter()
+                .sorted()
+                .map(|i| index.get_columns()[*i].clone())
+                .collect::<Vec<_>>(),
+            None => index.get_columns().clone(),
+        };
+        let positions = columns
+            .iter()
+            .map(|c| {
+                let storage_name = index.storage_name(c);
+                file_columns.iter().position(|n| n == storage_name)
+            })
+            .collect::<Vec<_>>();
+        let mut file_projection = positions
+            .iter()
+            .flatten()
+            .cloned()
+            .sorted()
+            .dedup()
+            .collect::<Vec<_>>();
+        if file_projection.is_empty() {
+            // Read any column to get the number of rows.
+            file_projection.push(0);
+        }
+        let schema = columns_schema(&columns).to_dfschema_ref()?;
+        let column_mapping = columns
+            .into_iter()
+            .zip(positions.into_iter())
+            .map(|(c, p)| (c, p.map(|p| file_projection.binary_search(&p).unwrap())))
+            .collect::<Vec<_>>();
+        Ok(Self {
+            path: path.to_string(),
+            projection: Some(file_projection),
+            batch_size,
+            row_group_filter,
+            column_mapping: Some(column_mapping),
+            schema,
+        })
+    }
+
+    /// Runs on a blocking thread, stops once the receiver is dropped.
+    fn send_batches(
+        &self,
+        tx: &mut mpsc::Sender<ArrowResult<RecordBatch>>,
+    ) -> Result<(), CubeError> {
+        let reader: Rc<dyn FileReader> = match &self.row_group_filter {
+            Some(filter) => {
+                let reader = RowGroupFilteredReader::open(&self.path, filter)?;
+                if reader.num_row_groups() == 0 {
+                    return Ok(());
+                }
+                Rc::new(reader)
+            }
+            None => Rc::new(SerializedFileReader::new(File::open(&self.path)?)?),
+        };
+        let mut arrow_reader = ParquetFileArrowReader::new(reader);
+        let batch_reader = match &self.projection {
+            Some(projection) => {
+                arrow_reader.get_record_reader_by_columns(projection.clone(), self.batch_size)?
+            }
+            None => arrow_reader.get_record_reader(self.batch_size)?,
+        };
+        let schema = self.schema.to_schema_ref();
+        for batch in batch_reader {
+            let batch = self.map_columns(&schema, batch?)?;
+            if futures::executor::block_on(tx.send(Ok(batch))).is_err() {
+                return Ok(());
+            }
+        }
+        Ok(())
+    }
+
+    fn map_columns(
+        &self,
+        schema: &SchemaRef,
+        batch: RecordBatch,
+    ) -> Result<RecordBatch, CubeError> {
+        let column_mapping = match &self.column_mapping {
+            Some(column_mapping) => column_mapping,
+            None => return Ok(batch),
+        };
+        let arrays = column_mapping
+            .iter()
+            .map(|(column, position)| match position {
+                Some(p) => Ok(batch.column(*p).clone()),
+                None => null_array(column, batch.num_rows()),
+            })
+            .collect::<Result<Vec<_>, CubeError>>()?;
+        Ok(RecordBatch::try_new(schema.clone(), arrays)?)
+    }
+}
+
+/// Batches of `FilteredParquetExec` read ahead of the consumer.
+const FILTERED_PARQUET_BATCHES_IN_FLIGHT: usize = 2;
+
+struct FilteredParquetStream {
+    schema: SchemaRef,
+    receiver: mpsc::Receiver<ArrowResult<RecordBatch>>,
+}
+
+impl Stream for FilteredParquetStream {
+    type Item = ArrowResult<RecordBatch>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+impl RecordBatchStream for FilteredParquetStream {
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+}
+
+#[async_trait]
+impl ExecutionPlan for FilteredParquetExec {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> DFSchemaRef {
+        self.schema.clone()
+    }
+
+    fn output_partitioning(&self) -> Partitioning {
+        Partitioning::UnknownPartitioning(1)
+    }
+
+    fn children(&self) -> Vec<Arc<dyn ExecutionPlan>> {
+        vec![]
+    }
+
+    fn with_new_children(
+        &self,
+        children: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> Result<Arc<dyn ExecutionPlan>, DataFusionError> {
+        if !children.is_empty() {
+            panic!("Expected to be a leaf node");
+        }
+        Ok(Arc::new(self.clone()))
+    }
+
+    async fn execute(
+        &self,
+        _partition: usize,
+    ) -> Result<Pin<Box<dyn RecordBatchStream + Send>>, DataFusionError> {
+        let exec = self.clone();
+        let (mut tx, receiver) = mpsc::channel(FILTERED_PARQUET_BATCHES_IN_FLIGHT);
+        tokio::task::spawn_blocking(move || {
+            if let Err(e) = exec.send_batches(&mut tx) {
+                let error = ArrowError::ExternalError(Box::new(e));
+                let _ = futures::executor::block_on(tx.send(Err(error)));
+            }
+        });
+        Ok(Box::pin(FilteredParquetStream {
+            schema: self.schema.to_schema_ref(),
+            receiver,
+        }))
+    }
+}
+
 pub struct ClusterSendExec {
     schema: DFSchemaRef,
-    partitions: Vec<Vec<IdRow<Partition>>>,
+    /// Partitions to execute by each node.
+    partitions: Vec<(String, Vec<IdRow<Partition>>)>,
     cluster: Arc<dyn Cluster>,
-    available_nodes: Vec<String>,
     serialized_plan: Arc<SerializedPlan>,
 }
 
@@ -588,7 +933,7 @@ impl ClusterSendExec {
         available_nodes: Vec<String>,
         union_snapshots: Vec<Vec<IndexSnapshot>>,
     ) -> Self {
-        let to_multiply = union_snapshots
+        let union_partitions = union_snapshots
             .into_iter()
             .map(|union| {
                 union
@@ -603,18 +948,52 @@ impl ClusterSendExec {
                     .collect::<Vec<_>>()
             })
             .collect::<Vec<_>>();
-        let partitions = to_multiply
-            .into_iter()
-            .multi_cartesian_product()
-            .collect::<Vec<Vec<_>>>();
+        let partitions = Self::distribute_partitions(union_partitions, &available_nodes);
         Self {
             schema,
             partitions,
             cluster,
-            available_nodes,
             serialized_plan,
         }
     }
+
+    /// Partitions of the largest union are split across `nodes` so scans scale with cluster size.
+    /// Each of them is owned by the same node while the node set is unchanged to keep its files cached.
+    /// Partitions of other unions are sent to every node as any of them can be joined with them.
+    pub fn distribute_partitions(
+        union_partitions: Vec<Vec<IdRow<Partition>>>,
+        nodes: &Vec<String>,
+    ) -> Vec<(String, Vec<IdRow<Partition>>)> {
+        if nodes.is_empty() || union_partitions.iter().any(|u| u.is_empty()) {
+            return Vec::new();
+        }
+        let split_index = match union_partitions.iter().position_max_by_key(|u| u.len()) {
+            Some(index) => index,
+            None => return Vec::new(),
+        };
+        let ring = HashRing::new(nodes);
+        let mut node_partitions = nodes
+            .iter()
+            .map(|n| (n.to_string(), Vec::new()))
+            .collect::<Vec<_>>();
+        for partition in union_partitions[split_index].iter() {
+            let owner = ring.node_for_partition(partition.get_id()).unwrap();
+            let node_index = nodes.iter().position(|n| n == owner).unwrap();
+            node_partitions[node_index].1.push(partition.clone());
+        }
+        node_partitions
+            .into_iter()
+            .filter(|(_, partitions)| !partitions.is_empty())
+            .map(|(node, mut partitions)| {
+                for (i, union) in union_partitions.iter().enumerate() {
+                    if i != split_index {
+                        partitions.extend(union.iter().cloned());
+                    }
+                }
+                (node, partitions)
+            })
+            .collect()
+    }
 }
 
 #[async_trait]
@@ -646,7 +1025,6 @@ impl ExecutionPlan for ClusterSendExec {
             schema: self.schema.clone(),
             partitions: self.partitions.clone(),
             cluster: self.cluster.clone(),
-            available_nodes: self.available_nodes.clone(),
             serialized_plan: self.serialized_plan.clone(),
         }))
     }
@@ -655,18 +1033,13 @@ impl ExecutionPlan for ClusterSendExec {
         &self,
         partition: usize,
     ) -> Result<Pin<Box<dyn RecordBatchStream + Send>>, DataFusionError> {
+        let (node_name, partitions) = &self.partitions[partition];
         let record_batches = self
             .cluster
             .run_select(
-                self.cluster
-                    .node_name_by_partitions(&self.partitions[partition])
-                    .await?,
-                self.serialized_plan.with_partition_id_to_execute(
-                    self.partitions[partition]
-                        .iter()
-                        .map(|p| p.get_id())
-                        .collect(),
-                ),
+                node_name.to_string(),
+                self.serialized_plan
+                    .with_partition_id_to_execute(partitions.iter().map(|p| p.get_id()).collect()),
             )
             .await?;
         // TODO .to_schema_ref()
@@ -698,9 +1071,9 @@ impl TableProvider for CubeTable {
         &self,
         projection: &Option<Vec<usize>>,
         batch_size: usize,
-        _filters: &[Expr],
+        filters: &[Expr],
     ) -> DFResult<Arc<dyn ExecutionPlan>> {
-        let res = self.async_scan(projection, batch_size)?;
+        let res = self.async_scan(projection, batch_size, filters)?;
         Ok(res)
     }
 
@@ -961,3 +1334,51 @@ impl SerializedRecordBatchStream {
         Ok(reader.collect::<Result<Vec<_>, _>>()?)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn partitions(ids: Vec<u64>) -> Vec<IdRow<Partition>> {
+        ids.into_iter()
+            .map(|id| IdRow::new(id, Partition::new(1, None, None)))
+            .collect()
+    }
+
+    fn ids(partitions: &Vec<IdRow<Partition>>) -> Vec<u64> {
+        partitions.iter().map(|p| p.get_id()).collect()
+    }
+
+    #[test]
+    fn distribute_partitions() {
+        let nodes = vec!["worker-1".to_string(), "worker-2".to_string()];
+
+        let distributed = ClusterSendExec::distribute_partitions(
+            vec![partitions((1..=20).collect()), partitions(vec![100])],
+            &nodes,
+        );
+        assert_eq!(distributed.len(), 2);
+        let ring = HashRing::new(&nodes);
+        let mut split_ids = Vec::new();
+        for (node, node_partitions) in distributed.iter() {
+            let node_ids = ids(node_partitions);
+            assert_eq!(node_ids.last(), Some(&100));
+            for id in node_ids[..node_ids.len() - 1].iter() {
+                assert_eq!(ring.node_for_partition(*id), Some(node));
+                split_ids.push(*id);
+            }
+        }
+        split_ids.sort();
+        assert_eq!(split_ids, (1..=20).collect::<Vec<_>>());
+
+        let distributed = ClusterSendExec::distribute_partitions(vec![partitions(vec![7])], &nodes);
+        assert_eq!(distributed.len(), 1);
+        assert_eq!(ring.node_for_partition(7), Some(&distributed[0].0));
+
+        let distributed = ClusterSendExec::distribute_partitions(
+            vec![partitions(vec![1, 2]), partitions(vec![])],
+            &nodes,
+        );
+        assert!(distributed.is_empty());
+    }
+}
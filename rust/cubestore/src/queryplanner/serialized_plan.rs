@@ -1,5 +1,6 @@
 use crate::metastore::table::{Table, TablePath};
 use crate::metastore::{Chunk, IdRow, Index, MetaStore, Partition};
+use crate::queryplanner::partition_filter::PartitionFilter;
 use crate::queryplanner::query_executor::CubeTable;
 use crate::queryplanner::udfs::aggregate_udf_by_kind;
 use crate::queryplanner::udfs::{
@@ -25,6 +26,8 @@ pub struct SerializedPlan {
     logical_plan: Arc<SerializedLogicalPlan>,
     schema_snapshot: Arc<SchemaSnapshot>,
     partition_ids_to_execute: HashSet<u64>,
+    /// Id of the router process the plan is executed for.
+    query_id: Option<u32>,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -293,6 +296,83 @@ impl SerializedLogicalPlan {
             },
         })
     }
+
+    /// Table and its column the output column is read from as is, i.e. without any computations
+    /// on top of it.
+    fn source_column(
+        &self,
+        column: &str,
+        relation: Option<&String>,
+    ) -> Option<(TablePath, String)> {
+        match self {
+            SerializedLogicalPlan::Projection {
+                expr,
+                input,
+                schema,
+            } => {
+                let mut positions = schema
+                    .fields()
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, f)| f.name() == column)
+                    .map(|(i, _)| i);
+                let i = positions.next()?;
+                if positions.next().is_some() {
+                    // Ambiguous name
+                    return None;
+                }
+                match &expr[i] {
+                    SerializedExpr::Column(c, a) => input.source_column(c, a.as_ref()),
+                    SerializedExpr::Alias(e, _) => match e.as_ref() {
+                        SerializedExpr::Column(c, a) => input.source_column(c, a.as_ref()),
+                        _ => None,
+                    },
+                    _ => None,
+                }
+            }
+            SerializedLogicalPlan::Aggregate {
+                input, group_expr, ..
+            } => group_expr.iter().find_map(|e| match e {
+                SerializedExpr::Column(c, a) if c == column => input.source_column(c, a.as_ref()),
+                _ => None,
+            }),
+            SerializedLogicalPlan::Filter { input, .. }
+            | SerializedLogicalPlan::Sort { input, .. }
+            | SerializedLogicalPlan::Limit { input, .. }
+            | SerializedLogicalPlan::Repartition { input, .. } => {
+                input.source_column(column, relation)
+            }
+            SerializedLogicalPlan::Join { left, right, .. } => match (
+                left.source_column(column, relation),
+                right.source_column(column, relation),
+            ) {
+                (Some(source), None) | (None, Some(source)) => Some(source),
+                // Ambiguous name
+                _ => None,
+            },
+            SerializedLogicalPlan::TableScan {
+                table_name,
+                source: SerializedTableSource::CubeTable(table),
+                projected_schema,
+                alias,
+                ..
+            } => {
+                if let Some(relation) = relation {
+                    if relation != table_name && Some(relation) != alias.as_ref() {
+                        return None;
+                    }
+                }
+                if projected_schema.fields().iter().any(|f| f.name() == column) {
+                    Some((table.table.clone(), column.to_string()))
+                } else {
+                    None
+                }
+            }
+            SerializedLogicalPlan::Union { .. } | SerializedLogicalPlan::EmptyRelation { .. } => {
+                None
+            }
+        }
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -448,6 +528,7 @@ impl SerializedPlan {
             logical_plan: Arc::new(serialized_logical_plan),
             schema_snapshot: Arc::new(SchemaSnapshot { index_snapshots }),
             partition_ids_to_execute: HashSet::new(),
+            query_id: None,
         })
     }
 
@@ -456,6 +537,7 @@ impl SerializedPlan {
             logical_plan: self.logical_plan.clone(),
             schema_snapshot: self.schema_snapshot.clone(),
             partition_ids_to_execute,
+            query_id: self.query_id,
         }
     }
 
@@ -463,6 +545,17 @@ impl SerializedPlan {
         self.partition_ids_to_execute.clone()
     }
 
+    pub fn with_query_id(&self, query_id: u32) -> Self {
+        Self {
+            query_id: Some(query_id),
+            ..self.clone()
+        }
+    }
+
+    pub fn query_id(&self) -> Option<u32> {
+        self.query_id
+    }
+
     pub fn logical_plan(
         &self,
         remote_to_local_names: &HashMap<String, String>,
@@ -474,6 +567,14 @@ impl SerializedPlan {
         )
     }
 
+    pub fn source_table(&self, column: &str) -> Option<TablePath> {
+        Some(self.logical_plan.source_column(column, None)?.0)
+    }
+
+    pub fn source_column(&self, column: &str) -> Option<(TablePath, String)> {
+        self.logical_plan.source_column(column, None)
+    }
+
     pub fn index_snapshots(&self) -> &Vec<IndexSnapshot> {
         &self.schema_snapshot.index_snapshots
     }
@@ -523,6 +624,7 @@ impl SerializedPlan {
             LogicalPlan::TableScan {
                 table_name,
                 projection,
+                filters,
                 ..
             } => {
                 let name_split = table_name.split(".").collect::<Vec<_>>();
@@ -608,9 +710,17 @@ impl SerializedPlan {
                     .get_active_partitions_and_chunks_by_index_id_for_select(index.get_id())
                     .await?;
 
+                let partition_filter = PartitionFilter::extract(index.get_row(), filters);
+
                 let mut partition_snapshots = Vec::new();
 
                 for (partition, chunks) in partitions.into_iter() {
+                    if !partition_filter.can_match(
+                        partition.get_row().get_min_val(),
+                        partition.get_row().get_max_val(),
+                    ) {
+                        continue;
+                    }
                     partition_snapshots.push(PartitionSnapshot { chunks, partition });
                 }
 
@@ -1,19 +1,25 @@
+use crate::cluster::hash_ring::HashRing;
 use crate::cluster::Cluster;
 use crate::config::ConfigObj;
-use crate::metastore::job::{Job, JobType};
-use crate::metastore::{MetaStore, MetaStoreEvent, RowKey, TableId};
+use crate::import::is_remote_location;
+use crate::metastore::job::{Job, JobStatus, JobType};
+use crate::metastore::{IdRow, MetaStore, MetaStoreEvent, RowKey, TableId};
 use crate::remotefs::RemoteFs;
-use crate::store::{ChunkStore, WALStore};
+use crate::store::{ChunkStore, WALDataStore, WALStore};
 use crate::CubeError;
-use log::error;
+use chrono::Utc;
+use log::{error, warn};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::broadcast::Receiver;
 use tokio::sync::{watch, Mutex};
+use tokio::time;
 
 pub struct SchedulerImpl {
     meta_store: Arc<dyn MetaStore>,
     cluster: Arc<dyn Cluster>,
     remote_fs: Arc<dyn RemoteFs>,
+    wal_store: Arc<dyn WALDataStore>,
     event_receiver: Mutex<Receiver<MetaStoreEvent>>,
     stop_sender: watch::Sender<bool>,
     stop_receiver: Mutex<watch::Receiver<bool>>,
@@ -25,6 +31,7 @@ impl SchedulerImpl {
         meta_store: Arc<dyn MetaStore>,
         cluster: Arc<dyn Cluster>,
         remote_fs: Arc<dyn RemoteFs>,
+        wal_store: Arc<dyn WALDataStore>,
         event_receiver: Receiver<MetaStoreEvent>,
         config: Arc<dyn ConfigObj>,
     ) -> SchedulerImpl {
@@ -33,6 +40,7 @@ impl SchedulerImpl {
             meta_store,
             cluster,
             remote_fs,
+            wal_store,
             event_receiver: Mutex::new(event_receiver),
             stop_sender: tx,
             stop_receiver: Mutex::new(rx),
@@ -41,6 +49,9 @@ impl SchedulerImpl {
     }
 
     pub async fn run_scheduler(&self) -> Result<(), CubeError> {
+        let mut file_deletion_interval = time::interval(Duration::from_secs(1));
+        let mut dead_node_jobs_interval =
+            time::interval(Duration::from_secs(self.config.heart_beat_timeout()));
         loop {
             let mut stop_receiver = self.stop_receiver.lock().await;
             let mut event_receiver = self.event_receiver.lock().await;
@@ -52,6 +63,18 @@ impl SchedulerImpl {
                         continue;
                     }
                 }
+                _ = file_deletion_interval.tick() => {
+                    if let Err(e) = self.delete_expired_files().await {
+                        error!("Error deleting files: {}", e);
+                    }
+                    continue;
+                }
+                _ = dead_node_jobs_interval.tick() => {
+                    if let Err(e) = self.reschedule_jobs_of_dead_nodes().await {
+                        error!("Error rescheduling jobs of dead nodes: {}", e);
+                    }
+                    continue;
+                }
                 event = event_receiver.recv() => {
                     event?
                 }
@@ -63,17 +86,125 @@ impl SchedulerImpl {
         }
     }
 
+    /// Re-schedules partitioning of WALs which were left pending by a previous run.
+    pub async fn recover_pending_wals(&self) -> Result<(), CubeError> {
+        for wal in self.wal_store.recover_wals().await?.into_iter() {
+            let job = self
+                .meta_store
+                .get_job_by_ref(
+                    RowKey::Table(TableId::WALs, wal.get_id()),
+                    JobType::WalPartitioning,
+                )
+                .await?;
+            if let Some(job) = job {
+                if let JobStatus::Scheduled(_) = job.get_row().status() {
+                    continue;
+                }
+                self.meta_store.delete_job(job.get_id()).await?;
+            }
+            self.schedule_wal_to_process(wal.get_id()).await?;
+        }
+        Ok(())
+    }
+
+    /// Tables being built by `CREATE OR REPLACE TABLE` are left only if the process crashed.
+    pub async fn drop_replacement_tables(&self) -> Result<(), CubeError> {
+        for table in self.meta_store.tables_table().all_rows().await?.into_iter() {
+            if table.get_row().is_replacement() {
+                self.meta_store.drop_table(table.get_id()).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Jobs of nodes which heart-beats expired are neither picked up nor finished, so they're
+    /// scheduled again on live nodes.
+    async fn reschedule_jobs_of_dead_nodes(&self) -> Result<(), CubeError> {
+        let live_nodes = self.cluster.available_nodes().await?;
+        for job in self.meta_store.get_all_jobs().await?.into_iter() {
+            let node = match job.get_row().status() {
+                JobStatus::Scheduled(node) | JobStatus::ProcessingBy(node) => node,
+                _ => continue,
+            };
+            if node == self.cluster.server_name() || live_nodes.contains(node) {
+                continue;
+            }
+            warn!("Rescheduling job of dead node {}: {:?}", node, job);
+            if let Err(e) = self.reschedule_job(&job).await {
+                error!("Error rescheduling {:?}: {}", job, e);
+            }
+        }
+        Ok(())
+    }
+
+    async fn reschedule_job(&self, job: &IdRow<Job>) -> Result<(), CubeError> {
+        self.meta_store.delete_job(job.get_id()).await?;
+        match (job.get_row().job_type(), job.get_row().row_reference()) {
+            (JobType::WalPartitioning, RowKey::Table(TableId::WALs, id)) => {
+                self.schedule_wal_to_process(*id).await
+            }
+            (JobType::PartitionCompaction, RowKey::Table(TableId::Partitions, id)) => {
+                self.schedule_partition_to_compact(*id).await
+            }
+            (JobType::Repartition, RowKey::Table(TableId::Partitions, id)) => {
+                self.schedule_repartition(*id).await
+            }
+            (JobType::TableImport, RowKey::Table(TableId::Tables, id)) => {
+                self.schedule_table_import(*id).await
+            }
+            _ => Err(CubeError::internal(format!(
+                "Incorrect row key for {:?}",
+                job
+            ))),
+        }
+    }
+
     pub fn stop_processing_loops(&self) -> Result<(), CubeError> {
         Ok(self.stop_sender.broadcast(true)?)
     }
 
+    /// Queries planned before a table was dropped or replaced can still read its files,
+    /// so files of recently used rows are kept until the not used timeout passes.
+    /// Such deletions are stored in the metastore so they survive restarts.
+    async fn delete_remote_file(&self, remote_path: String, used: bool) -> Result<(), CubeError> {
+        if !used {
+            return self.remote_fs.delete_file(remote_path.as_str()).await;
+        }
+        let deadline =
+            Utc::now() + chrono::Duration::seconds(self.config.not_used_timeout() as i64);
+        self.meta_store
+            .add_file_deletion(remote_path, deadline)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_expired_files(&self) -> Result<(), CubeError> {
+        let now = Utc::now();
+        for deletion in self.meta_store.get_file_deletions().await?.into_iter() {
+            if deletion.get_row().deadline() > &now {
+                continue;
+            }
+            let remote_path = deletion.get_row().remote_path();
+            if let Err(e) = self.remote_fs.delete_file(remote_path.as_str()).await {
+                error!("Error deleting {}: {}", remote_path, e);
+                continue;
+            }
+            self.meta_store
+                .delete_file_deletion(deletion.get_id())
+                .await?;
+        }
+        Ok(())
+    }
+
     async fn process_event(&self, event: MetaStoreEvent) -> Result<(), CubeError> {
         if let MetaStoreEvent::Insert(TableId::WALs, row_id)
         | MetaStoreEvent::Update(TableId::WALs, row_id) = event
         {
-            let wal = self.meta_store.get_wal(row_id).await?;
-            if wal.get_row().uploaded() {
-                self.schedule_wal_to_process(row_id).await?;
+            // Async upload can finish after the WAL has been partitioned and deleted
+            if let Ok(wal) = self.meta_store.get_wal(row_id).await {
+                if wal.get_row().written() {
+                    self.schedule_wal_to_process(row_id).await?;
+                }
             }
         }
         if let MetaStoreEvent::Insert(TableId::Chunks, row_id)
@@ -116,23 +247,32 @@ impl SchedulerImpl {
         }
         if let MetaStoreEvent::Insert(TableId::Tables, row_id) = event {
             let table = self.meta_store.get_table_by_id(row_id).await?;
-            if table.get_row().location().is_some() {
+            if table.get_row().locations().is_some() {
                 self.schedule_table_import(row_id).await?;
             }
         }
         if let MetaStoreEvent::Delete(TableId::WALs, row_id) = event {
             self.remote_fs
                 .delete_file(WALStore::wal_remote_path(row_id).as_str())
-                .await?
-        }
-        if let MetaStoreEvent::Delete(TableId::Chunks, row_id) = event {
+                .await?;
             self.remote_fs
-                .delete_file(ChunkStore::chunk_remote_path(row_id).as_str())
+                .delete_file(WALStore::legacy_wal_remote_path(row_id).as_str())
                 .await?
         }
+        if let MetaStoreEvent::DeleteChunk(chunk) = &event {
+            self.delete_remote_file(
+                ChunkStore::chunk_remote_path(chunk.get_id()),
+                chunk.get_row().is_used(self.config.not_used_timeout()),
+            )
+            .await?
+        }
         if let MetaStoreEvent::DeletePartition(partition) = &event {
             if let Some(file_name) = partition.get_row().get_full_name(partition.get_id()) {
-                self.remote_fs.delete_file(file_name.as_str()).await?;
+                self.delete_remote_file(
+                    file_name,
+                    partition.get_row().is_used(self.config.not_used_timeout()),
+                )
+                .await?;
             }
         }
         if let MetaStoreEvent::Update(TableId::Partitions, row_id) = event {
@@ -149,6 +289,8 @@ impl SchedulerImpl {
                         self.remote_fs.delete_file(file_name.as_str()).await?;
                     }
                 }
+            } else if !partition.get_row().pending_deletes().is_empty() {
+                self.schedule_partition_to_compact(row_id).await?;
             }
         }
         if let MetaStoreEvent::DeleteJob(job) = event {
@@ -176,69 +318,109 @@ impl SchedulerImpl {
     }
 
     async fn schedule_repartition(&self, partition_id: u64) -> Result<(), CubeError> {
-        let node = self.cluster.server_name().to_string(); // TODO find best node to run import
-        let job = self
-            .meta_store
-            .add_job(Job::new(
-                RowKey::Table(TableId::Partitions, partition_id),
-                JobType::Repartition,
-                node.to_string(),
-            ))
-            .await?;
-        if job.is_some() {
-            // TODO queue failover
-            self.cluster.notify_job_runner(node).await?;
-        }
-        Ok(())
+        let node = self.job_node(partition_id).await?;
+        self.schedule_job(
+            RowKey::Table(TableId::Partitions, partition_id),
+            JobType::Repartition,
+            node,
+        )
+        .await
     }
 
     async fn schedule_table_import(&self, table_id: u64) -> Result<(), CubeError> {
-        let node = self.cluster.server_name().to_string(); // TODO find best node to run import
-        let job = self
-            .meta_store
-            .add_job(Job::new(
-                RowKey::Table(TableId::Tables, table_id),
-                JobType::TableImport,
-                node.to_string(),
-            ))
-            .await?;
-        if job.is_some() {
-            // TODO queue failover
-            self.cluster.notify_job_runner(node).await?;
-        }
-        Ok(())
+        let table = self.meta_store.get_table_by_id(table_id).await?;
+        // Local paths are files on the router disk
+        let remote = match table.get_row().locations() {
+            Some(locations) => locations.iter().all(|l| is_remote_location(l)),
+            None => true,
+        };
+        let node = if remote {
+            self.job_node(table_id).await?
+        } else {
+            self.cluster.server_name().to_string()
+        };
+        self.schedule_job(
+            RowKey::Table(TableId::Tables, table_id),
+            JobType::TableImport,
+            node,
+        )
+        .await
     }
 
     async fn schedule_wal_to_process(&self, wal_id: u64) -> Result<(), CubeError> {
-        let wal_node_name = self.cluster.server_name().to_string(); // TODO move to WAL
-        let job = self
-            .meta_store
-            .add_job(Job::new(
-                RowKey::Table(TableId::WALs, wal_id),
-                JobType::WalPartitioning,
-                wal_node_name.clone(),
-            ))
-            .await?;
-        if job.is_some() {
-            // TODO queue failover
-            self.cluster.notify_job_runner(wal_node_name).await?;
-        }
-        Ok(())
+        let wal = self.meta_store.get_wal(wal_id).await?;
+        // Workers upload WALs before they're written, so not uploaded ones can be read only from
+        // the local disk of the router
+        let node = if wal.get_row().uploaded() {
+            self.job_node(wal.get_row().get_table_id()).await?
+        } else {
+            self.cluster.server_name().to_string()
+        };
+        self.schedule_job(
+            RowKey::Table(TableId::WALs, wal_id),
+            JobType::WalPartitioning,
+            node,
+        )
+        .await
     }
 
     async fn schedule_partition_to_compact(&self, partition_id: u64) -> Result<(), CubeError> {
-        let wal_node_name = self.cluster.server_name().to_string(); // TODO move to WAL
+        let node = self.job_node(partition_id).await?;
+        self.schedule_job(
+            RowKey::Table(TableId::Partitions, partition_id),
+            JobType::PartitionCompaction,
+            node,
+        )
+        .await
+    }
+
+    /// Jobs are sharded across select workers by the same hash ring selects use, so a worker
+    /// compacts partitions it has cached. Workers can run jobs only if the router metastore
+    /// port is open.
+    async fn job_node(&self, id: u64) -> Result<String, CubeError> {
+        if self.config.metastore_bind_address().is_none() {
+            return Ok(self.cluster.server_name().to_string());
+        }
+        let nodes = self.cluster.available_nodes().await?;
+        Ok(HashRing::new(&nodes)
+            .node_for_partition(id)
+            .cloned()
+            .unwrap_or_else(|| self.cluster.server_name().to_string()))
+    }
+
+    async fn schedule_job(
+        &self,
+        row_reference: RowKey,
+        job_type: JobType,
+        node: String,
+    ) -> Result<(), CubeError> {
         let job = self
             .meta_store
-            .add_job(Job::new(
-                RowKey::Table(TableId::Partitions, partition_id),
-                JobType::PartitionCompaction,
-                wal_node_name.clone(),
-            ))
+            .add_job(Job::new(row_reference, job_type, node.to_string()))
             .await?;
-        if job.is_some() {
-            // TODO queue failover
-            self.cluster.notify_job_runner(wal_node_name).await?;
+        if let Some(job) = job {
+            if let Err(e) = self.cluster.notify_job_runner(node.to_string()).await {
+                let router = self.cluster.server_name().to_string();
+                if node == router {
+                    return Err(e);
+                }
+                error!(
+                    "Error notifying {} of {:?}, running it on the router: {}",
+                    node, job, e
+                );
+                if let JobStatus::Scheduled(_) = self
+                    .meta_store
+                    .get_job(job.get_id())
+                    .await?
+                    .get_row()
+                    .status()
+                {
+                    self.meta_store
+                        .update_status(job.get_id(), JobStatus::Scheduled(router.to_string()))
+                        .await?;
+                    self.cluster.notify_job_runner(router).await?;
+                }
+            }
         }
         Ok(())
     }
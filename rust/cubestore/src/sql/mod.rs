@@ -1,4 +1,5 @@
 mod parser;
+mod visitor;
 
 use log::trace;
 
@@ -7,32 +8,115 @@ use sqlparser::ast::*;
 use sqlparser::dialect::Dialect;
 
 use crate::metastore::{
-    table::Table, IdRow, ImportFormat, Index, IndexDef, MetaStoreTable, RowKey, Schema, TableId,
+    table::{Table, REPLACEMENT_MARKER},
+    CsvOptions, IdRow, ImportFormat, Index, IndexDef, MetaStoreTable, RowKey, Schema, TableId,
 };
 use crate::table::{Row, TableValue, TimestampValue};
 use crate::CubeError;
 use crate::{
     metastore::{Column, ColumnType, MetaStore},
-    store::{DataFrame, WALDataStore},
+    store::{DataFrame, DataStream, WALDataStore},
 };
+use std::mem;
+use std::pin::Pin;
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::timeout;
 
 use crate::queryplanner::{QueryPlan, QueryPlanner};
 
-use crate::cluster::{Cluster, JobEvent};
+use crate::cluster::process_list::{ProcessGuard, ProcessInfo, ProcessList};
+use crate::cluster::{Cluster, JobEvent, JobResultListener};
+use crate::config::ConfigObj;
 
-use crate::metastore::job::JobType;
-use crate::queryplanner::query_executor::QueryExecutor;
+use crate::metastore::job::{JobStatus, JobType};
+use crate::queryplanner::partition_filter::RowFilter;
+use crate::queryplanner::query_executor::{arrow_to_column_type, QueryExecutor};
 use crate::sql::parser::CubeStoreParser;
+use crate::sql::visitor::{walk_statement, QueryVisitor};
+use arrow::datatypes::Field;
+use arrow::record_batch::RecordBatch;
+use datafusion::logical_plan::Operator;
 use datafusion::physical_plan::datetime_expressions::string_to_timestamp_nanos;
 use datafusion::sql::parser::Statement as DFStatement;
-use hex::FromHex;
+use futures::{Stream, StreamExt};
+use hex::{FromHex, ToHex};
 use itertools::Itertools;
+use parser::AlterTableOperation as CubeStoreAlterTableOperation;
 use parser::Statement as CubeStoreStatement;
+use uuid::Uuid;
 
 #[async_trait]
 pub trait SqlService: Send + Sync {
     async fn exec_query(&self, query: &str) -> Result<DataFrame, CubeError>;
+
+    async fn exec_query_with_context(
+        &self,
+        context: SqlQueryContext,
+        query: &str,
+    ) -> Result<DataFrame, CubeError>;
+
+    /// Parses `query` once so it can be executed many times with `?` placeholders bound to values.
+    fn prepare(&self, query: &str) -> Result<PreparedStatement, CubeError>;
+
+    async fn exec_prepared(
+        &self,
+        context: SqlQueryContext,
+        statement: &PreparedStatement,
+        params: Vec<TableValue>,
+    ) -> Result<DataFrame, CubeError>;
+
+    /// Select results are streamed as produced by the query executor without conversion to rows.
+    async fn exec_query_stream(
+        &self,
+        context: SqlQueryContext,
+        query: &str,
+    ) -> Result<DataStream, CubeError>;
+
+    async fn exec_prepared_stream(
+        &self,
+        context: SqlQueryContext,
+        statement: &PreparedStatement,
+        params: Vec<TableValue>,
+    ) -> Result<DataStream, CubeError>;
+
+    /// Writes batches to WALs of about `wal_chunk_size` rows as they arrive and waits until
+    /// they're moved to chunks. Batches read before an error are still inserted.
+    async fn insert_batches(
+        &self,
+        context: SqlQueryContext,
+        schema_name: &str,
+        table_name: &str,
+        batches: Pin<Box<dyn Stream<Item = Result<RecordBatch, CubeError>> + Send>>,
+    ) -> Result<u64, CubeError>;
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SqlQueryContext {
+    /// Connection of the protocol frontend shown in `SHOW PROCESSLIST`.
+    pub connection_id: Option<u32>,
+    pub user: Option<String>,
+    /// Schemas which can be accessed by the query, all schemas if `None`.
+    pub allowed_schemas: Option<Vec<String>>,
+    /// Schema of table names without one.
+    pub default_schema: Option<String>,
+}
+
+pub struct PreparedStatement {
+    query: String,
+    statement: CubeStoreStatement,
+    params_count: usize,
+    placeholder_prefix: String,
+}
+
+impl PreparedStatement {
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    pub fn params_count(&self) -> usize {
+        self.params_count
+    }
 }
 
 pub struct SqlServiceImpl {
@@ -41,6 +125,8 @@ pub struct SqlServiceImpl {
     query_planner: Arc<dyn QueryPlanner>,
     query_executor: Arc<dyn QueryExecutor>,
     cluster: Arc<dyn Cluster>,
+    process_list: Arc<ProcessList>,
+    config_obj: Arc<dyn ConfigObj>,
 }
 
 impl SqlServiceImpl {
@@ -50,6 +136,8 @@ impl SqlServiceImpl {
         query_planner: Arc<dyn QueryPlanner>,
         query_executor: Arc<dyn QueryExecutor>,
         cluster: Arc<dyn Cluster>,
+        process_list: Arc<ProcessList>,
+        config_obj: Arc<dyn ConfigObj>,
     ) -> Arc<SqlServiceImpl> {
         Arc::new(SqlServiceImpl {
             db,
@@ -57,6 +145,8 @@ impl SqlServiceImpl {
             query_planner,
             query_executor,
             cluster,
+            process_list,
+            config_obj,
         })
     }
 
@@ -74,10 +164,24 @@ impl SqlServiceImpl {
         table_name: String,
         columns: &Vec<ColumnDef>,
         external: bool,
-        location: Option<String>,
+        locations: Option<Vec<String>>,
+        with_options: Vec<SqlOption>,
         indexes: Vec<Statement>,
+        query: Option<(Box<Query>, ProcessGuard)>,
     ) -> Result<IdRow<Table>, CubeError> {
-        let columns_to_set = convert_columns_type(columns)?;
+        if external && query.is_some() {
+            return Err(CubeError::user(
+                "CREATE TABLE AS SELECT can't be used for tables with LOCATION".to_string(),
+            ));
+        }
+        let data = match query {
+            Some((query, process)) => Some(self.select_stream(query, process).await?),
+            None => None,
+        };
+        let columns_to_set = match &data {
+            Some((query_columns, _)) if columns.is_empty() => query_columns.clone(),
+            _ => convert_columns_type(columns)?,
+        };
         let mut indexes_to_create = Vec::new();
         for index in indexes.iter() {
             if let Statement::CreateIndex { name, columns, .. } = index {
@@ -88,6 +192,8 @@ impl SqlServiceImpl {
             }
         }
         if external {
+            let import_format =
+                import_format_from_options(&with_options, locations.as_deref().unwrap_or(&[]))?;
             let listener = self.cluster.job_result_listener();
             let table = self
                 .db
@@ -95,41 +201,24 @@ impl SqlServiceImpl {
                     schema_name,
                     table_name,
                     columns_to_set,
-                    location,
-                    Some(ImportFormat::CSV),
+                    locations,
+                    Some(import_format),
                     indexes_to_create,
                 )
                 .await?;
-            listener
-                .wait_for_job_result(
-                    RowKey::Table(TableId::Tables, table.get_id()),
-                    JobType::TableImport,
-                )
-                .await?;
-            let wal_listener = self.cluster.job_result_listener();
-            let wals = self.db.get_wals_for_table(table.get_id()).await?;
-            let events = wal_listener
-                .wait_for_job_results(
-                    wals.into_iter()
-                        .map(|wal| {
-                            (
-                                RowKey::Table(TableId::WALs, wal.get_id()),
-                                JobType::WalPartitioning,
-                            )
-                        })
-                        .collect(),
-                )
-                .await?;
-
-            for v in events {
-                if let JobEvent::Error(_, _, e) = v {
-                    return Err(CubeError::user(format!("Create table failed: {}", e)));
-                }
+            if let Err(e) = self.wait_for_import(listener, &table).await {
+                self.db.drop_table(table.get_id()).await?;
+                return Err(e);
             }
-
             Ok(table)
         } else {
-            self.db
+            if !with_options.is_empty() {
+                return Err(CubeError::user(
+                    "WITH options are supported only for tables with LOCATION".to_string(),
+                ));
+            }
+            let table = self
+                .db
                 .create_table(
                     schema_name,
                     table_name,
@@ -138,8 +227,94 @@ impl SqlServiceImpl {
                     None,
                     indexes_to_create,
                 )
-                .await
+                .await?;
+            if let Some((_, data)) = data {
+                let columns = table.get_row().get_columns().clone();
+                if let Err(e) = self.insert_stream(table.clone(), &columns, data).await {
+                    self.db.drop_table(table.get_id()).await?;
+                    return Err(e);
+                }
+            }
+            Ok(table)
+        }
+    }
+
+    /// `listener` should be created before the table so the import job result isn't missed.
+    async fn wait_for_import(
+        &self,
+        listener: JobResultListener,
+        table: &IdRow<Table>,
+    ) -> Result<(), CubeError> {
+        let import_result = listener
+            .wait_for_job_result(
+                RowKey::Table(TableId::Tables, table.get_id()),
+                JobType::TableImport,
+            )
+            .await?;
+        if let JobEvent::Error(_, _, e) = import_result {
+            return Err(CubeError::user(format!("Create table failed: {}", e)));
+        }
+        let wal_listener = self.cluster.job_result_listener();
+        let wals = self.db.get_wals_for_table(table.get_id()).await?;
+        let events = wal_listener
+            .wait_for_job_results(
+                wals.into_iter()
+                    .map(|wal| {
+                        (
+                            RowKey::Table(TableId::WALs, wal.get_id()),
+                            JobType::WalPartitioning,
+                        )
+                    })
+                    .collect(),
+            )
+            .await?;
+
+        for v in events {
+            if let JobEvent::Error(_, _, e) = v {
+                return Err(CubeError::user(format!("Create table failed: {}", e)));
+            }
         }
+        Ok(())
+    }
+
+    /// Data is loaded into a table under a temporary name first, so readers see either
+    /// the replaced table or the new one with all of its data.
+    async fn create_or_replace_table(
+        &self,
+        schema_name: String,
+        table_name: String,
+        columns: &Vec<ColumnDef>,
+        external: bool,
+        locations: Option<Vec<String>>,
+        with_options: Vec<SqlOption>,
+        indexes: Vec<Statement>,
+        query: Option<(Box<Query>, ProcessGuard)>,
+    ) -> Result<IdRow<Table>, CubeError> {
+        let temporary_name = format!("{}{}{}", table_name, REPLACEMENT_MARKER, Uuid::new_v4());
+        let created = self
+            .create_table(
+                schema_name.clone(),
+                temporary_name.clone(),
+                columns,
+                external,
+                locations,
+                with_options,
+                indexes,
+                query,
+            )
+            .await;
+        let table = match created {
+            Ok(table) => table,
+            Err(e) => {
+                if let Ok(table) = self.db.get_table(schema_name, temporary_name).await {
+                    self.db.drop_table(table.get_id()).await?;
+                }
+                return Err(e);
+            }
+        };
+        self.db
+            .replace_table(table.get_id(), schema_name, table_name)
+            .await
     }
 
     async fn create_index(
@@ -184,7 +359,7 @@ impl SqlServiceImpl {
                 item
             } else {
                 return Err(CubeError::user(format!(
-                    "Column {} does noot present in table {}.{}.",
+                    "Column {} is not present in table {}.{}.",
                     column.value, schema_name, table_name
                 )));
             };
@@ -193,11 +368,133 @@ impl SqlServiceImpl {
 
         let chunk_len = self.wal_store.get_wal_chunk_size();
 
+        let data_frames = data
+            .chunks(chunk_len)
+            .map(|rows_chunk| parse_chunk(rows_chunk, &real_col))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.add_wals(table, data_frames).await?;
+
+        Ok(data.len() as u64)
+    }
+
+    async fn insert_select(
+        &self,
+        schema_name: String,
+        table_name: String,
+        columns: &Vec<Ident>,
+        query: Box<Query>,
+        process: ProcessGuard,
+    ) -> Result<u64, CubeError> {
+        let table = self
+            .db
+            .get_table(schema_name.clone(), table_name.clone())
+            .await?;
+        let target_columns = if columns.is_empty() {
+            table.get_row().get_columns().clone()
+        } else {
+            columns
+                .iter()
+                .map(|column| {
+                    table
+                        .get_row()
+                        .get_columns()
+                        .iter()
+                        .find(|c| *c.get_name() == column.value)
+                        .cloned()
+                        .ok_or_else(|| {
+                            CubeError::user(format!(
+                                "Column {} is not present in table {}.{}.",
+                                column.value, schema_name, table_name
+                            ))
+                        })
+                })
+                .collect::<Result<Vec<_>, _>>()?
+        };
+        let (_, data) = self.select_stream(query, process).await?;
+        self.insert_stream(table, &target_columns, data).await
+    }
+
+    /// Query results are mapped to `columns` by position.
+    async fn insert_stream(
+        &self,
+        table: IdRow<Table>,
+        columns: &Vec<Column>,
+        data: DataStream,
+    ) -> Result<u64, CubeError> {
+        let fields = data.schema().fields().clone();
+        if fields.len() != columns.len() {
+            return Err(CubeError::user(format!(
+                "Query returns {} columns but {} columns are expected",
+                fields.len(),
+                columns.len()
+            )));
+        }
+        let schema = Arc::new(arrow::datatypes::Schema::new(
+            columns
+                .iter()
+                .zip(fields.iter())
+                .map(|(c, f)| Field::new(c.get_name(), f.data_type().clone(), true))
+                .collect(),
+        ));
+        let batches = data.map(move |batch| -> Result<RecordBatch, CubeError> {
+            Ok(RecordBatch::try_new(
+                schema.clone(),
+                batch?.columns().to_vec(),
+            )?)
+        });
+        self.add_wal_batches(table, batches).await
+    }
+
+    /// Batches are written to WALs of about `wal_chunk_size` rows as they're read.
+    async fn add_wal_batches(
+        &self,
+        table: IdRow<Table>,
+        mut batches: impl Stream<Item = Result<RecordBatch, CubeError>> + Send + Unpin,
+    ) -> Result<u64, CubeError> {
+        let chunk_size = self.wal_store.get_wal_chunk_size();
+        let listener = self.cluster.job_result_listener();
+        let mut wal_ids = Vec::new();
+        let mut pending = Vec::new();
+        let mut pending_rows = 0;
+        let mut rows = 0;
+        while let Some(batch) = batches.next().await {
+            let batch = batch?;
+            if batch.num_rows() == 0 {
+                continue;
+            }
+            pending_rows += batch.num_rows();
+            rows += batch.num_rows() as u64;
+            pending.push(batch);
+            if pending_rows >= chunk_size {
+                let wal = self
+                    .wal_store
+                    .add_wal_batches(table.clone(), mem::take(&mut pending))
+                    .await?;
+                wal_ids.push(wal.get_id());
+                pending_rows = 0;
+            }
+        }
+        if !pending.is_empty() {
+            let wal = self
+                .wal_store
+                .add_wal_batches(table.clone(), pending)
+                .await?;
+            wal_ids.push(wal.get_id());
+        }
+        self.wait_for_partitioning(listener, wal_ids).await?;
+        Ok(rows)
+    }
+
+    /// Writes WALs and waits until their rows are moved to chunks.
+    async fn add_wals(
+        &self,
+        table: IdRow<Table>,
+        data_frames: Vec<DataFrame>,
+    ) -> Result<(), CubeError> {
         let mut wal_ids = Vec::new();
 
         let listener = self.cluster.job_result_listener();
-        for rows_chunk in data.chunks(chunk_len) {
-            let data_frame = parse_chunk(rows_chunk, &real_col)?;
+        for data_frame in data_frames.into_iter() {
             wal_ids.push(
                 self.wal_store
                     .add_wal(table.clone(), data_frame)
@@ -206,6 +503,15 @@ impl SqlServiceImpl {
             );
         }
 
+        self.wait_for_partitioning(listener, wal_ids).await
+    }
+
+    /// `listener` should be created before WALs are added so their job results aren't missed.
+    async fn wait_for_partitioning(
+        &self,
+        listener: JobResultListener,
+        wal_ids: Vec<u64>,
+    ) -> Result<(), CubeError> {
         let res = listener
             .wait_for_job_results(
                 wal_ids
@@ -221,7 +527,245 @@ impl SqlServiceImpl {
             }
         }
 
-        Ok(data.len() as u64)
+        Ok(())
+    }
+
+    fn start_process(&self, context: &SqlQueryContext, q: &str) -> ProcessGuard {
+        self.process_list
+            .start(context.connection_id, context.user.clone(), q)
+    }
+
+    async fn allowed_schemas(
+        &self,
+        context: &SqlQueryContext,
+    ) -> Result<Vec<IdRow<Schema>>, CubeError> {
+        Ok(self
+            .db
+            .get_schemas()
+            .await?
+            .into_iter()
+            .filter(|s| is_schema_allowed(context, s.get_row().get_name()))
+            .collect())
+    }
+
+    async fn allowed_tables(
+        &self,
+        context: &SqlQueryContext,
+    ) -> Result<Vec<IdRow<Table>>, CubeError> {
+        let schema_ids = self
+            .allowed_schemas(context)
+            .await?
+            .iter()
+            .map(|s| s.get_id())
+            .collect::<Vec<_>>();
+        Ok(self
+            .db
+            .get_tables()
+            .await?
+            .into_iter()
+            .filter(|t| schema_ids.contains(&t.get_row().get_schema_id()))
+            .collect())
+    }
+
+    fn process_list_data_frame(&self, context: &SqlQueryContext) -> DataFrame {
+        DataFrame::new(
+            vec![
+                Column::new("id".to_string(), ColumnType::Int, 0),
+                Column::new("connection".to_string(), ColumnType::Int, 1),
+                Column::new("user".to_string(), ColumnType::String, 2),
+                Column::new("query".to_string(), ColumnType::String, 3),
+                Column::new("start_time".to_string(), ColumnType::Timestamp, 4),
+                Column::new("nodes".to_string(), ColumnType::String, 5),
+                Column::new("retries".to_string(), ColumnType::Int, 6),
+            ],
+            self.process_list
+                .processes()
+                .into_iter()
+                .filter(|p| can_manage_process(context, p))
+                .map(|p| {
+                    Row::new(vec![
+                        TableValue::Int(p.id as i64),
+                        p.connection_id
+                            .map(|c| TableValue::Int(c as i64))
+                            .unwrap_or(TableValue::Null),
+                        p.user.map(TableValue::String).unwrap_or(TableValue::Null),
+                        TableValue::String(p.query),
+                        TableValue::Timestamp(TimestampValue::new(p.start_time.timestamp_nanos())),
+                        TableValue::String(p.nodes.join(",")),
+                        TableValue::Int(p.retries as i64),
+                    ])
+                })
+                .collect(),
+        )
+    }
+
+    async fn nodes_data_frame(&self) -> Result<DataFrame, CubeError> {
+        Ok(DataFrame::new(
+            vec![
+                Column::new("name".to_string(), ColumnType::String, 0),
+                Column::new("status".to_string(), ColumnType::String, 1),
+                Column::new("last_heart_beat".to_string(), ColumnType::Timestamp, 2),
+            ],
+            self.cluster
+                .nodes()
+                .await?
+                .into_iter()
+                .map(|n| {
+                    Row::new(vec![
+                        TableValue::String(n.name),
+                        TableValue::String(if n.alive { "alive" } else { "dead" }.to_string()),
+                        TableValue::Timestamp(TimestampValue::new(
+                            n.last_heart_beat.timestamp_nanos(),
+                        )),
+                    ])
+                })
+                .collect(),
+        ))
+    }
+
+    /// Result columns selected from tables as is keep types of the table columns, e.g. HLL.
+    /// Process is finished once the returned stream is dropped.
+    async fn select_stream(
+        &self,
+        query: Box<Query>,
+        process: ProcessGuard,
+    ) -> Result<(Vec<Column>, DataStream), CubeError> {
+        let logical_plan = self
+            .query_planner
+            .logical_plan(DFStatement::Statement(Statement::Query(query)))
+            .await?;
+        match logical_plan {
+            QueryPlan::Meta(logical_plan) => {
+                let data_frame = self.query_planner.execute_meta_plan(logical_plan).await?;
+                let columns = data_frame.get_columns().clone();
+                Ok((columns, DataStream::from_data_frame(data_frame)?))
+            }
+            QueryPlan::Select(serialized) => {
+                let serialized = serialized.with_query_id(process.id());
+                let execution = self
+                    .query_executor
+                    .execute_router_plan_stream(serialized.clone(), self.cluster.clone());
+                let data_stream = tokio::select! {
+                    res = execution => res?,
+                    e = process.cancelled() => return Err(e),
+                };
+                let data_stream =
+                    data_stream.with_cancellation(async move { process.cancelled().await });
+                let fields = data_stream.schema().fields().clone();
+                let columns = fields
+                    .iter()
+                    .enumerate()
+                    .map(|(i, f)| {
+                        let source_type =
+                            serialized
+                                .source_column(f.name())
+                                .and_then(|(source, name)| {
+                                    source
+                                        .table
+                                        .get_row()
+                                        .get_columns()
+                                        .iter()
+                                        .find(|c| *c.get_name() == name)
+                                        .map(|c| c.get_column_type().clone())
+                                });
+                        let column_type = match source_type {
+                            Some(t) => t,
+                            None => arrow_to_column_type(f.data_type().clone())?,
+                        };
+                        Ok(Column::new(f.name().clone(), column_type, i))
+                    })
+                    .collect::<Result<Vec<_>, CubeError>>()?;
+                let source_tables = fields
+                    .iter()
+                    .map(|f| serialized.source_table(f.name()))
+                    .collect();
+                Ok((columns, data_stream.with_source_tables(source_tables)))
+            }
+        }
+    }
+
+    /// Rows are deleted by compactions of partitions which can contain them. Deletes aren't
+    /// atomic: concurrent selects can see rows deleted only from some of the partitions and rows
+    /// of concurrent inserts which aren't moved to chunks yet are kept. Compactions which don't
+    /// finish within the query timeout are left running.
+    async fn delete_rows(
+        &self,
+        schema_name: String,
+        table_name: String,
+        selection: Option<Expr>,
+    ) -> Result<(), CubeError> {
+        let table = self.db.get_table(schema_name, table_name).await?;
+        let filter = match selection {
+            Some(selection) => row_filter(&selection, table.get_row().get_columns())?,
+            None => RowFilter::All,
+        };
+        let indexes = self.db.get_table_indexes(table.get_id()).await?;
+        for index in indexes.iter() {
+            self.db
+                .add_partition_delete(index.get_id(), filter.clone())
+                .await?;
+        }
+        let query_timeout = self.config_obj.query_timeout();
+        timeout(
+            Duration::from_secs(query_timeout),
+            self.wait_for_deletes(&indexes),
+        )
+        .await
+        .map_err(|_| {
+            CubeError::user(format!(
+                "Delete from {} is still running after {} seconds",
+                table.get_row().get_table_name(),
+                query_timeout
+            ))
+        })?
+    }
+
+    async fn wait_for_deletes(&self, indexes: &Vec<IdRow<Index>>) -> Result<(), CubeError> {
+        // Compaction running while a delete is added passes it to the new partitions and chunks
+        // it didn't compact are left to repartitioning.
+        loop {
+            let listener = self.cluster.job_result_listener();
+            let mut pending = Vec::new();
+            for index in indexes.iter() {
+                let partitions = self
+                    .db
+                    .get_partitions_with_pending_deletes(index.get_id())
+                    .await?;
+                for partition in partitions {
+                    let job_type = if partition.get_row().is_active() {
+                        JobType::PartitionCompaction
+                    } else {
+                        JobType::Repartition
+                    };
+                    pending.push((
+                        RowKey::Table(TableId::Partitions, partition.get_id()),
+                        job_type,
+                    ));
+                }
+            }
+            if pending.is_empty() {
+                return Ok(());
+            }
+            for (row_key, job_type) in pending.iter() {
+                let job = self
+                    .db
+                    .get_job_by_ref(row_key.clone(), job_type.clone())
+                    .await?;
+                if let Some(job) = job {
+                    if let JobStatus::Error(_) | JobStatus::Timeout = job.get_row().status() {
+                        return Err(CubeError::user(format!(
+                            "Delete job failed: {:?}",
+                            job.get_row()
+                        )));
+                    }
+                }
+            }
+            for event in listener.wait_for_job_results(pending).await? {
+                if let JobEvent::Error(_, _, e) = event {
+                    return Err(CubeError::user(format!("Delete job failed: {}", e)));
+                }
+            }
+        }
     }
 }
 
@@ -252,23 +796,212 @@ impl Dialect for MySqlDialectWithBackTicks {
 #[async_trait]
 impl SqlService for SqlServiceImpl {
     async fn exec_query(&self, q: &str) -> Result<DataFrame, CubeError> {
+        self.exec_query_with_context(SqlQueryContext::default(), q)
+            .await
+    }
+
+    async fn exec_query_with_context(
+        &self,
+        context: SqlQueryContext,
+        q: &str,
+    ) -> Result<DataFrame, CubeError> {
         if !q.to_lowercase().starts_with("insert") {
             trace!("Query: '{}'", q);
         }
         if let Some(data_frame) = SqlServiceImpl::handle_workbench_queries(q) {
             return Ok(data_frame);
         }
-        let ast = {
-            let replaced_quote = q.replace("\\'", "''");
-            let mut parser = CubeStoreParser::new(&replaced_quote)?;
-            parser.parse_statement()?
-        };
+        let ast = parse_statement(q)?;
         // trace!("AST is: {:?}", ast);
+        self.exec_statement(&context, q, ast).await
+    }
+
+    fn prepare(&self, q: &str) -> Result<PreparedStatement, CubeError> {
+        let replaced_quote = q.replace("\\'", "''");
+        let placeholder_prefix = loop {
+            let prefix = format!("?{}?", Uuid::new_v4());
+            if !replaced_quote.contains(&prefix) {
+                break prefix;
+            }
+        };
+        let (mut parser, params_count) =
+            CubeStoreParser::new_with_placeholders(&replaced_quote, &placeholder_prefix)?;
+        let statement = parser.parse_statement()?;
+        Ok(PreparedStatement {
+            query: q.to_string(),
+            statement,
+            params_count,
+            placeholder_prefix,
+        })
+    }
+
+    async fn exec_prepared(
+        &self,
+        context: SqlQueryContext,
+        statement: &PreparedStatement,
+        params: Vec<TableValue>,
+    ) -> Result<DataFrame, CubeError> {
+        let ast = bind_prepared(statement, params)?;
+        self.exec_statement(&context, &statement.query, ast).await
+    }
+
+    async fn exec_query_stream(
+        &self,
+        context: SqlQueryContext,
+        q: &str,
+    ) -> Result<DataStream, CubeError> {
+        trace!("Query: '{}'", q);
+        if let Some(data_frame) = SqlServiceImpl::handle_workbench_queries(q) {
+            return DataStream::from_data_frame(data_frame);
+        }
+        let ast = parse_statement(q)?;
+        self.exec_statement_stream(&context, q, ast).await
+    }
+
+    async fn exec_prepared_stream(
+        &self,
+        context: SqlQueryContext,
+        statement: &PreparedStatement,
+        params: Vec<TableValue>,
+    ) -> Result<DataStream, CubeError> {
+        let ast = bind_prepared(statement, params)?;
+        self.exec_statement_stream(&context, &statement.query, ast)
+            .await
+    }
+
+    async fn insert_batches(
+        &self,
+        context: SqlQueryContext,
+        schema_name: &str,
+        table_name: &str,
+        batches: Pin<Box<dyn Stream<Item = Result<RecordBatch, CubeError>> + Send>>,
+    ) -> Result<u64, CubeError> {
+        check_access(
+            &context,
+            &format!("INSERT INTO {}.{}", schema_name, table_name),
+            Some(vec![schema_name.to_string()]),
+        )?;
+        let table = self
+            .db
+            .get_table(schema_name.to_string(), table_name.to_string())
+            .await?;
+        self.add_wal_batches(table, batches).await
+    }
+}
+
+fn bind_prepared(
+    statement: &PreparedStatement,
+    params: Vec<TableValue>,
+) -> Result<CubeStoreStatement, CubeError> {
+    if params.len() != statement.params_count {
+        return Err(CubeError::user(format!(
+            "Statement expects {} parameters but {} were provided",
+            statement.params_count,
+            params.len()
+        )));
+    }
+    let params = params.iter().map(param_to_expr).collect::<Vec<_>>();
+    let mut ast = statement.statement.clone();
+    let bound = bind_statement_params(&mut ast, &statement.placeholder_prefix, &params)?;
+    if bound != statement.params_count {
+        return Err(CubeError::user(format!(
+            "Unsupported placeholder position in prepared statement: '{}'",
+            statement.query
+        )));
+    }
+    Ok(ast)
+}
+
+/// Users with restricted schemas can only manage their own processes.
+fn can_manage_process(context: &SqlQueryContext, process: &ProcessInfo) -> bool {
+    context.allowed_schemas.is_none() || context.user == process.user
+}
+
+fn is_schema_allowed(context: &SqlQueryContext, schema: &str) -> bool {
+    match &context.allowed_schemas {
+        Some(allowed_schemas) => allowed_schemas
+            .iter()
+            .any(|a| a.eq_ignore_ascii_case(schema)),
+        None => true,
+    }
+}
+
+fn parse_statement(q: &str) -> Result<CubeStoreStatement, CubeError> {
+    let replaced_quote = q.replace("\\'", "''");
+    let mut parser = CubeStoreParser::new(&replaced_quote)?;
+    Ok(parser.parse_statement()?)
+}
+
+/// `schemas` are the ones accessed by statement `q`, `None` if they can't be determined.
+fn check_access(
+    context: &SqlQueryContext,
+    q: &str,
+    schemas: Option<Vec<String>>,
+) -> Result<(), CubeError> {
+    if context.allowed_schemas.is_some() {
+        let schemas = schemas.ok_or_else(|| {
+            CubeError::user(format!(
+                "Statement is not allowed for user {}: '{}'",
+                context.user.as_deref().unwrap_or(""),
+                q
+            ))
+        })?;
+        if let Some(schema) = schemas.iter().find(|s| !is_schema_allowed(context, s)) {
+            return Err(CubeError::user(format!(
+                "Access denied for user {} to schema '{}'",
+                context.user.as_deref().unwrap_or(""),
+                schema
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn with_default_schema(
+    context: &SqlQueryContext,
+    mut ast: CubeStoreStatement,
+) -> Result<CubeStoreStatement, CubeError> {
+    if let Some(default_schema) = &context.default_schema {
+        qualify_table_names(&mut ast, default_schema)?;
+    }
+    Ok(ast)
+}
+
+impl SqlServiceImpl {
+    /// Only selects are streamed, results of other statements are sent as a single batch.
+    async fn exec_statement_stream(
+        &self,
+        context: &SqlQueryContext,
+        q: &str,
+        ast: CubeStoreStatement,
+    ) -> Result<DataStream, CubeError> {
+        let ast = with_default_schema(context, ast)?;
+        match ast {
+            CubeStoreStatement::Statement(Statement::Query(query)) => {
+                check_access(context, q, query_schemas(&query))?;
+                let process = self.start_process(context, q);
+                let (_, data_stream) = self.select_stream(query, process).await?;
+                Ok(data_stream)
+            }
+            ast => DataStream::from_data_frame(self.exec_statement(context, q, ast).await?),
+        }
+    }
+
+    async fn exec_statement(
+        &self,
+        context: &SqlQueryContext,
+        q: &str,
+        ast: CubeStoreStatement,
+    ) -> Result<DataFrame, CubeError> {
+        let ast = with_default_schema(context, ast)?;
+        check_access(context, q, statement_schemas(&ast))?;
         match ast {
             CubeStoreStatement::Statement(Statement::ShowVariable { variable }) => {
                 match variable.value.to_lowercase() {
-                    s if s == "schemas" => Ok(DataFrame::from(self.db.get_schemas().await?)),
-                    s if s == "tables" => Ok(DataFrame::from(self.db.get_tables().await?)),
+                    s if s == "schemas" => {
+                        Ok(DataFrame::from(self.allowed_schemas(context).await?))
+                    }
+                    s if s == "tables" => Ok(DataFrame::from(self.allowed_tables(context).await?)),
                     s if s == "chunks" => {
                         Ok(DataFrame::from(self.db.chunks_table().all_rows().await?))
                     }
@@ -278,6 +1011,8 @@ impl SqlService for SqlServiceImpl {
                     s if s == "partitions" => {
                         Ok(DataFrame::from(self.db.partition_table().all_rows().await?))
                     }
+                    s if s == "processlist" => Ok(self.process_list_data_frame(context)),
+                    s if s == "nodes" => self.nodes_data_frame().await,
                     x => Err(CubeError::user(format!("Unknown SHOW: {}", x))),
                 }
             }
@@ -298,10 +1033,13 @@ impl SqlService for SqlServiceImpl {
                         name,
                         columns,
                         external,
-                        location,
+                        with_options,
+                        or_replace,
+                        query,
                         ..
                     },
                 indexes,
+                locations,
             } => {
                 let nv = &name.0;
                 if nv.len() != 2 {
@@ -312,17 +1050,33 @@ impl SqlService for SqlServiceImpl {
                 }
                 let schema_name = &nv[0].value;
                 let table_name = &nv[1].value;
+                let query = query.map(|query| (query, self.start_process(context, q)));
 
-                let res = self
-                    .create_table(
+                let res = if or_replace {
+                    self.create_or_replace_table(
                         schema_name.clone(),
                         table_name.clone(),
                         &columns,
                         external,
-                        location,
+                        locations,
+                        with_options,
                         indexes,
+                        query,
                     )
-                    .await?;
+                    .await?
+                } else {
+                    self.create_table(
+                        schema_name.clone(),
+                        table_name.clone(),
+                        &columns,
+                        external,
+                        locations,
+                        with_options,
+                        indexes,
+                        query,
+                    )
+                    .await?
+                };
                 Ok(DataFrame::from(vec![res]))
             }
             CubeStoreStatement::Statement(Statement::CreateIndex {
@@ -361,17 +1115,79 @@ impl SqlService for SqlServiceImpl {
                     .await?;
                 Ok(DataFrame::from(vec![res]))
             }
-            CubeStoreStatement::Statement(Statement::Drop {
-                object_type, names, ..
-            }) => {
-                match object_type {
-                    ObjectType::Schema => {
-                        self.db.delete_schema(names[0].to_string()).await?;
-                    }
-                    ObjectType::Table => {
-                        let table = self
-                            .db
-                            .get_table(names[0].0[0].to_string(), names[0].0[1].to_string())
+            CubeStoreStatement::AlterTable {
+                table_name,
+                operation,
+            } => {
+                if table_name.0.len() != 2 {
+                    return Err(CubeError::user(format!(
+                        "Schema's name should be present in table name but found: {}",
+                        table_name
+                    )));
+                }
+                let table = self
+                    .db
+                    .get_table(table_name.0[0].value.clone(), table_name.0[1].value.clone())
+                    .await?;
+                let res = match operation {
+                    CubeStoreAlterTableOperation::AddColumn {
+                        column_name,
+                        data_type,
+                    } => {
+                        self.db
+                            .alter_table_add_column(
+                                table.get_id(),
+                                column_name.value,
+                                convert_column_type(&data_type)?,
+                            )
+                            .await?
+                    }
+                    CubeStoreAlterTableOperation::DropColumn { column_name } => {
+                        self.db
+                            .alter_table_drop_column(table.get_id(), column_name.value)
+                            .await?
+                    }
+                    CubeStoreAlterTableOperation::RenameColumn {
+                        old_column_name,
+                        new_column_name,
+                    } => {
+                        self.db
+                            .alter_table_rename_column(
+                                table.get_id(),
+                                old_column_name.value,
+                                new_column_name.value,
+                            )
+                            .await?
+                    }
+                    CubeStoreAlterTableOperation::RenameTable { new_table_name } => {
+                        if new_table_name.0.len() != 2 {
+                            return Err(CubeError::user(format!(
+                                "Schema's name should be present in table name but found: {}",
+                                new_table_name
+                            )));
+                        }
+                        self.db
+                            .rename_table(
+                                table.get_id(),
+                                new_table_name.0[0].value.clone(),
+                                new_table_name.0[1].value.clone(),
+                            )
+                            .await?
+                    }
+                };
+                Ok(DataFrame::from(vec![res]))
+            }
+            CubeStoreStatement::Statement(Statement::Drop {
+                object_type, names, ..
+            }) => {
+                match object_type {
+                    ObjectType::Schema => {
+                        self.db.delete_schema(names[0].to_string()).await?;
+                    }
+                    ObjectType::Table => {
+                        let table = self
+                            .db
+                            .get_table(names[0].0[0].to_string(), names[0].0[1].to_string())
                             .await?;
                         self.db.drop_table(table.get_id()).await?;
                     }
@@ -384,15 +1200,6 @@ impl SqlService for SqlServiceImpl {
                 columns,
                 source,
             }) => {
-                let data = if let SetExpr::Values(Values(data_series)) = &source.body {
-                    data_series
-                } else {
-                    return Err(CubeError::user(format!(
-                        "Data should be present in query. Your query was '{}'",
-                        q
-                    )));
-                };
-
                 let nv = &table_name.0;
                 if nv.len() != 2 {
                     return Err(CubeError::user(format!("Schema's name should be present in query (boo.table1). Your query was '{}'", q)));
@@ -400,27 +1207,73 @@ impl SqlService for SqlServiceImpl {
                 let schema_name = &nv[0].value;
                 let table_name = &nv[1].value;
 
-                self.insert_data(schema_name.clone(), table_name.clone(), &columns, data)
+                if let SetExpr::Values(Values(data_series)) = &source.body {
+                    self.insert_data(
+                        schema_name.clone(),
+                        table_name.clone(),
+                        &columns,
+                        data_series,
+                    )
                     .await?;
+                } else {
+                    let process = self.start_process(context, q);
+                    self.insert_select(
+                        schema_name.clone(),
+                        table_name.clone(),
+                        &columns,
+                        source,
+                        process,
+                    )
+                    .await?;
+                }
                 Ok(DataFrame::new(vec![], vec![]))
             }
-            CubeStoreStatement::Statement(Statement::Query(q)) => {
-                let logical_plan = self
-                    .query_planner
-                    .logical_plan(DFStatement::Statement(Statement::Query(q)))
-                    .await?;
-                // TODO distribute and combine
-                let res = match logical_plan {
-                    QueryPlan::Meta(logical_plan) => {
-                        self.query_planner.execute_meta_plan(logical_plan).await?
-                    }
-                    QueryPlan::Select(serialized) => {
-                        self.query_executor
-                            .execute_router_plan(serialized, self.cluster.clone())
-                            .await?
-                    }
-                };
-                Ok(res)
+            CubeStoreStatement::Statement(Statement::Delete {
+                table_name,
+                selection,
+            }) => {
+                if table_name.0.len() != 2 {
+                    return Err(CubeError::user(format!(
+                        "Schema's name should be present in table name but found: {}",
+                        table_name
+                    )));
+                }
+                self.delete_rows(
+                    table_name.0[0].value.clone(),
+                    table_name.0[1].value.clone(),
+                    selection,
+                )
+                .await?;
+                Ok(DataFrame::new(vec![], vec![]))
+            }
+            CubeStoreStatement::KillQuery { query_id } => {
+                let process = self
+                    .process_list
+                    .get(query_id)
+                    .filter(|p| can_manage_process(context, p))
+                    .ok_or_else(|| CubeError::user(format!("Unknown query id: {}", query_id)))?;
+                self.process_list.kill(process.id)?;
+                Ok(DataFrame::new(vec![], vec![]))
+            }
+            CubeStoreStatement::Truncate { table_name } => {
+                if table_name.0.len() != 2 {
+                    return Err(CubeError::user(format!(
+                        "Schema's name should be present in table name but found: {}",
+                        table_name
+                    )));
+                }
+                self.delete_rows(
+                    table_name.0[0].value.clone(),
+                    table_name.0[1].value.clone(),
+                    None,
+                )
+                .await?;
+                Ok(DataFrame::new(vec![], vec![]))
+            }
+            CubeStoreStatement::Statement(Statement::Query(query)) => {
+                let process = self.start_process(context, q);
+                let (_, data_stream) = self.select_stream(query, process).await?;
+                data_stream.into_data_frame().await
             }
             _ => Err(CubeError::user(format!("Unsupported SQL: '{}'", q))),
         }
@@ -433,59 +1286,7 @@ fn convert_columns_type(columns: &Vec<ColumnDef>) -> Result<Vec<Column>, CubeErr
     for (i, col) in columns.iter().enumerate() {
         let cube_col = Column::new(
             col.name.value.clone(),
-            match &col.data_type {
-                DataType::Date
-                | DataType::Time
-                | DataType::Char(_)
-                | DataType::Varchar(_)
-                | DataType::Clob(_)
-                | DataType::Text => ColumnType::String,
-                DataType::Uuid
-                | DataType::Binary(_)
-                | DataType::Varbinary(_)
-                | DataType::Blob(_)
-                | DataType::Bytea
-                | DataType::Array(_) => ColumnType::Bytes,
-                DataType::Decimal(precision, scale) => {
-                    let mut precision = precision.unwrap_or(18);
-                    let mut scale = scale.unwrap_or(5);
-                    if precision > 18 {
-                        precision = 18;
-                    }
-                    if scale > 5 {
-                        scale = 10;
-                    }
-                    if scale > precision {
-                        precision = scale;
-                    }
-                    ColumnType::Decimal {
-                        precision: precision as i32,
-                        scale: scale as i32,
-                    }
-                }
-                DataType::SmallInt | DataType::Int | DataType::BigInt | DataType::Interval => {
-                    ColumnType::Int
-                }
-                DataType::Boolean => ColumnType::Boolean,
-                DataType::Float(_) | DataType::Real | DataType::Double => ColumnType::Float,
-                DataType::Timestamp => ColumnType::Timestamp,
-                DataType::Custom(custom) => {
-                    let custom_type_name = custom.to_string().to_lowercase();
-                    match custom_type_name.as_str() {
-                        "mediumint" => ColumnType::Int,
-                        "varbinary" => ColumnType::Bytes,
-                        "hyperloglog" => ColumnType::HyperLogLog,
-                        _ => return Err(CubeError::user(format!(
-                                "Custom type '{}' is not supported",
-                                custom)))
-                    }
-                }
-                DataType::Regclass => {
-                    return Err(CubeError::user(
-                        "Type 'RegClass' is not suppored.".to_string(),
-                    ));
-                }
-            },
+            convert_column_type(&col.data_type)?,
             i,
         );
         rolupdb_columns.push(cube_col);
@@ -493,6 +1294,496 @@ fn convert_columns_type(columns: &Vec<ColumnDef>) -> Result<Vec<Column>, CubeErr
     Ok(rolupdb_columns)
 }
 
+fn convert_column_type(data_type: &DataType) -> Result<ColumnType, CubeError> {
+    Ok(match data_type {
+        DataType::Date
+        | DataType::Time
+        | DataType::Char(_)
+        | DataType::Varchar(_)
+        | DataType::Clob(_)
+        | DataType::Text => ColumnType::String,
+        DataType::Uuid
+        | DataType::Binary(_)
+        | DataType::Varbinary(_)
+        | DataType::Blob(_)
+        | DataType::Bytea
+        | DataType::Array(_) => ColumnType::Bytes,
+        DataType::Decimal(precision, scale) => {
+            let mut precision = precision.unwrap_or(18);
+            let mut scale = scale.unwrap_or(5);
+            if precision > 18 {
+                precision = 18;
+            }
+            if scale > 5 {
+                scale = 10;
+            }
+            if scale > precision {
+                precision = scale;
+            }
+            ColumnType::Decimal {
+                precision: precision as i32,
+                scale: scale as i32,
+            }
+        }
+        DataType::SmallInt | DataType::Int | DataType::BigInt | DataType::Interval => {
+            ColumnType::Int
+        }
+        DataType::Boolean => ColumnType::Boolean,
+        DataType::Float(_) | DataType::Real | DataType::Double => ColumnType::Float,
+        DataType::Timestamp => ColumnType::Timestamp,
+        DataType::Custom(custom) => {
+            let custom_type_name = custom.to_string().to_lowercase();
+            match custom_type_name.as_str() {
+                "mediumint" => ColumnType::Int,
+                "varbinary" => ColumnType::Bytes,
+                "hyperloglog" => ColumnType::HyperLogLog,
+                _ => {
+                    return Err(CubeError::user(format!(
+                        "Custom type '{}' is not supported",
+                        custom
+                    )))
+                }
+            }
+        }
+        DataType::Regclass => {
+            return Err(CubeError::user(
+                "Type 'RegClass' is not suppored.".to_string(),
+            ));
+        }
+    })
+}
+
+fn import_format_from_options(
+    with_options: &[SqlOption],
+    locations: &[String],
+) -> Result<ImportFormat, CubeError> {
+    let mut format = None;
+    let mut csv_options = None;
+    for option in with_options.iter() {
+        let name = option.name.value.to_lowercase();
+        if name == "format" {
+            format = Some(option_string(option)?.to_lowercase());
+            continue;
+        }
+        let options = csv_options.get_or_insert_with(CsvOptions::default);
+        match name.as_str() {
+            "delimiter" => {
+                let delimiter = option_string(option)?;
+                if delimiter.len() != 1 {
+                    return Err(CubeError::user(format!(
+                        "CSV delimiter should be a single ASCII character but found: {}",
+                        option.value
+                    )));
+                }
+                options.delimiter = delimiter.as_bytes()[0];
+            }
+            "header" => options.has_header = option_bool(option)?,
+            "null_values" => {
+                options.null_values = option_string(option)?
+                    .split(',')
+                    .map(|s| s.to_string())
+                    .collect()
+            }
+            "strict" => options.strict = option_bool(option)?,
+            x => return Err(CubeError::user(format!("Unknown table option: {}", x))),
+        }
+    }
+    let format = format.unwrap_or_else(|| {
+        locations
+            .first()
+            .map(|l| format_from_extension(l))
+            .unwrap_or("csv")
+            .to_string()
+    });
+    match (format.as_str(), csv_options) {
+        ("csv", None) => Ok(ImportFormat::CSV),
+        ("csv", Some(options)) => Ok(ImportFormat::CSVWithOptions(options)),
+        ("parquet", None) => Ok(ImportFormat::Parquet),
+        ("arrow", None) | ("arrow_ipc", None) => Ok(ImportFormat::ArrowIpc),
+        ("parquet", Some(_)) | ("arrow", Some(_)) | ("arrow_ipc", Some(_)) => Err(CubeError::user(
+            format!("CSV options can't be used with {} format", format),
+        )),
+        (x, _) => Err(CubeError::user(format!("Unknown import format: {}", x))),
+    }
+}
+
+fn format_from_extension(location: &str) -> &'static str {
+    let path = location.split('?').next().unwrap();
+    let path = path.strip_suffix(".gz").unwrap_or(path);
+    if path.ends_with(".parquet") {
+        "parquet"
+    } else if path.ends_with(".arrow") || path.ends_with(".ipc") || path.ends_with(".feather") {
+        "arrow"
+    } else {
+        "csv"
+    }
+}
+
+fn option_string(option: &SqlOption) -> Result<String, CubeError> {
+    match &option.value {
+        Value::SingleQuotedString(s) => Ok(s.to_string()),
+        v => Err(CubeError::user(format!(
+            "Single quoted string is expected for option {} but {} found",
+            option.name, v
+        ))),
+    }
+}
+
+fn option_bool(option: &SqlOption) -> Result<bool, CubeError> {
+    match &option.value {
+        Value::Boolean(b) => Ok(*b),
+        Value::SingleQuotedString(s) if s.to_lowercase() == "true" => Ok(true),
+        Value::SingleQuotedString(s) if s.to_lowercase() == "false" => Ok(false),
+        v => Err(CubeError::user(format!(
+            "Boolean is expected for option {} but {} found",
+            option.name, v
+        ))),
+    }
+}
+
+fn row_filter(e: &Expr, columns: &Vec<Column>) -> Result<RowFilter, CubeError> {
+    Ok(match e {
+        Expr::Nested(e) => row_filter(e, columns)?,
+        Expr::BinaryOp {
+            left,
+            op: BinaryOperator::And,
+            right,
+        } => RowFilter::And(
+            Box::new(row_filter(left, columns)?),
+            Box::new(row_filter(right, columns)?),
+        ),
+        Expr::BinaryOp {
+            left,
+            op: BinaryOperator::Or,
+            right,
+        } => RowFilter::Or(
+            Box::new(row_filter(left, columns)?),
+            Box::new(row_filter(right, columns)?),
+        ),
+        Expr::UnaryOp {
+            op: UnaryOperator::Not,
+            expr,
+        } => RowFilter::Not(Box::new(row_filter(expr, columns)?)),
+        Expr::IsNull(expr) => RowFilter::IsNull {
+            column: filter_column(expr, columns)?.get_name().clone(),
+            negated: false,
+        },
+        Expr::IsNotNull(expr) => RowFilter::IsNull {
+            column: filter_column(expr, columns)?.get_name().clone(),
+            negated: true,
+        },
+        Expr::InList {
+            expr,
+            list,
+            negated,
+        } => {
+            let column = filter_column(expr, columns)?;
+            RowFilter::InList {
+                column: column.get_name().clone(),
+                list: list
+                    .iter()
+                    .map(|v| extract_data(v, &vec![column], 0))
+                    .collect::<Result<Vec<_>, _>>()?,
+                negated: *negated,
+            }
+        }
+        Expr::Between {
+            expr,
+            negated,
+            low,
+            high,
+        } => {
+            let column = filter_column(expr, columns)?;
+            let between = RowFilter::And(
+                Box::new(compare_filter(column, Operator::GtEq, low)?),
+                Box::new(compare_filter(column, Operator::LtEq, high)?),
+            );
+            if *negated {
+                RowFilter::Not(Box::new(between))
+            } else {
+                between
+            }
+        }
+        Expr::BinaryOp { left, op, right } => {
+            let op = match op {
+                BinaryOperator::Eq => Operator::Eq,
+                BinaryOperator::NotEq => Operator::NotEq,
+                BinaryOperator::Lt => Operator::Lt,
+                BinaryOperator::LtEq => Operator::LtEq,
+                BinaryOperator::Gt => Operator::Gt,
+                BinaryOperator::GtEq => Operator::GtEq,
+                x => {
+                    return Err(CubeError::user(format!(
+                        "Unsupported operator in DELETE condition: {}",
+                        x
+                    )))
+                }
+            };
+            if let Expr::Identifier(_) = left.as_ref() {
+                compare_filter(filter_column(left, columns)?, op, right)?
+            } else {
+                let op = match op {
+                    Operator::Lt => Operator::Gt,
+                    Operator::LtEq => Operator::GtEq,
+                    Operator::Gt => Operator::Lt,
+                    Operator::GtEq => Operator::LtEq,
+                    op => op,
+                };
+                compare_filter(filter_column(right, columns)?, op, left)?
+            }
+        }
+        x => {
+            return Err(CubeError::user(format!(
+                "Unsupported DELETE condition: {}",
+                x
+            )))
+        }
+    })
+}
+
+fn filter_column<'a>(e: &Expr, columns: &'a Vec<Column>) -> Result<&'a Column, CubeError> {
+    if let Expr::Identifier(ident) = e {
+        columns
+            .iter()
+            .find(|c| *c.get_name() == ident.value)
+            .ok_or_else(|| CubeError::user(format!("Column {} is not found", ident.value)))
+    } else {
+        Err(CubeError::user(format!(
+            "Column is expected in DELETE condition but {} found",
+            e
+        )))
+    }
+}
+
+fn compare_filter(column: &Column, op: Operator, value: &Expr) -> Result<RowFilter, CubeError> {
+    Ok(RowFilter::Compare {
+        column: column.get_name().clone(),
+        op,
+        value: extract_data(value, &vec![column], 0)?,
+    })
+}
+
+fn param_to_expr(value: &TableValue) -> Expr {
+    Expr::Value(match value {
+        TableValue::Null => Value::Null,
+        TableValue::String(v) => Value::SingleQuotedString(v.clone()),
+        TableValue::Int(v) => Value::Number(v.to_string()),
+        TableValue::Decimal(v) | TableValue::Float(v) => Value::Number(v.clone()),
+        TableValue::Timestamp(v) => Value::SingleQuotedString(v.to_string()),
+        TableValue::Boolean(v) => Value::Boolean(*v),
+        TableValue::Bytes(v) => Value::HexStringLiteral(v.encode_hex_upper::<String>()),
+    })
+}
+
+fn query_schemas(query: &Box<Query>) -> Option<Vec<String>> {
+    statement_schemas(&CubeStoreStatement::Statement(Statement::Query(
+        query.clone(),
+    )))
+}
+
+/// Schemas referenced by the statement or `None` if they can't be determined.
+fn statement_schemas(statement: &CubeStoreStatement) -> Option<Vec<String>> {
+    let mut collector = SchemaCollector {
+        schemas: Vec::new(),
+        cte_names: Vec::new(),
+        unqualified: false,
+    };
+    match statement {
+        CubeStoreStatement::Statement(Statement::Drop {
+            object_type: ObjectType::Schema,
+            names,
+            ..
+        }) => collector
+            .schemas
+            .extend(names.iter().map(|n| n.to_string())),
+        CubeStoreStatement::CreateSchema { schema_name, .. } => {
+            collector.schemas.push(schema_name.to_string())
+        }
+        // Locations are read with the server's credentials and network access.
+        CubeStoreStatement::CreateTable {
+            locations: Some(_), ..
+        } => return None,
+        _ => {}
+    }
+    if !walk_statement_tables(&mut statement.clone(), &mut collector).ok()? {
+        return None;
+    }
+    if collector.unqualified {
+        return None;
+    }
+    Some(collector.schemas)
+}
+
+/// Visits table names of statements which access control supports, returns `false` for other
+/// statements.
+fn walk_statement_tables(
+    statement: &mut CubeStoreStatement,
+    visitor: &mut dyn QueryVisitor,
+) -> Result<bool, CubeError> {
+    match statement {
+        CubeStoreStatement::Statement(Statement::SetVariable { .. })
+        | CubeStoreStatement::Statement(Statement::Drop {
+            object_type: ObjectType::Schema,
+            ..
+        })
+        | CubeStoreStatement::CreateSchema { .. }
+        | CubeStoreStatement::KillQuery { .. } => {}
+        CubeStoreStatement::Statement(Statement::Drop {
+            object_type: ObjectType::Table,
+            names,
+            ..
+        }) => {
+            for name in names.iter_mut() {
+                visitor.visit_table(name)?;
+            }
+        }
+        CubeStoreStatement::Statement(Statement::CreateIndex { table_name, .. }) => {
+            visitor.visit_table(table_name)?
+        }
+        CubeStoreStatement::Statement(s @ Statement::Query(_))
+        | CubeStoreStatement::Statement(s @ Statement::Insert { .. })
+        | CubeStoreStatement::Statement(s @ Statement::Delete { .. })
+        | CubeStoreStatement::CreateTable {
+            create_table: s, ..
+        } => walk_statement(s, visitor)?,
+        CubeStoreStatement::AlterTable {
+            table_name,
+            operation,
+        } => {
+            visitor.visit_table(table_name)?;
+            if let CubeStoreAlterTableOperation::RenameTable { new_table_name } = operation {
+                visitor.visit_table(new_table_name)?;
+            }
+        }
+        CubeStoreStatement::Truncate { table_name } => visitor.visit_table(table_name)?,
+        // Their output is filtered by the allowed schemas.
+        CubeStoreStatement::Statement(Statement::ShowVariable { variable })
+            if ["processlist", "schemas", "tables"]
+                .iter()
+                .any(|v| variable.value.eq_ignore_ascii_case(v)) => {}
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+fn is_cte_name(cte_names: &[String], name: &ObjectName) -> bool {
+    name.0.len() == 1
+        && cte_names
+            .iter()
+            .any(|c| c.eq_ignore_ascii_case(&name.0[0].value))
+}
+
+struct SchemaCollector {
+    schemas: Vec<String>,
+    cte_names: Vec<String>,
+    unqualified: bool,
+}
+
+impl QueryVisitor for SchemaCollector {
+    fn visit_expr(&mut self, _expr: &mut Expr) -> Result<bool, CubeError> {
+        Ok(true)
+    }
+
+    fn visit_table(&mut self, name: &mut ObjectName) -> Result<(), CubeError> {
+        if name.0.len() == 2 {
+            self.schemas.push(name.0[0].value.clone());
+        } else if !is_cte_name(&self.cte_names, name) {
+            self.unqualified = true;
+        }
+        Ok(())
+    }
+
+    fn visit_cte(&mut self, alias: &TableAlias) -> Result<(), CubeError> {
+        self.cte_names.push(alias.name.value.clone());
+        Ok(())
+    }
+}
+
+/// Prefixes table names without a schema with `default_schema` unless they refer to a common
+/// table expression.
+fn qualify_table_names(
+    statement: &mut CubeStoreStatement,
+    default_schema: &str,
+) -> Result<(), CubeError> {
+    let mut qualifier = TableQualifier {
+        default_schema,
+        cte_names: Vec::new(),
+    };
+    walk_statement_tables(statement, &mut qualifier)?;
+    Ok(())
+}
+
+struct TableQualifier<'a> {
+    default_schema: &'a str,
+    cte_names: Vec<String>,
+}
+
+impl QueryVisitor for TableQualifier<'_> {
+    fn visit_expr(&mut self, _expr: &mut Expr) -> Result<bool, CubeError> {
+        Ok(true)
+    }
+
+    fn visit_table(&mut self, name: &mut ObjectName) -> Result<(), CubeError> {
+        if name.0.len() == 1 && !is_cte_name(&self.cte_names, name) {
+            name.0.insert(0, Ident::new(self.default_schema));
+        }
+        Ok(())
+    }
+
+    fn visit_cte(&mut self, alias: &TableAlias) -> Result<(), CubeError> {
+        self.cte_names.push(alias.name.value.clone());
+        Ok(())
+    }
+}
+
+/// Replaces placeholders with `params` and returns the number of placeholders bound.
+fn bind_statement_params(
+    statement: &mut CubeStoreStatement,
+    placeholder_prefix: &str,
+    params: &Vec<Expr>,
+) -> Result<usize, CubeError> {
+    let mut binder = PlaceholderBinder {
+        placeholder_prefix,
+        params,
+        bound: 0,
+    };
+    match statement {
+        CubeStoreStatement::Statement(s)
+        | CubeStoreStatement::CreateTable {
+            create_table: s, ..
+        } => walk_statement(s, &mut binder)?,
+        _ => {}
+    }
+    Ok(binder.bound)
+}
+
+struct PlaceholderBinder<'a> {
+    placeholder_prefix: &'a str,
+    params: &'a Vec<Expr>,
+    bound: usize,
+}
+
+impl QueryVisitor for PlaceholderBinder<'_> {
+    fn visit_expr(&mut self, expr: &mut Expr) -> Result<bool, CubeError> {
+        if let Expr::Identifier(ident) = expr {
+            if let Some(index) = parser::placeholder_index(self.placeholder_prefix, ident) {
+                *expr = self
+                    .params
+                    .get(index)
+                    .ok_or_else(|| CubeError::internal(format!("Unknown placeholder: {}", index)))?
+                    .clone();
+                self.bound += 1;
+            }
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    fn visit_table(&mut self, _name: &mut ObjectName) -> Result<(), CubeError> {
+        Ok(())
+    }
+}
+
 fn parse_chunk(chunk: &[Vec<Expr>], column: &Vec<&Column>) -> Result<DataFrame, CubeError> {
     let mut res: Vec<Row> = Vec::new();
     for r in chunk {
@@ -692,13 +1983,18 @@ fn parse_decimal(cell: &Expr) -> Result<f64, CubeError> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cluster::hash_ring::HashRing;
     use crate::cluster::MockCluster;
     use crate::config::{Config, FileStoreProvider};
     use crate::metastore::RocksMetaStore;
     use crate::queryplanner::query_executor::MockQueryExecutor;
-    use crate::queryplanner::MockQueryPlanner;
+    use crate::queryplanner::{MockQueryPlanner, QueryPlannerImpl};
     use crate::remotefs::LocalDirRemoteFs;
     use crate::store::WALStore;
+    use chrono::Utc;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use futures::StreamExt;
     use itertools::Itertools;
     use rand::distributions::Alphanumeric;
     use rand::{thread_rng, Rng};
@@ -709,6 +2005,7 @@ mod tests {
     use std::time::Duration;
     use std::{env, fs};
     use uuid::Uuid;
+    use warp::Filter;
 
     #[actix_rt::test]
     async fn create_schema_test() {
@@ -726,13 +2023,20 @@ mod tests {
                 PathBuf::from(remote_store_path.clone()),
             );
             let meta_store = RocksMetaStore::new(path, remote_fs.clone(), config.config_obj());
-            let store = WALStore::new(meta_store.clone(), remote_fs.clone(), 10);
+            let store = WALStore::new(
+                meta_store.clone(),
+                remote_fs.clone(),
+                10,
+                config.config_obj(),
+            );
             let service = SqlServiceImpl::new(
                 meta_store,
                 store,
                 Arc::new(MockQueryPlanner::new()),
                 Arc::new(MockQueryExecutor::new()),
                 Arc::new(MockCluster::new()),
+                ProcessList::new(),
+                config.config_obj(),
             );
             let i = service.exec_query("CREATE SCHEMA foo").await.unwrap();
             assert_eq!(
@@ -763,13 +2067,20 @@ mod tests {
                 PathBuf::from(remote_store_path.clone()),
             );
             let meta_store = RocksMetaStore::new(path, remote_fs.clone(), config.config_obj());
-            let store = WALStore::new(meta_store.clone(), remote_fs.clone(), 10);
+            let store = WALStore::new(
+                meta_store.clone(),
+                remote_fs.clone(),
+                10,
+                config.config_obj(),
+            );
             let service = SqlServiceImpl::new(
                 meta_store,
                 store,
                 Arc::new(MockQueryPlanner::new()),
                 Arc::new(MockQueryExecutor::new()),
                 Arc::new(MockCluster::new()),
+                ProcessList::new(),
+                config.config_obj(),
             );
             let i = service.exec_query("CREATE SCHEMA Foo").await.unwrap();
             assert_eq!(
@@ -795,6 +2106,9 @@ mod tests {
                 TableValue::String("NULL".to_string()),
                 TableValue::String("NULL".to_string()),
                 TableValue::String("false".to_string()),
+                TableValue::String("NULL".to_string()),
+                TableValue::String("".to_string()),
+                TableValue::String("0".to_string()),
             ]));
         }
         let _ = DB::destroy(&Options::default(), path);
@@ -1500,11 +2814,9 @@ mod tests {
 
     #[tokio::test]
     async fn cluster() {
-        Config::test("cluster_router").update_config(|mut config| {
-            config.select_workers = vec!["127.0.0.1:4306".to_string(), "127.0.0.1:4307".to_string()];
-            config
-        }).start_test(async move |services| {
+        Config::run_test("cluster_router", async move |services| {
             let service = services.sql_service;
+            let cluster = services.cluster;
 
             service.exec_query("CREATE SCHEMA foo").await.unwrap();
 
@@ -1541,6 +2853,7 @@ mod tests {
 
             Config::test("cluster_worker_1").update_config(|mut config| {
                 config.worker_bind_address = Some("127.0.0.1:4306".to_string());
+                config.server_name = "127.0.0.1:4306".to_string();
                 config.store_provider = FileStoreProvider::Filesystem {
                     remote_dir: env::current_dir()
                         .unwrap()
@@ -1550,6 +2863,7 @@ mod tests {
             }).start_test_worker(async move |_| {
                 Config::test("cluster_worker_2").update_config(|mut config| {
                     config.worker_bind_address = Some("127.0.0.1:4307".to_string());
+                    config.server_name = "127.0.0.1:4307".to_string();
                     config.store_provider = FileStoreProvider::Filesystem {
                         remote_dir: env::current_dir()
                             .unwrap()
@@ -1557,6 +2871,24 @@ mod tests {
                     };
                     config
                 }).start_test_worker(async move |_| {
+                    let workers = vec!["127.0.0.1:4306".to_string(), "127.0.0.1:4307".to_string()];
+                    for _ in 0..50 {
+                        if cluster.available_nodes().await.unwrap() == workers {
+                            break;
+                        }
+                        tokio::time::delay_for(Duration::from_millis(100)).await;
+                    }
+                    assert_eq!(cluster.available_nodes().await.unwrap(), workers);
+
+                    let result = service.exec_query("SHOW NODES").await.unwrap();
+                    assert_eq!(
+                        result.get_rows().iter().map(|r| (r.values()[0].clone(), r.values()[1].clone())).collect::<Vec<_>>(),
+                        vec![
+                            (TableValue::String("127.0.0.1:4306".to_string()), TableValue::String("alive".to_string())),
+                            (TableValue::String("127.0.0.1:4307".to_string()), TableValue::String("alive".to_string())),
+                        ]
+                    );
+
                     let result = service.exec_query(
                         "SELECT city, name, sum(amount) FROM (SELECT * FROM foo.orders_1 UNION ALL SELECT * FROM foo.orders_2) o \
                 LEFT JOIN foo.customers c ON orders_customer_id = customer_id \
@@ -1579,19 +2911,230 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn in_list() {
-        Config::run_test("in_list", async move |services| {
+    async fn select_retries() {
+        Config::test("select_retries_router").update_config(|mut config| {
+            config.compaction_chunks_count_threshold = 100;
+            config.compaction_chunks_total_size_threshold = 100000;
+            config
+        }).start_test(async move |services| {
             let service = services.sql_service;
+            let cluster = services.cluster;
 
             service.exec_query("CREATE SCHEMA foo").await.unwrap();
+            service.exec_query("CREATE TABLE foo.orders (id int, amount int)").await.unwrap();
+            service.exec_query("INSERT INTO foo.orders (id, amount) VALUES (1, 10), (2, 5)").await.unwrap();
+
+            // Node which owns the only partition has a heart-beat but doesn't listen, so its sub-plan is
+            // retried on the other one.
+            let free_address = || {
+                let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+                format!("127.0.0.1:{}", listener.local_addr().unwrap().port())
+            };
+            let mut nodes = vec![free_address(), free_address()];
+            nodes.sort();
+            let partition_id = services.meta_store.get_active_partitions_by_index_id(1).await.unwrap()[0].get_id();
+            let dead_node = HashRing::new(&nodes).node_for_partition(partition_id).unwrap().to_string();
+            let worker_name = nodes.iter().find(|n| **n != dead_node).unwrap().to_string();
+
+            let upstream = env::current_dir().unwrap().join("select_retries_router-upstream");
+            let heart_beats_dir = upstream.join("node-heart-beats");
+            fs::create_dir_all(heart_beats_dir.clone()).unwrap();
+            // Heart-beat from the future doesn't expire while the test runs.
+            fs::write(heart_beats_dir.join(&dead_node), (Utc::now().timestamp() as u64 + 3600).to_be_bytes()).unwrap();
+
+            // Worker blocks on downloading the chunk replaced by a FIFO until its data is written to the FIFO,
+            // so the retried select can be seen in the process list.
+            let chunk_files = services.remote_fs.list("").await.unwrap()
+                .into_iter()
+                .filter(|f| f.ends_with(".chunk.parquet"))
+                .collect::<Vec<_>>();
+            assert_eq!(chunk_files.len(), 1);
+            let fifo = upstream.join(&chunk_files[0]);
+            let chunk_data = fs::read(&fifo).unwrap();
+            fs::remove_file(&fifo).unwrap();
+            assert!(std::process::Command::new("mkfifo").arg(&fifo).status().unwrap().success());
+
+            let worker_config = Config::test("select_retries_worker").update_config(|mut config| {
+                config.worker_bind_address = Some(worker_name.clone());
+                config.server_name = worker_name.clone();
+                config.store_provider = FileStoreProvider::Filesystem { remote_dir: upstream.clone() };
+                config
+            });
+            worker_config.start_test_worker(async move |_| {
+                for _ in 0..50 {
+                    if cluster.available_nodes().await.unwrap() == nodes {
+                        break;
+                    }
+                    tokio::time::delay_for(Duration::from_millis(100)).await;
+                }
+                assert_eq!(cluster.available_nodes().await.unwrap(), nodes);
 
-            service.exec_query("CREATE TABLE foo.customers (id text, city text, state text)").await.unwrap();
+                let service_to_run = service.clone();
+                let running = tokio::spawn(async move {
+                    service_to_run.exec_query("SELECT sum(amount) FROM foo.orders").await
+                });
 
-            service.exec_query(
-                "INSERT INTO foo.customers (id, city, state) VALUES ('a', 'San Francisco', 'CA'), ('b', 'New York', 'NY'), ('c', 'San Diego', 'CA'), ('d', 'Austin', 'TX')"
-            ).await.unwrap();
+                let nodes_used = TableValue::String(format!("{},{}", dead_node, worker_name));
+                let mut process = None;
+                for _ in 0..100 {
+                    let result = service.exec_query("SHOW PROCESSLIST").await.unwrap();
+                    if let Some(row) = result.get_rows().first() {
+                        if row.values()[5] == nodes_used {
+                            process = Some(row.clone());
+                            break;
+                        }
+                    }
+                    tokio::time::delay_for(Duration::from_millis(50)).await;
+                }
+                let process = process.expect("Select isn't retried");
+                assert_eq!(process.values()[6], TableValue::Int(1));
 
-            let result = service.exec_query("SELECT count(*) from foo.customers WHERE state in ('CA', 'TX')").await.unwrap();
+                tokio::task::spawn_blocking(move || fs::write(&fifo, chunk_data)).await.unwrap().unwrap();
+                let result = tokio::time::timeout(Duration::from_secs(5), running).await.unwrap().unwrap().unwrap();
+                assert_eq!(result.get_rows(), &vec![Row::new(vec![TableValue::Int(15)])]);
+            }).await;
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn heart_beats() {
+        Config::run_test("heart_beats", async move |services| {
+            let service = services.sql_service;
+            let cluster = services.cluster;
+
+            // Liveness is decided by the time inside of the file as all of them are just modified.
+            let heart_beats_dir = env::current_dir().unwrap().join("heart_beats-upstream").join("node-heart-beats");
+            fs::create_dir_all(heart_beats_dir.clone()).unwrap();
+            let now = Utc::now().timestamp() as u64;
+            fs::write(heart_beats_dir.join("127.0.0.1:4401"), now.to_be_bytes()).unwrap();
+            fs::write(heart_beats_dir.join("127.0.0.1:4402"), (now - 10).to_be_bytes()).unwrap();
+            fs::write(heart_beats_dir.join("127.0.0.1:4403"), (now - 120).to_be_bytes()).unwrap();
+
+            for _ in 0..50 {
+                if cluster.nodes().await.unwrap().len() == 2 {
+                    break;
+                }
+                tokio::time::delay_for(Duration::from_millis(100)).await;
+            }
+
+            let result = service.exec_query("SHOW NODES").await.unwrap();
+            assert_eq!(
+                result.get_rows().iter().map(|r| r.values().clone()).collect::<Vec<_>>(),
+                vec![
+                    vec![
+                        TableValue::String("127.0.0.1:4401".to_string()),
+                        TableValue::String("alive".to_string()),
+                        TableValue::Timestamp(TimestampValue::new(now as i64 * 1_000_000_000)),
+                    ],
+                    vec![
+                        TableValue::String("127.0.0.1:4402".to_string()),
+                        TableValue::String("dead".to_string()),
+                        TableValue::Timestamp(TimestampValue::new((now - 10) as i64 * 1_000_000_000)),
+                    ],
+                ]
+            );
+            assert_eq!(cluster.available_nodes().await.unwrap(), vec!["127.0.0.1:4401".to_string()]);
+            // Heart-beats of nodes dead for long are removed.
+            assert!(!heart_beats_dir.join("127.0.0.1:4403").exists());
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn static_select_workers() {
+        Config::test("static_select_workers").update_config(|mut config| {
+            config.select_workers = vec!["127.0.0.1:4404".to_string(), "127.0.0.1:4405".to_string()];
+            config
+        }).start_test(async move |services| {
+            let cluster = services.cluster;
+
+            // Listed workers are used while none of the workers sends heart-beats.
+            assert_eq!(
+                cluster.available_nodes().await.unwrap(),
+                vec!["127.0.0.1:4404".to_string(), "127.0.0.1:4405".to_string()]
+            );
+
+            let heart_beats_dir = env::current_dir().unwrap().join("static_select_workers-upstream").join("node-heart-beats");
+            fs::create_dir_all(heart_beats_dir.clone()).unwrap();
+            fs::write(heart_beats_dir.join("127.0.0.1:4406"), (Utc::now().timestamp() as u64).to_be_bytes()).unwrap();
+            for _ in 0..50 {
+                if cluster.available_nodes().await.unwrap() == vec!["127.0.0.1:4406".to_string()] {
+                    break;
+                }
+                tokio::time::delay_for(Duration::from_millis(100)).await;
+            }
+            assert_eq!(cluster.available_nodes().await.unwrap(), vec!["127.0.0.1:4406".to_string()]);
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn jobs_on_worker() {
+        let free_address = || {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            format!("127.0.0.1:{}", listener.local_addr().unwrap().port())
+        };
+        let meta_address = free_address();
+        let worker_name = free_address();
+        let router_meta_address = meta_address.clone();
+        Config::test("jobs_on_worker_router").update_config(|mut config| {
+            config.metastore_bind_address = Some(router_meta_address);
+            config
+        }).start_test(async move |services| {
+            let service = services.sql_service;
+            let cluster = services.cluster;
+
+            service.exec_query("CREATE SCHEMA foo").await.unwrap();
+            service.exec_query("CREATE TABLE foo.orders (id int, amount int)").await.unwrap();
+
+            let upstream = env::current_dir().unwrap().join("jobs_on_worker_router-upstream");
+            let worker_config = Config::test("jobs_on_worker_worker").update_config(|mut config| {
+                config.worker_bind_address = Some(worker_name.clone());
+                config.server_name = worker_name.clone();
+                config.metastore_remote_address = Some(meta_address.clone());
+                config.store_provider = FileStoreProvider::Filesystem { remote_dir: upstream.clone() };
+                config
+            });
+            worker_config.start_test_worker(async move |_| {
+                for _ in 0..50 {
+                    if cluster.available_nodes().await.unwrap() == vec![worker_name.clone()] {
+                        break;
+                    }
+                    tokio::time::delay_for(Duration::from_millis(100)).await;
+                }
+                assert_eq!(cluster.available_nodes().await.unwrap(), vec![worker_name.clone()]);
+
+                // Insert returns once the worker owning the table partitions the WAL and sends the result
+                // to the router.
+                service.exec_query("INSERT INTO foo.orders (id, amount) VALUES (1, 10), (2, 5)").await.unwrap();
+
+                let chunk_files = |dir: &str| {
+                    fs::read_dir(env::current_dir().unwrap().join(dir)).unwrap()
+                        .map(|f| f.unwrap().file_name().to_str().unwrap().to_string())
+                        .filter(|f| f.ends_with(".chunk.parquet"))
+                        .collect::<Vec<_>>()
+                };
+                assert_eq!(chunk_files("jobs_on_worker_worker-local-store").len(), 1);
+                assert!(chunk_files("jobs_on_worker_router-local-store").is_empty());
+
+                let result = service.exec_query("SELECT sum(amount) FROM foo.orders").await.unwrap();
+                assert_eq!(result.get_rows(), &vec![Row::new(vec![TableValue::Int(15)])]);
+            }).await;
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn in_list() {
+        Config::run_test("in_list", async move |services| {
+            let service = services.sql_service;
+
+            service.exec_query("CREATE SCHEMA foo").await.unwrap();
+
+            service.exec_query("CREATE TABLE foo.customers (id text, city text, state text)").await.unwrap();
+
+            service.exec_query(
+                "INSERT INTO foo.customers (id, city, state) VALUES ('a', 'San Francisco', 'CA'), ('b', 'New York', 'NY'), ('c', 'San Diego', 'CA'), ('d', 'Austin', 'TX')"
+            ).await.unwrap();
+
+            let result = service.exec_query("SELECT count(*) from foo.customers WHERE state in ('CA', 'TX')").await.unwrap();
 
             assert_eq!(result.get_rows()[0], Row::new(vec![TableValue::Int(3)]));
         }).await;
@@ -1905,6 +3448,316 @@ mod tests {
         }).await;
     }
 
+    #[tokio::test]
+    async fn create_table_with_small_import_batches() {
+        Config::test("create_table_with_small_import_batches").update_config(|mut c| {
+            c.import_batch_size = 2;
+            c
+        }).start_test(async move |services| {
+            let service = services.sql_service;
+
+            let path = {
+                let mut dir = env::temp_dir();
+                dir.push("small-import-batches.csv");
+
+                let mut file = File::create(dir.clone()).unwrap();
+
+                for i in 0..5 {
+                    file.write_all(format!("{},City {}\n", i, i).as_bytes()).unwrap();
+                }
+
+                dir
+            };
+
+            let _ = service.exec_query("CREATE SCHEMA IF NOT EXISTS Foo").await.unwrap();
+            let _ = service.exec_query(&format!("CREATE TABLE Foo.Persons (id int, city text) LOCATION '{}'", path.as_os_str().to_string_lossy())).await.unwrap();
+
+            let result = service.exec_query("SELECT count(*), sum(id) from Foo.Persons").await.unwrap();
+            assert_eq!(result.get_rows()[0], Row::new(vec![TableValue::Int(5), TableValue::Int(10)]));
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn create_table_with_csv_options() {
+        Config::run_test("create_table_with_csv_options", async move |services| {
+            let service = services.sql_service;
+
+            let path = {
+                let mut dir = env::temp_dir();
+                dir.push("csv-options.csv");
+
+                let mut file = File::create(dir.clone()).unwrap();
+
+                file.write_all("city;id;flag;t;data\n".as_bytes()).unwrap();
+                file.write_all("\"San \"\"Golden\"\" Francisco\";1;true;2020-01-01T00:00:00.000Z;0xdeadbeef\n".as_bytes()).unwrap();
+                file.write_all("\"New\nYork\";2;NA;NA;NA\n".as_bytes()).unwrap();
+
+                dir
+            };
+
+            let _ = service.exec_query("CREATE SCHEMA IF NOT EXISTS Foo").await.unwrap();
+            let _ = service.exec_query(&format!("CREATE TABLE Foo.Cities (id int, city text, flag boolean, t timestamp, data bytea) WITH (delimiter = ';', header = true, null_values = 'NA') LOCATION '{}'", path.as_os_str().to_string_lossy())).await.unwrap();
+
+            let result = service.exec_query("SELECT id, city, flag, data from Foo.Cities ORDER BY id").await.unwrap();
+            assert_eq!(
+                result.get_rows(),
+                &vec![
+                    Row::new(vec![
+                        TableValue::Int(1),
+                        TableValue::String("San \"Golden\" Francisco".to_string()),
+                        TableValue::Boolean(true),
+                        TableValue::Bytes(vec![0xde, 0xad, 0xbe, 0xef]),
+                    ]),
+                    Row::new(vec![
+                        TableValue::Int(2),
+                        TableValue::String("New\nYork".to_string()),
+                        TableValue::Null,
+                        TableValue::Null,
+                    ]),
+                ]
+            );
+
+            let result = service.exec_query("SELECT count(*) from Foo.Cities WHERE t >= to_timestamp('2020-01-01T00:00:00.000Z')").await.unwrap();
+            assert_eq!(result.get_rows()[0], Row::new(vec![TableValue::Int(1)]));
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn create_table_with_strict_csv() {
+        Config::run_test("create_table_with_strict_csv", async move |services| {
+            let service = services.sql_service;
+
+            let path = {
+                let mut dir = env::temp_dir();
+                dir.push("csv-strict.csv");
+
+                let mut file = File::create(dir.clone()).unwrap();
+
+                file.write_all("1,San Francisco\n".as_bytes()).unwrap();
+                file.write_all("two,New York\n".as_bytes()).unwrap();
+
+                dir
+            };
+
+            let _ = service.exec_query("CREATE SCHEMA IF NOT EXISTS Foo").await.unwrap();
+            let res = service.exec_query(&format!("CREATE TABLE Foo.Strict (id int, city text) WITH (strict = true) LOCATION '{}'", path.as_os_str().to_string_lossy())).await;
+            let error = format!("{:?}", res);
+            assert!(error.contains("row 2 (line 2), column 'id'"), "{}", error);
+
+            // Table of the failed import is dropped.
+            service.exec_query("CREATE TABLE Foo.Strict (id int, city text)").await.unwrap();
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn create_table_with_s3_gzip_location() {
+        Config::run_test("create_table_with_s3_gzip_location", async move |services| {
+            let service = services.sql_service;
+
+            let bucket_dir = env::current_dir()
+                .unwrap()
+                .join("create_table_with_s3_gzip_location-upstream")
+                .join("import-bucket")
+                .join("data");
+            fs::create_dir_all(bucket_dir.clone()).unwrap();
+            let mut encoder = GzEncoder::new(
+                File::create(bucket_dir.join("persons.csv.gz")).unwrap(),
+                Compression::default(),
+            );
+            encoder.write_all("1,San Francisco\n2,New York\n3,Boston\n".as_bytes()).unwrap();
+            encoder.finish().unwrap();
+
+            let _ = service.exec_query("CREATE SCHEMA IF NOT EXISTS Foo").await.unwrap();
+            let _ = service.exec_query("CREATE TABLE Foo.Persons (id int, city text) LOCATION 's3://import-bucket/data/persons.csv.gz'").await.unwrap();
+
+            let result = service.exec_query("SELECT count(*) as cnt from Foo.Persons").await.unwrap();
+            assert_eq!(result.get_rows()[0], Row::new(vec![TableValue::Int(3)]));
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn create_table_with_http_locations() {
+        Config::run_test("create_table_with_http_locations", async move |services| {
+            let service = services.sql_service;
+
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all("3,Boston\n4,Chicago\n".as_bytes()).unwrap();
+            let gzipped = encoder.finish().unwrap();
+
+            let routes = warp::path!("persons.csv")
+                .map(|| "1,San Francisco\n2,New York\n")
+                .or(warp::path!("persons.csv.gz").map(move || gzipped.clone()));
+            let (addr, server) = warp::serve(routes).bind_ephemeral(([127, 0, 0, 1], 0));
+            tokio::spawn(server);
+
+            let _ = service.exec_query("CREATE SCHEMA IF NOT EXISTS Foo").await.unwrap();
+            let _ = service.exec_query(&format!(
+                "CREATE TABLE Foo.Persons (id int, city text) LOCATION 'http://{0}/persons.csv', 'http://{0}/persons.csv.gz'",
+                addr
+            )).await.unwrap();
+
+            let result = service.exec_query("SELECT city from Foo.Persons ORDER BY id").await.unwrap();
+            assert_eq!(
+                result.get_rows().iter().map(|r| r.values()[0].clone()).collect::<Vec<_>>(),
+                vec!["San Francisco", "New York", "Boston", "Chicago"]
+                    .into_iter()
+                    .map(|c| TableValue::String(c.to_string()))
+                    .collect::<Vec<_>>()
+            );
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn create_table_with_parquet_location() {
+        Config::run_test("create_table_with_parquet_location", async move |services| {
+            use arrow::array::{Float64Array, Int32Array, StringArray, TimestampMillisecondArray};
+            use arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+            use arrow::record_batch::RecordBatch;
+            use parquet::arrow::ArrowWriter;
+
+            let service = services.sql_service;
+
+            let schema = Arc::new(Schema::new(vec![
+                Field::new("id", DataType::Int32, false),
+                Field::new("city", DataType::Utf8, true),
+                Field::new("amount", DataType::Float64, true),
+                Field::new("t", DataType::Timestamp(TimeUnit::Millisecond, None), true),
+            ]));
+            let batch = RecordBatch::try_new(
+                schema.clone(),
+                vec![
+                    Arc::new(Int32Array::from(vec![1, 2])),
+                    Arc::new(StringArray::from(vec![Some("San Francisco"), None])),
+                    Arc::new(Float64Array::from(vec![1.25, 2.5])),
+                    Arc::new(TimestampMillisecondArray::from(vec![1577836800000, 1577923200000])),
+                ],
+            )
+            .unwrap();
+            let path = env::temp_dir().join("import.parquet");
+            let mut writer = ArrowWriter::try_new(File::create(path.clone()).unwrap(), schema, None).unwrap();
+            writer.write(&batch).unwrap();
+            writer.close().unwrap();
+
+            let _ = service.exec_query("CREATE SCHEMA IF NOT EXISTS Foo").await.unwrap();
+            let _ = service.exec_query(&format!("CREATE TABLE Foo.Orders (id int, city text, amount decimal(10, 2), t timestamp) LOCATION '{}'", path.as_os_str().to_string_lossy())).await.unwrap();
+
+            let result = service.exec_query("SELECT id, city, amount, t from Foo.Orders ORDER BY id").await.unwrap();
+            assert_eq!(
+                result.get_rows(),
+                &vec![
+                    Row::new(vec![
+                        TableValue::Int(1),
+                        TableValue::String("San Francisco".to_string()),
+                        TableValue::Decimal("1.25".to_string()),
+                        TableValue::Timestamp(TimestampValue::new(1577836800000000000)),
+                    ]),
+                    Row::new(vec![
+                        TableValue::Int(2),
+                        TableValue::Null,
+                        TableValue::Decimal("2.5".to_string()),
+                        TableValue::Timestamp(TimestampValue::new(1577923200000000000)),
+                    ]),
+                ]
+            );
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn create_table_with_arrow_ipc_format() {
+        Config::run_test("create_table_with_arrow_ipc_format", async move |services| {
+            use arrow::array::{Int64Array, StringArray};
+            use arrow::datatypes::{DataType, Field, Schema};
+            use arrow::ipc::writer::FileWriter;
+            use arrow::record_batch::RecordBatch;
+
+            let service = services.sql_service;
+
+            let schema = Schema::new(vec![
+                Field::new("city", DataType::Utf8, false),
+                Field::new("id", DataType::Int64, false),
+            ]);
+            let batch = RecordBatch::try_new(
+                Arc::new(schema.clone()),
+                vec![
+                    Arc::new(StringArray::from(vec!["San Francisco", "New York", "Boston"])),
+                    Arc::new(Int64Array::from(vec![1, 2, 3])),
+                ],
+            )
+            .unwrap();
+            let path = env::temp_dir().join("import-arrow.data");
+            let mut writer = FileWriter::try_new(File::create(path.clone()).unwrap(), &schema).unwrap();
+            writer.write(&batch).unwrap();
+            writer.finish().unwrap();
+
+            let _ = service.exec_query("CREATE SCHEMA IF NOT EXISTS Foo").await.unwrap();
+            let _ = service.exec_query(&format!("CREATE TABLE Foo.Persons (id int, city text) WITH (format = 'arrow') LOCATION '{}'", path.as_os_str().to_string_lossy())).await.unwrap();
+
+            let result = service.exec_query("SELECT city from Foo.Persons WHERE id = 2").await.unwrap();
+            assert_eq!(result.get_rows()[0], Row::new(vec![TableValue::String("New York".to_string())]));
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn create_table_with_decimal_scales() {
+        Config::run_test("create_table_with_decimal_scales", async move |services| {
+            use arrow::array::{Float64Array, Int64Array, Int64Decimal2Array, Int64Decimal5Array};
+            use arrow::datatypes::{DataType, Field, Schema};
+            use arrow::ipc::writer::FileWriter;
+            use arrow::record_batch::RecordBatch;
+
+            let service = services.sql_service;
+
+            let schema = Schema::new(vec![
+                Field::new("id", DataType::Int64, false),
+                Field::new("d2", DataType::Int64Decimal(2), true),
+                Field::new("d5", DataType::Int64Decimal(5), true),
+                Field::new("f", DataType::Float64, true),
+            ]);
+            let batch = RecordBatch::try_new(
+                Arc::new(schema.clone()),
+                vec![
+                    Arc::new(Int64Array::from(vec![1, 2, 3])),
+                    Arc::new(Int64Decimal2Array::from(vec![Some(125), Some(-1), None])),
+                    Arc::new(Int64Decimal5Array::from(vec![Some(123456), Some(-100000), Some(1)])),
+                    Arc::new(Float64Array::from(vec![Some(0.1), Some(-12345.6789), None])),
+                ],
+            )
+            .unwrap();
+            let path = env::temp_dir().join("import-decimal-scales.arrow");
+            let mut writer = FileWriter::try_new(File::create(path.clone()).unwrap(), &schema).unwrap();
+            writer.write(&batch).unwrap();
+            writer.finish().unwrap();
+
+            let _ = service.exec_query("CREATE SCHEMA IF NOT EXISTS Foo").await.unwrap();
+            let _ = service.exec_query(&format!("CREATE TABLE Foo.Decimals (id int, d2 decimal(10, 2), d5 decimal(12, 5), f decimal(18, 4)) LOCATION '{}'", path.as_os_str().to_string_lossy())).await.unwrap();
+
+            let result = service.exec_query("SELECT id, d2, d5, f from Foo.Decimals ORDER BY id").await.unwrap();
+            assert_eq!(
+                result.get_rows(),
+                &vec![
+                    Row::new(vec![
+                        TableValue::Int(1),
+                        TableValue::Decimal("1.25".to_string()),
+                        TableValue::Decimal("1.23456".to_string()),
+                        TableValue::Decimal("0.1".to_string()),
+                    ]),
+                    Row::new(vec![
+                        TableValue::Int(2),
+                        TableValue::Decimal("-0.01".to_string()),
+                        TableValue::Decimal("-1".to_string()),
+                        TableValue::Decimal("-12345.6789".to_string()),
+                    ]),
+                    Row::new(vec![
+                        TableValue::Int(3),
+                        TableValue::Null,
+                        TableValue::Decimal("0.00001".to_string()),
+                        TableValue::Null,
+                    ]),
+                ]
+            );
+        }).await;
+    }
+
     #[tokio::test]
     async fn bytes() {
         Config::run_test("bytes", async move |services| {
@@ -2074,6 +3927,649 @@ mod tests {
             assert_eq!(result.get_rows()[0], Row::new(vec![TableValue::Int(20)]));
         }).await;
     }
+
+    #[tokio::test]
+    async fn partition_pruning() {
+        Config::test("partition_pruning").update_config(|mut config| {
+            config.partition_split_threshold = 5;
+            config.compaction_chunks_count_threshold = 0;
+            config
+        }).start_test(async move |services| {
+            let service = services.sql_service;
+
+            service.exec_query("CREATE SCHEMA foo").await.unwrap();
+
+            service.exec_query("CREATE TABLE foo.table (t int)").await.unwrap();
+
+            let listener = services.cluster.job_result_listener();
+
+            service.exec_query(
+                "INSERT INTO foo.table (t) VALUES (NULL), (1), (3), (5), (10), (20), (25), (25), (25), (25), (25)"
+            ).await.unwrap();
+
+            service.exec_query(
+                "INSERT INTO foo.table (t) VALUES (NULL), (NULL), (NULL), (2), (4), (5), (27), (28), (29)"
+            ).await.unwrap();
+
+            listener.wait_for_job_results(vec![
+                (RowKey::Table(TableId::Partitions, 1), JobType::PartitionCompaction),
+                (RowKey::Table(TableId::Partitions, 2), JobType::PartitionCompaction),
+                (RowKey::Table(TableId::Partitions, 3), JobType::PartitionCompaction),
+                (RowKey::Table(TableId::Partitions, 1), JobType::Repartition),
+                (RowKey::Table(TableId::Partitions, 2), JobType::Repartition),
+                (RowKey::Table(TableId::Partitions, 3), JobType::Repartition),
+            ]).await.unwrap();
+
+            let query_planner = QueryPlannerImpl::new(services.meta_store.clone());
+
+            for (query, partitions, count) in vec![
+                ("SELECT count(*) from foo.table WHERE t >= 10 AND t < 20", 1, 1),
+                ("SELECT count(*) from foo.table WHERE t BETWEEN 3 AND 10", 2, 5),
+                ("SELECT count(*) from foo.table WHERE t = 27", 1, 1),
+                ("SELECT count(*) from foo.table WHERE t > 0", 4, 16),
+            ] {
+                let statement = match CubeStoreParser::new(query).unwrap().parse_statement().unwrap() {
+                    CubeStoreStatement::Statement(s) => s,
+                    s => panic!("Unexpected statement: {:?}", s),
+                };
+                match query_planner.logical_plan(DFStatement::Statement(statement)).await.unwrap() {
+                    QueryPlan::Select(plan) => {
+                        assert_eq!(plan.index_snapshots()[0].partitions().len(), partitions, "{}", query);
+                    }
+                    QueryPlan::Meta(_) => panic!("Select plan expected for {}", query),
+                }
+
+                let result = service.exec_query(query).await.unwrap();
+                assert_eq!(result.get_rows()[0], Row::new(vec![TableValue::Int(count)]), "{}", query);
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn alter_table() {
+        Config::run_test("alter_table", async move |services| {
+            let service = services.sql_service;
+
+            service.exec_query("CREATE SCHEMA foo").await.unwrap();
+
+            service.exec_query("CREATE TABLE foo.orders (id int, city text)").await.unwrap();
+
+            service.exec_query(
+                "INSERT INTO foo.orders (id, city) VALUES (1, 'New York'), (2, 'San Francisco')"
+            ).await.unwrap();
+
+            service.exec_query("ALTER TABLE foo.orders ADD COLUMN amount int").await.unwrap();
+
+            service.exec_query(
+                "INSERT INTO foo.orders (id, city, amount) VALUES (3, 'Boston', 30)"
+            ).await.unwrap();
+
+            let result = service.exec_query("SELECT id, amount FROM foo.orders ORDER BY id").await.unwrap();
+            assert_eq!(result.get_rows(), &vec![
+                Row::new(vec![TableValue::Int(1), TableValue::Null]),
+                Row::new(vec![TableValue::Int(2), TableValue::Null]),
+                Row::new(vec![TableValue::Int(3), TableValue::Int(30)]),
+            ]);
+
+            service.exec_query("ALTER TABLE foo.orders RENAME COLUMN city TO town").await.unwrap();
+
+            let result = service.exec_query("SELECT town FROM foo.orders WHERE id = 2").await.unwrap();
+            assert_eq!(result.get_rows(), &vec![Row::new(vec![TableValue::String("San Francisco".to_string())])]);
+
+            assert!(service.exec_query("SELECT city FROM foo.orders").await.is_err());
+            assert!(service.exec_query("ALTER TABLE foo.orders ADD COLUMN town int").await.is_err());
+
+            service.exec_query("ALTER TABLE foo.orders DROP COLUMN town").await.unwrap();
+
+            service.exec_query(
+                "INSERT INTO foo.orders (id, amount) VALUES (4, 40)"
+            ).await.unwrap();
+
+            let result = service.exec_query("SELECT * FROM foo.orders ORDER BY id").await.unwrap();
+            assert_eq!(result.get_rows(), &vec![
+                Row::new(vec![TableValue::Int(1), TableValue::Null]),
+                Row::new(vec![TableValue::Int(2), TableValue::Null]),
+                Row::new(vec![TableValue::Int(3), TableValue::Int(30)]),
+                Row::new(vec![TableValue::Int(4), TableValue::Int(40)]),
+            ]);
+
+            assert!(service.exec_query("ALTER TABLE foo.orders DROP COLUMN town").await.is_err());
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn insert_select_and_create_table_as_select() {
+        Config::run_test("insert_select_and_create_table_as_select", async move |services| {
+            let service = services.sql_service;
+
+            service.exec_query("CREATE SCHEMA foo").await.unwrap();
+
+            service.exec_query("CREATE TABLE foo.orders (id int, city text, amount int)").await.unwrap();
+
+            service.exec_query(
+                "INSERT INTO foo.orders (id, city, amount) VALUES \
+                (1, 'New York', 10), (2, 'New York', 20), (3, 'Boston', 5), (4, 'Boston', 7)"
+            ).await.unwrap();
+
+            service.exec_query("CREATE TABLE foo.city_totals (city text, total decimal)").await.unwrap();
+
+            service.exec_query(
+                "INSERT INTO foo.city_totals (city, total) SELECT city, sum(amount) FROM foo.orders GROUP BY city"
+            ).await.unwrap();
+
+            let result = service.exec_query("SELECT city, total FROM foo.city_totals ORDER BY city").await.unwrap();
+            assert_eq!(result.get_rows(), &vec![
+                Row::new(vec![TableValue::String("Boston".to_string()), TableValue::Decimal("12".to_string())]),
+                Row::new(vec![TableValue::String("New York".to_string()), TableValue::Decimal("30".to_string())]),
+            ]);
+
+            service.exec_query(
+                "CREATE TABLE foo.large_orders AS SELECT id, amount AS large_amount FROM foo.orders WHERE amount >= 10"
+            ).await.unwrap();
+
+            let result = service.exec_query("SELECT id, large_amount FROM foo.large_orders ORDER BY id").await.unwrap();
+            assert_eq!(result.get_rows(), &vec![
+                Row::new(vec![TableValue::Int(1), TableValue::Int(10)]),
+                Row::new(vec![TableValue::Int(2), TableValue::Int(20)]),
+            ]);
+
+            assert!(service.exec_query("INSERT INTO foo.city_totals SELECT id FROM foo.orders").await.is_err());
+            assert!(service.exec_query("INSERT INTO foo.city_totals SELECT id, city FROM foo.orders").await.is_err());
+
+            service.exec_query("CREATE TABLE foo.empty_orders AS SELECT id, city FROM foo.orders WHERE amount > 100").await.unwrap();
+            let table = services.meta_store.get_table("foo".to_string(), "empty_orders".to_string()).await.unwrap();
+            assert_eq!(table.get_row().get_columns(), &vec![
+                Column::new("id".to_string(), ColumnType::Int, 0),
+                Column::new("city".to_string(), ColumnType::String, 1),
+            ]);
+
+            assert!(service.exec_query("CREATE TABLE foo.failed_orders (id int, total decimal) AS SELECT id, city FROM foo.orders").await.is_err());
+            assert!(services.meta_store.get_table("foo".to_string(), "failed_orders".to_string()).await.is_err());
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn insert_select_column_types() {
+        Config::run_test("insert_select_column_types", async move |services| {
+            let service = services.sql_service;
+
+            service.exec_query("CREATE SCHEMA foo").await.unwrap();
+
+            service.exec_query("CREATE TABLE foo.sketches (id int, hll hyperloglog, price decimal(10,5), ratio float)").await.unwrap();
+            service.exec_query(
+                "INSERT INTO foo.sketches (id, hll, price, ratio) VALUES \
+                (1, X'020C0200C02FF58941D5F0C6', 1.23456, 2.5), (2, X'020C0200C02FF58941D5F0C6', -1.23556, 0.125)"
+            ).await.unwrap();
+
+            service.exec_query("CREATE TABLE foo.sketches_copy AS SELECT id, hll, price FROM foo.sketches").await.unwrap();
+            let table = services.meta_store.get_table("foo".to_string(), "sketches_copy".to_string()).await.unwrap();
+            assert_eq!(table.get_row().get_columns()[1].get_column_type(), &ColumnType::HyperLogLog);
+            assert_eq!(table.get_row().get_columns()[2].get_column_type(), &ColumnType::Decimal { scale: 5, precision: 10 });
+
+            let expected = service.exec_query("SELECT cardinality(merge(hll)) FROM foo.sketches").await.unwrap();
+            let result = service.exec_query("SELECT cardinality(merge(hll)) FROM foo.sketches_copy").await.unwrap();
+            assert_eq!(result.get_rows(), expected.get_rows());
+
+            service.exec_query("CREATE TABLE foo.prices (id int, price decimal(10,2), ratio decimal(10,2), price_float float)").await.unwrap();
+            service.exec_query("INSERT INTO foo.prices (id, price, ratio, price_float) SELECT id, price, ratio, price FROM foo.sketches").await.unwrap();
+
+            let result = service.exec_query("SELECT id, price, ratio, price_float FROM foo.prices ORDER BY id").await.unwrap();
+            assert_eq!(result.get_rows(), &vec![
+                Row::new(vec![TableValue::Int(1), TableValue::Decimal("1.23".to_string()), TableValue::Decimal("2.5".to_string()), TableValue::Float("1.23456".to_string())]),
+                Row::new(vec![TableValue::Int(2), TableValue::Decimal("-1.24".to_string()), TableValue::Decimal("0.13".to_string()), TableValue::Float("-1.23556".to_string())]),
+            ]);
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn select_source_tables() {
+        Config::run_test("select_source_tables", async move |services| {
+            let service = services.sql_service;
+
+            service.exec_query("CREATE SCHEMA foo").await.unwrap();
+
+            service.exec_query("CREATE TABLE foo.orders (id int, city text, amount int)").await.unwrap();
+
+            service.exec_query("INSERT INTO foo.orders (id, city, amount) VALUES (1, 'New York', 10), (2, 'Boston', 5)").await.unwrap();
+
+            let result = service.exec_query("SELECT city c, sum(amount) FROM foo.orders GROUP BY city").await.unwrap();
+            let source = result.get_source_table(0).unwrap();
+            assert_eq!(source.table.get_row().get_table_name(), "orders");
+            assert_eq!(source.schema.get_row().get_name(), "foo");
+            assert!(result.get_source_table(1).is_none());
+
+            service.exec_query("CREATE TABLE foo.customers (id int, city text)").await.unwrap();
+
+            service.exec_query("INSERT INTO foo.customers (id, city) VALUES (2, 'Chicago')").await.unwrap();
+
+            let result = service.exec_query("SELECT o.amount, c.city FROM foo.orders o JOIN foo.customers c ON o.id = c.id").await.unwrap();
+            assert_eq!(result.get_source_table(0).unwrap().table.get_row().get_table_name(), "orders");
+            assert_eq!(result.get_source_table(1).unwrap().table.get_row().get_table_name(), "customers");
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn process_list() {
+        Config::run_test("process_list", async move |services| {
+            let service = services.sql_service;
+
+            let result = service.exec_query("SHOW PROCESSLIST").await.unwrap();
+            assert_eq!(
+                result.get_columns().iter().map(|c| c.get_name().as_str()).collect::<Vec<_>>(),
+                vec!["id", "connection", "user", "query", "start_time", "nodes", "retries"]
+            );
+            assert!(result.get_rows().is_empty());
+
+            let error = service.exec_query("KILL QUERY 1000").await.unwrap_err();
+            assert_eq!(error.message, "Unknown query id: 1000");
+            assert!(service.exec_query("KILL 1000").await.is_err());
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn kill_query() {
+        Config::test("kill_query_router").update_config(|mut config| {
+            config.compaction_chunks_count_threshold = 100;
+            config.compaction_chunks_total_size_threshold = 100000;
+            config
+        }).start_test(async move |services| {
+            let service = services.sql_service;
+            let cluster = services.cluster;
+
+            service.exec_query("CREATE SCHEMA foo").await.unwrap();
+            service.exec_query("CREATE TABLE foo.orders (id int, amount int)").await.unwrap();
+            service.exec_query("CREATE TABLE foo.orders_copy (id int, amount int)").await.unwrap();
+            service.exec_query("INSERT INTO foo.orders (id, amount) VALUES (1, 10), (2, 5)").await.unwrap();
+
+            // Worker blocks on downloading the chunk replaced by a FIFO until the FIFO is opened
+            // for writing, so its selects run until they are killed.
+            let upstream = env::current_dir().unwrap().join("kill_query_router-upstream");
+            let chunk_files = services.remote_fs.list("").await.unwrap()
+                .into_iter()
+                .filter(|f| f.ends_with(".chunk.parquet"))
+                .collect::<Vec<_>>();
+            assert_eq!(chunk_files.len(), 1);
+            let fifo = upstream.join(&chunk_files[0]);
+            fs::remove_file(&fifo).unwrap();
+            assert!(std::process::Command::new("mkfifo").arg(&fifo).status().unwrap().success());
+
+            let worker_name = {
+                let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+                format!("127.0.0.1:{}", listener.local_addr().unwrap().port())
+            };
+            let worker_config = Config::test("kill_query_worker").update_config(|mut config| {
+                config.worker_bind_address = Some(worker_name.clone());
+                config.server_name = worker_name.clone();
+                config.store_provider = FileStoreProvider::Filesystem { remote_dir: upstream.clone() };
+                config
+            });
+            worker_config.start_test_worker(async move |worker_services| {
+                let worker_service = worker_services.sql_service;
+
+                for _ in 0..50 {
+                    if cluster.available_nodes().await.unwrap() == vec![worker_name.clone()] {
+                        break;
+                    }
+                    tokio::time::delay_for(Duration::from_millis(100)).await;
+                }
+                assert_eq!(cluster.available_nodes().await.unwrap(), vec![worker_name.clone()]);
+
+                for query in vec![
+                    "SELECT id, amount FROM foo.orders",
+                    "INSERT INTO foo.orders_copy (id, amount) SELECT id, amount FROM foo.orders",
+                    "CREATE TABLE foo.orders_ctas AS SELECT id, amount FROM foo.orders",
+                ] {
+                    let service_to_run = service.clone();
+                    let running = tokio::spawn(async move { service_to_run.exec_query(query).await });
+
+                    let mut id = None;
+                    for _ in 0..100 {
+                        let router_processes = service.exec_query("SHOW PROCESSLIST").await.unwrap();
+                        let worker_processes = worker_service.exec_query("SHOW PROCESSLIST").await.unwrap();
+                        if let (Some(router_process), Some(worker_process)) = (router_processes.get_rows().first(), worker_processes.get_rows().first()) {
+                            assert_eq!(router_process.values()[3], TableValue::String(query.to_string()));
+                            assert_eq!(router_process.values()[5], TableValue::String(worker_name.clone()));
+                            if let TableValue::Int(router_id) = router_process.values()[0] {
+                                assert_eq!(worker_process.values()[3], TableValue::String(format!("Sub-plan of router query {}", router_id)));
+                                id = Some(router_id);
+                            }
+                            break;
+                        }
+                        tokio::time::delay_for(Duration::from_millis(50)).await;
+                    }
+                    let id = id.expect("Select is not running on the worker");
+
+                    service.exec_query(&format!("KILL QUERY {}", id)).await.unwrap();
+                    let error = tokio::time::timeout(Duration::from_secs(5), running).await.unwrap().unwrap().unwrap_err();
+                    assert!(error.message.contains("was cancelled"), "{}", error.message);
+                    assert!(service.exec_query("SHOW PROCESSLIST").await.unwrap().get_rows().is_empty());
+
+                    // Worker stops the select once the router cancels it.
+                    for _ in 0..100 {
+                        if worker_service.exec_query("SHOW PROCESSLIST").await.unwrap().get_rows().is_empty() {
+                            break;
+                        }
+                        tokio::time::delay_for(Duration::from_millis(50)).await;
+                    }
+                    assert!(worker_service.exec_query("SHOW PROCESSLIST").await.unwrap().get_rows().is_empty());
+                }
+
+                assert!(service.exec_query("SELECT * FROM foo.orders_copy").await.unwrap().get_rows().is_empty());
+                assert!(service.exec_query("SELECT * FROM foo.orders_ctas").await.is_err());
+
+                // Releases the downloads left blocked by the killed selects.
+                drop(fs::OpenOptions::new().read(true).write(true).open(&fifo).unwrap());
+            }).await;
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn select_stream() {
+        Config::run_test("select_stream", async move |services| {
+            let service = services.sql_service;
+
+            service.exec_query("CREATE SCHEMA foo").await.unwrap();
+
+            service.exec_query("CREATE TABLE foo.orders (id int, city text, amount int)").await.unwrap();
+
+            service.exec_query("INSERT INTO foo.orders (id, city, amount) VALUES (1, 'New York', 10), (2, 'Boston', 5), (3, 'New York', 7)").await.unwrap();
+
+            let stream = service.exec_query_stream(SqlQueryContext::default(), "SELECT city c, sum(amount) FROM foo.orders GROUP BY city ORDER BY 1").await.unwrap();
+            assert_eq!(stream.schema().field(0).name(), "c");
+            assert_eq!(stream.get_source_table(0).unwrap().table.get_row().get_table_name(), "orders");
+            let result = stream.into_data_frame().await.unwrap();
+            assert_eq!(result.get_rows(), &vec![
+                Row::new(vec![TableValue::String("Boston".to_string()), TableValue::Int(5)]),
+                Row::new(vec![TableValue::String("New York".to_string()), TableValue::Int(17)]),
+            ]);
+            assert!(result.get_source_table(1).is_none());
+
+            let stream = service.exec_query_stream(SqlQueryContext::default(), "SELECT * FROM foo.orders WHERE id > 10").await.unwrap();
+            assert_eq!(stream.collect::<Vec<_>>().await.into_iter().map(|b| b.unwrap().num_rows()).sum::<usize>(), 0);
+
+            let stream = service.exec_query_stream(SqlQueryContext::default(), "SHOW SCHEMAS").await.unwrap();
+            assert_eq!(stream.into_data_frame().await.unwrap().len(), 1);
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn allowed_schemas() {
+        Config::run_test("allowed_schemas", async move |services| {
+            let service = services.sql_service;
+
+            service.exec_query("CREATE SCHEMA foo").await.unwrap();
+            service.exec_query("CREATE SCHEMA bar").await.unwrap();
+            service.exec_query("CREATE TABLE foo.orders (id int, amount int)").await.unwrap();
+            service.exec_query("CREATE TABLE bar.secrets (id int, secret text)").await.unwrap();
+            service.exec_query("INSERT INTO foo.orders (id, amount) VALUES (1, 10)").await.unwrap();
+
+            let context = SqlQueryContext {
+                connection_id: None,
+                user: Some("analyst".to_string()),
+                allowed_schemas: Some(vec!["foo".to_string()]),
+                default_schema: None,
+            };
+
+            let result = service.exec_query_with_context(context.clone(), "SELECT id, amount FROM foo.orders").await.unwrap();
+            assert_eq!(result.get_rows(), &vec![Row::new(vec![TableValue::Int(1), TableValue::Int(10)])]);
+
+            assert!(service.exec_query_with_context(context.clone(), "SELECT id FROM bar.secrets").await.is_err());
+            assert!(service.exec_query_with_context(
+                context.clone(),
+                "SELECT id FROM foo.orders WHERE id IN (SELECT id FROM bar.secrets)"
+            ).await.is_err());
+            assert!(service.exec_query_with_context(context.clone(), "INSERT INTO foo.orders (id, amount) SELECT id, id FROM bar.secrets").await.is_err());
+            assert!(service.exec_query_with_context(context.clone(), "DROP TABLE bar.secrets").await.is_err());
+            let result = service.exec_query_with_context(context.clone(), "SHOW TABLES").await.unwrap();
+            assert_eq!(result.get_rows().len(), 1);
+            let result = service.exec_query_with_context(context.clone(), "SHOW SCHEMAS").await.unwrap();
+            assert_eq!(result.get_rows().len(), 1);
+            assert!(service.exec_query_with_context(context.clone(), "SHOW CHUNKS").await.is_err());
+            assert!(service.exec_query_with_context(context.clone(), "CREATE TABLE foo.imported (id int) LOCATION 'https://example.com/data.csv'").await.is_err());
+            assert!(service.exec_query_with_context(context.clone(), "SHOW PROCESSLIST").await.is_ok());
+
+            let select = service.prepare("SELECT secret FROM bar.secrets WHERE id = ?").unwrap();
+            assert!(service.exec_prepared(context.clone(), &select, vec![TableValue::Int(1)]).await.is_err());
+
+            assert!(service.exec_query_with_context(context.clone(), "SELECT id FROM orders").await.is_err());
+
+            let context = SqlQueryContext {
+                default_schema: Some("foo".to_string()),
+                ..context
+            };
+            let result = service.exec_query_with_context(context.clone(), "SELECT id, amount FROM orders").await.unwrap();
+            assert_eq!(result.get_rows(), &vec![Row::new(vec![TableValue::Int(1), TableValue::Int(10)])]);
+            let mut ast = parse_statement("WITH o AS (SELECT id FROM orders) SELECT o.id FROM o JOIN foo.orders ON o.id = foo.orders.id").unwrap();
+            qualify_table_names(&mut ast, "foo").unwrap();
+            assert_eq!(statement_schemas(&ast), Some(vec!["foo".to_string(), "foo".to_string()]));
+            assert!(service.exec_query_with_context(context.clone(), "SELECT id FROM bar.secrets").await.is_err());
+            assert!(service.exec_query_with_context(context.clone(), "WITH s AS (SELECT id FROM bar.secrets) SELECT id FROM s").await.is_err());
+            service.exec_query_with_context(context.clone(), "INSERT INTO orders (id, amount) VALUES (2, 20)").await.unwrap();
+            let result = service.exec_query("SELECT count(*) FROM foo.orders").await.unwrap();
+            assert_eq!(result.get_rows(), &vec![Row::new(vec![TableValue::Int(2)])]);
+
+            service.exec_query_with_context(context.clone(), "DELETE FROM foo.orders WHERE id = 1").await.unwrap();
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn prepared_statements() {
+        Config::run_test("prepared_statements", async move |services| {
+            let service = services.sql_service;
+
+            service.exec_query("CREATE SCHEMA foo").await.unwrap();
+
+            service.exec_query("CREATE TABLE foo.orders (id int, city text, amount int)").await.unwrap();
+
+            let insert = service.prepare("INSERT INTO foo.orders (id, city, amount) VALUES (?, ?, ?)").unwrap();
+            assert_eq!(insert.params_count(), 3);
+            service.exec_prepared(SqlQueryContext::default(), &insert, vec![TableValue::Int(1), TableValue::String("New York".to_string()), TableValue::Int(10)]).await.unwrap();
+            service.exec_prepared(SqlQueryContext::default(), &insert, vec![TableValue::Int(2), TableValue::String("Boston's".to_string()), TableValue::Null]).await.unwrap();
+
+            let select = service.prepare("SELECT id, amount FROM foo.orders WHERE city = ? OR id > ? ORDER BY id").unwrap();
+            assert_eq!(select.params_count(), 2);
+
+            let result = service.exec_prepared(SqlQueryContext::default(), &select, vec![TableValue::String("Boston's".to_string()), TableValue::Int(5)]).await.unwrap();
+            assert_eq!(result.get_rows(), &vec![
+                Row::new(vec![TableValue::Int(2), TableValue::Null]),
+            ]);
+
+            let result = service.exec_prepared(SqlQueryContext::default(), &select, vec![TableValue::String("Chicago".to_string()), TableValue::Int(0)]).await.unwrap();
+            assert_eq!(result.get_rows(), &vec![
+                Row::new(vec![TableValue::Int(1), TableValue::Int(10)]),
+                Row::new(vec![TableValue::Int(2), TableValue::Null]),
+            ]);
+
+            assert!(service.exec_prepared(SqlQueryContext::default(), &select, vec![TableValue::Int(0)]).await.is_err());
+            assert_eq!(service.prepare("SELECT '?' FROM foo.orders").unwrap().params_count(), 0);
+
+            service.exec_query("CREATE TABLE foo.blobs (`?0` int, data varbinary)").await.unwrap();
+            let insert = service.prepare("INSERT INTO foo.blobs (`?0`, data) VALUES (?, ?)").unwrap();
+            assert_eq!(insert.params_count(), 2);
+            service.exec_prepared(SqlQueryContext::default(), &insert, vec![TableValue::Int(1), TableValue::Bytes(vec![0, 159, 146, 150])]).await.unwrap();
+
+            let select = service.prepare("SELECT `?0`, data FROM foo.blobs WHERE `?0` = ?").unwrap();
+            let result = service.exec_prepared(SqlQueryContext::default(), &select, vec![TableValue::Int(1)]).await.unwrap();
+            assert_eq!(result.get_rows(), &vec![
+                Row::new(vec![TableValue::Int(1), TableValue::Bytes(vec![0, 159, 146, 150])]),
+            ]);
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn rename_and_replace_table() {
+        Config::run_test("rename_and_replace_table", async move |services| {
+            let service = services.sql_service;
+
+            service.exec_query("CREATE SCHEMA foo").await.unwrap();
+
+            service.exec_query("CREATE TABLE foo.orders_v1 (id int)").await.unwrap();
+
+            service.exec_query("INSERT INTO foo.orders_v1 (id) VALUES (1), (2)").await.unwrap();
+
+            service.exec_query("ALTER TABLE foo.orders_v1 RENAME TO foo.orders").await.unwrap();
+
+            let result = service.exec_query("SELECT count(*) FROM foo.orders").await.unwrap();
+            assert_eq!(result.get_rows(), &vec![Row::new(vec![TableValue::Int(2)])]);
+            assert!(service.exec_query("SELECT count(*) FROM foo.orders_v1").await.is_err());
+
+            service.exec_query("CREATE TABLE foo.orders_v2 (id int)").await.unwrap();
+            assert!(service.exec_query("ALTER TABLE foo.orders_v2 RENAME TO foo.orders").await.is_err());
+
+            service.exec_query("CREATE OR REPLACE TABLE foo.orders (id int, city text)").await.unwrap();
+
+            let result = service.exec_query("SELECT count(*) FROM foo.orders").await.unwrap();
+            assert_eq!(result.get_rows(), &vec![Row::new(vec![TableValue::Int(0)])]);
+
+            service.exec_query("INSERT INTO foo.orders (id, city) VALUES (3, 'Boston')").await.unwrap();
+
+            let result = service.exec_query("SELECT id, city FROM foo.orders").await.unwrap();
+            assert_eq!(result.get_rows(), &vec![
+                Row::new(vec![TableValue::Int(3), TableValue::String("Boston".to_string())])
+            ]);
+
+            service.exec_query("CREATE OR REPLACE TABLE foo.customers (id int)").await.unwrap();
+
+            let tables = services.meta_store.get_tables().await.unwrap()
+                .into_iter()
+                .map(|t| t.get_row().get_table_name().to_string())
+                .sorted()
+                .collect::<Vec<_>>();
+            assert_eq!(tables, vec!["customers".to_string(), "orders".to_string(), "orders_v2".to_string()]);
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn delete_and_truncate() {
+        Config::run_test("delete_and_truncate", async move |services| {
+            let service = services.sql_service;
+
+            service.exec_query("CREATE SCHEMA foo").await.unwrap();
+
+            service.exec_query("CREATE TABLE foo.events (day timestamp, city text, amount int)").await.unwrap();
+
+            service.exec_query(
+                "INSERT INTO foo.events (day, city, amount) VALUES \
+                ('2021-01-01T00:00:00Z', 'New York', 1), \
+                ('2021-01-01T00:00:00Z', 'San Francisco', 2), \
+                ('2021-01-02T00:00:00Z', 'New York', 3), \
+                ('2021-01-03T00:00:00Z', 'Boston', 4)"
+            ).await.unwrap();
+
+            service.exec_query(
+                "DELETE FROM foo.events WHERE day >= '2021-01-02T00:00:00Z' AND city = 'New York'"
+            ).await.unwrap();
+
+            let result = service.exec_query("SELECT amount FROM foo.events ORDER BY amount").await.unwrap();
+            assert_eq!(result.get_rows(), &vec![
+                Row::new(vec![TableValue::Int(1)]),
+                Row::new(vec![TableValue::Int(2)]),
+                Row::new(vec![TableValue::Int(4)]),
+            ]);
+
+            service.exec_query(
+                "DELETE FROM foo.events WHERE city IN ('San Francisco', 'Boston')"
+            ).await.unwrap();
+
+            let result = service.exec_query("SELECT amount FROM foo.events ORDER BY amount").await.unwrap();
+            assert_eq!(result.get_rows(), &vec![Row::new(vec![TableValue::Int(1)])]);
+
+            assert!(service.exec_query("DELETE FROM foo.events WHERE amount + 1 > 2").await.is_err());
+            assert!(service.exec_query("DELETE FROM foo.events WHERE country = 'US'").await.is_err());
+
+            service.exec_query("TRUNCATE TABLE foo.events").await.unwrap();
+
+            let result = service.exec_query("SELECT count(*) FROM foo.events").await.unwrap();
+            assert_eq!(result.get_rows(), &vec![Row::new(vec![TableValue::Int(0)])]);
+            // Truncated partitions are replaced by empty ones without waiting for compaction.
+            let partitions = services.meta_store.get_active_partitions_by_index_id(1).await.unwrap();
+            assert!(partitions.iter().all(|p| p.get_row().main_table_row_count() == 0 && p.get_row().pending_deletes().is_empty()));
+
+            service.exec_query(
+                "INSERT INTO foo.events (day, city, amount) VALUES ('2021-01-04T00:00:00Z', 'Boston', 5)"
+            ).await.unwrap();
+
+            let result = service.exec_query("SELECT count(*), sum(amount) FROM foo.events").await.unwrap();
+            assert_eq!(result.get_rows(), &vec![Row::new(vec![TableValue::Int(1), TableValue::Int(5)])]);
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn delete_during_compaction() {
+        Config::test("delete_during_compaction").update_config(|mut c| {
+            c.partition_split_threshold = 10;
+            c.compaction_chunks_count_threshold = 0;
+            c
+        }).start_test(async move |services| {
+            let service = services.sql_service;
+
+            service.exec_query("CREATE SCHEMA foo").await.unwrap();
+            service.exec_query("CREATE TABLE foo.numbers (id int)").await.unwrap();
+
+            let insert = |batch: i64| {
+                let values = (batch * 20..(batch + 1) * 20).map(|i| format!("({})", i)).join(", ");
+                format!("INSERT INTO foo.numbers (id) VALUES {}", values)
+            };
+
+            for batch in 0..5 {
+                service.exec_query(&insert(batch)).await.unwrap();
+            }
+
+            // Every insert schedules compaction of partitions the delete rewrites
+            let inserts = async {
+                for batch in 5..10 {
+                    service.exec_query(&insert(batch)).await.unwrap();
+                }
+            };
+            let (delete, _) = futures::future::join(
+                service.exec_query("DELETE FROM foo.numbers WHERE id < 50"),
+                inserts
+            ).await;
+            delete.unwrap();
+
+            let result = service.exec_query("SELECT count(*) FROM foo.numbers WHERE id < 50").await.unwrap();
+            assert_eq!(result.get_rows(), &vec![Row::new(vec![TableValue::Int(0)])]);
+
+            let result = service.exec_query("SELECT count(*), min(id), max(id) FROM foo.numbers").await.unwrap();
+            assert_eq!(result.get_rows(), &vec![Row::new(vec![TableValue::Int(150), TableValue::Int(50), TableValue::Int(199)])]);
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn used_files_deleted_after_timeout() {
+        Config::test("used_files_deleted_after_timeout").update_config(|mut c| {
+            c.query_timeout = 1;
+            c
+        }).start_test(async move |services| {
+            let service = services.sql_service;
+
+            service.exec_query("CREATE SCHEMA foo").await.unwrap();
+            service.exec_query("CREATE TABLE foo.numbers (num int)").await.unwrap();
+            service.exec_query("INSERT INTO foo.numbers (num) VALUES (1), (2)").await.unwrap();
+            service.exec_query("SELECT count(*) FROM foo.numbers").await.unwrap();
+
+            service.exec_query("DROP TABLE foo.numbers").await.unwrap();
+            tokio::time::delay_for(Duration::from_millis(500)).await;
+
+            let chunk_files = services.remote_fs.list("").await.unwrap()
+                .into_iter()
+                .filter(|f| f.ends_with(".chunk.parquet"))
+                .collect::<Vec<_>>();
+            assert_eq!(chunk_files.len(), 1);
+            let deletions = services.meta_store.get_file_deletions().await.unwrap();
+            assert_eq!(deletions.len(), 1);
+            assert_eq!(deletions[0].get_row().remote_path(), &chunk_files[0]);
+
+            tokio::time::delay_for(Duration::from_secs(3)).await;
+
+            let chunk_files = services.remote_fs.list("").await.unwrap()
+                .into_iter()
+                .filter(|f| f.ends_with(".chunk.parquet"))
+                .collect::<Vec<_>>();
+            assert!(chunk_files.is_empty());
+            assert!(services.meta_store.get_file_deletions().await.unwrap().is_empty());
+        }).await;
+    }
 }
 
 impl SqlServiceImpl {
@@ -1,4 +1,4 @@
-use sqlparser::ast::{ObjectName, Statement as SQLStatement};
+use sqlparser::ast::{DataType, Ident, ObjectName, Statement as SQLStatement};
 use sqlparser::dialect::keywords::Keyword;
 use sqlparser::dialect::Dialect;
 use sqlparser::parser::{Parser, ParserError};
@@ -31,11 +31,54 @@ pub enum Statement {
     CreateTable {
         create_table: SQLStatement,
         indexes: Vec<SQLStatement>,
+        locations: Option<Vec<String>>,
     },
     CreateSchema {
         schema_name: ObjectName,
         if_not_exists: bool,
     },
+    AlterTable {
+        table_name: ObjectName,
+        operation: AlterTableOperation,
+    },
+    Truncate {
+        table_name: ObjectName,
+    },
+    KillQuery {
+        query_id: u32,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AlterTableOperation {
+    AddColumn {
+        column_name: Ident,
+        data_type: DataType,
+    },
+    DropColumn {
+        column_name: Ident,
+    },
+    RenameColumn {
+        old_column_name: Ident,
+        new_column_name: Ident,
+    },
+    RenameTable {
+        new_table_name: ObjectName,
+    },
+}
+
+/// Placeholders are named with `prefix` which isn't present in the query,
+/// so identifiers of the query can't be taken for them.
+pub fn placeholder_name(prefix: &str, index: usize) -> String {
+    format!("{}{}", prefix, index)
+}
+
+/// Returns index of the placeholder if `ident` was produced from `?` in a prepared statement.
+pub fn placeholder_index(prefix: &str, ident: &Ident) -> Option<usize> {
+    if ident.quote_style != Some('`') {
+        return None;
+    }
+    ident.value.strip_prefix(prefix)?.parse::<usize>().ok()
 }
 
 pub struct CubeStoreParser<'a> {
@@ -52,6 +95,37 @@ impl<'a> CubeStoreParser<'a> {
         })
     }
 
+    /// `?` placeholders are parsed as quoted identifiers which are replaced by values on execution.
+    pub fn new_with_placeholders(
+        sql: &str,
+        placeholder_prefix: &str,
+    ) -> Result<(Self, usize), ParserError> {
+        let dialect = &MySqlDialectWithBackTicks {};
+        let mut tokenizer = Tokenizer::new(dialect, sql);
+        let mut params_count = 0;
+        let tokens = tokenizer
+            .tokenize()?
+            .into_iter()
+            .map(|token| match token {
+                Token::Char('?') => {
+                    let placeholder = Token::make_word(
+                        &placeholder_name(placeholder_prefix, params_count),
+                        Some('`'),
+                    );
+                    params_count += 1;
+                    placeholder
+                }
+                token => token,
+            })
+            .collect();
+        Ok((
+            CubeStoreParser {
+                parser: Parser::new(tokens, dialect),
+            },
+            params_count,
+        ))
+    }
+
     pub fn parse_statement(&mut self) -> Result<Statement, ParserError> {
         match self.parser.peek_token() {
             Token::Word(w) => match w.keyword {
@@ -59,6 +133,18 @@ impl<'a> CubeStoreParser<'a> {
                     self.parser.next_token();
                     self.parse_create()
                 }
+                Keyword::ALTER => {
+                    self.parser.next_token();
+                    self.parse_alter_table()
+                }
+                Keyword::TRUNCATE => {
+                    self.parser.next_token();
+                    self.parse_truncate()
+                }
+                _ if w.value.eq_ignore_ascii_case("kill") => {
+                    self.parser.next_token();
+                    self.parse_kill()
+                }
                 _ => Ok(Statement::Statement(self.parser.parse_statement()?)),
             },
             _ => Ok(Statement::Statement(self.parser.parse_statement()?)),
@@ -66,17 +152,23 @@ impl<'a> CubeStoreParser<'a> {
     }
 
     pub fn parse_create(&mut self) -> Result<Statement, ParserError> {
-        if self.parser.parse_keyword(Keyword::SCHEMA) {
+        let or_replace = self.parser.parse_keywords(&[Keyword::OR, Keyword::REPLACE]);
+        if self.parser.parse_keyword(Keyword::TABLE) {
+            self.parse_create_table(or_replace)
+        } else if or_replace {
+            Err(ParserError::ParserError(format!(
+                "Expected TABLE after CREATE OR REPLACE, found: {}",
+                self.parser.peek_token()
+            )))
+        } else if self.parser.parse_keyword(Keyword::SCHEMA) {
             self.parse_create_schema()
-        } else if self.parser.parse_keyword(Keyword::TABLE) {
-            self.parse_create_table()
         } else {
             Ok(Statement::Statement(self.parser.parse_create()?))
         }
     }
 
-    pub fn parse_create_table(&mut self) -> Result<Statement, ParserError> {
-        let statement = self.parser.parse_create_table(false)?;
+    pub fn parse_create_table(&mut self, or_replace: bool) -> Result<Statement, ParserError> {
+        let statement = self.parser.parse_create_table(or_replace)?;
         if let SQLStatement::CreateTable {
             name,
             columns,
@@ -96,8 +188,11 @@ impl<'a> CubeStoreParser<'a> {
                 indexes.push(self.parse_with_index(name.clone())?);
             }
 
-            let location = if self.parser.parse_keyword(Keyword::LOCATION) {
-                Some(self.parser.parse_literal_string()?)
+            let locations = if self.parser.parse_keyword(Keyword::LOCATION) {
+                Some(
+                    self.parser
+                        .parse_comma_separated(Parser::parse_literal_string)?,
+                )
             } else {
                 None
             };
@@ -110,13 +205,14 @@ impl<'a> CubeStoreParser<'a> {
                     constraints,
                     with_options,
                     if_not_exists,
-                    external: location.is_some(),
+                    external: locations.is_some(),
                     file_format,
-                    location,
+                    location: locations.as_ref().map(|l| l[0].clone()),
                     query,
                     without_rowid,
                 },
                 indexes,
+                locations,
             })
         } else {
             Ok(Statement::Statement(statement))
@@ -142,6 +238,85 @@ impl<'a> CubeStoreParser<'a> {
         })
     }
 
+    pub fn parse_alter_table(&mut self) -> Result<Statement, ParserError> {
+        self.parser.expect_keyword(Keyword::TABLE)?;
+        let table_name = self.parser.parse_object_name()?;
+        let operation = if self.parser.parse_keyword(Keyword::ADD) {
+            self.parser.parse_keyword(Keyword::COLUMN);
+            let column_name = self.parser.parse_identifier()?;
+            let data_type = self.parser.parse_data_type()?;
+            AlterTableOperation::AddColumn {
+                column_name,
+                data_type,
+            }
+        } else if self.parser.parse_keyword(Keyword::DROP) {
+            self.parser.parse_keyword(Keyword::COLUMN);
+            AlterTableOperation::DropColumn {
+                column_name: self.parser.parse_identifier()?,
+            }
+        } else if self.parse_custom_keyword("RENAME") {
+            if self.parser.parse_keyword(Keyword::TO) {
+                AlterTableOperation::RenameTable {
+                    new_table_name: self.parser.parse_object_name()?,
+                }
+            } else {
+                self.parser.expect_keyword(Keyword::COLUMN)?;
+                let old_column_name = self.parser.parse_identifier()?;
+                self.parser.expect_keyword(Keyword::TO)?;
+                let new_column_name = self.parser.parse_identifier()?;
+                AlterTableOperation::RenameColumn {
+                    old_column_name,
+                    new_column_name,
+                }
+            }
+        } else {
+            return Err(ParserError::ParserError(format!(
+                "Expected ADD, DROP or RENAME after ALTER TABLE {}, found: {}",
+                table_name,
+                self.parser.peek_token()
+            )));
+        };
+        Ok(Statement::AlterTable {
+            table_name,
+            operation,
+        })
+    }
+
+    pub fn parse_truncate(&mut self) -> Result<Statement, ParserError> {
+        self.parser.parse_keyword(Keyword::TABLE);
+        let table_name = self.parser.parse_object_name()?;
+        Ok(Statement::Truncate { table_name })
+    }
+
+    pub fn parse_kill(&mut self) -> Result<Statement, ParserError> {
+        if !self.parse_custom_keyword("QUERY") {
+            return Err(ParserError::ParserError(format!(
+                "Only KILL QUERY is supported, found: {}",
+                self.parser.peek_token()
+            )));
+        }
+        let query_id = self.parser.parse_literal_uint()?;
+        if query_id > u32::MAX as u64 {
+            return Err(ParserError::ParserError(format!(
+                "Invalid query id: {}",
+                query_id
+            )));
+        }
+        Ok(Statement::KillQuery {
+            query_id: query_id as u32,
+        })
+    }
+
+    fn parse_custom_keyword(&mut self, keyword: &str) -> bool {
+        match self.parser.peek_token() {
+            Token::Word(w) if w.value.eq_ignore_ascii_case(keyword) => {
+                self.parser.next_token();
+                true
+            }
+            _ => false,
+        }
+    }
+
     fn parse_create_schema(&mut self) -> Result<Statement, ParserError> {
         let if_not_exists =
             self.parser
@@ -0,0 +1,193 @@
+use crate::CubeError;
+use sqlparser::ast::*;
+
+pub trait QueryVisitor {
+    /// Returns `false` if sub expressions shouldn't be visited.
+    fn visit_expr(&mut self, expr: &mut Expr) -> Result<bool, CubeError>;
+
+    fn visit_table(&mut self, name: &mut ObjectName) -> Result<(), CubeError>;
+
+    /// Called after the query of a common table expression is visited.
+    fn visit_cte(&mut self, _alias: &TableAlias) -> Result<(), CubeError> {
+        Ok(())
+    }
+}
+
+pub fn walk_statement(
+    statement: &mut Statement,
+    visitor: &mut dyn QueryVisitor,
+) -> Result<(), CubeError> {
+    match statement {
+        Statement::Query(q) => walk_query(q, visitor)?,
+        Statement::Insert {
+            table_name, source, ..
+        } => {
+            visitor.visit_table(table_name)?;
+            walk_query(source, visitor)?;
+        }
+        Statement::Delete {
+            table_name,
+            selection,
+        } => {
+            visitor.visit_table(table_name)?;
+            if let Some(e) = selection {
+                walk_expr(e, visitor)?;
+            }
+        }
+        Statement::CreateTable { name, query, .. } => {
+            visitor.visit_table(name)?;
+            if let Some(q) = query {
+                walk_query(q, visitor)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+pub fn walk_query(query: &mut Query, visitor: &mut dyn QueryVisitor) -> Result<(), CubeError> {
+    if let Some(with) = &mut query.with {
+        for cte in with.cte_tables.iter_mut() {
+            walk_query(&mut cte.query, visitor)?;
+            visitor.visit_cte(&cte.alias)?;
+        }
+    }
+    walk_set_expr(&mut query.body, visitor)?;
+    for o in query.order_by.iter_mut() {
+        walk_expr(&mut o.expr, visitor)?;
+    }
+    if let Some(e) = &mut query.limit {
+        walk_expr(e, visitor)?;
+    }
+    if let Some(o) = &mut query.offset {
+        walk_expr(&mut o.value, visitor)?;
+    }
+    Ok(())
+}
+
+fn walk_set_expr(set_expr: &mut SetExpr, visitor: &mut dyn QueryVisitor) -> Result<(), CubeError> {
+    match set_expr {
+        SetExpr::Select(select) => {
+            for item in select.projection.iter_mut() {
+                match item {
+                    SelectItem::UnnamedExpr(e) | SelectItem::ExprWithAlias { expr: e, .. } => {
+                        walk_expr(e, visitor)?
+                    }
+                    _ => {}
+                }
+            }
+            for t in select.from.iter_mut() {
+                walk_table_with_joins(t, visitor)?;
+            }
+            if let Some(e) = &mut select.selection {
+                walk_expr(e, visitor)?;
+            }
+            for e in select.group_by.iter_mut() {
+                walk_expr(e, visitor)?;
+            }
+            if let Some(e) = &mut select.having {
+                walk_expr(e, visitor)?;
+            }
+        }
+        SetExpr::Query(q) => walk_query(q, visitor)?,
+        SetExpr::SetOperation { left, right, .. } => {
+            walk_set_expr(left, visitor)?;
+            walk_set_expr(right, visitor)?;
+        }
+        SetExpr::Values(Values(rows)) => {
+            for e in rows.iter_mut().flatten() {
+                walk_expr(e, visitor)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn walk_table_with_joins(
+    table: &mut TableWithJoins,
+    visitor: &mut dyn QueryVisitor,
+) -> Result<(), CubeError> {
+    walk_table_factor(&mut table.relation, visitor)?;
+    for j in table.joins.iter_mut() {
+        walk_table_factor(&mut j.relation, visitor)?;
+        match &mut j.join_operator {
+            JoinOperator::Inner(JoinConstraint::On(e))
+            | JoinOperator::LeftOuter(JoinConstraint::On(e))
+            | JoinOperator::RightOuter(JoinConstraint::On(e))
+            | JoinOperator::FullOuter(JoinConstraint::On(e)) => walk_expr(e, visitor)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn walk_table_factor(
+    table_factor: &mut TableFactor,
+    visitor: &mut dyn QueryVisitor,
+) -> Result<(), CubeError> {
+    match table_factor {
+        TableFactor::Table { name, .. } => visitor.visit_table(name)?,
+        TableFactor::Derived { subquery, .. } => walk_query(subquery, visitor)?,
+        TableFactor::NestedJoin(t) => walk_table_with_joins(t, visitor)?,
+    }
+    Ok(())
+}
+
+pub fn walk_expr(expr: &mut Expr, visitor: &mut dyn QueryVisitor) -> Result<(), CubeError> {
+    if !visitor.visit_expr(expr)? {
+        return Ok(());
+    }
+    match expr {
+        Expr::IsNull(e) | Expr::IsNotNull(e) | Expr::Nested(e) => walk_expr(e, visitor)?,
+        Expr::UnaryOp { expr: e, .. }
+        | Expr::Cast { expr: e, .. }
+        | Expr::Extract { expr: e, .. } => walk_expr(e, visitor)?,
+        Expr::BinaryOp { left, right, .. } => {
+            walk_expr(left, visitor)?;
+            walk_expr(right, visitor)?;
+        }
+        Expr::Between {
+            expr: e, low, high, ..
+        } => {
+            walk_expr(e, visitor)?;
+            walk_expr(low, visitor)?;
+            walk_expr(high, visitor)?;
+        }
+        Expr::InList { expr: e, list, .. } => {
+            walk_expr(e, visitor)?;
+            for e in list.iter_mut() {
+                walk_expr(e, visitor)?;
+            }
+        }
+        Expr::InSubquery {
+            expr: e, subquery, ..
+        } => {
+            walk_expr(e, visitor)?;
+            walk_query(subquery, visitor)?;
+        }
+        Expr::Case {
+            operand,
+            conditions,
+            results,
+            else_result,
+        } => {
+            if let Some(e) = operand {
+                walk_expr(e, visitor)?;
+            }
+            for e in conditions.iter_mut().chain(results.iter_mut()) {
+                walk_expr(e, visitor)?;
+            }
+            if let Some(e) = else_result {
+                walk_expr(e, visitor)?;
+            }
+        }
+        Expr::Function(f) => {
+            for e in f.args.iter_mut() {
+                walk_expr(e, visitor)?;
+            }
+        }
+        Expr::Subquery(q) | Expr::Exists(q) => walk_query(q, visitor)?,
+        _ => {}
+    }
+    Ok(())
+}
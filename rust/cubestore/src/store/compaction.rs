@@ -1,15 +1,20 @@
 use crate::config::ConfigObj;
-use crate::metastore::{MetaStore, MetaStoreTable};
+use crate::metastore::{
+    Chunk, IdRow, Index, MetaStore, MetaStoreTable, Partition, PartitionDelete,
+};
 use crate::remotefs::RemoteFs;
 use crate::store::ChunkDataStore;
 use crate::table::parquet::ParquetTableStore;
-use crate::table::TableStore;
+use crate::table::{Row, TableStore};
 use crate::CubeError;
 use async_trait::async_trait;
 use itertools::{EitherOrBoth, Itertools};
+use mockall::automock;
 use num::integer::div_ceil;
+use std::cmp::max;
 use std::sync::Arc;
 
+#[automock]
 #[async_trait]
 pub trait CompactionService: Send + Sync {
     async fn compact(&self, partition_id: u64) -> Result<(), CubeError>;
@@ -36,6 +41,128 @@ impl CompactionServiceImpl {
             config,
         })
     }
+
+    /// Rewrites the partition and its chunks without rows matching pending deletes.
+    /// The partition file is read twice, to count retained rows and to merge them, so only one
+    /// row group of it is held in memory at a time.
+    async fn compact_with_deletes(
+        &self,
+        partition: IdRow<Partition>,
+        index: IdRow<Index>,
+        chunks: Vec<IdRow<Chunk>>,
+    ) -> Result<(), CubeError> {
+        let deletes = partition.get_row().pending_deletes().clone();
+        let applied_delete_count = deletes.len();
+        let index_row = index.get_row().clone();
+        let sort_key_size = index_row.sort_key_size();
+        let chunks_row_count = chunks
+            .iter()
+            .map(|c| c.get_row().get_row_count())
+            .sum::<u64>();
+        let total_count = partition.get_row().main_table_row_count() + chunks_row_count;
+
+        let mut rows = Vec::new();
+        for chunk in chunks.iter() {
+            let chunk_deletes = deletes
+                .iter()
+                .filter(|d| d.max_chunk_id >= chunk.get_id())
+                .cloned()
+                .collect::<Vec<_>>();
+            let mut data = self.chunk_store.get_chunk(chunk.clone()).await?;
+            rows.extend(
+                data.mut_rows()
+                    .drain(..)
+                    .filter(|r| !is_deleted(&chunk_deletes, &index_row, r)),
+            );
+        }
+        rows.sort_by(|a, b| a.sort_key(sort_key_size).cmp(&b.sort_key(sort_key_size)));
+
+        // Partition file isn't read if all of its rows are deleted
+        let file_deleted = deletes.iter().any(|d| {
+            d.filter.matches_partition(
+                &index_row,
+                partition.get_row().get_min_val(),
+                partition.get_row().get_max_val(),
+            )
+        });
+        let old_partition_local = match partition.get_row().get_full_name(partition.get_id()) {
+            Some(f) if !file_deleted => Some(self.remote_fs.download_file(&f).await?),
+            _ => None,
+        };
+        let deletes = Arc::new(deletes);
+        let retained_file_row_count = if let Some(f) = old_partition_local.clone() {
+            let store = ParquetTableStore::new(index_row.clone(), 16384); // TODO config
+            let deletes = deletes.clone();
+            let index_row = index_row.clone();
+            tokio::task::spawn_blocking(move || {
+                store.count_retained_rows(&f, &|r| !is_deleted(&deletes, &index_row, r))
+            })
+            .await??
+        } else {
+            0
+        };
+        let retained_row_count = retained_file_row_count + rows.len();
+
+        // Empty partition keeps the key range of the old one so new rows still have a place to go.
+        let new_partitions_count = max(
+            div_ceil(
+                retained_row_count as u64,
+                self.config.partition_split_threshold(),
+            ),
+            1,
+        );
+        let mut new_partitions = Vec::new();
+        for _ in 0..new_partitions_count {
+            new_partitions.push(
+                self.meta_store
+                    .create_partition(partition.get_row().child(partition.get_id()))
+                    .await?,
+            );
+        }
+        let mut new_partition_local_files = Vec::new();
+        for p in new_partitions.iter() {
+            let new_remote_path = p.get_row().get_full_name(p.get_id()).unwrap();
+            new_partition_local_files.push(self.remote_fs.local_file(&new_remote_path).await?)
+        }
+
+        let store = ParquetTableStore::new(index_row.clone(), 16384); // TODO config
+        let count_and_min_max = tokio::task::spawn_blocking(move || {
+            store.merge_retained_rows(
+                old_partition_local.as_ref().map(|s| s.as_str()),
+                new_partition_local_files,
+                rows,
+                retained_row_count,
+                sort_key_size,
+                &|r| !is_deleted(&deletes, &index_row, r),
+            )
+        })
+        .await??;
+        let new_active_min_max = new_partitions_min_max(partition.get_row(), &count_and_min_max);
+
+        let mut new_active = Vec::new();
+        for (i, p) in new_partitions.into_iter().enumerate() {
+            if i < new_active_min_max.len() {
+                let new_remote_path = p.get_row().get_full_name(p.get_id()).unwrap();
+                self.remote_fs.upload_file(new_remote_path.as_str()).await?;
+                new_active.push(p.get_id());
+            } else {
+                self.meta_store.partition_table().delete(p.get_id()).await?;
+            }
+        }
+
+        self.meta_store
+            .swap_active_partitions(
+                vec![partition.get_id()],
+                new_active,
+                chunks.iter().map(|c| c.get_id()).collect(),
+                new_active_min_max,
+                total_count - retained_row_count as u64,
+                applied_delete_count,
+            )
+            .await?;
+
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -49,6 +176,9 @@ impl CompactionService for CompactionServiceImpl {
             .meta_store
             .get_partition_for_compaction(partition_id)
             .await?;
+        if !partition.get_row().pending_deletes().is_empty() {
+            return self.compact_with_deletes(partition, index, chunks).await;
+        }
         let partition_id = partition.get_id();
         let chunks_row_count = chunks
             .iter()
@@ -183,6 +313,8 @@ impl CompactionService for CompactionServiceImpl {
                         }
                     })
                     .collect::<Result<Vec<_>, CubeError>>()?,
+                0,
+                0,
             )
             .await?;
 
@@ -190,6 +322,42 @@ impl CompactionService for CompactionServiceImpl {
     }
 }
 
+pub(crate) fn is_deleted(deletes: &[PartitionDelete], index: &Index, row: &Row) -> bool {
+    deletes.iter().any(|d| d.filter.matches(index, row))
+}
+
+/// New partitions together cover the whole sort key range of the old one.
+fn new_partitions_min_max(
+    partition: &Partition,
+    count_and_min_max: &Vec<(u64, (Row, Row))>,
+) -> Vec<(u64, (Option<Row>, Option<Row>))> {
+    if count_and_min_max.is_empty() {
+        return vec![(
+            0,
+            (
+                partition.get_min_val().clone(),
+                partition.get_max_val().clone(),
+            ),
+        )];
+    }
+    count_and_min_max
+        .iter()
+        .enumerate()
+        .map(|(i, (count, (min, _)))| {
+            let min = if i == 0 {
+                partition.get_min_val().clone()
+            } else {
+                Some(min.clone())
+            };
+            let max = match count_and_min_max.get(i + 1) {
+                Some((_, (next_min, _))) => Some(next_min.clone()),
+                None => partition.get_max_val().clone(),
+            };
+            (*count, (min, max))
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
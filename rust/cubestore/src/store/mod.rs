@@ -6,37 +6,70 @@ use datafusion::physical_plan::ExecutionPlan;
 use serde::{de, Deserialize, Serialize};
 extern crate bincode;
 
-use bincode::{deserialize_from, serialize_into};
+use bincode::deserialize_from;
 
+use crate::config::{ConfigObj, WalDurability};
 use crate::metastore::{
-    table::Table, Chunk, Column, ColumnType, IdRow, Index, MetaStore, MetaStoreTable, Partition,
-    WAL,
+    table::{Table, TablePath},
+    Chunk, Column, ColumnType, IdRow, Index, MetaStore, MetaStoreTable, Partition, WAL,
 };
+use crate::queryplanner::query_executor::batch_to_dataframe;
 use crate::remotefs::RemoteFs;
 use crate::table::{Row, TableStore, TableValue};
 use crate::CubeError;
-use arrow::datatypes::Schema;
-use std::{
-    fs::File,
-    io::{BufReader, BufWriter, Write},
-    sync::Arc,
-};
-
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use futures::task::{Context, Poll};
+use futures::{future, stream, Future, Stream, StreamExt};
+use std::mem;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::{cmp::Ordering, fs::File, io::BufReader, sync::Arc};
+
+use crate::store::compaction::is_deleted;
 use crate::table::parquet::ParquetTableStore;
-use arrow::array::{Array, Int64Builder, StringBuilder};
+use arrow::array::{
+    Array, ArrayRef, BinaryArray, BinaryBuilder, BooleanArray, BooleanBuilder, Float64Array,
+    Float64Builder, Int64Array, Int64Builder, Int64Decimal0Array, Int64Decimal10Array,
+    Int64Decimal1Array, Int64Decimal2Array, Int64Decimal3Array, Int64Decimal4Array,
+    Int64Decimal5Array, StringArray, StringBuilder, TimestampMicrosecondArray,
+    TimestampMicrosecondBuilder, UInt32Array,
+};
+use arrow::compute::{cast, take};
+use arrow::ipc::reader::FileReader;
+use arrow::ipc::writer::FileWriter;
 use arrow::record_batch::RecordBatch;
-use log::trace;
+use bigdecimal::{BigDecimal, Num, ToPrimitive};
+use datafusion::physical_plan::datetime_expressions::string_to_timestamp_nanos;
+use log::{error, trace, warn};
 use mockall::automock;
 
 #[derive(Serialize, Deserialize, Eq, PartialEq, Debug)]
 pub struct DataFrame {
     columns: Vec<Column>,
     data: Vec<Row>,
+    #[serde(default)]
+    source_tables: Vec<Option<TablePath>>,
 }
 
 impl DataFrame {
     pub fn new(columns: Vec<Column>, data: Vec<Row>) -> DataFrame {
-        DataFrame { columns, data }
+        DataFrame {
+            columns,
+            data,
+            source_tables: Vec::new(),
+        }
+    }
+
+    /// Tables the columns were selected from, `None` for computed columns.
+    pub fn with_source_tables(self, source_tables: Vec<Option<TablePath>>) -> DataFrame {
+        DataFrame {
+            source_tables,
+            ..self
+        }
+    }
+
+    pub fn get_source_table(&self, column_index: usize) -> Option<&TablePath> {
+        self.source_tables.get(column_index)?.as_ref()
     }
 
     pub fn len(&self) -> usize {
@@ -132,6 +165,114 @@ impl DataFrame {
     }
 }
 
+/// Columnar query result which is produced while it's read.
+pub struct DataStream {
+    schema: SchemaRef,
+    prefetched: Option<RecordBatch>,
+    // Makes the stream `Sync` as required by HTTP bodies and gRPC responses.
+    // It's accessed only through `&mut self` so it's never locked concurrently.
+    batches: Mutex<Pin<Box<dyn Stream<Item = Result<RecordBatch, CubeError>> + Send>>>,
+    source_tables: Vec<Option<TablePath>>,
+}
+
+impl DataStream {
+    pub fn new(
+        schema: SchemaRef,
+        batches: Pin<Box<dyn Stream<Item = Result<RecordBatch, CubeError>> + Send>>,
+    ) -> DataStream {
+        DataStream {
+            schema,
+            prefetched: None,
+            batches: Mutex::new(batches),
+            source_tables: Vec::new(),
+        }
+    }
+
+    pub fn from_data_frame(data_frame: DataFrame) -> Result<DataStream, CubeError> {
+        let schema = columns_schema(data_frame.get_columns());
+        let batches = if data_frame.get_columns().is_empty() || data_frame.len() == 0 {
+            Vec::new()
+        } else {
+            vec![Ok(rows_to_record_batch(
+                data_frame.get_columns(),
+                data_frame.get_rows(),
+            )?)]
+        };
+        Ok(DataStream::new(schema, Box::pin(stream::iter(batches)))
+            .with_source_tables(data_frame.source_tables))
+    }
+
+    /// Tables the columns were selected from, `None` for computed columns.
+    pub fn with_source_tables(self, source_tables: Vec<Option<TablePath>>) -> DataStream {
+        DataStream {
+            source_tables,
+            ..self
+        }
+    }
+
+    pub fn get_source_table(&self, column_index: usize) -> Option<&TablePath> {
+        self.source_tables.get(column_index)?.as_ref()
+    }
+
+    /// Stream ends with the error returned by `cancelled` if it resolves before all batches are read.
+    pub fn with_cancellation(
+        self,
+        cancelled: impl Future<Output = CubeError> + Send + 'static,
+    ) -> DataStream {
+        let batches = self
+            .batches
+            .into_inner()
+            .unwrap()
+            .map(Some)
+            .chain(stream::once(future::ready(None)));
+        let cancelled = stream::once(async move { Some(Err(cancelled.await)) });
+        let prefetched = self.prefetched.map(Ok);
+        let batches = stream::iter(prefetched).chain(
+            stream::select(batches, cancelled)
+                .take_while(|b| future::ready(b.is_some()))
+                .map(|b| b.unwrap()),
+        );
+        DataStream {
+            schema: self.schema,
+            prefetched: None,
+            batches: Mutex::new(Box::pin(batches)),
+            source_tables: self.source_tables,
+        }
+    }
+
+    pub fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    /// Waits for the first batch so execution errors can be reported before any results are sent.
+    pub async fn prefetch(&mut self) -> Result<(), CubeError> {
+        if self.prefetched.is_none() {
+            self.prefetched = self.batches.get_mut().unwrap().next().await.transpose()?;
+        }
+        Ok(())
+    }
+
+    pub async fn into_data_frame(mut self) -> Result<DataFrame, CubeError> {
+        let mut batches = Vec::new();
+        while let Some(batch) = self.next().await {
+            batches.push(batch?);
+        }
+        Ok(batch_to_dataframe(&batches)?.with_source_tables(self.source_tables))
+    }
+}
+
+impl Stream for DataStream {
+    type Item = Result<RecordBatch, CubeError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if let Some(batch) = this.prefetched.take() {
+            return Poll::Ready(Some(Ok(batch)));
+        }
+        this.batches.get_mut().unwrap().poll_next_unpin(cx)
+    }
+}
+
 #[derive(Serialize, Deserialize, Eq, PartialEq, Debug)]
 pub struct ChunkData {
     data_frame: DataFrame,
@@ -159,6 +300,7 @@ pub struct WALStore {
     meta_store: Arc<dyn MetaStore>,
     remote_fs: Arc<dyn RemoteFs>,
     wal_chunk_size: usize,
+    config: Arc<dyn ConfigObj>,
 }
 
 pub struct ChunkStore {
@@ -168,14 +310,6 @@ pub struct ChunkStore {
     chunk_size: usize,
 }
 
-fn save<T: Serialize>(path: String, data: T) -> Result<(), CubeError> {
-    let file = File::create(path)?;
-    let mut f = BufWriter::new(file);
-    serialize_into(&mut f, &data).unwrap();
-    f.flush()?;
-    Ok(())
-}
-
 fn load<T: de::DeserializeOwned>(path: String) -> Result<T, CubeError> {
     let f = File::open(path)?;
     let f = BufReader::new(f);
@@ -184,12 +318,537 @@ fn load<T: de::DeserializeOwned>(path: String) -> Result<T, CubeError> {
     Ok(res)
 }
 
+fn save_batches(path: String, batches: Vec<RecordBatch>) -> Result<(), CubeError> {
+    let file = File::create(path)?;
+    let mut writer = FileWriter::try_new(file, &batches[0].schema())?;
+    for batch in batches.iter() {
+        writer.write(batch)?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+fn load_batches(path: String) -> Result<Vec<RecordBatch>, CubeError> {
+    let reader = FileReader::try_new(BufReader::new(File::open(path)?))?;
+    Ok(reader.collect::<Result<Vec<_>, _>>()?)
+}
+
+/// Position of the first row in `order` which key isn't less than `bound`.
+fn lower_bound(keys: &[ArrayRef], order: &[u32], bound: &[ArrayRef]) -> usize {
+    order
+        .binary_search_by(|i| compare_keys(keys, *i as usize, bound, 0).then(Ordering::Greater))
+        .unwrap_err()
+}
+
+fn compare_keys(left: &[ArrayRef], i: usize, right: &[ArrayRef], j: usize) -> Ordering {
+    left.iter()
+        .zip(right.iter())
+        .map(|(l, r)| compare_array_values(l, i, r, j))
+        .find(|o| *o != Ordering::Equal)
+        .unwrap_or(Ordering::Equal)
+}
+
+macro_rules! compare_values {
+    ($left: expr, $i: expr, $right: expr, $j: expr, $array_type: ident) => {{
+        let l = $left.as_any().downcast_ref::<$array_type>().unwrap();
+        let r = $right.as_any().downcast_ref::<$array_type>().unwrap();
+        l.value($i)
+            .partial_cmp(&r.value($j))
+            .unwrap_or(Ordering::Equal)
+    }};
+}
+
+/// Orders values of arrays of the same type like `RowSortKey` does: NULLs go first.
+fn compare_array_values(left: &ArrayRef, i: usize, right: &ArrayRef, j: usize) -> Ordering {
+    match (left.is_null(i), right.is_null(j)) {
+        (true, true) => return Ordering::Equal,
+        (true, false) => return Ordering::Less,
+        (false, true) => return Ordering::Greater,
+        (false, false) => {}
+    }
+    match left.data_type() {
+        DataType::Utf8 => compare_values!(left, i, right, j, StringArray),
+        DataType::Int64 => compare_values!(left, i, right, j, Int64Array),
+        DataType::Float64 => compare_values!(left, i, right, j, Float64Array),
+        DataType::Boolean => compare_values!(left, i, right, j, BooleanArray),
+        DataType::Binary => compare_values!(left, i, right, j, BinaryArray),
+        DataType::Timestamp(TimeUnit::Microsecond, None) => {
+            compare_values!(left, i, right, j, TimestampMicrosecondArray)
+        }
+        DataType::Int64Decimal(0) => compare_values!(left, i, right, j, Int64Decimal0Array),
+        DataType::Int64Decimal(1) => compare_values!(left, i, right, j, Int64Decimal1Array),
+        DataType::Int64Decimal(2) => compare_values!(left, i, right, j, Int64Decimal2Array),
+        DataType::Int64Decimal(3) => compare_values!(left, i, right, j, Int64Decimal3Array),
+        DataType::Int64Decimal(4) => compare_values!(left, i, right, j, Int64Decimal4Array),
+        DataType::Int64Decimal(5) => compare_values!(left, i, right, j, Int64Decimal5Array),
+        DataType::Int64Decimal(10) => compare_values!(left, i, right, j, Int64Decimal10Array),
+        x => panic!("Can't compare values of {:?}", x),
+    }
+}
+
+pub fn columns_schema(columns: &Vec<Column>) -> SchemaRef {
+    Arc::new(Schema::new(
+        columns
+            .iter()
+            .map(|c| {
+                let field: Field = c.clone().into();
+                Field::new(field.name(), field.data_type().clone(), true)
+            })
+            .collect(),
+    ))
+}
+
+fn unexpected_value(value: &TableValue, column: &Column) -> CubeError {
+    CubeError::internal(format!(
+        "Unexpected value {:?} for column {}",
+        value, column
+    ))
+}
+
+fn column_values<'a, T>(
+    rows: &'a [Row],
+    column: &Column,
+    convert: impl Fn(&'a TableValue) -> Result<T, CubeError>,
+) -> Result<Vec<Option<T>>, CubeError> {
+    rows.iter()
+        .map(|row| match &row.values()[column.get_index()] {
+            TableValue::Null => Ok(None),
+            value => Ok(Some(convert(value)?)),
+        })
+        .collect()
+}
+
+fn decimal_to_i64(value: &str, column: &Column) -> Result<i64, CubeError> {
+    BigDecimal::from_str_radix(value, 10)?
+        .with_scale(column.get_column_type().target_scale() as i64)
+        .as_bigint_and_exponent()
+        .0
+        .to_i64()
+        .ok_or_else(|| CubeError::internal(format!("Can't convert to i64 decimal: {}", value)))
+}
+
+macro_rules! decimal_array {
+    ($rows: expr, $column: expr, $array_type: ident) => {
+        Arc::new($array_type::from(column_values(
+            $rows,
+            $column,
+            |v| match v {
+                TableValue::Decimal(d) => decimal_to_i64(d, $column),
+                v => Err(unexpected_value(v, $column)),
+            },
+        )?)) as ArrayRef
+    };
+}
+
+/// Converts rows indexed by `columns` positions into a batch with one array per column.
+pub fn rows_to_record_batch(columns: &Vec<Column>, rows: &[Row]) -> Result<RecordBatch, CubeError> {
+    let mut arrays: Vec<ArrayRef> = Vec::with_capacity(columns.len());
+    for column in columns.iter() {
+        let array: ArrayRef = match column.get_column_type() {
+            ColumnType::String => Arc::new(StringArray::from(column_values(
+                rows,
+                column,
+                |v| match v {
+                    TableValue::String(s) => Ok(s.as_str()),
+                    v => Err(unexpected_value(v, column)),
+                },
+            )?)),
+            ColumnType::Int => Arc::new(Int64Array::from(column_values(
+                rows,
+                column,
+                |v| match v {
+                    TableValue::Int(i) => Ok(*i),
+                    v => Err(unexpected_value(v, column)),
+                },
+            )?)),
+            ColumnType::Timestamp => Arc::new(TimestampMicrosecondArray::from(column_values(
+                rows,
+                column,
+                |v| match v {
+                    TableValue::Timestamp(t) => Ok(t.get_time_stamp() / 1000),
+                    v => Err(unexpected_value(v, column)),
+                },
+            )?)),
+            ColumnType::Boolean => Arc::new(BooleanArray::from(column_values(
+                rows,
+                column,
+                |v| match v {
+                    TableValue::Boolean(b) => Ok(*b),
+                    v => Err(unexpected_value(v, column)),
+                },
+            )?)),
+            ColumnType::Float => Arc::new(Float64Array::from(column_values(
+                rows,
+                column,
+                |v| match v {
+                    TableValue::Float(f) => Ok(f.parse::<f64>()?),
+                    v => Err(unexpected_value(v, column)),
+                },
+            )?)),
+            ColumnType::Bytes | ColumnType::HyperLogLog => Arc::new(BinaryArray::from(
+                column_values(rows, column, |v| match v {
+                    TableValue::Bytes(b) => Ok(b.as_slice()),
+                    v => Err(unexpected_value(v, column)),
+                })?,
+            )),
+            ColumnType::Decimal { .. } => match column.get_column_type().target_scale() {
+                0 => decimal_array!(rows, column, Int64Decimal0Array),
+                1 => decimal_array!(rows, column, Int64Decimal1Array),
+                2 => decimal_array!(rows, column, Int64Decimal2Array),
+                3 => decimal_array!(rows, column, Int64Decimal3Array),
+                4 => decimal_array!(rows, column, Int64Decimal4Array),
+                5 => decimal_array!(rows, column, Int64Decimal5Array),
+                10 => decimal_array!(rows, column, Int64Decimal10Array),
+                x => {
+                    return Err(CubeError::internal(format!(
+                        "Unsupported decimal scale: {}",
+                        x
+                    )))
+                }
+            },
+        };
+        arrays.push(array);
+    }
+    Ok(RecordBatch::try_new(columns_schema(columns), arrays)?)
+}
+
+enum ColumnBuilder {
+    String(StringBuilder),
+    Int(Int64Builder),
+    Timestamp(TimestampMicrosecondBuilder),
+    Boolean(BooleanBuilder),
+    Float(Float64Builder),
+    Bytes(BinaryBuilder),
+    Decimal(Vec<Option<i64>>),
+}
+
+/// Appends rows to arrays of `columns` as they arrive, so rows aren't kept until a batch is full.
+pub struct RecordBatchBuilder {
+    columns: Vec<Column>,
+    capacity: usize,
+    builders: Vec<ColumnBuilder>,
+    len: usize,
+}
+
+impl RecordBatchBuilder {
+    pub fn new(columns: Vec<Column>, capacity: usize) -> RecordBatchBuilder {
+        let builders = column_builders(&columns, capacity);
+        RecordBatchBuilder {
+            columns,
+            capacity,
+            builders,
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Values of `row` are indexed by `columns` positions.
+    pub fn append(&mut self, row: &Row) -> Result<(), CubeError> {
+        for (column, builder) in self.columns.iter().zip(self.builders.iter_mut()) {
+            match (builder, &row.values()[column.get_index()]) {
+                (ColumnBuilder::String(b), TableValue::Null) => b.append_null()?,
+                (ColumnBuilder::String(b), TableValue::String(v)) => b.append_value(v.as_str())?,
+                (ColumnBuilder::Int(b), TableValue::Null) => b.append_null()?,
+                (ColumnBuilder::Int(b), TableValue::Int(v)) => b.append_value(*v)?,
+                (ColumnBuilder::Timestamp(b), TableValue::Null) => b.append_null()?,
+                (ColumnBuilder::Timestamp(b), TableValue::Timestamp(v)) => {
+                    b.append_value(v.get_time_stamp() / 1000)?
+                }
+                (ColumnBuilder::Boolean(b), TableValue::Null) => b.append_null()?,
+                (ColumnBuilder::Boolean(b), TableValue::Boolean(v)) => b.append_value(*v)?,
+                (ColumnBuilder::Float(b), TableValue::Null) => b.append_null()?,
+                (ColumnBuilder::Float(b), TableValue::Float(v)) => {
+                    b.append_value(v.parse::<f64>()?)?
+                }
+                (ColumnBuilder::Bytes(b), TableValue::Null) => b.append_null()?,
+                (ColumnBuilder::Bytes(b), TableValue::Bytes(v)) => b.append_value(v.as_slice())?,
+                (ColumnBuilder::Decimal(b), TableValue::Null) => b.push(None),
+                (ColumnBuilder::Decimal(b), TableValue::Decimal(v)) => {
+                    b.push(Some(decimal_to_i64(v, column)?))
+                }
+                (_, v) => return Err(unexpected_value(v, column)),
+            }
+        }
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Builds a batch of the rows appended so far and starts a new one.
+    pub fn flush(&mut self) -> Result<RecordBatch, CubeError> {
+        let builders = mem::replace(
+            &mut self.builders,
+            column_builders(&self.columns, self.capacity),
+        );
+        self.len = 0;
+        let arrays = self
+            .columns
+            .iter()
+            .zip(builders.into_iter())
+            .map(|(column, builder)| -> Result<ArrayRef, CubeError> {
+                Ok(match builder {
+                    ColumnBuilder::String(mut b) => Arc::new(b.finish()),
+                    ColumnBuilder::Int(mut b) => Arc::new(b.finish()),
+                    ColumnBuilder::Timestamp(mut b) => Arc::new(b.finish()),
+                    ColumnBuilder::Boolean(mut b) => Arc::new(b.finish()),
+                    ColumnBuilder::Float(mut b) => Arc::new(b.finish()),
+                    ColumnBuilder::Bytes(mut b) => Arc::new(b.finish()),
+                    ColumnBuilder::Decimal(values) => {
+                        scaled_decimal_array(values, column.get_column_type().target_scale())?
+                    }
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(RecordBatch::try_new(columns_schema(&self.columns), arrays)?)
+    }
+}
+
+fn column_builders(columns: &Vec<Column>, capacity: usize) -> Vec<ColumnBuilder> {
+    columns
+        .iter()
+        .map(|c| match c.get_column_type() {
+            ColumnType::String => ColumnBuilder::String(StringBuilder::new(capacity)),
+            ColumnType::Int => ColumnBuilder::Int(Int64Builder::new(capacity)),
+            ColumnType::Timestamp => {
+                ColumnBuilder::Timestamp(TimestampMicrosecondBuilder::new(capacity))
+            }
+            ColumnType::Boolean => ColumnBuilder::Boolean(BooleanBuilder::new(capacity)),
+            ColumnType::Float => ColumnBuilder::Float(Float64Builder::new(capacity)),
+            ColumnType::Bytes | ColumnType::HyperLogLog => {
+                ColumnBuilder::Bytes(BinaryBuilder::new(capacity))
+            }
+            ColumnType::Decimal { .. } => ColumnBuilder::Decimal(Vec::with_capacity(capacity)),
+        })
+        .collect()
+}
+
+pub fn null_array(column: &Column, len: usize) -> Result<ArrayRef, CubeError> {
+    let rows = vec![Row::new(vec![TableValue::Null]); len];
+    Ok(rows_to_record_batch(&vec![column.replace_index(0)], &rows)?
+        .column(0)
+        .clone())
+}
+
+/// Picks arrays of `columns` from the batch by their storage names.
+/// Columns added after the batch was written are filled with NULLs.
+pub fn project_batch(
+    batch: &RecordBatch,
+    columns: &Vec<Column>,
+    storage_name: impl Fn(&Column) -> String,
+) -> Result<Vec<ArrayRef>, CubeError> {
+    let schema = batch.schema();
+    columns
+        .iter()
+        .map(|c| match schema.index_of(&storage_name(c)) {
+            Ok(i) => Ok(batch.column(i).clone()),
+            Err(_) => null_array(c, batch.num_rows()),
+        })
+        .collect()
+}
+
+fn storage_columns(table: &Table) -> Vec<Column> {
+    table
+        .get_columns()
+        .iter()
+        .map(|c| {
+            Column::new(
+                table.storage_name(c).clone(),
+                c.get_column_type().clone(),
+                c.get_index(),
+            )
+        })
+        .collect()
+}
+
+/// Converts a batch with columns named after table columns to the storage schema.
+/// Columns missing in the batch are filled with NULLs.
+fn table_batch(table: &Table, batch: &RecordBatch) -> Result<RecordBatch, CubeError> {
+    let columns = table.get_columns();
+    if let Some(field) = batch
+        .schema()
+        .fields()
+        .iter()
+        .find(|f| !columns.iter().any(|c| c.get_name() == f.name()))
+    {
+        return Err(CubeError::user(format!(
+            "Column {} is not present in table {}",
+            field.name(),
+            table.get_table_name()
+        )));
+    }
+    let schema = columns_schema(&storage_columns(table));
+    let arrays = project_batch(batch, columns, |c| c.get_name().clone())?
+        .into_iter()
+        .zip(schema.fields().iter().zip(columns.iter()))
+        .map(|(array, (field, column))| {
+            if array.data_type() == field.data_type() {
+                return Ok(array);
+            }
+            cast_array(&array, field.data_type(), column).map_err(|e| {
+                CubeError::user(format!(
+                    "Can't convert {:?} to {} for column {}: {}",
+                    array.data_type(),
+                    column.get_column_type(),
+                    column.get_name(),
+                    e
+                ))
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(RecordBatch::try_new(schema, arrays)?)
+}
+
+/// Decimals are converted with integer arithmetic as arrow casts them through f64.
+fn cast_array(
+    array: &ArrayRef,
+    data_type: &DataType,
+    column: &Column,
+) -> Result<ArrayRef, CubeError> {
+    match (array.data_type(), column.get_column_type()) {
+        (_, ColumnType::Decimal { .. }) => decimal_array(array, column),
+        (DataType::Int64Decimal(_), ColumnType::Float) => {
+            let (values, scale) = decimal_values(array).unwrap();
+            let divisor = 10f64.powi(scale);
+            Ok(Arc::new(Float64Array::from(
+                values
+                    .into_iter()
+                    .map(|v| v.map(|v| v as f64 / divisor))
+                    .collect::<Vec<_>>(),
+            )))
+        }
+        (DataType::Utf8, ColumnType::Timestamp) => {
+            let a = array.as_any().downcast_ref::<StringArray>().unwrap();
+            let values = (0..a.len())
+                .map(|i| -> Result<_, CubeError> {
+                    if a.is_null(i) {
+                        Ok(None)
+                    } else {
+                        Ok(Some(string_to_timestamp_nanos(a.value(i))? / 1000))
+                    }
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(Arc::new(TimestampMicrosecondArray::from(values)))
+        }
+        _ => Ok(cast(array, data_type)?),
+    }
+}
+
+macro_rules! int_values {
+    ($array: expr, $array_type: ident) => {{
+        let a = $array.as_any().downcast_ref::<$array_type>().unwrap();
+        (0..a.len())
+            .map(|i| if a.is_null(i) { None } else { Some(a.value(i)) })
+            .collect::<Vec<_>>()
+    }};
+}
+
+/// Unscaled values and the scale of an integer or decimal array.
+fn decimal_values(array: &ArrayRef) -> Option<(Vec<Option<i64>>, i32)> {
+    Some(match array.data_type() {
+        DataType::Int64 => (int_values!(array, Int64Array), 0),
+        DataType::Int64Decimal(0) => (int_values!(array, Int64Decimal0Array), 0),
+        DataType::Int64Decimal(1) => (int_values!(array, Int64Decimal1Array), 1),
+        DataType::Int64Decimal(2) => (int_values!(array, Int64Decimal2Array), 2),
+        DataType::Int64Decimal(3) => (int_values!(array, Int64Decimal3Array), 3),
+        DataType::Int64Decimal(4) => (int_values!(array, Int64Decimal4Array), 4),
+        DataType::Int64Decimal(5) => (int_values!(array, Int64Decimal5Array), 5),
+        DataType::Int64Decimal(10) => (int_values!(array, Int64Decimal10Array), 10),
+        _ => return None,
+    })
+}
+
+/// Rounds half away from zero when the scale is reduced.
+fn rescale(value: i64, scale: i32, target_scale: i32) -> Result<i64, CubeError> {
+    if target_scale >= scale {
+        value
+            .checked_mul(10i64.pow((target_scale - scale) as u32))
+            .ok_or_else(|| {
+                CubeError::user(format!(
+                    "Decimal {} with scale {} is out of range for scale {}",
+                    value, scale, target_scale
+                ))
+            })
+    } else {
+        let divisor = 10i64.pow((scale - target_scale) as u32);
+        Ok(value / divisor + value % divisor * 2 / divisor)
+    }
+}
+
+fn decimal_array(array: &ArrayRef, column: &Column) -> Result<ArrayRef, CubeError> {
+    let target_scale = column.get_column_type().target_scale();
+    let values = if let Some((values, scale)) = decimal_values(array) {
+        values
+            .into_iter()
+            .map(|v| v.map(|v| rescale(v, scale, target_scale)).transpose())
+            .collect::<Result<Vec<_>, _>>()?
+    } else if let DataType::Float64 = array.data_type() {
+        let a = array.as_any().downcast_ref::<Float64Array>().unwrap();
+        let multiplier = 10f64.powi(target_scale);
+        (0..a.len())
+            .map(|i| {
+                if a.is_null(i) {
+                    return Ok(None);
+                }
+                let v = (a.value(i) * multiplier).round();
+                if v.is_finite() && v.abs() < i64::MAX as f64 {
+                    Ok(Some(v as i64))
+                } else {
+                    Err(CubeError::user(format!(
+                        "Float {} is out of range for decimal",
+                        a.value(i)
+                    )))
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?
+    } else {
+        return Err(CubeError::user(format!(
+            "Can't convert {:?} to decimal",
+            array.data_type()
+        )));
+    };
+    scaled_decimal_array(values, target_scale)
+}
+
+fn scaled_decimal_array(
+    values: Vec<Option<i64>>,
+    target_scale: i32,
+) -> Result<ArrayRef, CubeError> {
+    let array: ArrayRef = match target_scale {
+        0 => Arc::new(Int64Decimal0Array::from(values)),
+        1 => Arc::new(Int64Decimal1Array::from(values)),
+        2 => Arc::new(Int64Decimal2Array::from(values)),
+        3 => Arc::new(Int64Decimal3Array::from(values)),
+        4 => Arc::new(Int64Decimal4Array::from(values)),
+        5 => Arc::new(Int64Decimal5Array::from(values)),
+        10 => Arc::new(Int64Decimal10Array::from(values)),
+        x => {
+            return Err(CubeError::internal(format!(
+                "Unsupported decimal scale: {}",
+                x
+            )))
+        }
+    };
+    Ok(array)
+}
+
 #[async_trait]
 pub trait WALDataStore: Send + Sync {
     async fn add_wal(&self, table: IdRow<Table>, data: DataFrame) -> Result<IdRow<WAL>, CubeError>;
+    /// Batches are written to a single WAL. Their columns are matched with table columns by name.
+    async fn add_wal_batches(
+        &self,
+        table: IdRow<Table>,
+        batches: Vec<RecordBatch>,
+    ) -> Result<IdRow<WAL>, CubeError>;
     async fn get_wal(&self, wal_id: u64) -> Result<DataFrame, CubeError>;
+    async fn get_wal_batches(&self, wal_id: u64) -> Result<Vec<RecordBatch>, CubeError>;
     async fn delete_wal(&self, wal_id: u64) -> Result<(), CubeError>;
     fn get_wal_chunk_size(&self) -> usize;
+    /// Uploads WALs left not uploaded by a previous run according to the durability mode
+    /// and drops WALs which files are lost. Returns WALs which are ready to be partitioned.
+    async fn recover_wals(&self) -> Result<Vec<IdRow<WAL>>, CubeError>;
 }
 
 #[automock]
@@ -207,71 +866,223 @@ impl WALStore {
         meta_store: Arc<dyn MetaStore>,
         remote_fs: Arc<dyn RemoteFs>,
         wal_chunk_size: usize,
+        config: Arc<dyn ConfigObj>,
     ) -> Arc<WALStore> {
         let store = WALStore {
             meta_store,
             remote_fs,
             wal_chunk_size,
+            config,
         };
 
         Arc::new(store)
     }
 
     pub fn wal_remote_path(wal_id: u64) -> String {
+        format!("{}.wal.arrow", wal_id)
+    }
+
+    pub fn legacy_wal_remote_path(wal_id: u64) -> String {
         format!("{}.wal", wal_id)
     }
-}
 
-#[async_trait]
-impl WALDataStore for WALStore {
-    async fn add_wal(&self, table: IdRow<Table>, data: DataFrame) -> Result<IdRow<WAL>, CubeError> {
-        let wal = self
+    async fn download_wal(
+        &self,
+        wal_id: u64,
+    ) -> Result<(IdRow<WAL>, IdRow<Table>, String), CubeError> {
+        let wal = self.meta_store.get_wal(wal_id).await?;
+        if !wal.get_row().written() {
+            return Err(CubeError::internal(format!(
+                "Trying to get not written WAL: {:?}",
+                wal
+            )));
+        }
+        let table = self
             .meta_store
-            .create_wal(table.get_id(), data.len())
+            .get_table_by_id(wal.get_row().table_id())
             .await?;
+        let remote_path = if wal.get_row().columnar() {
+            WALStore::wal_remote_path(wal_id)
+        } else {
+            WALStore::legacy_wal_remote_path(wal_id)
+        };
+        self.remote_fs.download_file(&remote_path).await?;
+        let local_file = self.remote_fs.local_file(&remote_path).await?;
+        Ok((wal, table, local_file))
+    }
+
+    async fn save_wal(
+        &self,
+        table_id: u64,
+        batches: Vec<RecordBatch>,
+    ) -> Result<IdRow<WAL>, CubeError> {
+        let row_count = batches.iter().map(|b| b.num_rows()).sum();
+        let wal = self.meta_store.create_wal(table_id, row_count).await?;
         let remote_path = WALStore::wal_remote_path(wal.get_id()).clone();
         let local_file = self.remote_fs.local_file(&remote_path).await?;
-        tokio::task::spawn_blocking(move || -> Result<(), CubeError> {
-            save(local_file, data)?;
-            Ok(())
-        })
-        .await??;
-        // TODO do not upload WAL to speed up S3 uploads
-        // self.remote_fs
-        //     .upload_file(&WALStore::wal_remote_path(wal.get_id()))
-        //     .await?;
-        self.meta_store.wal_uploaded(wal.get_id()).await?;
-        Ok(wal)
+        tokio::task::spawn_blocking(move || save_batches(local_file, batches)).await??;
+        // Not uploaded WALs are partitioned on the router, so workers upload them synchronously
+        let durability = if self.config.metastore_remote_address().is_some() {
+            WalDurability::Sync
+        } else {
+            self.config.wal_durability()
+        };
+        match durability {
+            WalDurability::Local => self.meta_store.wal_written(wal.get_id()).await,
+            WalDurability::Async => {
+                let wal = self.meta_store.wal_written(wal.get_id()).await?;
+                let remote_fs = self.remote_fs.clone();
+                let meta_store = self.meta_store.clone();
+                let wal_id = wal.get_id();
+                tokio::spawn(async move {
+                    if let Err(e) = remote_fs.upload_file(&remote_path).await {
+                        error!("Error during upload of WAL {}: {}", wal_id, e);
+                        return;
+                    }
+                    if let Err(e) = meta_store.wal_uploaded(wal_id).await {
+                        if meta_store.get_wal(wal_id).await.is_err() {
+                            // WAL has been partitioned and deleted during upload
+                            let _ = remote_fs.delete_file(&remote_path).await;
+                        } else {
+                            error!("Error marking WAL {} as uploaded: {}", wal_id, e);
+                        }
+                    }
+                });
+                Ok(wal)
+            }
+            WalDurability::Sync => {
+                self.remote_fs.upload_file(&remote_path).await?;
+                self.meta_store.wal_uploaded(wal.get_id()).await
+            }
+        }
     }
+}
 
-    async fn get_wal(&self, wal_id: u64) -> Result<DataFrame, CubeError> {
-        let wal = self.meta_store.get_wal(wal_id).await?;
-        if !wal.get_row().uploaded() {
+#[async_trait]
+impl WALDataStore for WALStore {
+    async fn add_wal(&self, table: IdRow<Table>, data: DataFrame) -> Result<IdRow<WAL>, CubeError> {
+        let columns = storage_columns(table.get_row());
+        let batch =
+            tokio::task::spawn_blocking(move || rows_to_record_batch(&columns, data.get_rows()))
+                .await??;
+        self.save_wal(table.get_id(), vec![batch]).await
+    }
+
+    async fn add_wal_batches(
+        &self,
+        table: IdRow<Table>,
+        batches: Vec<RecordBatch>,
+    ) -> Result<IdRow<WAL>, CubeError> {
+        if batches.is_empty() {
             return Err(CubeError::internal(format!(
-                "Trying to get not uploaded WAL: {:?}",
-                wal
+                "No batches to write to WAL of table {}",
+                table.get_id()
             )));
         }
-        let remote_path = WALStore::wal_remote_path(wal_id);
-        self.remote_fs.download_file(&remote_path).await?;
-        let local_file = self.remote_fs.local_file(&remote_path).await?;
+        let batches = batches
+            .iter()
+            .map(|b| table_batch(table.get_row(), b))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.save_wal(table.get_id(), batches).await
+    }
+
+    async fn get_wal(&self, wal_id: u64) -> Result<DataFrame, CubeError> {
+        let (wal, table, local_file) = self.download_wal(wal_id).await?;
+        let columnar = wal.get_row().columnar();
+        let columns = table.get_row().get_columns().clone();
         Ok(
             tokio::task::spawn_blocking(move || -> Result<DataFrame, CubeError> {
-                Ok(load::<DataFrame>(local_file)?)
+                if columnar {
+                    let schema = columns_schema(&columns);
+                    let batches = load_batches(local_file)?
+                        .iter()
+                        .map(|b| -> Result<RecordBatch, CubeError> {
+                            let arrays = project_batch(b, &columns, |c| {
+                                table.get_row().storage_name(c).clone()
+                            })?;
+                            Ok(RecordBatch::try_new(schema.clone(), arrays)?)
+                        })
+                        .collect::<Result<Vec<_>, _>>()?;
+                    let rows = batch_to_dataframe(&batches)?.into_rows();
+                    Ok(DataFrame::new(columns, rows))
+                } else {
+                    Ok(load::<DataFrame>(local_file)?)
+                }
+            })
+            .await??,
+        )
+    }
+
+    async fn get_wal_batches(&self, wal_id: u64) -> Result<Vec<RecordBatch>, CubeError> {
+        let (wal, _, local_file) = self.download_wal(wal_id).await?;
+        let columnar = wal.get_row().columnar();
+        Ok(
+            tokio::task::spawn_blocking(move || -> Result<Vec<RecordBatch>, CubeError> {
+                if columnar {
+                    load_batches(local_file)
+                } else {
+                    let data = load::<DataFrame>(local_file)?;
+                    Ok(vec![rows_to_record_batch(
+                        data.get_columns(),
+                        data.get_rows(),
+                    )?])
+                }
             })
             .await??,
         )
     }
 
     async fn delete_wal(&self, wal_id: u64) -> Result<(), CubeError> {
-        let remote_path = WALStore::wal_remote_path(wal_id);
-        self.remote_fs.delete_file(&remote_path).await?;
+        self.remote_fs
+            .delete_file(&WALStore::wal_remote_path(wal_id))
+            .await?;
+        self.remote_fs
+            .delete_file(&WALStore::legacy_wal_remote_path(wal_id))
+            .await?;
         Ok(())
     }
 
     fn get_wal_chunk_size(&self) -> usize {
         self.wal_chunk_size
     }
+
+    async fn recover_wals(&self) -> Result<Vec<IdRow<WAL>>, CubeError> {
+        let mut ready = Vec::new();
+        for wal in self.meta_store.get_all_wals().await?.into_iter() {
+            if !wal.get_row().written() {
+                warn!("Dropping WAL which was never completely written: {:?}", wal);
+                self.meta_store.delete_wal(wal.get_id()).await?;
+                continue;
+            }
+            if wal.get_row().uploaded() {
+                ready.push(wal);
+                continue;
+            }
+            let remote_path = WALStore::wal_remote_path(wal.get_id());
+            let local_file = self.remote_fs.local_file(&remote_path).await?;
+            if tokio::fs::metadata(&local_file).await.is_err() {
+                error!(
+                    "Dropping WAL which file {} is lost before upload: {:?}",
+                    local_file, wal
+                );
+                self.meta_store.delete_wal(wal.get_id()).await?;
+                continue;
+            }
+            if self.config.wal_durability() == WalDurability::Local {
+                ready.push(wal);
+                continue;
+            }
+            match self.remote_fs.upload_file(&remote_path).await {
+                Ok(()) => ready.push(self.meta_store.wal_uploaded(wal.get_id()).await?),
+                Err(e) => {
+                    // Still can be partitioned from the local file
+                    error!("Error during upload of WAL {}: {}", wal.get_id(), e);
+                    ready.push(wal);
+                }
+            }
+        }
+        Ok(ready)
+    }
 }
 
 impl ChunkStore {
@@ -309,18 +1120,13 @@ impl ChunkDataStore for ChunkStore {
     async fn partition(&self, wal_id: u64) -> Result<(), CubeError> {
         let wal = self.meta_store.get_wal(wal_id).await?;
         let table_id = wal.get_row().table_id();
-        let data = self.wal_store.get_wal(wal_id).await?;
+        let batches = self.wal_store.get_wal_batches(wal_id).await?;
         let indexes = self.meta_store.get_table_indexes(table_id).await?;
         let mut new_chunks = Vec::new();
         for index in indexes.iter() {
-            new_chunks.append(
-                &mut self
-                    .partition_data_frame(
-                        index.get_id(),
-                        data.remap_columns(index.get_row().columns().clone())?,
-                    )
-                    .await?,
-            ); // TODO dataframe clone
+            for batch in batches.iter() {
+                new_chunks.append(&mut self.partition_batch(index.clone(), batch).await?);
+            }
         }
 
         self.meta_store
@@ -346,12 +1152,31 @@ impl ChunkDataStore for ChunkStore {
             .meta_store
             .get_chunks_by_partition(partition_id, false)
             .await?;
+        let index = self
+            .meta_store
+            .index_table()
+            .row_by_id_or_not_found(partition.get_row().get_index_id())
+            .await?;
         let mut new_chunks = Vec::new();
         let mut old_chunks = Vec::new();
+        let mut deleted_row_count = 0;
         for chunk in chunks.into_iter() {
             let chunk_id = chunk.get_id();
             old_chunks.push(chunk_id);
-            let data = self.get_chunk(chunk).await?;
+            // Chunks not compacted before the partition was deactivated still have to drop
+            // rows deleted after they were created
+            let deletes = partition
+                .get_row()
+                .pending_deletes()
+                .iter()
+                .filter(|d| d.max_chunk_id >= chunk_id)
+                .cloned()
+                .collect::<Vec<_>>();
+            let mut data = self.get_chunk(chunk).await?;
+            let row_count = data.get_rows().len();
+            data.mut_rows()
+                .retain(|r| !is_deleted(&deletes, index.get_row(), r));
+            deleted_row_count += (row_count - data.get_rows().len()) as u64;
             new_chunks.append(
                 &mut self
                     .partition_data_frame(partition.get_row().get_index_id(), data)
@@ -363,6 +1188,7 @@ impl ChunkDataStore for ChunkStore {
             .swap_chunks(
                 old_chunks,
                 new_chunks.into_iter().map(|c| c.get_id()).collect(),
+                deleted_row_count,
             )
             .await?;
 
@@ -433,10 +1259,12 @@ mod tests {
     use crate::config::Config;
     use crate::metastore::RocksMetaStore;
     use crate::remotefs::LocalDirRemoteFs;
+    use crate::table::TimestampValue;
     use crate::{metastore::ColumnType, table::TableValue};
     use rocksdb::{Options, DB};
     use std::fs;
     use std::path::PathBuf;
+    use std::time::Duration;
 
     #[actix_rt::test]
     async fn create_wal_test() {
@@ -457,6 +1285,7 @@ mod tests {
                 RocksMetaStore::new(path, remote_fs.clone(), config.config_obj()),
                 remote_fs.clone(),
                 10,
+                config.config_obj(),
             );
 
             let col = vec![
@@ -515,31 +1344,507 @@ mod tests {
     }
 
     #[actix_rt::test]
-    async fn create_chunk_test() {
-        let config = Config::test("create_chunk_test");
-        let path = "/tmp/test_create_chunk";
-        let wal_store_path = path.to_string() + &"_store_wal".to_string();
-        let wal_remote_store_path = path.to_string() + &"_remote_store_wal".to_string();
-        let chunk_store_path = path.to_string() + &"_store_chunk".to_string();
-        let chunk_remote_store_path = path.to_string() + &"_remote_store_chunk".to_string();
-
+    async fn sync_wal_upload_test() {
+        let config = Config::test("sync_wal_upload_test").update_config(|mut c| {
+            c.wal_durability = WalDurability::Sync;
+            c
+        });
+        let path = "/tmp/test_sync_wal_upload";
+        let remote_store_path = path.to_string() + &"_remote_store".to_string();
+        let local_store_path = path.to_string() + &"_local_store".to_string();
         let _ = DB::destroy(&Options::default(), path);
-        let _ = fs::remove_dir_all(wal_store_path.clone());
-        let _ = fs::remove_dir_all(wal_remote_store_path.clone());
-        let _ = fs::remove_dir_all(chunk_store_path.clone());
-        let _ = fs::remove_dir_all(chunk_remote_store_path.clone());
+        let _ = fs::remove_dir_all(remote_store_path.clone());
+        let _ = fs::remove_dir_all(local_store_path.clone());
+
         {
             let remote_fs = LocalDirRemoteFs::new(
-                PathBuf::from(chunk_store_path.clone()),
-                PathBuf::from(chunk_remote_store_path.clone()),
+                PathBuf::from(remote_store_path.clone()),
+                PathBuf::from(local_store_path.clone()),
             );
             let meta_store = RocksMetaStore::new(path, remote_fs.clone(), config.config_obj());
-            let wal_store = WALStore::new(meta_store.clone(), remote_fs.clone(), 10);
-            let chunk_store =
-                ChunkStore::new(meta_store.clone(), remote_fs.clone(), wal_store.clone(), 10);
-
-            let col = vec![
-                Column::new("foo_int".to_string(), ColumnType::Int, 0),
+            let store = WALStore::new(
+                meta_store.clone(),
+                remote_fs.clone(),
+                10,
+                config.config_obj(),
+            );
+
+            let col = vec![Column::new("foo_int".to_string(), ColumnType::Int, 0)];
+            let rows = (0..5)
+                .map(|i| Row::new(vec![TableValue::Int(i)]))
+                .collect::<Vec<_>>();
+
+            meta_store
+                .create_schema("s".to_string(), false)
+                .await
+                .unwrap();
+            let table = meta_store
+                .create_table(
+                    "s".to_string(),
+                    "foo".to_string(),
+                    col.clone(),
+                    None,
+                    None,
+                    Vec::new(),
+                )
+                .await
+                .unwrap();
+            let wal = store
+                .add_wal(table, DataFrame::new(col.clone(), rows.clone()))
+                .await
+                .unwrap();
+
+            let file_name = WALStore::wal_remote_path(wal.get_id());
+            assert!(PathBuf::from(remote_store_path.clone())
+                .join(&file_name)
+                .exists());
+
+            // Local copy is lost with the node
+            fs::remove_file(PathBuf::from(local_store_path.clone()).join(&file_name)).unwrap();
+            let restored_wal = store.get_wal(wal.get_id()).await.unwrap();
+            assert_eq!(restored_wal, DataFrame::new(col, rows));
+        }
+        let _ = DB::destroy(&Options::default(), path);
+        let _ = fs::remove_dir_all(remote_store_path.clone());
+        let _ = fs::remove_dir_all(local_store_path.clone());
+    }
+
+    #[test]
+    fn record_batch_builder() {
+        let columns = vec![
+            Column::new("id".to_string(), ColumnType::Int, 0),
+            Column::new("city".to_string(), ColumnType::String, 1),
+            Column::new(
+                "amount".to_string(),
+                ColumnType::Decimal {
+                    scale: 2,
+                    precision: 10,
+                },
+                2,
+            ),
+            Column::new("created".to_string(), ColumnType::Timestamp, 3),
+        ];
+        let rows = (0..5)
+            .map(|i| {
+                Row::new(vec![
+                    TableValue::Int(i),
+                    if i % 2 == 0 {
+                        TableValue::String(format!("City {}", i))
+                    } else {
+                        TableValue::Null
+                    },
+                    TableValue::Decimal(format!("{}.5", i)),
+                    TableValue::Timestamp(TimestampValue::new(i * 1000)),
+                ])
+            })
+            .collect::<Vec<_>>();
+        let mut builder = RecordBatchBuilder::new(columns.clone(), 3);
+        for row in rows.iter().take(3) {
+            builder.append(row).unwrap();
+        }
+        assert_eq!(builder.len(), 3);
+        let first = builder.flush().unwrap();
+        assert_eq!(builder.len(), 0);
+        for row in rows.iter().skip(3) {
+            builder.append(row).unwrap();
+        }
+        let second = builder.flush().unwrap();
+        assert_eq!(
+            batch_to_dataframe(&vec![first, second])
+                .unwrap()
+                .into_rows(),
+            batch_to_dataframe(&vec![rows_to_record_batch(&columns, &rows).unwrap()])
+                .unwrap()
+                .into_rows()
+        );
+
+        let mut builder = RecordBatchBuilder::new(columns, 1);
+        assert!(builder
+            .append(&Row::new(vec![
+                TableValue::String("1".to_string()),
+                TableValue::Null,
+                TableValue::Null,
+                TableValue::Null,
+            ]))
+            .is_err());
+    }
+
+    async fn create_int_table(meta_store: &RocksMetaStore) -> (Vec<Column>, IdRow<Table>) {
+        let col = vec![Column::new("foo_int".to_string(), ColumnType::Int, 0)];
+        meta_store
+            .create_schema("s".to_string(), false)
+            .await
+            .unwrap();
+        let table = meta_store
+            .create_table(
+                "s".to_string(),
+                "foo".to_string(),
+                col.clone(),
+                None,
+                None,
+                Vec::new(),
+            )
+            .await
+            .unwrap();
+        (col, table)
+    }
+
+    fn int_rows(count: i64) -> Vec<Row> {
+        (0..count)
+            .map(|i| Row::new(vec![TableValue::Int(i)]))
+            .collect::<Vec<_>>()
+    }
+
+    #[actix_rt::test]
+    async fn async_wal_upload_test() {
+        let config = Config::test("async_wal_upload_test").update_config(|mut c| {
+            c.wal_durability = WalDurability::Async;
+            c
+        });
+        let path = "/tmp/test_async_wal_upload";
+        let remote_store_path = path.to_string() + &"_remote_store".to_string();
+        let local_store_path = path.to_string() + &"_local_store".to_string();
+        let _ = DB::destroy(&Options::default(), path);
+        let _ = fs::remove_dir_all(remote_store_path.clone());
+        let _ = fs::remove_dir_all(local_store_path.clone());
+
+        {
+            let remote_fs = LocalDirRemoteFs::new(
+                PathBuf::from(remote_store_path.clone()),
+                PathBuf::from(local_store_path.clone()),
+            );
+            let meta_store = RocksMetaStore::new(path, remote_fs.clone(), config.config_obj());
+            let store = WALStore::new(
+                meta_store.clone(),
+                remote_fs.clone(),
+                10,
+                config.config_obj(),
+            );
+            let (col, table) = create_int_table(&meta_store).await;
+            let wal = store
+                .add_wal(table, DataFrame::new(col.clone(), int_rows(5)))
+                .await
+                .unwrap();
+            assert!(wal.get_row().written());
+
+            let mut wal = meta_store.get_wal(wal.get_id()).await.unwrap();
+            for _ in 0..50 {
+                if wal.get_row().uploaded() {
+                    break;
+                }
+                tokio::time::delay_for(Duration::from_millis(100)).await;
+                wal = meta_store.get_wal(wal.get_id()).await.unwrap();
+            }
+            assert!(wal.get_row().uploaded());
+            assert!(PathBuf::from(remote_store_path.clone())
+                .join(WALStore::wal_remote_path(wal.get_id()))
+                .exists());
+        }
+        let _ = DB::destroy(&Options::default(), path);
+        let _ = fs::remove_dir_all(remote_store_path.clone());
+        let _ = fs::remove_dir_all(local_store_path.clone());
+    }
+
+    #[actix_rt::test]
+    async fn failed_wal_upload_test() {
+        let config = Config::test("failed_wal_upload_test").update_config(|mut c| {
+            c.wal_durability = WalDurability::Async;
+            c
+        });
+        let path = "/tmp/test_failed_wal_upload";
+        let remote_store_path = path.to_string() + &"_remote_store".to_string();
+        let local_store_path = path.to_string() + &"_local_store".to_string();
+        let _ = DB::destroy(&Options::default(), path);
+        let _ = fs::remove_file(remote_store_path.clone());
+        let _ = fs::remove_dir_all(local_store_path.clone());
+
+        {
+            // Uploads fail as the remote dir can't be created
+            fs::write(remote_store_path.clone(), "").unwrap();
+            let remote_fs = LocalDirRemoteFs::new(
+                PathBuf::from(remote_store_path.clone()),
+                PathBuf::from(local_store_path.clone()),
+            );
+            let meta_store = RocksMetaStore::new(path, remote_fs.clone(), config.config_obj());
+            let store = WALStore::new(
+                meta_store.clone(),
+                remote_fs.clone(),
+                10,
+                config.config_obj(),
+            );
+            let (col, table) = create_int_table(&meta_store).await;
+            let wal = store
+                .add_wal(table.clone(), DataFrame::new(col.clone(), int_rows(5)))
+                .await
+                .unwrap();
+
+            tokio::time::delay_for(Duration::from_millis(500)).await;
+            let wal = meta_store.get_wal(wal.get_id()).await.unwrap();
+            assert!(wal.get_row().written());
+            assert!(!wal.get_row().uploaded());
+            // Can still be partitioned from the local file
+            let restored_wal = store.get_wal(wal.get_id()).await.unwrap();
+            assert_eq!(restored_wal, DataFrame::new(col.clone(), int_rows(5)));
+
+            let sync_config = config.update_config(|mut c| {
+                c.wal_durability = WalDurability::Sync;
+                c
+            });
+            let sync_store = WALStore::new(
+                meta_store.clone(),
+                remote_fs.clone(),
+                10,
+                sync_config.config_obj(),
+            );
+            assert!(sync_store
+                .add_wal(table, DataFrame::new(col.clone(), int_rows(5)))
+                .await
+                .is_err());
+            let wals = meta_store.get_all_wals().await.unwrap();
+            assert_eq!(wals.len(), 2);
+            assert!(!wals[1].get_row().written());
+        }
+        let _ = DB::destroy(&Options::default(), path);
+        let _ = fs::remove_file(remote_store_path.clone());
+        let _ = fs::remove_dir_all(local_store_path.clone());
+    }
+
+    #[actix_rt::test]
+    async fn recover_wals_test() {
+        let config = Config::test("recover_wals_test").update_config(|mut c| {
+            c.wal_durability = WalDurability::Local;
+            c
+        });
+        let path = "/tmp/test_recover_wals";
+        let remote_store_path = path.to_string() + &"_remote_store".to_string();
+        let local_store_path = path.to_string() + &"_local_store".to_string();
+        let _ = DB::destroy(&Options::default(), path);
+        let _ = fs::remove_dir_all(remote_store_path.clone());
+        let _ = fs::remove_dir_all(local_store_path.clone());
+
+        {
+            let remote_fs = LocalDirRemoteFs::new(
+                PathBuf::from(remote_store_path.clone()),
+                PathBuf::from(local_store_path.clone()),
+            );
+            let meta_store = RocksMetaStore::new(path, remote_fs.clone(), config.config_obj());
+            let store = WALStore::new(
+                meta_store.clone(),
+                remote_fs.clone(),
+                10,
+                config.config_obj(),
+            );
+            let (col, table) = create_int_table(&meta_store).await;
+            let wal = store
+                .add_wal(table.clone(), DataFrame::new(col.clone(), int_rows(5)))
+                .await
+                .unwrap();
+            assert!(wal.get_row().written());
+            assert!(!wal.get_row().uploaded());
+            let lost_wal = store
+                .add_wal(table.clone(), DataFrame::new(col.clone(), int_rows(3)))
+                .await
+                .unwrap();
+            fs::remove_file(
+                PathBuf::from(local_store_path.clone())
+                    .join(WALStore::wal_remote_path(lost_wal.get_id())),
+            )
+            .unwrap();
+            // Node died while writing the file
+            meta_store.create_wal(table.get_id(), 2).await.unwrap();
+
+            let recovered = store.recover_wals().await.unwrap();
+            assert_eq!(recovered, vec![wal.clone()]);
+            assert_eq!(meta_store.get_all_wals().await.unwrap(), vec![wal.clone()]);
+
+            let async_config = config.update_config(|mut c| {
+                c.wal_durability = WalDurability::Async;
+                c
+            });
+            let async_store = WALStore::new(
+                meta_store.clone(),
+                remote_fs.clone(),
+                10,
+                async_config.config_obj(),
+            );
+            let recovered = async_store.recover_wals().await.unwrap();
+            assert_eq!(recovered.len(), 1);
+            assert!(recovered[0].get_row().uploaded());
+            assert!(PathBuf::from(remote_store_path.clone())
+                .join(WALStore::wal_remote_path(wal.get_id()))
+                .exists());
+        }
+        let _ = DB::destroy(&Options::default(), path);
+        let _ = fs::remove_dir_all(remote_store_path.clone());
+        let _ = fs::remove_dir_all(local_store_path.clone());
+    }
+
+    #[actix_rt::test]
+    async fn worker_wal_upload_test() {
+        let config = Config::test("worker_wal_upload_test").update_config(|mut c| {
+            c.wal_durability = WalDurability::Local;
+            c.metastore_remote_address = Some("router:9999".to_string());
+            c
+        });
+        let path = "/tmp/test_worker_wal_upload";
+        let remote_store_path = path.to_string() + &"_remote_store".to_string();
+        let local_store_path = path.to_string() + &"_local_store".to_string();
+        let _ = DB::destroy(&Options::default(), path);
+        let _ = fs::remove_dir_all(remote_store_path.clone());
+        let _ = fs::remove_dir_all(local_store_path.clone());
+
+        {
+            let remote_fs = LocalDirRemoteFs::new(
+                PathBuf::from(remote_store_path.clone()),
+                PathBuf::from(local_store_path.clone()),
+            );
+            let meta_store = RocksMetaStore::new(path, remote_fs.clone(), config.config_obj());
+            let store = WALStore::new(
+                meta_store.clone(),
+                remote_fs.clone(),
+                10,
+                config.config_obj(),
+            );
+            let (col, table) = create_int_table(&meta_store).await;
+            let wal = store
+                .add_wal(table, DataFrame::new(col, int_rows(5)))
+                .await
+                .unwrap();
+            assert!(wal.get_row().uploaded());
+            assert!(PathBuf::from(remote_store_path.clone())
+                .join(WALStore::wal_remote_path(wal.get_id()))
+                .exists());
+        }
+        let _ = DB::destroy(&Options::default(), path);
+        let _ = fs::remove_dir_all(remote_store_path.clone());
+        let _ = fs::remove_dir_all(local_store_path.clone());
+    }
+
+    #[actix_rt::test]
+    async fn columnar_wal_test() {
+        let config = Config::test("columnar_wal_test");
+        let path = "/tmp/test_columnar_wal";
+        let store_path = path.to_string() + &"_store".to_string();
+        let remote_store_path = path.to_string() + &"_remote_store".to_string();
+        let _ = DB::destroy(&Options::default(), path);
+        let _ = fs::remove_dir_all(store_path.clone());
+        let _ = fs::remove_dir_all(remote_store_path.clone());
+
+        {
+            let remote_fs = LocalDirRemoteFs::new(
+                PathBuf::from(store_path.clone()),
+                PathBuf::from(remote_store_path.clone()),
+            );
+            let meta_store = RocksMetaStore::new(path, remote_fs.clone(), config.config_obj());
+            let store = WALStore::new(
+                meta_store.clone(),
+                remote_fs.clone(),
+                10,
+                config.config_obj(),
+            );
+
+            let col = vec![
+                Column::new("id".to_string(), ColumnType::Int, 0),
+                Column::new("name".to_string(), ColumnType::String, 1),
+                Column::new(
+                    "amount".to_string(),
+                    ColumnType::Decimal {
+                        scale: 2,
+                        precision: 10,
+                    },
+                    2,
+                ),
+                Column::new("ratio".to_string(), ColumnType::Float, 3),
+                Column::new("created".to_string(), ColumnType::Timestamp, 4),
+                Column::new("active".to_string(), ColumnType::Boolean, 5),
+                Column::new("data".to_string(), ColumnType::Bytes, 6),
+            ];
+            let rows = vec![
+                Row::new(vec![
+                    TableValue::Int(1),
+                    TableValue::String("foo".to_string()),
+                    TableValue::Decimal("1.5".to_string()),
+                    TableValue::Float("2.25".to_string()),
+                    TableValue::Timestamp(TimestampValue::new(1_000_000_000)),
+                    TableValue::Boolean(true),
+                    TableValue::Bytes(vec![1, 2, 3]),
+                ]),
+                Row::new(vec![
+                    TableValue::Int(2),
+                    TableValue::Null,
+                    TableValue::Null,
+                    TableValue::Null,
+                    TableValue::Null,
+                    TableValue::Null,
+                    TableValue::Null,
+                ]),
+            ];
+
+            meta_store
+                .create_schema("s".to_string(), false)
+                .await
+                .unwrap();
+            let table = meta_store
+                .create_table(
+                    "s".to_string(),
+                    "foo".to_string(),
+                    col.clone(),
+                    None,
+                    None,
+                    Vec::new(),
+                )
+                .await
+                .unwrap();
+            let wal = store
+                .add_wal(table.clone(), DataFrame::new(col.clone(), rows.clone()))
+                .await
+                .unwrap();
+            assert!(wal.get_row().columnar());
+
+            let batches = store.get_wal_batches(wal.get_id()).await.unwrap();
+            assert_eq!(batches.len(), 1);
+            assert_eq!(batches[0].num_columns(), 7);
+            assert_eq!(batches[0].num_rows(), 2);
+
+            let restored_wal = store.get_wal(wal.get_id()).await.unwrap();
+            assert_eq!(restored_wal, DataFrame::new(col.clone(), rows));
+        }
+        let _ = DB::destroy(&Options::default(), path);
+        let _ = fs::remove_dir_all(store_path.clone());
+        let _ = fs::remove_dir_all(remote_store_path.clone());
+    }
+
+    #[actix_rt::test]
+    async fn create_chunk_test() {
+        let config = Config::test("create_chunk_test");
+        let path = "/tmp/test_create_chunk";
+        let wal_store_path = path.to_string() + &"_store_wal".to_string();
+        let wal_remote_store_path = path.to_string() + &"_remote_store_wal".to_string();
+        let chunk_store_path = path.to_string() + &"_store_chunk".to_string();
+        let chunk_remote_store_path = path.to_string() + &"_remote_store_chunk".to_string();
+
+        let _ = DB::destroy(&Options::default(), path);
+        let _ = fs::remove_dir_all(wal_store_path.clone());
+        let _ = fs::remove_dir_all(wal_remote_store_path.clone());
+        let _ = fs::remove_dir_all(chunk_store_path.clone());
+        let _ = fs::remove_dir_all(chunk_remote_store_path.clone());
+        {
+            let remote_fs = LocalDirRemoteFs::new(
+                PathBuf::from(chunk_store_path.clone()),
+                PathBuf::from(chunk_remote_store_path.clone()),
+            );
+            let meta_store = RocksMetaStore::new(path, remote_fs.clone(), config.config_obj());
+            let wal_store = WALStore::new(
+                meta_store.clone(),
+                remote_fs.clone(),
+                10,
+                config.config_obj(),
+            );
+            let chunk_store =
+                ChunkStore::new(meta_store.clone(), remote_fs.clone(), wal_store.clone(), 10);
+
+            let col = vec![
+                Column::new("foo_int".to_string(), ColumnType::Int, 0),
                 Column::new("foo".to_string(), ColumnType::String, 1),
                 Column::new("boo".to_string(), ColumnType::String, 2),
             ];
@@ -593,7 +1898,7 @@ mod tests {
                 .await
                 .unwrap();
             meta_store
-                .swap_chunks(Vec::new(), vec![chunk.get_id()])
+                .swap_chunks(Vec::new(), vec![chunk.get_id()], 0)
                 .await
                 .unwrap();
             let chunk = meta_store.get_chunk(1).await.unwrap();
@@ -608,6 +1913,112 @@ mod tests {
         let _ = fs::remove_dir_all(chunk_store_path.clone());
         let _ = fs::remove_dir_all(chunk_remote_store_path.clone());
     }
+
+    #[actix_rt::test]
+    async fn partition_columnar_wal_test() {
+        let config = Config::test("partition_columnar_wal_test");
+        let path = "/tmp/test_partition_columnar_wal";
+        let store_path = path.to_string() + &"_store".to_string();
+        let remote_store_path = path.to_string() + &"_remote_store".to_string();
+        let _ = DB::destroy(&Options::default(), path);
+        let _ = fs::remove_dir_all(store_path.clone());
+        let _ = fs::remove_dir_all(remote_store_path.clone());
+        {
+            let remote_fs = LocalDirRemoteFs::new(
+                PathBuf::from(store_path.clone()),
+                PathBuf::from(remote_store_path.clone()),
+            );
+            let meta_store = RocksMetaStore::new(path, remote_fs.clone(), config.config_obj());
+            let wal_store = WALStore::new(
+                meta_store.clone(),
+                remote_fs.clone(),
+                10,
+                config.config_obj(),
+            );
+            let chunk_store =
+                ChunkStore::new(meta_store.clone(), remote_fs.clone(), wal_store.clone(), 10);
+
+            let col = vec![
+                Column::new(
+                    "amount".to_string(),
+                    ColumnType::Decimal {
+                        scale: 2,
+                        precision: 10,
+                    },
+                    0,
+                ),
+                Column::new("name".to_string(), ColumnType::String, 1),
+                Column::new("created".to_string(), ColumnType::Timestamp, 2),
+            ];
+            let row = |amount: Option<&str>, name: &str| {
+                Row::new(vec![
+                    amount
+                        .map(|a| TableValue::Decimal(a.to_string()))
+                        .unwrap_or(TableValue::Null),
+                    TableValue::String(name.to_string()),
+                    TableValue::Timestamp(TimestampValue::new(1_000_000_000)),
+                ])
+            };
+
+            meta_store
+                .create_schema("s".to_string(), false)
+                .await
+                .unwrap();
+            let table = meta_store
+                .create_table(
+                    "s".to_string(),
+                    "foo".to_string(),
+                    col.clone(),
+                    None,
+                    None,
+                    Vec::new(),
+                )
+                .await
+                .unwrap();
+            let wal = wal_store
+                .add_wal(
+                    table.clone(),
+                    DataFrame::new(
+                        col.clone(),
+                        vec![
+                            row(Some("10"), "c"),
+                            row(Some("9.5"), "b"),
+                            row(None, "a"),
+                            row(Some("-1.25"), "d"),
+                        ],
+                    ),
+                )
+                .await
+                .unwrap();
+            chunk_store.partition(wal.get_id()).await.unwrap();
+
+            let index = meta_store.get_default_index(table.get_id()).await.unwrap();
+            let partitions = meta_store
+                .get_active_partitions_by_index_id(index.get_id())
+                .await
+                .unwrap();
+            let chunks = meta_store
+                .get_chunks_by_partition(partitions[0].get_id(), false)
+                .await
+                .unwrap();
+            assert_eq!(chunks.len(), 1);
+            assert_eq!(chunks[0].get_row().get_row_count(), 4);
+
+            let restored_chunk = chunk_store.get_chunk(chunks[0].clone()).await.unwrap();
+            assert_eq!(
+                restored_chunk.data,
+                vec![
+                    row(None, "a"),
+                    row(Some("-1.25"), "d"),
+                    row(Some("9.50"), "b"),
+                    row(Some("10.00"), "c"),
+                ]
+            );
+        }
+        let _ = DB::destroy(&Options::default(), path);
+        let _ = fs::remove_dir_all(store_path.clone());
+        let _ = fs::remove_dir_all(remote_store_path.clone());
+    }
 }
 
 impl ChunkStore {
@@ -666,6 +2077,72 @@ impl ChunkStore {
         Ok(new_chunks)
     }
 
+    async fn partition_batch(
+        &self,
+        index: IdRow<Index>,
+        batch: &RecordBatch,
+    ) -> Result<Vec<IdRow<Chunk>>, CubeError> {
+        let partitions = self
+            .meta_store
+            .get_active_partitions_by_index_id(index.get_id())
+            .await?;
+        let key_size = index.get_row().sort_key_size() as usize;
+        let columns = index.get_row().get_columns().clone();
+        let num_rows = batch.num_rows();
+
+        let arrays = project_batch(batch, &columns, |c| index.get_row().storage_name(c).clone())?;
+        let keys = &arrays[0..key_size];
+        let mut order = (0..num_rows as u32).collect::<Vec<_>>();
+        order.sort_by(|a, b| compare_keys(keys, *a as usize, keys, *b as usize));
+        let indices = UInt32Array::from(order.clone());
+        let mut sorted = Vec::with_capacity(arrays.len());
+        for array in arrays.iter() {
+            sorted.push(take(array.as_ref(), &indices, None)?);
+        }
+
+        let key_columns = columns[0..key_size].to_vec();
+        let bound_position = |bound: &Row| -> Result<usize, CubeError> {
+            let bound = rows_to_record_batch(&key_columns, &[bound.clone()])?;
+            Ok(lower_bound(keys, &order, bound.columns()))
+        };
+
+        let mut new_chunks = Vec::new();
+        let mut written_rows = 0;
+
+        for partition in partitions.into_iter() {
+            let start = match partition.get_row().get_min_val() {
+                Some(min) => bound_position(min)?,
+                None => 0,
+            };
+            let end = match partition.get_row().get_max_val() {
+                Some(max) => bound_position(max)?,
+                None => num_rows,
+            };
+            if end > start {
+                written_rows += end - start;
+                let slice = sorted
+                    .iter()
+                    .map(|a| a.slice(start, end - start))
+                    .collect::<Vec<_>>();
+                new_chunks.push(
+                    self.add_chunk_arrays(index.clone(), partition, slice)
+                        .await?,
+                );
+            }
+        }
+
+        if written_rows != num_rows {
+            return Err(CubeError::internal(format!(
+                "Active partitions of index {} cover {} of {} rows",
+                index.get_id(),
+                written_rows,
+                num_rows
+            )));
+        }
+
+        Ok(new_chunks)
+    }
+
     async fn add_chunk(
         &self,
         index: IdRow<Index>,
@@ -695,4 +2172,28 @@ impl ChunkStore {
             .await?;
         Ok(chunk)
     }
+
+    /// Same as `add_chunk` for sorted arrays ordered as index columns.
+    async fn add_chunk_arrays(
+        &self,
+        index: IdRow<Index>,
+        partition: IdRow<Partition>,
+        arrays: Vec<ArrayRef>,
+    ) -> Result<IdRow<Chunk>, CubeError> {
+        let num_rows = arrays.first().map(|a| a.len()).unwrap_or(0);
+        let chunk = self
+            .meta_store
+            .create_chunk(partition.get_id(), num_rows)
+            .await?;
+        trace!("New chunk allocated during partitioning: {:?}", chunk);
+        let remote_path = ChunkStore::chunk_file_name(chunk.clone()).clone();
+        let local_file = self.remote_fs.local_file(&remote_path).await?;
+        tokio::task::spawn_blocking(move || -> Result<(), CubeError> {
+            let parquet = ParquetTableStore::new(index.get_row().clone(), 16384); // TODO config
+            parquet.write_arrays(&local_file, &arrays)
+        })
+        .await??;
+        self.remote_fs.upload_file(&remote_path).await?;
+        Ok(chunk)
+    }
 }
@@ -1,3 +1,4 @@
+use crate::queryplanner::partition_filter::RowGroupFilter;
 use crate::CubeError;
 use chrono::{SecondsFormat, TimeZone, Utc};
 use serde::{Deserialize, Serialize};
@@ -135,6 +136,7 @@ pub trait TableStore {
         &self,
         file: &str,
         columns: &Vec<crate::metastore::Column>,
+        row_group_filter: Option<&RowGroupFilter>,
         limit: usize,
     ) -> Result<Vec<Row>, CubeError>;
 
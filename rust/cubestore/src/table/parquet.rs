@@ -1,14 +1,26 @@
 use super::TimestampValue;
 use crate::metastore::{Column, ColumnType, Index};
+use crate::queryplanner::partition_filter::RowGroupFilter;
 use crate::table::{Row, RowSortKey, TableStore, TableValue};
 use crate::CubeError;
+use arrow::array::{
+    Array, ArrayRef, BinaryArray, BooleanArray, Float64Array, Int64Array, Int64Decimal0Array,
+    Int64Decimal10Array, Int64Decimal1Array, Int64Decimal2Array, Int64Decimal3Array,
+    Int64Decimal4Array, Int64Decimal5Array, StringArray, TimestampMicrosecondArray,
+};
+use arrow::datatypes::{DataType, TimeUnit};
 use parquet::column::reader::ColumnReader;
 use parquet::column::writer::ColumnWriter;
 use parquet::data_type::*;
+use parquet::errors::ParquetError;
+use parquet::file::metadata::{FileMetaData, ParquetMetaData};
 use parquet::file::properties::{WriterProperties, WriterVersion};
-use parquet::file::reader::{FileReader, SerializedFileReader};
+use parquet::file::reader::{FileReader, RowGroupReader, SerializedFileReader};
+use parquet::file::statistics::Statistics;
 use parquet::file::writer::{FileWriter, SerializedFileWriter};
+use parquet::record::reader::RowIter;
 use parquet::schema::types;
+use parquet_format::KeyValue;
 use std::cmp::{max, min};
 use std::fs::File;
 
@@ -27,7 +39,6 @@ pub struct RowParquetWriter {
     parquet_writer: SerializedFileWriter<File>,
     buffer: Vec<Row>,
     row_group_size: usize,
-    sort_key_size: u64,
 }
 
 enum ColumnAccessor {
@@ -39,7 +50,7 @@ enum ColumnAccessor {
 
 pub struct RowParquetReader<'a> {
     pub parquet_reader: SerializedFileReader<File>,
-    column_with_buffer: Vec<(&'a Column, usize, ColumnAccessor, Option<Vec<i16>>)>,
+    column_with_buffer: Vec<(&'a Column, Option<usize>, ColumnAccessor, Option<Vec<i16>>)>,
 }
 
 impl TableStore for ParquetTableStore {
@@ -50,40 +61,21 @@ impl TableStore for ParquetTableStore {
         rows: Vec<Row>,
         sort_key_size: u64,
     ) -> Result<Vec<(u64, (Row, Row))>, CubeError> {
-        let mut writers = Vec::new();
-        for f in dest_files.iter() {
-            writers.push(RowParquetWriter::open(
-                &self.table,
-                f,
-                self.row_group_size,
-                sort_key_size,
-            )?);
-        }
-        if source_file.is_none() {
-            let mut split_writer = SplitRowParquetWriter::new(writers, rows.len(), sort_key_size);
-            split_writer.write_rows(rows.as_slice())?;
-            return Ok(split_writer.close()?);
-        }
-
-        let mut reader = RowParquetReader::open(&self.table, source_file.unwrap(), None)?;
-        let mut right_position = 0;
-        let total_row_number =
-            reader.parquet_reader.metadata().file_metadata().num_rows() as usize + rows.len();
-        let mut split_writer = SplitRowParquetWriter::new(writers, total_row_number, sort_key_size);
-
-        for row_group_index in 0..reader.parquet_reader.num_row_groups() {
-            let read_rows = reader.read_rows(row_group_index)?;
-            let (new_pos, to_write) =
-                ParquetTableStore::merge_sort(read_rows, &rows, right_position, sort_key_size);
-            split_writer.write_rows(to_write.as_slice())?;
-            right_position = new_pos;
-        }
-
-        if right_position < rows.len() {
-            split_writer.write_rows(&rows[right_position..rows.len()])?;
+        let mut total_row_number = rows.len();
+        if let Some(source_file) = source_file {
+            total_row_number += SerializedFileReader::new(File::open(source_file)?)?
+                .metadata()
+                .file_metadata()
+                .num_rows() as usize;
         }
-
-        Ok(split_writer.close()?)
+        self.merge_retained_rows(
+            source_file,
+            dest_files,
+            rows,
+            total_row_number,
+            sort_key_size,
+            &|_| true,
+        )
     }
 
     fn read_rows(&self, file: &str) -> Result<Vec<Row>, CubeError> {
@@ -100,11 +92,20 @@ impl TableStore for ParquetTableStore {
         &self,
         file: &str,
         columns: &Vec<Column>,
+        row_group_filter: Option<&RowGroupFilter>,
         limit: usize,
     ) -> Result<Vec<Row>, CubeError> {
         let mut result = Vec::<Row>::new();
         let mut reader = RowParquetReader::open(&self.table, file, Some(columns))?;
+        let full_statistics = has_full_statistics(&reader.parquet_reader);
         'outer: for row_group_index in 0..reader.parquet_reader.num_row_groups() {
+            if let Some(filter) = row_group_filter {
+                let row_group_metadata =
+                    reader.parquet_reader.metadata().row_group(row_group_index);
+                if !filter.can_match(row_group_metadata, full_statistics) {
+                    continue;
+                }
+            }
             let row_group = reader.read_rows(row_group_index)?;
             for row in &row_group {
                 if result.len() >= limit {
@@ -131,6 +132,104 @@ impl TableStore for ParquetTableStore {
     // }
 }
 
+/// Marks files having min/max statistics for every column.
+const FULL_STATISTICS_KEY: &str = "cube.full_statistics";
+
+pub fn has_full_statistics(reader: &SerializedFileReader<File>) -> bool {
+    reader
+        .metadata()
+        .file_metadata()
+        .key_value_metadata()
+        .as_ref()
+        .map(|kv| kv.iter().any(|kv| kv.key == FULL_STATISTICS_KEY))
+        .unwrap_or(false)
+}
+
+pub fn min_max_from_statistics(
+    column_type: &ColumnType,
+    statistics: &Statistics,
+) -> Option<(TableValue, TableValue)> {
+    if !statistics.has_min_max_set() {
+        return None;
+    }
+    match (column_type, statistics) {
+        (ColumnType::Int, Statistics::Int64(s)) => {
+            Some((TableValue::Int(*s.min()), TableValue::Int(*s.max())))
+        }
+        (ColumnType::Timestamp, Statistics::Int64(s)) => Some((
+            TableValue::Timestamp(TimestampValue::new(*s.min() * 1000)),
+            TableValue::Timestamp(TimestampValue::new(*s.max() * 1000)),
+        )),
+        (ColumnType::String, Statistics::ByteArray(s)) => Some((
+            TableValue::String(s.min().as_utf8().ok()?.to_string()),
+            TableValue::String(s.max().as_utf8().ok()?.to_string()),
+        )),
+        (ColumnType::Boolean, Statistics::Boolean(s)) => {
+            Some((TableValue::Boolean(*s.min()), TableValue::Boolean(*s.max())))
+        }
+        _ => None,
+    }
+}
+
+/// Exposes only row groups of a file accepted by the filter.
+pub struct RowGroupFilteredReader {
+    reader: SerializedFileReader<File>,
+    row_groups: Vec<usize>,
+    metadata: ParquetMetaData,
+}
+
+impl RowGroupFilteredReader {
+    pub fn open(file: &str, filter: &RowGroupFilter) -> Result<RowGroupFilteredReader, CubeError> {
+        let reader = SerializedFileReader::new(File::open(file)?)?;
+        let full_statistics = has_full_statistics(&reader);
+        let metadata = reader.metadata();
+        let row_groups = (0..reader.num_row_groups())
+            .filter(|i| filter.can_match(metadata.row_group(*i), full_statistics))
+            .collect::<Vec<_>>();
+        let file_metadata = metadata.file_metadata();
+        let filtered_metadata = ParquetMetaData::new(
+            FileMetaData::new(
+                file_metadata.version(),
+                row_groups
+                    .iter()
+                    .map(|i| metadata.row_group(*i).num_rows())
+                    .sum(),
+                file_metadata.created_by().clone(),
+                file_metadata.key_value_metadata().clone(),
+                file_metadata.schema_descr_ptr(),
+                file_metadata.column_orders().cloned(),
+            ),
+            row_groups
+                .iter()
+                .map(|i| metadata.row_group(*i).clone())
+                .collect(),
+        );
+        Ok(RowGroupFilteredReader {
+            reader,
+            row_groups,
+            metadata: filtered_metadata,
+        })
+    }
+}
+
+impl FileReader for RowGroupFilteredReader {
+    fn metadata(&self) -> &ParquetMetaData {
+        &self.metadata
+    }
+
+    fn num_row_groups(&self) -> usize {
+        self.row_groups.len()
+    }
+
+    fn get_row_group(&self, i: usize) -> Result<Box<dyn RowGroupReader + '_>, ParquetError> {
+        self.reader.get_row_group(self.row_groups[i])
+    }
+
+    fn get_row_iter(&self, projection: Option<types::Type>) -> Result<RowIter, ParquetError> {
+        RowIter::from_file(projection, self)
+    }
+}
+
 impl ParquetTableStore {
     pub fn new(table: Index, row_group_size: usize) -> ParquetTableStore {
         ParquetTableStore {
@@ -139,6 +238,76 @@ impl ParquetTableStore {
         }
     }
 
+    /// Counts rows of the file accepted by `keep` reading one row group at a time.
+    pub fn count_retained_rows(
+        &self,
+        file: &str,
+        keep: &dyn Fn(&Row) -> bool,
+    ) -> Result<usize, CubeError> {
+        let mut reader = RowParquetReader::open(&self.table, file, None)?;
+        let mut count = 0;
+        for row_group_index in 0..reader.parquet_reader.num_row_groups() {
+            count += reader
+                .read_rows(row_group_index)?
+                .iter()
+                .filter(|r| keep(r))
+                .count();
+        }
+        Ok(count)
+    }
+
+    /// Merges sorted `rows` with rows of the source file accepted by `keep`.
+    /// `total_row_number` is the number of rows to be written which are split evenly between
+    /// destination files.
+    pub fn merge_retained_rows(
+        &self,
+        source_file: Option<&str>,
+        dest_files: Vec<String>,
+        rows: Vec<Row>,
+        total_row_number: usize,
+        sort_key_size: u64,
+        keep: &dyn Fn(&Row) -> bool,
+    ) -> Result<Vec<(u64, (Row, Row))>, CubeError> {
+        let mut writers = Vec::new();
+        for f in dest_files.iter() {
+            writers.push(RowParquetWriter::open(&self.table, f, self.row_group_size)?);
+        }
+        let mut split_writer = SplitRowParquetWriter::new(writers, total_row_number, sort_key_size);
+        if source_file.is_none() {
+            split_writer.write_rows(rows.as_slice())?;
+            return Ok(split_writer.close()?);
+        }
+
+        let mut reader = RowParquetReader::open(&self.table, source_file.unwrap(), None)?;
+        let mut right_position = 0;
+
+        for row_group_index in 0..reader.parquet_reader.num_row_groups() {
+            let mut read_rows = reader.read_rows(row_group_index)?;
+            read_rows.retain(|r| keep(r));
+            if read_rows.is_empty() {
+                continue;
+            }
+            let (new_pos, to_write) =
+                ParquetTableStore::merge_sort(read_rows, &rows, right_position, sort_key_size);
+            split_writer.write_rows(to_write.as_slice())?;
+            right_position = new_pos;
+        }
+
+        if right_position < rows.len() {
+            split_writer.write_rows(&rows[right_position..rows.len()])?;
+        }
+
+        Ok(split_writer.close()?)
+    }
+
+    /// Writes arrays of already sorted rows ordered as index columns.
+    pub fn write_arrays(&self, dest_file: &str, arrays: &[ArrayRef]) -> Result<(), CubeError> {
+        let mut writer = RowParquetWriter::open(&self.table, dest_file, self.row_group_size)?;
+        writer.write_arrays(arrays)?;
+        writer.parquet_writer.close()?;
+        Ok(())
+    }
+
     fn merge_sort(
         left: Vec<Row>,
         right: &Vec<Row>,
@@ -185,13 +354,26 @@ impl<'a> RowParquetReader<'a> {
         let file = File::open(file)?;
         let parquet_reader = SerializedFileReader::new(file)?;
 
+        let file_columns = parquet_reader
+            .metadata()
+            .file_metadata()
+            .schema_descr()
+            .columns()
+            .iter()
+            .map(|c| c.name().to_string())
+            .collect::<Vec<_>>();
         let column_with_buffer = columns_to_read
             .unwrap_or(table.get_columns())
             .iter()
             .map(|c| {
                 (
                     c,
-                    c.get_index(),
+                    if table.has_evolved_schema() {
+                        let storage_name = table.storage_name(c);
+                        file_columns.iter().position(|n| n == storage_name)
+                    } else {
+                        Some(c.get_index())
+                    },
                     match c.get_column_type() {
                         ColumnType::String => ColumnAccessor::Bytes(vec![ByteArray::new(); 16384]),
                         ColumnType::Bytes | ColumnType::HyperLogLog => ColumnAccessor::Bytes(vec![ByteArray::new(); 16384]),
@@ -215,8 +397,15 @@ impl<'a> RowParquetReader<'a> {
     fn load_row_group(&mut self, row_group_index: usize) -> Result<usize, CubeError> {
         let row_group = self.parquet_reader.get_row_group(row_group_index)?;
         let mut values_read = 0;
+        let mut columns_read = 0;
         for (_, index, column_accessor, def_levels) in &mut self.column_with_buffer {
-            let mut col_reader = row_group.get_column_reader(*index).unwrap();
+            // Columns added by ALTER TABLE after the file was written are left NULL.
+            let index = match index {
+                Some(index) => *index,
+                None => continue,
+            };
+            columns_read += 1;
+            let mut col_reader = row_group.get_column_reader(index).unwrap();
             match column_accessor {
                 ColumnAccessor::Bytes(buffer) => {
                     if let ColumnReader::ByteArrayColumnReader(ref mut reader) = col_reader {
@@ -280,6 +469,9 @@ impl<'a> RowParquetReader<'a> {
                 }
             };
         }
+        if columns_read == 0 {
+            values_read = min(row_group.metadata().num_rows() as usize, 16384);
+        }
         Ok(values_read)
     }
 
@@ -554,7 +746,6 @@ impl RowParquetWriter {
         table: &'a Index,
         file: &'a str,
         row_group_size: usize,
-        sort_key_size: u64,
     ) -> Result<RowParquetWriter, CubeError> {
         let file = File::create(file)?;
 
@@ -563,7 +754,11 @@ impl RowParquetWriter {
             .iter()
             .map(|column| {
                 // TODO pass nullable columns
-                Arc::new(parquet::schema::types::Type::from(column))
+                Arc::new(parquet::schema::types::Type::from(&Column::new(
+                    table.storage_name(column).clone(),
+                    column.get_column_type().clone(),
+                    column.get_index(),
+                )))
             })
             .collect();
 
@@ -582,7 +777,6 @@ impl RowParquetWriter {
             parquet_writer,
             row_group_size,
             buffer: Vec::with_capacity(row_group_size as usize),
-            sort_key_size,
         })
     }
 
@@ -646,20 +840,8 @@ impl RowParquetWriter {
                                 }
                             })
                             .collect::<Result<Vec<i64>, _>>()?;
-                        let min = if self.sort_key_size >= column_index as u64
-                            && column_values.len() > 0
-                        {
-                            Some(column_values[0].clone())
-                        } else {
-                            None
-                        };
-                        let max = if self.sort_key_size >= column_index as u64
-                            && column_values.len() > 0
-                        {
-                            Some(column_values[column_values.len() - 1].clone())
-                        } else {
-                            None
-                        };
+                        let min = column_values.iter().min().cloned();
+                        let max = column_values.iter().max().cloned();
                         let def_levels = self.get_def_levels(
                             batch_size,
                             row_batch_index,
@@ -698,20 +880,14 @@ impl RowParquetWriter {
                                 }
                             })
                             .collect::<Result<Vec<f64>, _>>()?;
-                        let min = if self.sort_key_size >= column_index as u64
-                            && column_values.len() > 0
-                        {
-                            Some(column_values[0].clone())
-                        } else {
-                            None
-                        };
-                        let max = if self.sort_key_size >= column_index as u64
-                            && column_values.len() > 0
-                        {
-                            Some(column_values[column_values.len() - 1].clone())
-                        } else {
-                            None
-                        };
+                        let min = column_values
+                            .iter()
+                            .cloned()
+                            .fold(None, |m: Option<f64>, v| Some(m.map_or(v, |m| m.min(v))));
+                        let max = column_values
+                            .iter()
+                            .cloned()
+                            .fold(None, |m: Option<f64>, v| Some(m.map_or(v, |m| m.max(v))));
                         let def_levels = self.get_def_levels(
                             batch_size,
                             row_batch_index,
@@ -747,20 +923,14 @@ impl RowParquetWriter {
                                 }
                             })
                             .collect::<Vec<ByteArray>>();
-                        let min = if self.sort_key_size >= column_index as u64
-                            && column_values.len() > 0
-                        {
-                            Some(column_values[0].clone())
-                        } else {
-                            None
-                        };
-                        let max = if self.sort_key_size >= column_index as u64
-                            && column_values.len() > 0
-                        {
-                            Some(column_values[column_values.len() - 1].clone())
-                        } else {
-                            None
-                        };
+                        let min = column_values
+                            .iter()
+                            .min_by(|a, b| a.data().cmp(b.data()))
+                            .cloned();
+                        let max = column_values
+                            .iter()
+                            .max_by(|a, b| a.data().cmp(b.data()))
+                            .cloned();
                         let def_levels = self.get_def_levels(
                             batch_size,
                             row_batch_index,
@@ -795,20 +965,8 @@ impl RowParquetWriter {
                                 }
                             })
                             .collect::<Vec<bool>>();
-                        let min = if self.sort_key_size >= column_index as u64
-                            && column_values.len() > 0
-                        {
-                            Some(column_values[0].clone())
-                        } else {
-                            None
-                        };
-                        let max = if self.sort_key_size >= column_index as u64
-                            && column_values.len() > 0
-                        {
-                            Some(column_values[column_values.len() - 1].clone())
-                        } else {
-                            None
-                        };
+                        let min = column_values.iter().min().cloned();
+                        let max = column_values.iter().max().cloned();
                         let def_levels = self.get_def_levels(
                             batch_size,
                             row_batch_index,
@@ -844,6 +1002,24 @@ impl RowParquetWriter {
         Ok(())
     }
 
+    fn write_arrays(&mut self, arrays: &[ArrayRef]) -> Result<(), CubeError> {
+        let num_rows = arrays.first().map(|a| a.len()).unwrap_or(0);
+        let mut offset = 0;
+        while offset < num_rows {
+            let len = min(self.row_group_size, num_rows - offset);
+            let mut row_group_writer = self.parquet_writer.next_row_group()?;
+            let mut column_index = 0;
+            while let Some(mut col_writer) = row_group_writer.next_column()? {
+                write_array(&mut col_writer, &arrays[column_index], offset, len)?;
+                row_group_writer.close_column(col_writer)?;
+                column_index += 1;
+            }
+            self.parquet_writer.close_row_group(row_group_writer)?;
+            offset += len;
+        }
+        Ok(())
+    }
+
     fn get_def_levels(
         &self,
         batch_size: usize,
@@ -876,10 +1052,10 @@ impl RowParquetWriter {
     fn writer_props() -> Arc<WriterProperties> {
         Arc::new(
             WriterProperties::builder()
-                // .set_key_value_metadata(Some(vec![KeyValue::new(
-                //     "key".to_string(),
-                //     "value".to_string(),
-                // )]))
+                .set_key_value_metadata(Some(vec![KeyValue::new(
+                    FULL_STATISTICS_KEY.to_string(),
+                    "true".to_string(),
+                )]))
                 .set_writer_version(WriterVersion::PARQUET_2_0)
                 .set_statistics_enabled(true)
                 // .set_column_dictionary_enabled(ColumnPath::new(vec!["col0".to_string()]), true)
@@ -889,9 +1065,152 @@ impl RowParquetWriter {
     }
 }
 
+macro_rules! array_values {
+    ($array: expr, $array_type: ident, $offset: expr, $len: expr, $convert: expr) => {{
+        let a = $array.as_any().downcast_ref::<$array_type>().unwrap();
+        ($offset..$offset + $len)
+            .filter(|i| !a.is_null(*i))
+            .map(|i| ($convert)(a.value(i)))
+            .collect::<Vec<_>>()
+    }};
+}
+
+fn write_array(
+    col_writer: &mut ColumnWriter,
+    array: &ArrayRef,
+    offset: usize,
+    len: usize,
+) -> Result<(), CubeError> {
+    let def_levels = (offset..offset + len)
+        .map(|i| if array.is_null(i) { 0 } else { 1 })
+        .collect::<Vec<i16>>();
+    let unsupported = || {
+        CubeError::internal(format!(
+            "Unsupported array type for parquet column: {:?}",
+            array.data_type()
+        ))
+    };
+    match col_writer {
+        ColumnWriter::Int64ColumnWriter(ref mut typed) => {
+            let column_values = match array.data_type() {
+                DataType::Int64 => array_values!(array, Int64Array, offset, len, |v| v),
+                DataType::Timestamp(TimeUnit::Microsecond, None) => {
+                    array_values!(array, TimestampMicrosecondArray, offset, len, |v| v)
+                }
+                DataType::Int64Decimal(0) => {
+                    array_values!(array, Int64Decimal0Array, offset, len, |v| v)
+                }
+                DataType::Int64Decimal(1) => {
+                    array_values!(array, Int64Decimal1Array, offset, len, |v| v)
+                }
+                DataType::Int64Decimal(2) => {
+                    array_values!(array, Int64Decimal2Array, offset, len, |v| v)
+                }
+                DataType::Int64Decimal(3) => {
+                    array_values!(array, Int64Decimal3Array, offset, len, |v| v)
+                }
+                DataType::Int64Decimal(4) => {
+                    array_values!(array, Int64Decimal4Array, offset, len, |v| v)
+                }
+                DataType::Int64Decimal(5) => {
+                    array_values!(array, Int64Decimal5Array, offset, len, |v| v)
+                }
+                DataType::Int64Decimal(10) => {
+                    array_values!(array, Int64Decimal10Array, offset, len, |v| v)
+                }
+                _ => return Err(unsupported()),
+            };
+            let min = column_values.iter().min().cloned();
+            let max = column_values.iter().max().cloned();
+            typed.write_batch_with_statistics(
+                &column_values,
+                Some(&def_levels),
+                None,
+                &min,
+                &max,
+                None,
+                None,
+            )?;
+        }
+        ColumnWriter::DoubleColumnWriter(ref mut typed) => {
+            let column_values = match array.data_type() {
+                DataType::Float64 => array_values!(array, Float64Array, offset, len, |v| v),
+                _ => return Err(unsupported()),
+            };
+            let min = column_values
+                .iter()
+                .cloned()
+                .fold(None, |m: Option<f64>, v| Some(m.map_or(v, |m| m.min(v))));
+            let max = column_values
+                .iter()
+                .cloned()
+                .fold(None, |m: Option<f64>, v| Some(m.map_or(v, |m| m.max(v))));
+            typed.write_batch_with_statistics(
+                &column_values,
+                Some(&def_levels),
+                None,
+                &min,
+                &max,
+                None,
+                None,
+            )?;
+        }
+        ColumnWriter::ByteArrayColumnWriter(ref mut typed) => {
+            let column_values = match array.data_type() {
+                DataType::Utf8 => {
+                    array_values!(array, StringArray, offset, len, |v| ByteArray::from(v))
+                }
+                DataType::Binary => {
+                    array_values!(array, BinaryArray, offset, len, |v: &[u8]| ByteArray::from(
+                        v.to_vec()
+                    ))
+                }
+                _ => return Err(unsupported()),
+            };
+            let min = column_values
+                .iter()
+                .min_by(|a, b| a.data().cmp(b.data()))
+                .cloned();
+            let max = column_values
+                .iter()
+                .max_by(|a, b| a.data().cmp(b.data()))
+                .cloned();
+            typed.write_batch_with_statistics(
+                &column_values,
+                Some(&def_levels),
+                None,
+                &min,
+                &max,
+                None,
+                None,
+            )?;
+        }
+        ColumnWriter::BoolColumnWriter(ref mut typed) => {
+            let column_values = match array.data_type() {
+                DataType::Boolean => array_values!(array, BooleanArray, offset, len, |v| v),
+                _ => return Err(unsupported()),
+            };
+            let min = column_values.iter().min().cloned();
+            let max = column_values.iter().max().cloned();
+            typed.write_batch_with_statistics(
+                &column_values,
+                Some(&def_levels),
+                None,
+                &min,
+                &max,
+                None,
+                None,
+            )?;
+        }
+        _ => return Err(unsupported()),
+    };
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use crate::metastore::{Column, ColumnType, Index};
+    use crate::queryplanner::partition_filter::RowGroupFilter;
     use crate::table::parquet::{ColumnAccessor, ParquetTableStore, RowParquetReader};
     use crate::table::{Row, TableStore, TableValue};
     use std::{fs, io};
@@ -900,6 +1219,8 @@ mod tests {
 
     use bigdecimal::BigDecimal;
     use csv::ReaderBuilder;
+    use datafusion::logical_plan::{Expr, Operator};
+    use datafusion::scalar::ScalarValue;
     use num::BigInt;
     use parquet::file::reader::FileReader;
     use parquet::file::statistics::Statistics;
@@ -1083,6 +1404,87 @@ mod tests {
         fs::remove_file(split_2).unwrap();
     }
 
+    #[test]
+    fn read_filtered_rows() {
+        let index = Index::try_new(
+            "foo".to_string(),
+            1,
+            vec![
+                Column::new("foo_int".to_string(), ColumnType::Int, 0),
+                Column::new("foo".to_string(), ColumnType::String, 1),
+            ],
+            1,
+        )
+        .unwrap();
+        let store = ParquetTableStore {
+            table: index.clone(),
+            row_group_size: 10,
+        };
+        let file_name = "foo-filtered.parquet";
+
+        let rows = (0..100)
+            .map(|i| {
+                Row::new(vec![
+                    TableValue::Int(i),
+                    TableValue::String(format!("Foo {}", i)),
+                ])
+            })
+            .collect::<Vec<_>>();
+        store
+            .merge_rows(None, vec![file_name.to_string()], rows, 1)
+            .unwrap();
+
+        let column = |name: &str| Expr::Column(name.to_string(), None);
+        let range_filter = RowGroupFilter::extract(
+            &index,
+            &[
+                Expr::BinaryExpr {
+                    left: Box::new(column("foo_int")),
+                    op: Operator::GtEq,
+                    right: Box::new(Expr::Literal(ScalarValue::Int64(Some(35)))),
+                },
+                Expr::BinaryExpr {
+                    left: Box::new(column("foo_int")),
+                    op: Operator::Lt,
+                    right: Box::new(Expr::Literal(ScalarValue::Int64(Some(42)))),
+                },
+            ],
+        )
+        .unwrap();
+        let read_rows = store
+            .read_filtered_rows(file_name, index.get_columns(), Some(&range_filter), 1000)
+            .unwrap();
+        assert_eq!(
+            read_rows
+                .iter()
+                .map(|r| r.values()[0].clone())
+                .collect::<Vec<_>>(),
+            (30..50).map(TableValue::Int).collect::<Vec<_>>()
+        );
+
+        let string_filter = RowGroupFilter::extract(
+            &index,
+            &[Expr::BinaryExpr {
+                left: Box::new(column("foo")),
+                op: Operator::Eq,
+                right: Box::new(Expr::Literal(ScalarValue::Utf8(Some("Foo 5".to_string())))),
+            }],
+        )
+        .unwrap();
+        let read_rows = store
+            .read_filtered_rows(file_name, index.get_columns(), Some(&string_filter), 1000)
+            .unwrap();
+        assert_eq!(
+            read_rows
+                .iter()
+                .map(|r| r.values()[0].clone())
+                .collect::<Vec<_>>(),
+            (0..10).map(TableValue::Int).collect::<Vec<_>>()
+        );
+
+        fs::remove_file(file_name).unwrap();
+    }
+
     #[bench]
     fn filter_count(b: &mut Bencher) {
         if let Ok((store, columns_to_read)) = prepare_donors() {